@@ -0,0 +1,224 @@
+//! Tamper evident logs of the connections of a node, see [`AuditLog`].
+//!
+//! Unlike the logs of [`logging`](crate::logging), these are not redacted,
+//! since their purpose is to tell which nodes exchanged data.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use iroh::{NodeId, SecretKey};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// A record of an [`AuditLog`], stored as a line of json.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Seconds since the unix epoch.
+    pub time: u64,
+    /// The node that wrote the log.
+    pub local: NodeId,
+    /// The node on the other side of the connection.
+    pub remote: NodeId,
+    /// The protocol of the connection.
+    pub alpn: String,
+    /// What happened, e.g. `connected` or `rejected: wrong password`.
+    pub event: String,
+    /// Hex encoded hash of the password that was given, if any, keyed with
+    /// the secret key of the local node.
+    ///
+    /// Only the local node can tell which password was used, and the hash
+    /// can not be used to guess the password without the key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Hex encoded hash of the previous line, or zeros for the first record.
+    pub prev: String,
+    /// Hex encoded signature of the local node over the record without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl AuditRecord {
+    /// The bytes that are signed, the json of the record without signature.
+    fn signed_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let record = Self {
+            signature: None,
+            ..self.clone()
+        };
+        Ok(serde_json::to_vec(&record)?)
+    }
+}
+
+/// An append only log of connections, where every record contains the hash
+/// of the previous line and is signed by the local node.
+///
+/// Changing or removing a record breaks the chain of hashes, and a changed
+/// record can not be signed again without the secret key, which is detected
+/// by [`AuditLog::verify`]. Removing records from the end can only be detected
+/// by comparing the hash of the last record with one that was kept elsewhere.
+#[derive(Debug)]
+pub struct AuditLog {
+    secret_key: SecretKey,
+    /// Key of the password hashes, derived from the secret key.
+    token_key: [u8; 32],
+    /// The file, and the hash of its last line.
+    file: Mutex<(File, blake3::Hash)>,
+    /// The same file, to sync it without holding the lock.
+    sync: File,
+}
+
+impl AuditLog {
+    /// Open the log at `path` for appending records of the node of
+    /// `secret_key`.
+    ///
+    /// The log is created if it does not exist. Existing logs are verified
+    /// first, and logs that were modified or written by another node are not
+    /// appended to.
+    pub fn open(path: &Path, secret_key: SecretKey) -> anyhow::Result<Self> {
+        let last = match Self::verify(path) {
+            Ok((_, Some(local), _)) if local != secret_key.public() => {
+                anyhow::bail!("the log was written by {local}, not by this node")
+            }
+            Ok((_, _, last)) => last,
+            Err(cause)
+                if cause
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|cause| cause.kind() == io::ErrorKind::NotFound) =>
+            {
+                blake3::Hash::from_bytes([0; 32])
+            }
+            Err(cause) => return Err(cause),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let sync = file.try_clone()?;
+        let token_key = blake3::derive_key("sendme audit log token", &secret_key.to_bytes());
+        Ok(Self {
+            secret_key,
+            token_key,
+            file: Mutex::new((file, last)),
+            sync,
+        })
+    }
+
+    /// Append a record of an `event` on a connection with `remote`.
+    ///
+    /// Only a keyed hash of the `password` is recorded, see
+    /// [`AuditRecord::token`]. The record is not synced to disk, see
+    /// [`AuditLog::sync`].
+    pub fn append(
+        &self,
+        remote: NodeId,
+        alpn: &[u8],
+        event: &str,
+        password: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let token = password.map(|password| {
+            blake3::keyed_hash(&self.token_key, password.as_bytes())
+                .to_hex()
+                .to_string()
+        });
+        let mut file = self.file.lock().unwrap();
+        let mut record = AuditRecord {
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            local: self.secret_key.public(),
+            remote,
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+            event: event.to_string(),
+            token,
+            prev: file.1.to_hex().to_string(),
+            signature: None,
+        };
+        let signature = self.secret_key.sign(&record.signed_bytes()?);
+        record.signature = Some(hex::encode(signature.to_bytes()));
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        file.0.write_all(line.as_bytes())?;
+        file.1 = blake3::hash(line.as_bytes());
+        Ok(())
+    }
+
+    /// Sync the appended records to disk.
+    ///
+    /// This blocks, but does not hold up other appends.
+    pub fn sync(&self) -> io::Result<()> {
+        self.sync.sync_data()
+    }
+
+    /// Check the chain of hashes and the signatures of the log at `path`.
+    ///
+    /// All records must be signed by the same node. Returns the number of
+    /// records, the node that wrote them, if there are any, and the hash of the
+    /// last line.
+    pub fn verify(path: &Path) -> anyhow::Result<(usize, Option<NodeId>, blake3::Hash)> {
+        let text = std::fs::read_to_string(path)?;
+        let mut last = blake3::Hash::from_bytes([0; 32]);
+        let mut local = None;
+        let mut count = 0;
+        for line in text.split_inclusive('\n') {
+            count += 1;
+            anyhow::ensure!(line.ends_with('\n'), "record {count} is incomplete");
+            let record: AuditRecord =
+                serde_json::from_str(line).with_context(|| format!("record {count} is invalid"))?;
+            anyhow::ensure!(
+                record.prev == last.to_hex().as_str(),
+                "record {count} does not follow the one before, the log was modified"
+            );
+            anyhow::ensure!(
+                *local.get_or_insert(record.local) == record.local,
+                "record {count} is of another node, the log was modified"
+            );
+            let signature: [u8; 64] = record
+                .signature
+                .as_deref()
+                .and_then(|signature| hex::decode(signature).ok())
+                .and_then(|signature| signature.try_into().ok())
+                .with_context(|| format!("record {count} is not signed"))?;
+            let signature = ed25519_dalek::Signature::from_bytes(&signature);
+            record
+                .local
+                .verify(&record.signed_bytes()?, &signature)
+                .with_context(|| format!("record {count} has an invalid signature"))?;
+            last = blake3::hash(line.as_bytes());
+        }
+        Ok((count, local, last))
+    }
+}
+
+/// The log of this process, see [`record`].
+static LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Open the log at `path`, if any, for the node of `secret_key`, to record
+/// the connections of this process in.
+pub fn open_log(path: Option<&Path>, secret_key: &SecretKey) -> anyhow::Result<()> {
+    if let Some(path) = path {
+        let log = AuditLog::open(path, secret_key.clone())
+            .with_context(|| format!("failed to open audit log {}", path.display()))?;
+        LOG.set(log).ok();
+    }
+    Ok(())
+}
+
+/// Record an `event` on a connection with `remote` in the log of this
+/// process, if one was opened with [`open_log`].
+///
+/// Only a keyed hash of the `password` is recorded. The record is synced to
+/// disk on the blocking thread pool, so progress is not held up by the disk.
+pub fn record(remote: NodeId, alpn: &[u8], event: &str, password: Option<&str>) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    if let Err(cause) = log.append(remote, alpn, event, password) {
+        error!("failed to write the audit log: {cause}");
+        return;
+    }
+    tokio::task::spawn_blocking(move || {
+        if let Err(cause) = log.sync() {
+            error!("failed to sync the audit log: {cause}");
+        }
+    });
+}
@@ -0,0 +1,288 @@
+//! Short codes like `7-crimson-otter`, that can be read out instead of a ticket.
+//!
+//! The sender waits for the receiver on a rendezvous node, whose key is derived
+//! from the number and the first word of the code. Both sides then run a
+//! password authenticated key exchange (SPAKE2) with the whole code, so a peer
+//! that does not know the code only gets a single guess, after which the code
+//! is burned. Only then is the real ticket sent.
+
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::Context;
+use iroh::{
+    discovery::{dns::DnsDiscovery, pkarr::PkarrPublisher},
+    endpoint::{Connection, RecvStream, SendStream},
+    protocol::{AcceptError, ProtocolHandler, Router},
+    Endpoint, NodeAddr, NodeId, SecretKey, Watcher,
+};
+use rand::Rng;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use tokio::sync::mpsc;
+
+use crate::ticket::Ticket;
+
+/// ALPN of the short code exchange.
+const CODE_ALPN: &[u8] = b"/sendme/code/0";
+
+/// Context for deriving the key of the rendezvous node from a code.
+const RENDEZVOUS_CONTEXT: &str = "sendme 2025-07 short code rendezvous";
+
+/// Identity of both sides of the symmetric key exchange.
+const SPAKE_IDENTITY: &[u8] = b"sendme short code";
+
+/// Largest number of a code.
+const MAX_NAMEPLATE: u16 = 999;
+
+/// Maximum size of a message of the code exchange, tickets are far smaller.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// Words of a code, 8 bits each.
+const WORDS: &[&str; 256] = &[
+    "acacia", "acorn", "adobe", "agate", "alder", "almond", "amber", "anchor", "apple", "apron",
+    "arbor", "arrow", "aspen", "atlas", "autumn", "badger", "bamboo", "banjo", "barley", "basil",
+    "beacon", "bear", "beaver", "beetle", "birch", "bison", "blanket", "blossom", "bonsai",
+    "boulder", "bramble", "breeze", "brook", "bucket", "buffalo", "cabin", "cactus", "camel",
+    "canary", "candle", "canoe", "canyon", "carbon", "cargo", "carp", "carrot", "castle", "cedar",
+    "cello", "cherry", "chestnut", "cider", "cinder", "citrus", "clover", "cobalt", "cobra",
+    "cocoa", "comet", "copper", "coral", "cosmos", "cotton", "cougar", "coyote", "crab", "crane",
+    "crater", "cricket", "crimson", "crystal", "cypress", "dahlia", "daisy", "delta", "desert",
+    "dingo", "dodo", "dolphin", "dove", "dragon", "drum", "dune", "eagle", "elk", "ember", "emu",
+    "falcon", "fennel", "fern", "ferret", "fiddle", "finch", "fjord", "flint", "forest", "fossil",
+    "fox", "frog", "garden", "garnet", "gecko", "geyser", "ginger", "glacier", "gnat", "goose",
+    "granite", "grape", "gravel", "gull", "hare", "hawk", "hazel", "heron", "hickory", "honey",
+    "hornet", "husky", "ibis", "iceberg", "indigo", "iris", "island", "ivory", "jackal", "jade",
+    "jaguar", "jasmine", "jay", "jelly", "juniper", "kayak", "kelp", "kettle", "kiwi", "koala",
+    "lagoon", "lamb", "lantern", "larch", "lark", "lemon", "lemur", "lentil", "lilac", "lily",
+    "lime", "linen", "lizard", "llama", "lotus", "lynx", "magnet", "mango", "maple", "marble",
+    "marten", "meadow", "melon", "mink", "mint", "mole", "moose", "moss", "mulberry", "mule",
+    "nectar", "newt", "nutmeg", "oasis", "ocean", "olive", "onyx", "orange", "orca", "orchid",
+    "osprey", "otter", "owl", "oyster", "paddle", "panda", "papaya", "parrot", "peach", "pearl",
+    "pebble", "pecan", "pelican", "pepper", "pine", "plum", "pollen", "poppy", "prairie", "puffin",
+    "puma", "pumpkin", "quail", "quartz", "quill", "rabbit", "radish", "raven", "reef", "ribbon",
+    "river", "robin", "rocket", "ruby", "saffron", "salmon", "sapphire", "satin", "seal", "shadow",
+    "shark", "sierra", "silver", "sloth", "snail", "sparrow", "spruce", "squid", "stork", "summit",
+    "sunset", "swan", "tapir", "teal", "thistle", "thunder", "tiger", "timber", "topaz", "trout",
+    "tulip", "tundra", "turnip", "turtle", "umber", "valley", "velvet", "violet", "viper",
+    "walnut", "walrus", "willow", "wolf", "wombat", "wren", "yak", "yarrow", "zebra", "zinc",
+];
+
+/// A code like `7-crimson-otter` to exchange a ticket, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortCode {
+    nameplate: u16,
+    words: [&'static str; 2],
+}
+
+impl ShortCode {
+    /// Create a new random code.
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            nameplate: rng.gen_range(1..=MAX_NAMEPLATE),
+            words: [
+                WORDS[rng.gen_range(0..WORDS.len())],
+                WORDS[rng.gen_range(0..WORDS.len())],
+            ],
+        }
+    }
+
+    /// The key of the rendezvous node, which does not depend on the last word.
+    fn rendezvous_key(&self) -> SecretKey {
+        let input = format!("{}-{}", self.nameplate, self.words[0]);
+        SecretKey::from_bytes(&blake3::derive_key(RENDEZVOUS_CONTEXT, input.as_bytes()))
+    }
+}
+
+impl Display for ShortCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", self.nameplate, self.words[0], self.words[1])
+    }
+}
+
+impl FromStr for ShortCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.trim().split('-').collect::<Vec<_>>();
+        let [nameplate, first, second] = parts.as_slice() else {
+            anyhow::bail!("a code has the form 7-crimson-otter");
+        };
+        let nameplate = nameplate
+            .parse::<u16>()
+            .ok()
+            .filter(|n| (1..=MAX_NAMEPLATE).contains(n))
+            .with_context(|| format!("invalid number {nameplate} in code"))?;
+        let word = |word: &str| {
+            WORDS
+                .iter()
+                .find(|w| w.eq_ignore_ascii_case(word))
+                .copied()
+                .with_context(|| format!("unknown word {word} in code"))
+        };
+        Ok(Self {
+            nameplate,
+            words: [word(first)?, word(second)?],
+        })
+    }
+}
+
+/// Write a length prefixed message.
+async fn write_message(send: &mut SendStream, data: &[u8]) -> anyhow::Result<()> {
+    send.write_all(&(data.len() as u32).to_le_bytes()).await?;
+    send.write_all(data).await?;
+    Ok(())
+}
+
+/// Read a length prefixed message.
+async fn read_message(recv: &mut RecvStream) -> anyhow::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    recv.read_exact(&mut len).await?;
+    let len = u32::from_le_bytes(len) as usize;
+    anyhow::ensure!(len <= MAX_MESSAGE_SIZE, "message too large");
+    let mut data = vec![0u8; len];
+    recv.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// Run the key exchange for `code` and return the shared key.
+///
+/// The key only matches the one of the peer if both used the same code.
+async fn exchange_key(
+    code: &ShortCode,
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+) -> anyhow::Result<[u8; 32]> {
+    let (spake, outbound) = Spake2::<Ed25519Group>::start_symmetric(
+        &Password::new(code.to_string().as_bytes()),
+        &Identity::new(SPAKE_IDENTITY),
+    );
+    write_message(send, &outbound).await?;
+    let inbound = read_message(recv).await?;
+    let key = spake
+        .finish(&inbound)
+        .map_err(|cause| anyhow::anyhow!("key exchange failed: {cause:?}"))?;
+    key.try_into()
+        .map_err(|_| anyhow::anyhow!("key exchange failed: unexpected key size"))
+}
+
+/// Proof that the receiver knows the code.
+fn receiver_proof(key: &[u8; 32]) -> blake3::Hash {
+    blake3::keyed_hash(key, b"receiver")
+}
+
+/// Proof that the ticket comes from the sender, who knows the code.
+fn sender_proof(key: &[u8; 32], ticket: &[u8]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new_keyed(key);
+    hasher.update(b"sender");
+    hasher.update(ticket);
+    hasher.finalize()
+}
+
+/// Check a proof received from the peer, in constant time.
+fn check_proof(proof: &[u8], expected: blake3::Hash) -> bool {
+    <[u8; 32]>::try_from(proof).is_ok_and(|proof| blake3::Hash::from(proof) == expected)
+}
+
+/// Sender side of the exchange, handing out the ticket for a single attempt.
+#[derive(Debug, Clone)]
+struct Offer {
+    code: ShortCode,
+    ticket: String,
+    /// The result of the first attempt, with the node id of the receiver.
+    done: mpsc::Sender<anyhow::Result<NodeId>>,
+}
+
+impl Offer {
+    async fn handle(&self, connection: Connection) -> anyhow::Result<NodeId> {
+        let node_id = connection.remote_node_id()?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let key = exchange_key(&self.code, &mut send, &mut recv).await?;
+        let proof = read_message(&mut recv).await?;
+        anyhow::ensure!(
+            check_proof(&proof, receiver_proof(&key)),
+            "{} tried a wrong code, the code is no longer valid",
+            node_id.fmt_short()
+        );
+        let ticket = self.ticket.as_bytes();
+        write_message(&mut send, ticket).await?;
+        write_message(&mut send, sender_proof(&key, ticket).as_bytes()).await?;
+        send.finish()?;
+        // wait for the receiver to read the ticket
+        connection.closed().await;
+        Ok(node_id)
+    }
+}
+
+impl ProtocolHandler for Offer {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        let res = self.handle(connection).await;
+        let err = res.as_ref().err().map(|cause| cause.to_string());
+        self.done.send(res).await.ok();
+        match err {
+            Some(cause) => Err(std::io::Error::other(cause).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Hand out `ticket` to the first receiver that knows `code`.
+///
+/// Returns the node id of the receiver once it got the ticket. Fails after
+/// the first attempt with a wrong code, since codes are easy to guess given
+/// enough attempts.
+pub async fn offer(code: &ShortCode, ticket: &Ticket) -> anyhow::Result<NodeId> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![CODE_ALPN.to_vec()])
+        .secret_key(code.rendezvous_key())
+        .add_discovery(PkarrPublisher::n0_dns())
+        .bind()
+        .await?;
+    let (done, mut attempts) = mpsc::channel(1);
+    let handler = Offer {
+        code: code.clone(),
+        ticket: ticket.to_string(),
+        done,
+    };
+    let router = Router::builder(endpoint).accept(CODE_ALPN, handler).spawn();
+    // make sure there is an address to publish
+    let _ = router.endpoint().home_relay().initialized().await?;
+    let res = attempts
+        .recv()
+        .await
+        .context("the code exchange stopped unexpectedly")?;
+    router.shutdown().await?;
+    res
+}
+
+/// Get the ticket for `code` from the sender.
+pub async fn redeem(code: &ShortCode) -> anyhow::Result<Ticket> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![])
+        .add_discovery(DnsDiscovery::n0_dns())
+        .bind()
+        .await?;
+    let res = async {
+        let addr = NodeAddr::new(code.rendezvous_key().public());
+        let connection = endpoint
+            .connect(addr, CODE_ALPN)
+            .await
+            .context("no sender found for this code")?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        let key = exchange_key(code, &mut send, &mut recv).await?;
+        write_message(&mut send, receiver_proof(&key).as_bytes()).await?;
+        let ticket = read_message(&mut recv)
+            .await
+            .context("the sender rejected the code")?;
+        let proof = read_message(&mut recv).await?;
+        anyhow::ensure!(
+            check_proof(&proof, sender_proof(&key, &ticket)),
+            "the sender could not prove that it knows the code"
+        );
+        connection.close(0u32.into(), b"done");
+        let ticket = String::from_utf8(ticket).context("invalid ticket from sender")?;
+        Ticket::from_str(&ticket).context("invalid ticket from sender")
+    }
+    .await;
+    endpoint.close().await;
+    res
+}
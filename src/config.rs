@@ -0,0 +1,255 @@
+//! The config file of sendme, written by `sendme init`.
+//!
+//! All settings are optional, and command line options take precedence.
+
+use std::{collections::BTreeMap, path::PathBuf, str::FromStr};
+
+use anyhow::Context;
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use iroh::{NodeId, SecretKey};
+use serde::{Deserialize, Serialize};
+
+/// Name of the config file in the sendme config directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// How progress is shown while sending and receiving.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressMode {
+    /// Progress bars on stderr.
+    #[default]
+    Bars,
+    /// No progress, like `--no-progress`.
+    None,
+}
+
+/// A named set of options for `sendme send --profile`.
+///
+/// Options given on the command line are added to these, or replace them if
+/// they can only be given once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// The files and directories to send, unless paths are given on the
+    /// command line. Relative paths are relative to the current directory.
+    pub paths: Vec<PathBuf>,
+    /// Globs of entries to share, see `sendme send --include`.
+    #[serde(alias = "includes")]
+    pub include: Vec<String>,
+    /// Globs of entries to leave out, see `sendme send --exclude`.
+    #[serde(alias = "excludes")]
+    pub exclude: Vec<String>,
+    /// Skip the files that git would ignore.
+    pub gitignore: bool,
+    /// Node ids or names of peers to serve, see `sendme send --allow`.
+    pub allow: Vec<String>,
+    /// A short message to show to every receiver.
+    pub message: Option<String>,
+    /// Stop providing the data after this time, see `sendme send --expire`.
+    pub expire: Option<String>,
+}
+
+/// A time window with a rate limit for `sendme serve`, written as
+/// `[[schedule]]` tables.
+///
+/// Times are in local time. The first window that contains the current time
+/// applies, outside of all windows there is no limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateWindow {
+    /// Days of the week, e.g. `["mon", "tue"]`, or every day if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    /// Start of the window as `HH:MM`.
+    pub start: String,
+    /// End of the window as `HH:MM`, exclusive. Windows that end before they
+    /// start continue past midnight, the same start and end is the whole day.
+    pub end: String,
+    /// Bytes per second, with an optional `K`, `M` or `G` suffix.
+    pub rate: String,
+}
+
+impl RateWindow {
+    /// Whether `time` is in this window.
+    ///
+    /// The days of windows that continue past midnight are the days they start.
+    pub fn contains(&self, time: NaiveDateTime) -> anyhow::Result<bool> {
+        let parse = |text: &str| {
+            NaiveTime::parse_from_str(text, "%H:%M")
+                .with_context(|| format!("invalid time {text}, expected HH:MM"))
+        };
+        let (start, end) = (parse(&self.start)?, parse(&self.end)?);
+        let (day, in_time) = if start < end {
+            (time.weekday(), start <= time.time() && time.time() < end)
+        } else if time.time() >= start {
+            (time.weekday(), true)
+        } else {
+            // after midnight, in the window of the day before
+            (time.weekday().pred(), time.time() < end)
+        };
+        let days = self
+            .days
+            .iter()
+            .map(|name| Weekday::from_str(name).map_err(|_| anyhow::anyhow!("invalid day {name}")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(in_time && (days.is_empty() || days.contains(&day)))
+    }
+
+    /// Check the times, days and rate of this window.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.contains(NaiveDateTime::default())?;
+        parse_rate(&self.rate)?;
+        Ok(())
+    }
+}
+
+/// Check all windows of `schedule`, see [`RateWindow::validate`].
+pub fn validate_schedule(schedule: &[RateWindow]) -> anyhow::Result<()> {
+    for (i, window) in schedule.iter().enumerate() {
+        window
+            .validate()
+            .with_context(|| format!("invalid schedule window {}", i + 1))?;
+    }
+    Ok(())
+}
+
+/// The rate limit of `schedule` at `time`, see [`RateWindow`].
+pub fn scheduled_rate(schedule: &[RateWindow], time: NaiveDateTime) -> anyhow::Result<Option<u64>> {
+    for window in schedule {
+        if window.contains(time)? {
+            return parse_rate(&window.rate).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a rate in bytes per second, with an optional `K`, `M` or `G` suffix
+/// for multiples of 1024, like curl.
+pub fn parse_rate(rate: &str) -> anyhow::Result<u64> {
+    parse_size(rate)
+}
+
+/// Parse a number of bytes, with an optional `K`, `M` or `G` suffix for
+/// multiples of 1024.
+pub fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let (number, unit) = match size.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&size[..i], c.to_ascii_uppercase()),
+        _ => (size, 'B'),
+    };
+    let factor: u64 = match unit {
+        'B' => 1,
+        'K' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        _ => anyhow::bail!("unknown unit {unit}, expected K, M or G"),
+    };
+    let number: u64 = number.parse().context("invalid number")?;
+    let size = number
+        .checked_mul(factor)
+        .context("the number is too large")?;
+    anyhow::ensure!(size > 0, "the number must be larger than 0");
+    Ok(size)
+}
+
+/// The settings of the config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Hex encoded secret key to use instead of a new random one, so the node
+    /// id stays the same. `IROH_SECRET` takes precedence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_key: Option<String>,
+    /// Directory to receive into, instead of the current directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_dir: Option<PathBuf>,
+    /// The relay mode, `default`, `disabled` or relay urls separated by commas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<String>,
+    /// How progress is shown.
+    pub progress: ProgressMode,
+    /// Named sets of send options, written as `[profile.<name>]` tables.
+    #[serde(rename = "profile", skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Names for the node ids of peers, see [`Config::resolve_peer`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub peers: BTreeMap<String, NodeId>,
+    /// Rate limits of `sendme serve` by time of day, see [`RateWindow`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<RateWindow>,
+    /// Always use the public DNS discovery, like `--dns-discovery`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dns_discovery: bool,
+    /// Keep a debug log of every run for `sendme report`, like `--report-log`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub report_log: bool,
+}
+
+impl Config {
+    /// The path of the config file in the user config directory.
+    pub fn path() -> anyhow::Result<PathBuf> {
+        let dir = dirs::config_dir().context("unable to determine the user config directory")?;
+        Ok(dir.join("sendme").join(CONFIG_FILE))
+    }
+
+    /// Load the config file, or the default config if there is none.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Self::path()?;
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(cause) => {
+                return Err(cause).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Write the config file, returning its path.
+    ///
+    /// The file is only readable by the user, since it can contain the secret key.
+    pub fn save(&self) -> anyhow::Result<PathBuf> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let text = toml::to_string_pretty(self)?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        // the mode only applies to new files, so also fix up existing ones
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        std::io::Write::write_all(&mut file, text.as_bytes())?;
+        Ok(path)
+    }
+
+    /// Parse a node id, or look up the name of a peer.
+    pub fn resolve_peer(&self, peer: &str) -> anyhow::Result<NodeId> {
+        if let Ok(node_id) = NodeId::from_str(peer) {
+            return Ok(node_id);
+        }
+        self.peers.get(peer).copied().with_context(|| {
+            format!("{peer} is neither a node id nor a known peer, see `sendme peers add`")
+        })
+    }
+
+    /// The configured secret key, if any.
+    pub fn secret_key(&self) -> anyhow::Result<Option<SecretKey>> {
+        self.secret_key
+            .as_deref()
+            .map(|key| SecretKey::from_str(key).context("invalid secret key in config file"))
+            .transpose()
+    }
+}
+
+/// The sendme directory in the user cache directory.
+pub fn cache_dir() -> anyhow::Result<PathBuf> {
+    let dir = dirs::cache_dir().context("unable to determine the user cache directory")?;
+    Ok(dir.join("sendme"))
+}
@@ -0,0 +1,612 @@
+//! Exporting collections from a store to the file system.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+#[cfg(feature = "zstd")]
+use async_compression::tokio::bufread::ZstdDecoder;
+use indicatif::MultiProgress;
+#[cfg(feature = "zstd")]
+use iroh_blobs::{api::blobs::EncodedItem, protocol::ChunkRanges};
+use iroh_blobs::{
+    api::{
+        blobs::{ExportMode, ExportOptions as BlobExportOptions, ExportProgressItem},
+        Store,
+    },
+    format::collection::Collection,
+    Hash,
+};
+use n0_future::StreamExt;
+#[cfg(feature = "zstd")]
+use tokio::{
+    fs::{create_dir_all, File},
+    io::BufWriter,
+};
+#[cfg(feature = "zstd")]
+use tokio_util::io::StreamReader;
+use tracing::trace;
+
+use crate::{
+    progress::{make_export_item_progress, make_export_overall_progress},
+    ticket::ShareMeta,
+};
+
+fn validate_path_component(component: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !component.contains('/'),
+        "path components must not contain the only correct path separator, /"
+    );
+    Ok(())
+}
+
+/// Maximum length of a path on windows, unless long paths are enabled.
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Part of [`WINDOWS_MAX_PATH`] that we assume is taken by the receiver's
+/// current directory.
+const TARGET_DIR_ALLOWANCE: usize = 60;
+
+/// Maximum length of a single path component in bytes on most file systems.
+const MAX_COMPONENT_LEN: usize = 255;
+
+/// File names that are reserved on windows, regardless of the extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Check if a collection entry name can be exported on common platforms.
+///
+/// Returns a description of the first problem found, if any.
+pub fn portability_problem(name: &str) -> Option<String> {
+    if name.encode_utf16().count() + TARGET_DIR_ALLOWANCE > WINDOWS_MAX_PATH {
+        return Some(format!(
+            "path is likely to exceed the windows limit of {WINDOWS_MAX_PATH} characters"
+        ));
+    }
+    for component in name.split('/') {
+        if component.len() > MAX_COMPONENT_LEN {
+            return Some(format!(
+                "{component:?} is longer than {MAX_COMPONENT_LEN} bytes"
+            ));
+        }
+        if let Some(c) = component
+            .chars()
+            .find(|c| matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') || c.is_control())
+        {
+            return Some(format!(
+                "{component:?} contains {c:?}, which is invalid on windows"
+            ));
+        }
+        if component.ends_with('.') || component.ends_with(' ') {
+            return Some(format!(
+                "{component:?} ends with a dot or space, which is invalid on windows"
+            ));
+        }
+        let stem = component.split('.').next().unwrap_or_default();
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        {
+            return Some(format!("{component:?} is a reserved name on windows"));
+        }
+    }
+    None
+}
+
+/// Limits on the collections a receiver accepts.
+///
+/// These protect the receiver from pathological shares, e.g. with millions of
+/// entries or names that are thousands of characters long, and are checked
+/// before anything is exported.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Maximum number of entries in a collection.
+    pub max_entries: u64,
+    /// Maximum length of an entry name in bytes, including directories.
+    pub max_name_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_entries: 1_000_000,
+            max_name_len: 4096,
+        }
+    }
+}
+
+impl Limits {
+    /// Check the number of entries, which is known before the names are.
+    pub fn check_entries(&self, entries: u64) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            entries <= self.max_entries,
+            "share has {entries} entries, more than the limit of {}",
+            self.max_entries
+        );
+        Ok(())
+    }
+
+    /// Check the number of entries and the names of a collection.
+    pub fn check(&self, collection: &Collection) -> anyhow::Result<()> {
+        self.check_entries(collection.len() as u64)?;
+        if let Some((name, _)) = collection
+            .iter()
+            .find(|(name, _)| name.len() > self.max_name_len)
+        {
+            let prefix = name.chars().take(40).collect::<String>();
+            anyhow::bail!(
+                "share has an entry name of {} bytes, more than the limit of {}: {prefix:?}...",
+                name.len(),
+                self.max_name_len
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The path of the collection entry `name` when exported into `root`.
+pub fn get_export_path(root: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let parts = name.split('/');
+    let mut path = root.to_path_buf();
+    for part in parts {
+        validate_path_component(part)?;
+        path.push(part);
+    }
+    Ok(path)
+}
+
+/// The path to export the entry `name` to.
+///
+/// Names that were escaped because they are not valid unicode are restored from
+/// the metadata where the platform supports it. The raw name must escape to
+/// `name`, so a share can not export an entry to a path other than the one that
+/// is shown for it.
+fn export_target(root: &Path, name: &str, meta: &ShareMeta) -> anyhow::Result<PathBuf> {
+    #[cfg(unix)]
+    if let Some(raw) = meta.raw_names.get(name) {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        use crate::import::escape_component;
+
+        let raw = hex::decode(raw).with_context(|| format!("invalid raw name of {name}"))?;
+        let mut path = root.to_path_buf();
+        let mut escaped = Vec::new();
+        for part in raw.split(|b| *b == b'/') {
+            anyhow::ensure!(
+                !part.is_empty() && part != b"." && part != b"..",
+                "invalid raw name of {name}"
+            );
+            escaped.push(escape_component(OsStr::from_bytes(part)));
+            path.push(OsStr::from_bytes(part));
+        }
+        anyhow::ensure!(
+            escaped.join("/") == name,
+            "raw name of {name} does not match the name"
+        );
+        return Ok(path);
+    }
+    #[cfg(not(unix))]
+    let _ = meta;
+    get_export_path(root, name)
+}
+
+/// Directory in the export root that overwritten files are moved to, see
+/// [`export`].
+pub const BACKUP_DIR: &str = ".sendme-backup";
+
+/// What to do if the target of an entry already exists, see [`export`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Replace the existing file, keeping it in [`BACKUP_DIR`] unless backups
+    /// are disabled.
+    Overwrite,
+    /// Keep the existing file, and don't export the entry.
+    Skip,
+    /// Export the entry next to the existing file, as `name (1).ext`.
+    Rename,
+    /// Fail before anything is exported.
+    #[default]
+    Fail,
+}
+
+impl Display for OnConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Overwrite => "overwrite",
+            Self::Skip => "skip",
+            Self::Rename => "rename",
+            Self::Fail => "fail",
+        })
+    }
+}
+
+impl FromStr for OnConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(Self::Overwrite),
+            "skip" => Ok(Self::Skip),
+            "rename" => Ok(Self::Rename),
+            "fail" => Ok(Self::Fail),
+            _ => anyhow::bail!("expected one of overwrite, skip, rename or fail"),
+        }
+    }
+}
+
+/// The first path like `name (1).ext` next to `target` that is neither taken
+/// by another entry nor exists.
+fn renamed_target(target: &Path, taken: &BTreeSet<PathBuf>) -> anyhow::Result<PathBuf> {
+    const MAX_ATTEMPTS: u32 = 10_000;
+    let stem = target
+        .file_stem()
+        .with_context(|| format!("invalid target {}", target.display()))?;
+    for i in 1..=MAX_ATTEMPTS {
+        let mut file_name = stem.to_os_string();
+        file_name.push(format!(" ({i})"));
+        if let Some(extension) = target.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        let candidate = target.with_file_name(file_name);
+        if !taken.contains(&candidate) && !candidate.exists() {
+            return Ok(candidate);
+        }
+    }
+    anyhow::bail!("no free name for {}", target.display())
+}
+
+async fn export_single_file(
+    db: &Store,
+    mp: &MultiProgress,
+    hash: &Hash,
+    target: PathBuf,
+    name: &String,
+) -> anyhow::Result<()> {
+    let mut stream = db
+        .export_with_opts(BlobExportOptions {
+            hash: *hash,
+            target: target.clone(),
+            mode: ExportMode::TryReference,
+        })
+        .stream()
+        .await;
+
+    let pb = mp.add(make_export_item_progress());
+    pb.set_message(format!("exporting {name}"));
+
+    while let Some(item) = stream.next().await {
+        match item {
+            ExportProgressItem::Size(size) => {
+                pb.set_length(size);
+            }
+            ExportProgressItem::CopyProgress(offset) => {
+                pb.set_position(offset);
+            }
+            ExportProgressItem::Done => {
+                pb.finish_and_clear();
+            }
+            ExportProgressItem::Error(cause) => {
+                pb.finish_and_clear();
+                anyhow::bail!("error exporting {}: {}", name, cause);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Most links followed when resolving a symlink target, like the limit of the
+/// operating systems.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Check that the target of the symlink entry `name` is relative and stays
+/// inside the shared directory.
+///
+/// The target is resolved through the other `symlinks` of the share, so a
+/// chain of links that each stay inside can not lead out of it either.
+pub fn check_symlink_target(
+    name: &str,
+    target: &str,
+    symlinks: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let mut dir = name.split('/').map(str::to_string).collect::<Vec<_>>();
+    dir.pop();
+    let mut hops = 0;
+    resolve_symlink_target(&mut dir, target, symlinks, &mut hops)
+}
+
+/// Resolve `target` relative to the directory `dir` of the share, following
+/// the `symlinks` of the share.
+fn resolve_symlink_target(
+    dir: &mut Vec<String>,
+    target: &str,
+    symlinks: &BTreeMap<String, String>,
+    hops: &mut usize,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !target.is_empty() && !target.starts_with('/'),
+        "target {target} is not relative"
+    );
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                anyhow::ensure!(
+                    dir.pop().is_some(),
+                    "target {target} leaves the shared directory"
+                );
+            }
+            part => {
+                validate_path_component(part)?;
+                anyhow::ensure!(!part.contains('\\'), "invalid target {target}");
+                dir.push(part.to_string());
+                if let Some(next) = symlinks.get(&dir.join("/")) {
+                    *hops += 1;
+                    anyhow::ensure!(*hops <= MAX_SYMLINK_HOPS, "too many levels of symlinks");
+                    dir.pop();
+                    resolve_symlink_target(dir, next, symlinks, hops)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create a symlink at `link` that points to the `/` separated `target`.
+#[cfg(unix)]
+fn create_symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Create a symlink at `link` that points to the `/` separated `target`.
+#[cfg(windows)]
+fn create_symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    let target = target.replace('/', "\\");
+    // windows needs to know whether the target is a directory
+    let resolved = link.parent().map(|dir| dir.join(&target));
+    if resolved.is_some_and(|resolved| resolved.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Create a symlink at `link` that points to the `/` separated `target`.
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &str, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Set the modification time of an exported file, in seconds since the unix
+/// epoch.
+fn set_mtime(target: &Path, secs: u64) -> anyhow::Result<()> {
+    let file = std::fs::File::options().write(true).open(target)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(secs))?;
+    Ok(())
+}
+
+/// How [`export`] writes the entries of a share.
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    /// What to do with targets that already exist, checked for all entries
+    /// before anything is exported.
+    pub on_conflict: OnConflict,
+    /// Move overwritten files to a new directory in [`BACKUP_DIR`] instead of
+    /// deleting them.
+    pub backup: bool,
+    /// Restore the modification times of the entries.
+    pub restore_times: bool,
+    /// Decompress the entries, only with the `zstd` feature.
+    pub decompress: bool,
+    /// Add `.zst` to the names of compressed entries that are not decompressed.
+    pub postfix_target: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            on_conflict: OnConflict::default(),
+            backup: true,
+            restore_times: true,
+            decompress: false,
+            postfix_target: false,
+        }
+    }
+}
+
+/// Export all entries of a collection into `root`.
+///
+/// `meta` is the metadata of the share, to restore the original names and
+/// modification times of entries, see [`ExportOptions`].
+///
+/// The symlinks of the share are recreated after all entries are exported.
+/// Links that point outside of the shared directory, or whose path already
+/// exists, are skipped.
+pub async fn export(
+    db: &Store,
+    collection: Collection,
+    meta: &ShareMeta,
+    root: &Path,
+    mp: &mut MultiProgress,
+    opts: ExportOptions,
+) -> anyhow::Result<()> {
+    let ExportOptions {
+        on_conflict,
+        backup,
+        restore_times,
+        decompress: _decompress,
+        postfix_target: _postfix_target,
+    } = opts;
+    // a new backup directory for every export, so backups never overwrite each other
+    let backup_dir = {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        root.join(BACKUP_DIR).join(now.as_millis().to_string())
+    };
+    // two entries must never be restored to the same path
+    let mut raw_paths = BTreeSet::new();
+    if let Some((name, _)) = meta
+        .raw_names
+        .iter()
+        .find(|(_, raw)| !raw_paths.insert(raw.to_ascii_lowercase()))
+    {
+        anyhow::bail!("raw name of {name} is used by another entry");
+    }
+    let mut backed_up = 0;
+    let mut taken = BTreeSet::new();
+    let mut entries = Vec::with_capacity(collection.len());
+    for (name, hash) in collection.iter() {
+        let target = export_target(root, name, meta)?;
+
+        #[cfg(not(feature = "zstd"))]
+        let target = if _postfix_target {
+            let file_name = target
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| format!("{}.zst", n))
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::Other, "Invalid file name")
+                })?;
+
+            target.with_file_name(file_name)
+        } else {
+            target
+        };
+
+        let target = if !target.exists() {
+            target
+        } else {
+            match on_conflict {
+                OnConflict::Overwrite => {
+                    anyhow::ensure!(
+                        !target.is_dir(),
+                        "target {} is a directory",
+                        target.display()
+                    );
+                    target
+                }
+                OnConflict::Skip => {
+                    eprintln!("skipping {name}, {} already exists", target.display());
+                    continue;
+                }
+                OnConflict::Rename => renamed_target(&target, &taken)?,
+                OnConflict::Fail => {
+                    eprintln!(
+                        "target {} already exists. Export stopped.",
+                        target.display()
+                    );
+                    eprintln!("You can remove the file or directory and try again. The download will not be repeated.");
+                    anyhow::bail!("target {} already exists", target.display());
+                }
+            }
+        };
+        taken.insert(target.clone());
+        entries.push((name, hash, target));
+    }
+
+    let op = mp.add(make_export_overall_progress());
+    op.set_length(entries.len() as u64);
+    for (i, (name, hash, target)) in entries.into_iter().enumerate() {
+        op.set_position(i as u64);
+        trace!("exporting {name} {hash} to {}", target.display());
+        let t0 = Instant::now();
+        if on_conflict == OnConflict::Overwrite && target.is_file() {
+            if backup {
+                let relative = target.strip_prefix(root)?;
+                let backup_target = backup_dir.join(relative);
+                if let Some(parent) = backup_target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::rename(&target, &backup_target)
+                    .await
+                    .with_context(|| format!("failed to back up {}", target.display()))?;
+                backed_up += 1;
+            } else {
+                tokio::fs::remove_file(&target).await?;
+            }
+        }
+
+        #[cfg(feature = "zstd")]
+        if _decompress {
+            let pb = mp.add(make_export_item_progress());
+            pb.set_message(format!("Decompressing {name}"));
+            let byte_stream = db
+                .export_bao(*hash, ChunkRanges::all())
+                .stream()
+                .inspect(|res| match res {
+                    EncodedItem::Size(size) => {
+                        pb.set_length(*size);
+                    }
+                    EncodedItem::Leaf(leaf) => {
+                        pb.set_position(leaf.offset);
+                    }
+                    EncodedItem::Done => {
+                        pb.finish_and_clear();
+                    }
+                    _ => {}
+                })
+                .filter_map(|res| match res {
+                    EncodedItem::Leaf(leaf) => Some(Ok(leaf.data)),
+                    EncodedItem::Error(err) => Some(Err(tokio::io::Error::other(err.to_string()))),
+                    _ => None,
+                });
+
+            let reader = StreamReader::new(byte_stream);
+            let mut decoder = ZstdDecoder::new(reader);
+            if let Some(parent) = target.parent() {
+                create_dir_all(parent).await?;
+            }
+            let target_file = File::create(&target).await?;
+            let mut output_writer = BufWriter::new(target_file);
+            tokio::io::copy(&mut decoder, &mut output_writer).await?;
+        } else {
+            export_single_file(db, mp, hash, target.clone(), name).await?;
+        }
+
+        #[cfg(not(feature = "zstd"))]
+        {
+            export_single_file(db, mp, hash, target.clone(), name).await?;
+        }
+
+        trace!("exported {name} in {:?}", t0.elapsed());
+
+        if let Some(mtime) = meta.mtimes.get(name).filter(|_| restore_times) {
+            // the data is there, so a missing time is not worth failing for
+            if let Err(cause) = set_mtime(&target, *mtime) {
+                eprintln!("failed to set the modification time of {name}: {cause}");
+            }
+        }
+    }
+    op.finish_and_clear();
+    if backed_up > 0 {
+        eprintln!(
+            "overwrote {backed_up} files, the previous versions are in {}",
+            backup_dir.display()
+        );
+    }
+
+    for (name, link_target) in &meta.symlinks {
+        if let Err(cause) = check_symlink_target(name, link_target, &meta.symlinks) {
+            eprintln!("skipping symlink {name}: {cause}");
+            continue;
+        }
+        let link = export_target(root, name, meta)?;
+        if link.symlink_metadata().is_ok() {
+            eprintln!("skipping symlink {name}, {} already exists", link.display());
+            continue;
+        }
+        if let Some(parent) = link.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        create_symlink(link_target, &link)
+            .with_context(|| format!("failed to create symlink {}", link.display()))?;
+    }
+    Ok(())
+}
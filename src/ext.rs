@@ -0,0 +1,935 @@
+//! The sendme extension protocol, which providers serve next to the blobs
+//! protocol for chat, pinning, address and rate limit updates.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use console::style;
+use indicatif::{HumanBytes, MultiProgress};
+use iroh::{
+    discovery::dns::DnsDiscovery,
+    endpoint::{Connection, ReadExactError, RecvStream, SendStream},
+    protocol::{AcceptError, ProtocolHandler},
+    Endpoint, NodeAddr, NodeId, SecretKey, Watcher,
+};
+use iroh_blobs::{
+    api::Store,
+    get::request::{get_hash_seq_and_sizes, get_verified_size},
+    net_protocol::Blobs,
+    BlobFormat, Hash,
+};
+use n0_future::{task::AbortOnDropHandle, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    io::AsyncBufReadExt,
+    select,
+    sync::{broadcast, mpsc, watch},
+};
+use tracing::{trace, warn};
+
+use crate::{
+    audit,
+    net::NetOptions,
+    transfer::{execute_get, show_get_error, Pacer},
+    Ticket,
+};
+
+/// How far ahead of `--limit-rate` a receiver can get before its connections
+/// are closed, for the data in flight and in the receive buffers.
+const RATE_SLACK: u64 = 16 * 1024 * 1024;
+
+/// Rate budgets of `sendme serve` that receivers share, see [`RateLimit`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Budgets {
+    /// Bytes per second for all receivers together.
+    pub(crate) global: Option<u64>,
+    /// Bytes per second for all receivers of a share together, by the hash of
+    /// the share.
+    pub(crate) shares: BTreeMap<Hash, u64>,
+}
+
+/// The provider side of `send --limit-rate` and the schedule of `serve`, for
+/// receivers that don't honor the limit they are asked for.
+///
+/// Every receiver has a token bucket that holds at most one second of data,
+/// like the one receivers use. Receivers that get more than [`RATE_SLACK`]
+/// ahead of their bucket have their connections closed. The bucket is kept
+/// when they reconnect, so they can't skip the debt.
+///
+/// The [`Budgets`] are split evenly between the receivers that share them, so
+/// the rate of a receiver changes when others come and go.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimit {
+    /// The limit of each receiver.
+    rate: watch::Receiver<Option<u64>>,
+    budgets: watch::Receiver<Budgets>,
+    connections: Arc<std::sync::Mutex<BTreeMap<NodeId, Vec<Connection>>>>,
+    buckets: Arc<std::sync::Mutex<BTreeMap<NodeId, (f64, Instant)>>>,
+    /// The blobs each receiver requested, to find the receivers of a share.
+    requested: Arc<std::sync::Mutex<BTreeMap<NodeId, BTreeSet<Hash>>>>,
+    /// Notified when receivers come and go, which changes their share of the
+    /// budgets.
+    receivers: Arc<watch::Sender<()>>,
+}
+
+impl RateLimit {
+    pub(crate) fn new(
+        rate: watch::Receiver<Option<u64>>,
+        budgets: watch::Receiver<Budgets>,
+    ) -> Self {
+        Self {
+            rate,
+            budgets,
+            connections: Default::default(),
+            buckets: Default::default(),
+            requested: Default::default(),
+            receivers: Arc::new(watch::channel(()).0),
+        }
+    }
+
+    /// The rate `node_id` is limited to, its own limit or its share of the
+    /// budgets, whichever is lower.
+    fn rate_of(&self, node_id: &NodeId) -> Option<u64> {
+        let mut rate = *self.rate.borrow();
+        let mut limit = |budget: u64, receivers: usize| {
+            let share = budget / receivers.max(1) as u64;
+            rate = Some(rate.map_or(share, |rate| rate.min(share)));
+        };
+        let budgets = self.budgets.borrow();
+        if let Some(global) = budgets.global {
+            limit(global, self.connections.lock().unwrap().len());
+        }
+        let requested = self.requested.lock().unwrap();
+        for hash in requested.get(node_id).into_iter().flatten() {
+            if let Some(budget) = budgets.shares.get(hash) {
+                let receivers = requested.values().filter(|hashes| hashes.contains(hash));
+                limit(*budget, receivers.count());
+            }
+        }
+        rate
+    }
+
+    /// Record that `node_id` requested `hash`, so it counts as a receiver of
+    /// the share if `hash` is the hash of one.
+    pub(crate) fn requested(&self, node_id: NodeId, hash: Hash) {
+        let mut requested = self.requested.lock().unwrap();
+        if requested.entry(node_id).or_default().insert(hash) {
+            drop(requested);
+            self.receivers.send_replace(());
+        }
+    }
+
+    /// Take `bytes` from the token `bucket` of `node_id`, after refilling it.
+    ///
+    /// Returns false if the bucket is too far behind the limit.
+    fn take(&self, node_id: &NodeId, bucket: &mut (f64, Instant), bytes: u64) -> bool {
+        let Some(rate) = self.rate_of(node_id) else {
+            *bucket = (0.0, Instant::now());
+            return true;
+        };
+        let rate = rate.max(1) as f64;
+        let now = Instant::now();
+        let (tokens, last) = bucket;
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+        *tokens -= bytes as f64;
+        *last = now;
+        *tokens >= -(RATE_SLACK as f64)
+    }
+
+    /// Record `bytes` sent to `node_id`, closing its data connections if it
+    /// is too far ahead of the limit.
+    ///
+    /// Returns false if the connections were closed.
+    pub(crate) fn sent(&self, node_id: NodeId, bytes: u64) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry(node_id)
+            .or_insert_with(|| (0.0, Instant::now()));
+        if self.take(&node_id, bucket, bytes) {
+            return true;
+        }
+        warn!(%node_id, "receiver exceeds the rate limit");
+        self.close(&node_id, b"rate limit exceeded");
+        false
+    }
+
+    /// Close the data connections of `node_id`.
+    pub(crate) fn close(&self, node_id: &NodeId, reason: &[u8]) {
+        let connections = self.connections.lock().unwrap();
+        for connection in connections.get(node_id).into_iter().flatten() {
+            connection.close(1u32.into(), reason);
+        }
+    }
+
+    /// Forget a receiver that is gone, and its bucket if it caught up with the
+    /// limit.
+    pub(crate) fn forget(&self, node_id: &NodeId) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.get_mut(node_id).is_some_and(|bucket| {
+            self.take(node_id, bucket, 0);
+            bucket.0 >= 0.0
+        }) {
+            buckets.remove(node_id);
+        }
+        drop(buckets);
+        self.requested.lock().unwrap().remove(node_id);
+        self.receivers.send_replace(());
+    }
+}
+
+/// The blobs protocol, keeping track of the connections for [`RateLimit`].
+#[derive(Debug, Clone)]
+pub(crate) struct LimitedBlobs {
+    pub(crate) blobs: Blobs,
+    pub(crate) limit: RateLimit,
+}
+
+impl ProtocolHandler for LimitedBlobs {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        let node_id = connection.remote_node_id().map_err(std::io::Error::other)?;
+        let id = connection.stable_id();
+        self.limit
+            .connections
+            .lock()
+            .unwrap()
+            .entry(node_id)
+            .or_default()
+            .push(connection.clone());
+        self.limit.receivers.send_replace(());
+        let res = self.blobs.accept(connection).await;
+        let mut connections = self.limit.connections.lock().unwrap();
+        if let Some(list) = connections.get_mut(&node_id) {
+            list.retain(|connection| connection.stable_id() != id);
+            if list.is_empty() {
+                connections.remove(&node_id);
+            }
+        }
+        drop(connections);
+        self.limit.receivers.send_replace(());
+        res
+    }
+}
+
+/// ALPN of the sendme extension protocol.
+///
+/// Providers serve this next to the iroh-blobs ALPN, so sendme receivers can
+/// negotiate additional features while plain iroh-blobs clients keep working.
+pub(crate) const EXT_ALPN: &[u8] = b"/sendme/ext/0";
+
+/// Extension features this version of sendme supports.
+const EXT_FEATURES: &[&str] = &[CHAT_FEATURE, PIN_FEATURE, ADDR_FEATURE, RATE_FEATURE];
+
+/// Exchange of short text messages, see [`ChatMessage`].
+pub(crate) const CHAT_FEATURE: &str = "chat";
+
+/// Requests to fetch and keep other data, see [`ExtRequest::Pin`].
+const PIN_FEATURE: &str = "pin";
+
+/// The name of the tag that keeps pinned data in the store.
+pub(crate) fn pin_tag(hash: &Hash) -> String {
+    format!("pin-{hash}")
+}
+
+/// Notifications about address changes of the provider, see [`AddrUpdate`].
+pub(crate) const ADDR_FEATURE: &str = "addr";
+
+/// Changes of the rate limit the provider asks for, see [`RateUpdate`].
+pub(crate) const RATE_FEATURE: &str = "rate";
+
+/// How long a receiver waits for the hello of the provider before getting data.
+pub(crate) const EXT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// First message on an extension connection, sent by both sides.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Hello {
+    /// The extension features the peer supports.
+    pub(crate) features: Vec<String>,
+    /// Whether the provider asks receivers to back off on congestion.
+    #[serde(default)]
+    pub(crate) nice: bool,
+    /// Bytes per second the provider asks receivers to download at most.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) limit_rate: Option<u64>,
+    /// The password of the receiver, for providers that require one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) password: Option<String>,
+    /// Why the provider rejected the receiver, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rejected: Option<String>,
+}
+
+impl Hello {
+    fn new() -> Self {
+        Self {
+            features: EXT_FEATURES.iter().map(|f| f.to_string()).collect(),
+            nice: false,
+            limit_rate: None,
+            password: None,
+            rejected: None,
+        }
+    }
+
+    /// Whether both this version and the peer support `feature`.
+    pub(crate) fn supports(&self, feature: &str) -> bool {
+        EXT_FEATURES.contains(&feature) && self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// A text message, sent on the hello stream after the hellos if both sides
+/// support [`CHAT_FEATURE`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatMessage {
+    text: String,
+}
+
+/// Exchange text messages with the peer.
+///
+/// Sends `message` if there is one, then every line of `lines`, and shows the
+/// messages of the peer. Our side of the stream is finished once `lines` is
+/// closed, or right away without `lines`. Returns once the peer finished its
+/// side as well.
+pub(crate) async fn chat(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    message: Option<String>,
+    mut lines: Option<broadcast::Receiver<String>>,
+    mp: MultiProgress,
+    peer: &str,
+) -> anyhow::Result<()> {
+    if let Some(text) = message {
+        write_message(&mut send, &ChatMessage { text }).await?;
+    }
+    if lines.is_none() {
+        send.finish()?;
+    }
+    let mut peer_done = false;
+    while !peer_done || lines.is_some() {
+        select! {
+            msg = read_message_or_end::<ChatMessage>(&mut recv), if !peer_done => {
+                let Some(msg) = msg? else {
+                    peer_done = true;
+                    continue;
+                };
+                // strip control characters, so the peer can not mess with the terminal
+                let text = msg
+                    .text
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .collect::<String>();
+                mp.println(format!("{} {text}", style(format!("{peer}:")).cyan()))?;
+            }
+            line = async { lines.as_mut().unwrap().recv().await }, if lines.is_some() => {
+                match line {
+                    Ok(text) => write_message(&mut send, &ChatMessage { text }).await?,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => {
+                        lines = None;
+                        send.finish()?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the lines of stdin as chat messages, see `--chat`.
+///
+/// Empty lines are skipped. The channel is closed when stdin is.
+pub(crate) fn chat_input() -> broadcast::Receiver<String> {
+    let (tx, rx) = broadcast::channel(16);
+    n0_future::task::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if !line.trim().is_empty() {
+                tx.send(line).ok();
+            }
+        }
+    });
+    rx
+}
+
+/// The new address of the provider, sent on its own unidirectional stream if
+/// both sides support [`ADDR_FEATURE`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AddrUpdate {
+    addr: NodeAddr,
+}
+
+/// Send the address of `endpoint` to the peer whenever it changes.
+async fn send_addr_updates(endpoint: Endpoint, connection: Connection) -> anyhow::Result<()> {
+    let mut updates = endpoint.node_addr().stream_updates_only();
+    while let Some(addr) = updates.next().await {
+        let Some(addr) = addr else {
+            continue;
+        };
+        let mut send = connection.open_uni().await?;
+        write_message(&mut send, &ProviderUpdate::Addr(AddrUpdate { addr })).await?;
+        send.finish()?;
+    }
+    Ok(())
+}
+
+/// A new rate limit of the provider, sent on its own unidirectional stream if
+/// both sides support [`RATE_FEATURE`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RateUpdate {
+    limit_rate: Option<u64>,
+}
+
+/// Any of the messages the provider sends on unidirectional streams.
+///
+/// The messages are tagged with their `type`, so a message that doesn't
+/// parse can't be taken for a rate update without a limit. Older receivers
+/// ignore the tag.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ProviderUpdate {
+    Addr(AddrUpdate),
+    Rate(RateUpdate),
+}
+
+/// Send the rate that `limit` limits the peer `node_id` to whenever it
+/// changes.
+async fn send_rate_updates(
+    limit: RateLimit,
+    node_id: NodeId,
+    connection: Connection,
+) -> anyhow::Result<()> {
+    let mut rate = limit.rate.clone();
+    let mut budgets = limit.budgets.clone();
+    let mut receivers = limit.receivers.subscribe();
+    let mut current = limit.rate_of(&node_id);
+    loop {
+        let res = select! {
+            res = rate.changed() => res,
+            res = budgets.changed() => res,
+            res = receivers.changed() => res,
+        };
+        if res.is_err() {
+            break;
+        }
+        let limit_rate = limit.rate_of(&node_id);
+        if limit_rate == current {
+            continue;
+        }
+        current = limit_rate;
+        let mut send = connection.open_uni().await?;
+        write_message(&mut send, &ProviderUpdate::Rate(RateUpdate { limit_rate })).await?;
+        send.finish()?;
+    }
+    Ok(())
+}
+
+/// Apply the updates of the provider: new addresses are added to `endpoint`,
+/// so reconnects after losing the connection use them, and new rate limits
+/// go to `pacer`. With `direct_only`, the relays of the addresses are left out.
+pub(crate) async fn receive_updates(
+    endpoint: Endpoint,
+    connection: Connection,
+    pacer: Arc<Pacer>,
+    direct_only: bool,
+) -> anyhow::Result<()> {
+    let node_id = connection.remote_node_id()?;
+    while let Ok(mut recv) = connection.accept_uni().await {
+        let update = match read_message(&mut recv).await {
+            Ok(update) => update,
+            Err(cause) => {
+                // e.g. the untagged updates of older providers
+                warn!("ignoring provider update: {cause:#}");
+                continue;
+            }
+        };
+        match update {
+            ProviderUpdate::Addr(mut update) => {
+                // only accept addresses for the provider itself
+                if update.addr.node_id != node_id {
+                    warn!("ignoring address of another node {}", update.addr.node_id);
+                    continue;
+                }
+                trace!("provider address changed to {:?}", update.addr);
+                if direct_only {
+                    update.addr.relay_url = None;
+                }
+                if let Err(cause) = endpoint.add_node_addr(update.addr) {
+                    warn!("ignoring provider address: {cause:#}");
+                }
+            }
+            ProviderUpdate::Rate(update) => {
+                trace!("provider rate limit changed to {:?}", update.limit_rate);
+                pacer.request(update.limit_rate);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Maximum size of a single extension message.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Write a length prefixed json message.
+async fn write_message(send: &mut SendStream, msg: &impl Serialize) -> anyhow::Result<()> {
+    let data = serde_json::to_vec(msg)?;
+    anyhow::ensure!(data.len() <= MAX_MESSAGE_SIZE, "message too large");
+    send.write_all(&(data.len() as u32).to_le_bytes()).await?;
+    send.write_all(&data).await?;
+    Ok(())
+}
+
+/// Read a length prefixed json message.
+async fn read_message<T: DeserializeOwned>(recv: &mut RecvStream) -> anyhow::Result<T> {
+    read_message_or_end(recv)
+        .await?
+        .context("the stream ended before the message")
+}
+
+/// Read a length prefixed json message, or `None` if the peer finished the
+/// stream instead.
+async fn read_message_or_end<T: DeserializeOwned>(
+    recv: &mut RecvStream,
+) -> anyhow::Result<Option<T>> {
+    let mut len = [0u8; 4];
+    match recv.read_exact(&mut len).await {
+        Ok(()) => {}
+        Err(ReadExactError::FinishedEarly(0)) => return Ok(None),
+        Err(cause) => return Err(cause.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    anyhow::ensure!(len <= MAX_MESSAGE_SIZE, "message too large");
+    let mut data = vec![0u8; len];
+    recv.read_exact(&mut data).await?;
+    Ok(Some(serde_json::from_slice(&data)?))
+}
+
+/// A request on its own stream of an extension connection, after the hellos.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum ExtRequest {
+    /// Fetch the data of the ticket and keep it.
+    Pin { ticket: String },
+}
+
+/// Wrong passwords a node may give before it has to wait, see [`Failures`].
+const AUTH_FREE_ATTEMPTS: u32 = 3;
+
+/// Longest time a node has to wait after giving wrong passwords.
+const AUTH_MAX_BAN: Duration = Duration::from_secs(60 * 60);
+
+/// Wrong passwords of all nodes per minute, after which no more passwords of
+/// new nodes are checked for the rest of the minute.
+///
+/// Node ids are free to create, so the limit per node alone does not stop an
+/// attacker from guessing.
+const AUTH_MAX_FAILURES_PER_MINUTE: u32 = 20;
+
+/// Most nodes whose wrong passwords are remembered, since node ids are free to
+/// create.
+const AUTH_MAX_TRACKED_NODES: usize = 1024;
+
+/// Wrong passwords given to a provider, to make guessing infeasible.
+#[derive(Debug, Default)]
+struct Failures {
+    /// Wrong passwords per node, and until when the node has to wait.
+    nodes: BTreeMap<NodeId, (u32, Instant)>,
+    /// Start of the current minute and the wrong passwords of all nodes in it.
+    window: Option<(Instant, u32)>,
+}
+
+impl Failures {
+    /// Check if `node_id` may try a password now.
+    ///
+    /// The limit for all nodes only applies if `global` is set.
+    fn check(&self, node_id: &NodeId, now: Instant, global: bool) -> Result<(), &'static str> {
+        if let Some((_, until)) = self.nodes.get(node_id) {
+            if *until > now {
+                return Err("too many wrong passwords, try again later");
+            }
+        }
+        if !global {
+            return Ok(());
+        }
+        if let Some((start, failures)) = self.window {
+            if now.duration_since(start) < Duration::from_secs(60)
+                && failures >= AUTH_MAX_FAILURES_PER_MINUTE
+            {
+                return Err("too many wrong passwords, try again later");
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a wrong password, doubling the time the node has to wait.
+    fn record(&mut self, node_id: NodeId, now: Instant) {
+        let (failures, until) = self.nodes.entry(node_id).or_insert((0, now));
+        *failures += 1;
+        if *failures > AUTH_FREE_ATTEMPTS {
+            let exp = (*failures - AUTH_FREE_ATTEMPTS - 1).min(16);
+            *until = now + Duration::from_secs(1 << exp).min(AUTH_MAX_BAN);
+        }
+        self.window = match self.window {
+            Some((start, failures)) if now.duration_since(start) < Duration::from_secs(60) => {
+                Some((start, failures + 1))
+            }
+            _ => Some((now, 1)),
+        };
+        if self.nodes.len() > AUTH_MAX_TRACKED_NODES {
+            // first forget the nodes that could try again long ago, then the
+            // ones that can try again the soonest
+            self.nodes
+                .retain(|_, (_, until)| now.saturating_duration_since(*until) < AUTH_MAX_BAN);
+            while self.nodes.len() > AUTH_MAX_TRACKED_NODES {
+                let Some(oldest) = self
+                    .nodes
+                    .iter()
+                    .min_by_key(|(_, (_, until))| *until)
+                    .map(|(node_id, _)| *node_id)
+                else {
+                    break;
+                };
+                self.nodes.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// The nodes a provider serves, see
+/// [`SendOptions::allow`](crate::send::SendOptions::allow) and
+/// [`SendOptions::password`](crate::send::SendOptions::password).
+#[derive(Debug)]
+pub(crate) struct Authorized {
+    /// Nodes that may connect at all, or everyone if empty.
+    allow: BTreeSet<NodeId>,
+    password: Option<String>,
+    /// Nodes that gave the right password.
+    nodes: std::sync::Mutex<BTreeSet<NodeId>>,
+    failures: std::sync::Mutex<Failures>,
+    /// The node of the self test, which is always permitted.
+    pub(crate) self_test: std::sync::Mutex<Option<NodeId>>,
+    /// Only permit a single receiver, see
+    /// [`SendOptions::once`](crate::send::SendOptions::once).
+    once: bool,
+    /// With `once`, the receiver that is getting or got the data.
+    recipient: std::sync::Mutex<Option<NodeId>>,
+    /// When the share expires, see
+    /// [`SendOptions::expire`](crate::send::SendOptions::expire). Set once the
+    /// ticket is made.
+    pub(crate) expires: OnceLock<SystemTime>,
+}
+
+impl Authorized {
+    pub(crate) fn new(
+        allow: impl IntoIterator<Item = NodeId>,
+        password: Option<String>,
+        once: bool,
+    ) -> Self {
+        Self {
+            allow: allow.into_iter().collect(),
+            password,
+            nodes: Default::default(),
+            failures: Default::default(),
+            self_test: Default::default(),
+            once,
+            recipient: Default::default(),
+            expires: OnceLock::new(),
+        }
+    }
+
+    /// Let another node try with `once`, after `node_id` disconnected without
+    /// getting the data.
+    pub(crate) fn release(&self, node_id: &NodeId) {
+        let mut recipient = self.recipient.lock().unwrap();
+        if recipient.as_ref() == Some(node_id) {
+            *recipient = None;
+        }
+    }
+
+    pub(crate) fn is_self_test(&self, node_id: &NodeId) -> bool {
+        self.self_test.lock().unwrap().as_ref() == Some(node_id)
+    }
+
+    fn check_expired(&self) -> Result<(), &'static str> {
+        match self.expires.get() {
+            Some(expires) if SystemTime::now() >= *expires => Err("the share expired"),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_allowed(&self, node_id: &NodeId) -> Result<(), &'static str> {
+        if self.allow.is_empty() || self.allow.contains(node_id) {
+            Ok(())
+        } else {
+            Err("not in the allow list")
+        }
+    }
+
+    /// Check the password a node sent in its [`Hello`], and remember the node
+    /// if it is right.
+    ///
+    /// Nodes that give wrong passwords have to wait before they can try again.
+    /// Once there were too many wrong passwords from all nodes, only nodes
+    /// that gave the right password before can try, so guessing with new node
+    /// ids does not lock them out.
+    fn check(&self, node_id: NodeId, password: Option<&str>) -> Result<(), &'static str> {
+        self.check_expired()?;
+        self.check_allowed(&node_id)?;
+        let Some(expected) = &self.password else {
+            return Ok(());
+        };
+        let password = password.ok_or("password required")?;
+        // compare the hashes, which is constant time
+        let right = blake3::hash(password.as_bytes()) == blake3::hash(expected.as_bytes());
+        let known = self.nodes.lock().unwrap().contains(&node_id);
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        failures.check(&node_id, now, !known)?;
+        if !right {
+            failures.record(node_id, now);
+            return Err("wrong password");
+        }
+        failures.nodes.remove(&node_id);
+        self.nodes.lock().unwrap().insert(node_id);
+        Ok(())
+    }
+
+    /// Check if `node_id` may get data.
+    pub(crate) fn permit(&self, node_id: &NodeId) -> Result<(), &'static str> {
+        if self.is_self_test(node_id) {
+            return Ok(());
+        }
+        self.check_expired()?;
+        self.check_allowed(node_id)?;
+        if self.password.is_some() && !self.nodes.lock().unwrap().contains(node_id) {
+            return Err("no password given");
+        }
+        if self.once {
+            let mut recipient = self.recipient.lock().unwrap();
+            match *recipient {
+                Some(recipient) if recipient != *node_id => return Err("ticket already used"),
+                _ => *recipient = Some(*node_id),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Provider side of the extension protocol.
+#[derive(Debug, Clone)]
+pub(crate) struct Extensions {
+    /// Message to send to every receiver.
+    pub(crate) message: Option<String>,
+    pub(crate) mp: MultiProgress,
+    pub(crate) store: Store,
+    pub(crate) endpoint: Endpoint,
+    /// Nodes that may send [`ExtRequest::Pin`].
+    pub(crate) allow_pin: Vec<NodeId>,
+    /// Upper bound for the size of pinned data.
+    pub(crate) max_pin_size: u64,
+    /// Where to report pinned data, to keep it across restarts, see
+    /// [`Server::pin`](crate::serve::Server::pin).
+    pub(crate) pins: Option<mpsc::Sender<Ticket>>,
+    /// Ask receivers to back off on congestion.
+    pub(crate) nice: bool,
+    /// Ask receivers to download at most the rate of this limit, the
+    /// receivers are told when it changes.
+    pub(crate) limit: RateLimit,
+    /// The receivers that gave the password, if one is required.
+    pub(crate) auth: Arc<Authorized>,
+    /// Messages to send to every receiver, see `--chat`.
+    pub(crate) chat: Option<Arc<broadcast::Receiver<String>>>,
+}
+
+impl Extensions {
+    async fn handle(&self, connection: Connection) -> anyhow::Result<()> {
+        let node_id = connection.remote_node_id()?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let hello: Hello = read_message(&mut recv).await?;
+        trace!("extension hello {:?}", hello.features);
+        let password = hello.password.as_deref();
+        if let Err(reason) = self.auth.check(node_id, password) {
+            audit::record(node_id, EXT_ALPN, &format!("rejected: {reason}"), password);
+            self.mp
+                .println(format!("rejected {}: {reason}", node_id.fmt_short()))?;
+            let own_hello = Hello {
+                rejected: Some(reason.to_string()),
+                ..Hello::new()
+            };
+            write_message(&mut send, &own_hello).await?;
+            send.finish()?;
+            connection.closed().await;
+            return Ok(());
+        }
+        audit::record(node_id, EXT_ALPN, "hello", password);
+        let own_hello = Hello {
+            nice: self.nice,
+            limit_rate: self.limit.rate_of(&node_id),
+            ..Hello::new()
+        };
+        write_message(&mut send, &own_hello).await?;
+        let _rate = hello.supports(RATE_FEATURE).then(|| {
+            let limit = self.limit.clone();
+            let connection = connection.clone();
+            AbortOnDropHandle::new(n0_future::task::spawn(async move {
+                send_rate_updates(limit, node_id, connection).await.ok();
+            }))
+        });
+        let _addr = hello.supports(ADDR_FEATURE).then(|| {
+            let endpoint = self.endpoint.clone();
+            let connection = connection.clone();
+            AbortOnDropHandle::new(n0_future::task::spawn(async move {
+                send_addr_updates(endpoint, connection).await.ok();
+            }))
+        });
+        let _chat = hello.supports(CHAT_FEATURE).then(|| {
+            let message = self.message.clone();
+            let lines = self.chat.as_ref().map(|lines| lines.resubscribe());
+            let mp = self.mp.clone();
+            let peer = node_id.fmt_short().to_string();
+            AbortOnDropHandle::new(n0_future::task::spawn(async move {
+                chat(send, recv, message, lines, mp, &peer).await.ok();
+            }))
+        });
+        while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+            let this = self.clone();
+            n0_future::task::spawn(async move {
+                let request = read_message(&mut recv).await?;
+                let response = this
+                    .handle_request(node_id, request)
+                    .await
+                    .map_err(|cause| cause.to_string());
+                write_message(&mut send, &response).await?;
+                send.finish()?;
+                anyhow::Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    async fn handle_request(&self, node_id: NodeId, request: ExtRequest) -> anyhow::Result<String> {
+        match request {
+            ExtRequest::Pin { ticket } => {
+                anyhow::ensure!(
+                    self.allow_pin.contains(&node_id),
+                    "node {node_id} is not allowed to pin"
+                );
+                let ticket = Ticket::from_str(&ticket)?;
+                let hash_and_format = ticket.hash_and_format();
+                self.mp.println(format!(
+                    "{} is pinning {}",
+                    node_id.fmt_short(),
+                    hash_and_format.hash
+                ))?;
+                let local = self.store.remote().local(hash_and_format).await?;
+                if !local.is_complete() {
+                    let connection = self
+                        .endpoint
+                        .connect(ticket.node_addr().clone(), iroh_blobs::protocol::ALPN)
+                        .await?;
+                    // the sizes are verified, so the data can not be larger
+                    let hash = &hash_and_format.hash;
+                    let size = match hash_and_format.format {
+                        BlobFormat::Raw => get_verified_size(&connection, hash)
+                            .await
+                            .map(|(size, _)| size),
+                        BlobFormat::HashSeq => {
+                            get_hash_seq_and_sizes(&connection, hash, self.max_pin_size, None)
+                                .await
+                                .map(|(_, sizes)| sizes.iter().sum())
+                        }
+                    }
+                    .map_err(show_get_error)?;
+                    anyhow::ensure!(
+                        size <= self.max_pin_size,
+                        "{} is larger than the limit of {}",
+                        HumanBytes(size),
+                        HumanBytes(self.max_pin_size)
+                    );
+                    execute_get(&self.store, connection, local.missing(), None, |_| {}).await?;
+                }
+                self.store
+                    .tags()
+                    .set(pin_tag(&hash_and_format.hash), hash_and_format)
+                    .await?;
+                if let Some(pins) = &self.pins {
+                    pins.send(ticket).await?;
+                }
+                self.mp
+                    .println(format!("pinned {}", hash_and_format.hash))?;
+                Ok(format!("pinned {}", hash_and_format.hash))
+            }
+        }
+    }
+
+    /// Connect to the extension protocol of a provider and exchange hellos.
+    ///
+    /// Fails if the provider does not speak the extension protocol, e.g.
+    /// because it is a plain iroh-blobs node.
+    pub(crate) async fn connect(
+        endpoint: &Endpoint,
+        addr: NodeAddr,
+        password: Option<String>,
+    ) -> anyhow::Result<(Connection, Hello, SendStream, RecvStream)> {
+        let node_id = addr.node_id;
+        let connection = endpoint.connect(addr, EXT_ALPN).await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        let own_hello = Hello {
+            password,
+            ..Hello::new()
+        };
+        write_message(&mut send, &own_hello).await?;
+        let hello: Hello = read_message(&mut recv).await?;
+        let event = match &hello.rejected {
+            Some(reason) => format!("rejected: {reason}"),
+            None => "hello".to_string(),
+        };
+        audit::record(node_id, EXT_ALPN, &event, own_hello.password.as_deref());
+        Ok((connection, hello, send, recv))
+    }
+}
+
+impl ProtocolHandler for Extensions {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        self.handle(connection)
+            .await
+            .map_err(|cause| std::io::Error::other(cause).into())
+    }
+}
+
+/// Ask the provider of `provider` to fetch and keep the data of `ticket`, and
+/// return its answer.
+pub async fn pin(
+    net: &NetOptions,
+    secret_key: SecretKey,
+    provider: &Ticket,
+    ticket: &Ticket,
+) -> anyhow::Result<String> {
+    let mut builder = Endpoint::builder()
+        .alpns(vec![])
+        .secret_key(secret_key)
+        .relay_mode(net.relay_mode());
+    let addr = provider.node_addr().clone();
+    if (addr.relay_url.is_none() && addr.direct_addresses.is_empty()) || net.dns_discovery {
+        builder = builder.add_discovery(DnsDiscovery::n0_dns());
+    }
+    builder = net.bind(builder);
+    let endpoint = builder.bind().await?;
+    let (connection, hello, _, _) = Extensions::connect(&endpoint, addr, None).await?;
+    anyhow::ensure!(
+        hello.supports(PIN_FEATURE),
+        "the provider does not support pinning"
+    );
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let request = ExtRequest::Pin {
+        ticket: ticket.to_string(),
+    };
+    write_message(&mut send, &request).await?;
+    send.finish()?;
+    let response: Result<String, String> = read_message(&mut recv).await?;
+    let response = response.map_err(|cause| anyhow::anyhow!("provider refused: {cause}"))?;
+    connection.close(0u32.into(), b"done");
+    endpoint.close().await;
+    Ok(response)
+}
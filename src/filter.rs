@@ -0,0 +1,90 @@
+//! Glob filters for the entries of a share.
+
+use anyhow::Context;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Which entries of a share to use, from `send --include` and `--exclude`, or
+/// `receive --only` and `--skip`.
+///
+/// Globs match entry names, which start with the name of the shared
+/// directory. Like in gitignore, globs without a slash match at any depth, and
+/// a trailing slash is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    only: Option<GlobSet>,
+    skip: Option<GlobSet>,
+}
+
+impl EntryFilter {
+    /// A filter for entries that match one of `only`, if any, and none of
+    /// `skip`.
+    pub fn new(only: &[String], skip: &[String]) -> anyhow::Result<Self> {
+        fn build(globs: &[String]) -> anyhow::Result<Option<GlobSet>> {
+            if globs.is_empty() {
+                return Ok(None);
+            }
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(entry_glob(glob)?);
+            }
+            Ok(Some(builder.build()?))
+        }
+        Ok(Self {
+            only: build(only)?,
+            skip: build(skip)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only.is_none() && self.skip.is_none()
+    }
+
+    /// Whether the entry `name` passes the filter.
+    pub fn matches(&self, name: &str) -> bool {
+        self.only.as_ref().map_or(true, |only| only.is_match(name)) && !self.skips(name)
+    }
+
+    /// Whether `name` matches one of the skipped globs.
+    ///
+    /// For directories this means that nothing below them passes the filter.
+    pub fn skips(&self, name: &str) -> bool {
+        self.skip.as_ref().is_some_and(|skip| skip.is_match(name))
+    }
+}
+
+/// Parse a glob that matches entry names, see [`EntryFilter`].
+fn entry_glob(glob: &str) -> anyhow::Result<Glob> {
+    // like gitignore, a glob without a slash matches at any depth
+    let trimmed = glob.trim_end_matches('/');
+    let pattern = if trimmed.contains('/') {
+        trimmed.to_string()
+    } else {
+        format!("**/{trimmed}")
+    };
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid glob {glob}"))
+}
+
+/// The entries of `names` that match one of `globs`, from `send --first`.
+///
+/// Entries are ordered by the first glob they match, and by name for the same
+/// glob.
+pub fn first_entries<'a>(
+    globs: &[String],
+    names: impl Iterator<Item = &'a str> + Clone,
+) -> anyhow::Result<Vec<String>> {
+    let mut res = Vec::new();
+    for glob in globs {
+        let glob = entry_glob(glob)?.compile_matcher();
+        let mut matches = names
+            .clone()
+            .filter(|name| glob.is_match(name) && !res.iter().any(|other| other == *name))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        matches.sort();
+        res.extend(matches);
+    }
+    Ok(res)
+}
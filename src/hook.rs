@@ -0,0 +1,72 @@
+//! The hooks and desktop notifications that report finished transfers.
+
+use std::path::Path;
+
+use anyhow::Context;
+use iroh::NodeId;
+
+use crate::Ticket;
+
+/// Show a desktop notification, only with the `notifications` feature.
+///
+/// Failing to show it is only logged, e.g. on machines without a desktop.
+pub async fn notify_desktop(summary: &str, body: &str) {
+    #[cfg(feature = "notifications")]
+    {
+        let notification = notify_rust::Notification::new()
+            .appname("sendme")
+            .summary(summary)
+            .body(body)
+            .finalize();
+        // showing a notification blocks until the notification server answered
+        match tokio::task::spawn_blocking(move || notification.show().map(|_| ())).await {
+            Ok(Ok(())) => {}
+            Ok(Err(cause)) => tracing::warn!("failed to show notification: {cause}"),
+            Err(cause) => tracing::warn!("failed to show notification: {cause}"),
+        }
+    }
+    #[cfg(not(feature = "notifications"))]
+    let _ = (summary, body);
+}
+
+/// The shell command to run the hook `command` with, without its environment.
+pub(crate) fn hook_command(command: &str) -> tokio::process::Command {
+    #[cfg(windows)]
+    let mut hook = {
+        let mut hook = tokio::process::Command::new("cmd");
+        hook.arg("/C");
+        hook
+    };
+    #[cfg(not(windows))]
+    let mut hook = {
+        let mut hook = tokio::process::Command::new("sh");
+        hook.arg("-c");
+        hook
+    };
+    hook.arg(command)
+        .stdin(std::process::Stdio::null())
+        // stdout may carry the ticket or data
+        .stdout(std::io::stderr());
+    hook
+}
+
+/// Run the `--on-complete` hook `command` for a transfer of `bytes` of the
+/// share of `ticket` with `peer`, and wait for it.
+pub(crate) async fn run_hook(
+    command: &str,
+    ticket: &Ticket,
+    peer: NodeId,
+    bytes: u64,
+    path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut hook = hook_command(command);
+    hook.env("SENDME_TICKET", ticket.to_string())
+        .env("SENDME_PEER", peer.to_string())
+        .env("SENDME_BYTES", bytes.to_string());
+    if let Some(path) = path {
+        hook.env("SENDME_PATH", path);
+    }
+    let status = hook.status().await.context("failed to run the hook")?;
+    anyhow::ensure!(status.success(), "the hook failed with {status}");
+    Ok(())
+}
@@ -0,0 +1,1050 @@
+//! Importing files and directories into a store.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fmt::Display,
+    fs::FileType,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+#[cfg(feature = "zstd")]
+use async_compression::tokio::bufread::ZstdEncoder;
+#[cfg(feature = "zstd")]
+use async_compression::Level;
+use futures_buffered::BufferedStreamExt;
+use indicatif::MultiProgress;
+use iroh::SecretKey;
+use iroh_blobs::{
+    api::{
+        blobs::{AddPathOptions, AddProgress, AddProgressItem, ImportMode},
+        Store, TempTag,
+    },
+    format::collection::Collection,
+    BlobFormat, Hash, HashAndFormat,
+};
+use n0_future::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+#[cfg(feature = "zstd")]
+use tokio::{fs::File, io::BufReader};
+use tokio_util::io::ReaderStream;
+use tracing::{error, trace};
+use walkdir::WalkDir;
+
+use crate::{
+    filter::{first_entries, EntryFilter},
+    manifest::{inline_entries, sign_manifest, store_collection, META_NAME},
+    progress::{make_import_item_progress, make_import_overall_progress},
+    ticket::ShareMeta,
+};
+
+/// This function converts an already canonicalized path to a string.
+///
+/// If `must_be_relative` is true, the function will fail if any component of the path is
+/// `Component::RootDir`
+///
+/// This function will also fail if the path is non canonical, i.e. contains
+/// `..` or `.`, or if the path components contain any windows or unix path
+/// separators.
+///
+/// Components that are not valid unicode are escaped, see [`escape_component`].
+pub fn canonicalized_path_to_string(
+    path: impl AsRef<Path>,
+    must_be_relative: bool,
+) -> anyhow::Result<String> {
+    let mut path_str = String::new();
+    let parts = path
+        .as_ref()
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(x) => {
+                let c = match x.to_str() {
+                    Some(c) => Cow::Borrowed(c),
+                    None => Cow::Owned(escape_component(x)),
+                };
+
+                if !c.contains('/') && !c.contains('\\') {
+                    Some(Ok(c))
+                } else {
+                    Some(Err(anyhow::anyhow!("invalid path component {:?}", c)))
+                }
+            }
+            Component::RootDir => {
+                if must_be_relative {
+                    Some(Err(anyhow::anyhow!("invalid path component {:?}", c)))
+                } else {
+                    path_str.push('/');
+                    None
+                }
+            }
+            _ => Some(Err(anyhow::anyhow!("invalid path component {:?}", c))),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let parts = parts.join("/");
+    path_str.push_str(&parts);
+    Ok(path_str)
+}
+
+/// Escape a path component that is not valid unicode.
+///
+/// Invalid bytes are written as `%XX` and `%` as `%25`, so different names stay
+/// different. On platforms where paths are not bytes, invalid characters are
+/// replaced instead.
+pub(crate) fn escape_component(component: &OsStr) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut res = String::new();
+        for chunk in component.as_bytes().utf8_chunks() {
+            res.push_str(&chunk.valid().replace('%', "%25"));
+            for byte in chunk.invalid() {
+                res.push_str(&format!("%{byte:02X}"));
+            }
+        }
+        res
+    }
+    #[cfg(not(unix))]
+    component.to_string_lossy().into_owned()
+}
+
+/// Hex encoding of the bytes of a relative path that is not valid unicode.
+///
+/// Returns `None` for valid paths, and on platforms where paths are not bytes.
+fn raw_name(relative: &Path) -> Option<String> {
+    if relative.to_str().is_some() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        Some(hex::encode(relative.as_os_str().as_bytes()))
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// Files up to this size are imported from memory instead of by reference.
+const INLINE_THRESHOLD: u64 = 16 * 1024;
+
+/// Add a single file to the store.
+///
+/// Files up to [`INLINE_THRESHOLD`] are read into memory, so the store can
+/// inline them into its database. For collections of many tiny files, this
+/// avoids keeping a reference and an outboard file per entry, which is slow
+/// to create and to export.
+async fn add_file(db: &Store, path: PathBuf) -> anyhow::Result<AddProgress> {
+    let size = tokio::fs::metadata(&path).await?.len();
+    let import = if size <= INLINE_THRESHOLD {
+        db.add_bytes(tokio::fs::read(&path).await?)
+    } else {
+        db.add_path_with_opts(AddPathOptions {
+            path,
+            mode: ImportMode::TryReference,
+            format: BlobFormat::Raw,
+        })
+    };
+    Ok(import)
+}
+
+/// Bytes per second that blake3 hashes on a single thread of this cpu.
+///
+/// This is measured, so it reflects the SIMD implementation that blake3 picked
+/// at runtime, without guessing it from the cpu features.
+pub fn blake3_throughput() -> u64 {
+    let data = vec![0u8; 16 * 1024 * 1024];
+    let t0 = std::time::Instant::now();
+    blake3::hash(&data);
+    (data.len() as f64 / t0.elapsed().as_secs_f64()) as u64
+}
+
+/// Files from this size are hashed with several threads, see [`hash_parallel`].
+const PARALLEL_HASH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Hash the file at `path` with `threads` threads.
+///
+/// The store hashes a file on a single thread while importing it, which is
+/// slow for very large files. This is used to find large files whose data is
+/// already in the store under a different fingerprint, e.g. because they were
+/// copied or touched, so they don't have to be imported again.
+async fn hash_parallel(path: PathBuf, threads: usize) -> anyhow::Result<Hash> {
+    tokio::task::spawn_blocking(move || {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?;
+        let hash = pool.install(|| {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_mmap_rayon(&path)?;
+            anyhow::Ok(hasher.finalize())
+        })?;
+        Ok(Hash::from_bytes(*hash.as_bytes()))
+    })
+    .await?
+}
+
+/// Size and modification time of a file.
+///
+/// A file with the same fingerprint as before is assumed to be unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileFingerprint {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+}
+
+impl FileFingerprint {
+    fn new(path: &Path) -> anyhow::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+        Ok(Self {
+            size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+        })
+    }
+}
+
+/// Hashes of previously imported files, persisted next to the cached store.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    entries: BTreeMap<String, (FileFingerprint, Hash)>,
+}
+
+impl HashCache {
+    const FILE_NAME: &'static str = "hashes.json";
+
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        match std::fs::read(dir.join(Self::FILE_NAME)) {
+            Ok(data) => Ok(serde_json::from_slice(&data).unwrap_or_else(|cause| {
+                // the cache is just an optimization, so start over if it is corrupt
+                error!("ignoring corrupt hash cache: {cause}");
+                Self::default()
+            })),
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(cause) => Err(cause.into()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::write(dir.join(Self::FILE_NAME), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Whether no file was imported with this cache yet.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn get(&self, path: &Path, fingerprint: FileFingerprint) -> Option<Hash> {
+        let (cached, hash) = self.entries.get(&*path.to_string_lossy())?;
+        (*cached == fingerprint).then_some(*hash)
+    }
+
+    fn insert(&mut self, path: &Path, fingerprint: FileFingerprint, hash: Hash) {
+        self.entries
+            .insert(path.to_string_lossy().into_owned(), (fingerprint, hash));
+    }
+}
+
+/// When the blobs of a persistent store were last shared, persisted next to
+/// the store.
+///
+/// Stores that are kept across shares only grow, so once they are larger than
+/// a limit, the blobs that were not shared for the longest time are deleted,
+/// see [`CacheUsage::evict`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheUsage {
+    /// Seconds since the unix epoch of the last share, per hex encoded hash.
+    blobs: BTreeMap<String, u64>,
+}
+
+impl CacheUsage {
+    const FILE_NAME: &'static str = "usage.json";
+
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        match std::fs::read(dir.join(Self::FILE_NAME)) {
+            Ok(data) => Ok(serde_json::from_slice(&data).unwrap_or_else(|cause| {
+                error!("ignoring corrupt cache usage: {cause}");
+                Self::default()
+            })),
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(cause) => Err(cause.into()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::write(dir.join(Self::FILE_NAME), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Record that `hashes` are shared now.
+    pub fn touch(&mut self, hashes: impl IntoIterator<Item = Hash>) -> anyhow::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        for hash in hashes {
+            self.blobs.insert(hash.to_hex().to_string(), now);
+        }
+        Ok(())
+    }
+
+    /// Delete the blobs from `db` that were not shared for the longest time,
+    /// until the recorded blobs take at most `max_size` bytes.
+    ///
+    /// Blobs in `keep` are never deleted, so the limit can be exceeded by the
+    /// current share. Blobs of older versions of sendme are not recorded, and
+    /// are kept. Returns the number of bytes that were deleted.
+    pub async fn evict(
+        &mut self,
+        db: &Store,
+        max_size: u64,
+        keep: &BTreeSet<Hash>,
+    ) -> anyhow::Result<u64> {
+        let mut blobs = Vec::new();
+        let mut total = 0;
+        for (hash, used) in &self.blobs {
+            let hash = Hash::from_str(hash)?;
+            let size = db
+                .remote()
+                .local(HashAndFormat::raw(hash))
+                .await?
+                .local_bytes();
+            total += size;
+            blobs.push((*used, hash, size));
+        }
+        // least recently shared first
+        blobs.sort();
+        let mut evicted = Vec::new();
+        let mut deleted = 0;
+        for (_, hash, size) in blobs {
+            if total <= max_size {
+                break;
+            }
+            if keep.contains(&hash) {
+                continue;
+            }
+            total -= size;
+            deleted += size;
+            evicted.push(hash);
+        }
+        for hash in &evicted {
+            self.blobs.remove(&hash.to_hex().to_string());
+        }
+        if !evicted.is_empty() {
+            trace!("evicting {} blobs from the cache", evicted.len());
+            db.blobs().delete(evicted).await?;
+        }
+        Ok(deleted)
+    }
+}
+
+/// How symlinks in a shared directory are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Skip symlinks.
+    #[default]
+    Ignore,
+    /// Share the files and directories that symlinks point to, as if they
+    /// were in place of the links.
+    Follow,
+    /// Share the symlinks themselves, see [`ShareMeta::symlinks`].
+    Preserve,
+}
+
+impl Display for Symlinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ignore => "ignore",
+            Self::Follow => "follow",
+            Self::Preserve => "preserve",
+        })
+    }
+}
+
+impl FromStr for Symlinks {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "follow" => Ok(Self::Follow),
+            "preserve" => Ok(Self::Preserve),
+            _ => anyhow::bail!("expected one of ignore, follow or preserve"),
+        }
+    }
+}
+
+/// The `/` separated target of a symlink, if it is relative and valid unicode.
+fn link_target_to_string(target: &Path) -> Option<String> {
+    let parts = target
+        .components()
+        .map(|c| match c {
+            Component::Normal(x) => x.to_str().filter(|x| !x.contains(['/', '\\'])),
+            Component::ParentDir => Some(".."),
+            Component::CurDir => Some("."),
+            Component::RootDir | Component::Prefix(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.join("/"))
+}
+
+/// Which files of a directory are shared, see [`list_files`].
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// How to handle symlinks.
+    pub symlinks: Symlinks,
+    /// Skip files that are ignored by `.gitignore` or `.ignore` files, also
+    /// outside of git repositories.
+    pub gitignore: bool,
+    /// Only share entries that match the filter.
+    pub filter: EntryFilter,
+    /// Globs of the entries to serve first, see [`ShareMeta::first`].
+    pub first: Vec<String>,
+}
+
+/// Whether the walk of a shared directory should descend into `path`.
+fn walk_dir(root: &Path, filter: &EntryFilter, depth: usize, path: &Path) -> bool {
+    // the shared directory itself is never skipped
+    depth == 0
+        || path
+            .strip_prefix(root)
+            .ok()
+            .and_then(|relative| canonicalized_path_to_string(relative, true).ok())
+            .map_or(true, |name| !filter.skips(&name))
+}
+
+/// The files that are shared for `path`, as entry names and file paths,
+/// sorted by name, and the preserved symlinks, as entry names and link targets.
+///
+/// Names are relative to the parent of `path`, so they start with the name of
+/// a shared directory. Symlinks are handled according to `opts`, and only
+/// entries that match its filter are listed. Directories that are skipped by
+/// the filter or ignored are not walked at all.
+pub fn list_files(
+    path: &Path,
+    opts: &WalkOptions,
+) -> anyhow::Result<(Vec<(String, PathBuf)>, BTreeMap<String, String>)> {
+    let path = path.canonicalize()?;
+    anyhow::ensure!(path.exists(), "path {} does not exist", path.display());
+    let root = path.parent().context("context get parent")?;
+    let follow_links = opts.symlinks == Symlinks::Follow;
+    // both walkers also work for files, so we don't need to special case them.
+    // they also detect loops when following links.
+    let files: Box<dyn Iterator<Item = anyhow::Result<(PathBuf, FileType)>> + '_> =
+        if opts.gitignore {
+            let (root, filter) = (root.to_path_buf(), opts.filter.clone());
+            let files = ignore::WalkBuilder::new(&path)
+                .standard_filters(false)
+                .git_ignore(true)
+                .git_exclude(true)
+                .ignore(true)
+                .parents(true)
+                .require_git(false)
+                .follow_links(follow_links)
+                .filter_entry(move |entry| {
+                    let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                    !is_dir || walk_dir(&root, &filter, entry.depth(), entry.path())
+                })
+                .build()
+                .map(|entry| {
+                    let entry = entry?;
+                    let file_type = entry.file_type().context("entry without file type")?;
+                    Ok((entry.into_path(), file_type))
+                });
+            Box::new(files)
+        } else {
+            let files = WalkDir::new(&path)
+                .follow_links(follow_links)
+                .into_iter()
+                .filter_entry(|entry| {
+                    !entry.file_type().is_dir()
+                        || walk_dir(root, &opts.filter, entry.depth(), entry.path())
+                })
+                .map(|entry| {
+                    let entry = entry?;
+                    let file_type = entry.file_type();
+                    Ok((entry.into_path(), file_type))
+                });
+            Box::new(files)
+        };
+    let mut links = BTreeMap::new();
+    // flatten the directory structure into a list of (name, path) pairs.
+    let mut data_sources: Vec<(String, PathBuf)> = files
+        .map(|entry| {
+            let (path, file_type) = entry?;
+            if file_type.is_dir() {
+                // directories are handled by the walker
+                return Ok(None);
+            }
+            let name = canonicalized_path_to_string(path.strip_prefix(root)?, true)?;
+            if !opts.filter.matches(&name) {
+                return Ok(None);
+            }
+            if file_type.is_symlink() && opts.symlinks == Symlinks::Preserve {
+                let target = std::fs::read_link(&path)?;
+                match link_target_to_string(&target) {
+                    Some(target) => {
+                        links.insert(name, target);
+                    }
+                    None => eprintln!(
+                        "skipping symlink {name}, its target {} is not relative",
+                        target.display()
+                    ),
+                }
+                return Ok(None);
+            }
+            if !file_type.is_file() {
+                // skip other symlinks
+                return Ok(None);
+            }
+            anyhow::Ok(Some((name, path)))
+        })
+        .filter_map(Result::transpose)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    data_sources.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok((data_sources, links))
+}
+
+/// Fail if `name` is the name of the metadata entry, see [`META_NAME`].
+///
+/// Only top level entries can collide with it, since the metadata entry is
+/// never in a directory.
+fn check_reserved(name: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        name != META_NAME,
+        "{META_NAME} is reserved for the share metadata, rename the file to share it"
+    );
+    Ok(())
+}
+
+/// Check if `path` is a metadata entry that an older receiver wrote to disk.
+///
+/// Older versions of sendme export the metadata entry as a regular file, see
+/// [`META_NAME`], so it shows up when sharing the files of a receive directory.
+/// Any json object parses as metadata, so only non-default metadata counts.
+fn is_stale_meta(path: &Path) -> bool {
+    std::fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<ShareMeta>(&data).ok())
+        .is_some_and(|meta| meta != ShareMeta::default())
+}
+
+/// The files that are shared for `paths`, like [`list_files`] for each of them.
+///
+/// The entries of all paths are merged as if the paths were in one directory.
+/// Fails if two paths have the same name, or if a path has the name of the
+/// metadata entry. Metadata entries that older receivers wrote to disk are
+/// skipped instead.
+pub fn list_paths(
+    paths: &[PathBuf],
+    opts: &WalkOptions,
+) -> anyhow::Result<(Vec<(String, PathBuf)>, BTreeMap<String, String>)> {
+    let mut names = BTreeMap::new();
+    let mut files = Vec::new();
+    let mut links = BTreeMap::new();
+    for path in paths {
+        // all entries of a path start with its name, so it is enough to compare these
+        let name = path
+            .canonicalize()?
+            .file_name()
+            .context("path without a name")?
+            .to_os_string();
+        if name == META_NAME && path.is_file() && is_stale_meta(path) {
+            eprintln!(
+                "skipping {}, it is the metadata of a share received by an older version of sendme",
+                path.display()
+            );
+            continue;
+        }
+        check_reserved(&name.to_string_lossy())?;
+        if let Some(other) = names.insert(name, path) {
+            anyhow::bail!(
+                "{} and {} have the same name, only one of them can be shared",
+                other.display(),
+                path.display()
+            );
+        }
+        let (path_files, path_links) = list_files(path, opts)?;
+        files.extend(path_files);
+        links.extend(path_links);
+    }
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok((files, links))
+}
+
+/// Size of tar headers, and the unit of tar entries.
+const TAR_BLOCK_SIZE: u64 = 512;
+
+/// Upper bound for the size of tar entries that hold long names.
+const MAX_TAR_NAME_SIZE: u64 = 64 * 1024;
+
+/// Read the data of a tar entry of `size` bytes and its padding from `reader`.
+async fn read_tar_data(
+    reader: &mut (impl AsyncRead + Unpin),
+    size: u64,
+) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(size <= MAX_TAR_NAME_SIZE, "tar header entry is too large");
+    let mut data = vec![0u8; size.div_ceil(TAR_BLOCK_SIZE) as usize * TAR_BLOCK_SIZE as usize];
+    reader.read_exact(&mut data).await?;
+    data.truncate(size as usize);
+    Ok(data)
+}
+
+/// The name of a tar entry as an entry name, without a leading `./` or a
+/// trailing `/`.
+fn tar_entry_name(path: &[u8]) -> anyhow::Result<String> {
+    let path = std::str::from_utf8(path).context("tar entry name is not valid unicode")?;
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    let name = canonicalized_path_to_string(Path::new(path), true)
+        .with_context(|| format!("invalid tar entry name {path}"))?;
+    check_reserved(&name)?;
+    Ok(name)
+}
+
+/// Import the entries of a tar stream read from `reader`, e.g. stdin, as a
+/// share.
+///
+/// The data of the entries is streamed into the store, so neither the archive
+/// nor the files are ever on disk. The names come from the tar headers,
+/// including GNU and pax long names and pax sizes. Symlinks and modification
+/// times are kept, hard links become entries with the data of their target
+/// and directories are skipped. Other entry types, like devices and sparse
+/// files, are an error. Returns the same as [`import`].
+pub async fn import_tar(
+    mut reader: impl AsyncRead + Unpin,
+    db: &Store,
+    secret_key: &SecretKey,
+    mp: &mut MultiProgress,
+) -> anyhow::Result<(TempTag, u64, Collection, Vec<u64>, ShareMeta)> {
+    let mut entries = BTreeMap::new();
+    let mut tags = Vec::new();
+    let mut mtimes = BTreeMap::new();
+    let mut symlinks = BTreeMap::new();
+    // from the extension headers before an entry
+    let mut long_name = None;
+    let mut long_link = None;
+    let mut long_size = None;
+    loop {
+        let mut block = [0u8; TAR_BLOCK_SIZE as usize];
+        reader
+            .read_exact(&mut block)
+            .await
+            .context("the tar stream is truncated")?;
+        // the end of archive marker
+        if block.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        let header = tar::Header::from_byte_slice(&block);
+        let entry_type = header.entry_type();
+        if entry_type.is_gnu_longname() || entry_type.is_gnu_longlink() {
+            let mut data = read_tar_data(&mut reader, header.entry_size()?).await?;
+            // the name is nul terminated
+            while data.last() == Some(&0) {
+                data.pop();
+            }
+            if entry_type.is_gnu_longname() {
+                long_name = Some(data);
+            } else {
+                long_link = Some(data);
+            }
+            continue;
+        }
+        if entry_type.is_pax_local_extensions() {
+            let data = read_tar_data(&mut reader, header.entry_size()?).await?;
+            for extension in tar::PaxExtensions::new(&data) {
+                let extension = extension?;
+                match extension.key() {
+                    Ok("path") => long_name = Some(extension.value_bytes().to_vec()),
+                    Ok("linkpath") => long_link = Some(extension.value_bytes().to_vec()),
+                    Ok("size") => {
+                        let size = extension.value().ok().and_then(|size| size.parse().ok());
+                        long_size = Some(size.context("invalid pax size")?);
+                    }
+                    // the data of sparse files is a map of the data, not the file
+                    Ok(key) if key.starts_with("GNU.sparse.") => {
+                        anyhow::bail!("sparse files are not supported")
+                    }
+                    _ => {}
+                }
+            }
+            continue;
+        }
+        if entry_type.is_pax_global_extensions() {
+            // the defaults of all entries, none of them are used
+            read_tar_data(&mut reader, header.entry_size()?).await?;
+            continue;
+        }
+        let size = match long_size.take() {
+            Some(size) => size,
+            None => header.entry_size()?,
+        };
+        let path = match long_name.take() {
+            Some(path) => path,
+            None => header.path_bytes().into_owned(),
+        };
+        let link = match long_link.take() {
+            Some(link) => Some(link),
+            None => header.link_name_bytes().map(|link| link.into_owned()),
+        };
+        if entry_type.is_file() || entry_type.is_contiguous() {
+            let name = tar_entry_name(&path)?;
+            let pb = mp.add(make_import_item_progress());
+            pb.set_message(format!("copying {name}"));
+            pb.set_length(size);
+            // the store reads the entry from a pipe, while it is copied into it
+            let (mut writer, pipe) = tokio::io::duplex(64 * 1024);
+            let copy = async {
+                let mut data = (&mut reader).take(size);
+                let copied = tokio::io::copy(&mut data, &mut writer).await?;
+                // closing the pipe ends the import
+                drop(writer);
+                anyhow::ensure!(copied == size, "the tar stream is truncated");
+                anyhow::Ok(())
+            };
+            let add = async {
+                let mut stream = db.add_stream(ReaderStream::new(pipe)).await.stream().await;
+                while let Some(item) = stream.next().await {
+                    match item {
+                        AddProgressItem::CopyProgress(offset) => pb.set_position(offset),
+                        AddProgressItem::Error(cause) => {
+                            anyhow::bail!("error importing {name}: {cause}")
+                        }
+                        AddProgressItem::Done(tag) => return Ok(tag),
+                        _ => {}
+                    }
+                }
+                anyhow::bail!("import stream ended without a tag")
+            };
+            let (copied, tag) = tokio::join!(copy, add);
+            pb.finish_and_clear();
+            copied?;
+            let tag = tag?;
+            trace!("importing {name} from tar {}", tag.hash());
+            entries.insert(name.clone(), (*tag.hash(), size));
+            tags.push(tag);
+            mtimes.insert(name, header.mtime()?);
+            // skip the padding
+            let padding = (TAR_BLOCK_SIZE - size % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+            tokio::io::copy(&mut (&mut reader).take(padding), &mut tokio::io::sink()).await?;
+            continue;
+        }
+        // the remaining entry types have no data that is shared
+        let skip = size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+        tokio::io::copy(&mut (&mut reader).take(skip), &mut tokio::io::sink()).await?;
+        if entry_type.is_dir() {
+            continue;
+        }
+        let name = tar_entry_name(&path)?;
+        match (entry_type, link) {
+            (tar::EntryType::Symlink, Some(target)) => {
+                let target = String::from_utf8(target)
+                    .ok()
+                    .filter(|target| !target.starts_with('/'));
+                match target {
+                    Some(target) => {
+                        symlinks.insert(name, target);
+                    }
+                    None => eprintln!("skipping symlink {name}, its target is not relative"),
+                }
+            }
+            (tar::EntryType::Link, Some(target)) => {
+                let target = tar_entry_name(&target)?;
+                let entry = *entries
+                    .get(&target)
+                    .with_context(|| format!("{name} links to {target}, which is missing"))?;
+                entries.insert(name.clone(), entry);
+                mtimes.insert(name, header.mtime()?);
+            }
+            _ => anyhow::bail!("{name} is not a file, directory or link"),
+        }
+    }
+    let size = entries.values().map(|(_, size)| *size).sum::<u64>();
+    let sizes = entries.values().map(|(_, size)| *size).collect::<Vec<_>>();
+    let collection = entries
+        .into_iter()
+        .map(|(name, (hash, _))| (name, hash))
+        .collect::<Collection>();
+    let meta = ShareMeta {
+        signature: Some(sign_manifest(&collection, &BTreeMap::new(), secret_key)),
+        mtimes,
+        symlinks,
+        inline: inline_entries(db, &collection, &sizes).await?,
+        ..Default::default()
+    };
+    let temp_tag = store_collection(db, &collection, &meta).await?;
+    // the data is now protected by the collection
+    drop(tags);
+    Ok((temp_tag, size, collection, sizes, meta))
+}
+
+/// Import the data of `reader`, e.g. stdin, as a share of a single file called
+/// `name`.
+///
+/// The data is streamed into the store, so it never has to be on disk as a
+/// file. Returns the same as [`import`].
+pub async fn import_reader(
+    name: &str,
+    reader: impl AsyncRead + Send + Sync + Unpin + 'static,
+    db: &Store,
+    secret_key: &SecretKey,
+    mp: &mut MultiProgress,
+) -> anyhow::Result<(TempTag, u64, Collection, Vec<u64>, ShareMeta)> {
+    let name = canonicalized_path_to_string(Path::new(name), true)?;
+    anyhow::ensure!(
+        !name.is_empty() && !name.contains('/'),
+        "invalid file name {name}"
+    );
+    check_reserved(&name)?;
+    let pb = mp.add(make_import_item_progress());
+    pb.set_message(format!("copying {name}"));
+    let mut stream = db
+        .add_stream(ReaderStream::new(reader))
+        .await
+        .stream()
+        .await;
+    let mut size = 0;
+    let temp_tag = loop {
+        let item = stream
+            .next()
+            .await
+            .context("import stream ended without a tag")?;
+        match item {
+            AddProgressItem::Size(item_size) => {
+                size = item_size;
+                pb.set_length(item_size);
+            }
+            AddProgressItem::CopyProgress(offset) => {
+                pb.set_position(offset);
+            }
+            AddProgressItem::CopyDone => {
+                pb.set_message(format!("computing outboard {name}"));
+                pb.set_position(0);
+            }
+            AddProgressItem::OutboardProgress(offset) => {
+                pb.set_position(offset);
+            }
+            AddProgressItem::Error(cause) => {
+                pb.finish_and_clear();
+                anyhow::bail!("error importing {}: {}", name, cause);
+            }
+            AddProgressItem::Done(tt) => {
+                pb.finish_and_clear();
+                break tt;
+            }
+        }
+    };
+    let collection = std::iter::once((name, *temp_tag.hash())).collect::<Collection>();
+    let meta = ShareMeta {
+        signature: Some(sign_manifest(&collection, &BTreeMap::new(), secret_key)),
+        ..Default::default()
+    };
+    let collection_tag = store_collection(db, &collection, &meta).await?;
+    // the data is now protected by the collection
+    drop(temp_tag);
+    Ok((collection_tag, size, collection, vec![size], meta))
+}
+
+/// Import from files and directories into the database.
+///
+/// The returned tag always refers to a collection. If the input is a file, this
+/// is a collection with a single blob, named like the file.
+///
+/// If the input is a directory, the collection contains all the files in the
+/// directory. Several paths are merged into one collection, see [`list_paths`].
+///
+/// The stored collection additionally contains the [`ShareMeta`], with the
+/// manifest signed by `secret_key`. The returned collection does not. The
+/// returned sizes are the sizes of the collection entries, in order.
+///
+/// Files that are in `hash_cache` with the same fingerprint and are complete in
+/// the store are not imported again. Newly imported files are added to the cache.
+///
+/// Which files of a directory are imported is set by `walk`, see
+/// [`list_files`].
+#[allow(clippy::too_many_arguments)]
+pub async fn import(
+    paths: &[PathBuf],
+    db: &Store,
+    secret_key: &SecretKey,
+    hash_cache: &mut HashCache,
+    parallelism: usize,
+    mp: &mut MultiProgress,
+    walk: &WalkOptions,
+    _do_compress: bool,
+    _compression_level: u8,
+) -> anyhow::Result<(TempTag, u64, Collection, Vec<u64>, ShareMeta)> {
+    let paths = paths
+        .iter()
+        .map(|path| path.canonicalize())
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let (data_sources, symlinks) = list_paths(&paths, walk)?;
+    // names that are not valid unicode are escaped, keep the originals to restore them
+    let raw_names = data_sources
+        .iter()
+        .filter_map(|(name, path)| {
+            let root = paths.iter().find(|root| path.starts_with(root))?.parent()?;
+            let raw = raw_name(path.strip_prefix(root).ok()?)?;
+            Some((name.clone(), raw))
+        })
+        .collect::<BTreeMap<_, _>>();
+    // look up unchanged files in the hash cache. Compressed blobs are never
+    // cached, since their hash depends on the compression level.
+    let mut fingerprints = BTreeMap::new();
+    let mut mtimes = BTreeMap::new();
+    let mut cached = Vec::new();
+    let mut data_sources_to_import = Vec::new();
+    for (name, path) in data_sources {
+        let fingerprint = FileFingerprint::new(&path)?;
+        mtimes.insert(name.clone(), fingerprint.mtime_secs);
+        if !_do_compress {
+            let mut hash = hash_cache.get(&path, fingerprint);
+            // only a store that was shared from before can have the data of a
+            // changed file, and only for large files is this worth the reading
+            if hash.is_none()
+                && fingerprint.size >= PARALLEL_HASH_SIZE
+                && parallelism > 1
+                && !hash_cache.is_empty()
+            {
+                hash = Some(hash_parallel(path.clone(), parallelism).await?);
+            }
+            if let Some(hash) = hash {
+                if db
+                    .remote()
+                    .local(HashAndFormat::raw(hash))
+                    .await?
+                    .is_complete()
+                {
+                    hash_cache.insert(&path, fingerprint, hash);
+                    cached.push((name, hash, fingerprint.size));
+                    continue;
+                }
+            }
+        }
+        fingerprints.insert(name.clone(), (path.clone(), fingerprint));
+        data_sources_to_import.push((name, path));
+    }
+    if !cached.is_empty() {
+        trace!("{} files unchanged, skipping import", cached.len());
+    }
+    let data_sources = data_sources_to_import;
+    // import all the files, using num_cpus workers, return names and temp tags
+    let op = mp.add(make_import_overall_progress());
+    op.set_message(format!("importing {} files", data_sources.len()));
+    op.set_length(data_sources.len() as u64);
+    let names_and_tags = n0_future::stream::iter(data_sources)
+        .map(|(name, path)| {
+            let db = db.clone();
+            let op = op.clone();
+            let mp = mp.clone();
+            async move {
+                op.inc(1);
+                let pb = mp.add(make_import_item_progress());
+                pb.set_message(format!("copying {name}"));
+                let import: AddProgress;
+
+                #[cfg(feature = "zstd")]
+                if _do_compress {
+                    let file_stream = File::open(&path).await?;
+                    pb.set_message(format!("Compressing {name}"));
+                    pb.set_length(file_stream.metadata().await?.len());
+                    let reader = BufReader::new(file_stream);
+                    let encoder =
+                        ZstdEncoder::with_quality(reader, Level::Precise(_compression_level as _));
+
+                    let compressed_stream = ReaderStream::new(encoder);
+                    import = db.add_stream(compressed_stream).await;
+                } else {
+                    import = add_file(&db, path).await?;
+                }
+
+                #[cfg(not(feature = "zstd"))]
+                {
+                    import = add_file(&db, path).await?;
+                }
+
+                let mut stream = import.stream().await;
+                let mut item_size = 0;
+                let temp_tag = loop {
+                    let item = stream
+                        .next()
+                        .await
+                        .context("import stream ended without a tag")?;
+                    trace!("importing {name} {item:?}");
+                    match item {
+                        AddProgressItem::Size(size) => {
+                            item_size = size;
+                            pb.set_length(size);
+                        }
+                        AddProgressItem::CopyProgress(offset) => {
+                            pb.set_position(offset);
+                        }
+                        AddProgressItem::CopyDone => {
+                            pb.set_message(format!("computing outboard {name}"));
+                            pb.set_position(0);
+                        }
+                        AddProgressItem::OutboardProgress(offset) => {
+                            pb.set_position(offset);
+                        }
+                        AddProgressItem::Error(cause) => {
+                            pb.finish_and_clear();
+                            anyhow::bail!("error importing {}: {}", name, cause);
+                        }
+                        AddProgressItem::Done(tt) => {
+                            pb.finish_and_clear();
+                            break tt;
+                        }
+                    }
+                };
+                anyhow::Ok((name, temp_tag, item_size))
+            }
+        })
+        .buffered_unordered(parallelism)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    op.finish_and_clear();
+    // we must keep the tags around so the data does not get gced.
+    let mut entries = cached;
+    let mut tags = Vec::new();
+    for (name, tag, size) in names_and_tags {
+        if !_do_compress {
+            if let Some((path, fingerprint)) = fingerprints.get(&name) {
+                hash_cache.insert(path, *fingerprint, *tag.hash());
+            }
+        }
+        entries.push((name, *tag.hash(), size));
+        tags.push(tag);
+    }
+    entries.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+    // total size of all files
+    let size = entries.iter().map(|(_, _, size)| *size).sum::<u64>();
+    let sizes = entries.iter().map(|(_, _, size)| *size).collect::<Vec<_>>();
+    // collect the (name, hash) tuples into a collection
+    let collection = entries
+        .into_iter()
+        .map(|(name, hash, _)| (name, hash))
+        .collect::<Collection>();
+    let first = first_entries(
+        &walk.first,
+        collection.iter().map(|(name, _)| name.as_str()),
+    )?;
+    let meta = ShareMeta {
+        signature: Some(sign_manifest(&collection, &raw_names, secret_key)),
+        raw_names,
+        mtimes,
+        symlinks,
+        compressed: _do_compress,
+        first,
+        inline: inline_entries(db, &collection, &sizes).await?,
+    };
+    let temp_tag = store_collection(db, &collection, &meta).await?;
+    // now that the collection is stored, we can drop the tags
+    // data is protected by the collection
+    drop(tags);
+    Ok((temp_tag, size, collection, sizes, meta))
+}
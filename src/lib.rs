@@ -0,0 +1,177 @@
+//! Send files and directories between machines, using blake3 verified streaming.
+//!
+//! This is the library behind the `sendme` command line tool. [`send()`] shares
+//! a file or directory and [`receive()`] downloads a share into a directory.
+//! Frontends that need the options of the command line tool use
+//! [`send::send`], [`receive::receive`] and [`serve::serve`], the other modules
+//! contain the building blocks, e.g. to show progress.
+
+pub mod audit;
+pub mod code;
+pub mod config;
+pub mod export;
+pub mod ext;
+pub mod filter;
+pub mod hook;
+pub mod import;
+pub mod local;
+pub mod logging;
+pub mod manifest;
+pub mod metrics;
+pub mod net;
+pub mod output;
+pub mod pack;
+pub mod progress;
+pub mod provide;
+pub mod receive;
+pub mod send;
+pub mod serve;
+pub mod ticket;
+pub mod transfer;
+
+use std::path::{Path, PathBuf};
+
+use data_encoding::HEXLOWER;
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use iroh::{discovery::dns::DnsDiscovery, protocol::Router, Endpoint, SecretKey, Watcher};
+use iroh_blobs::{
+    api::TempTag, format::collection::Collection, net_protocol::Blobs, store::fs::FsStore,
+};
+use rand::Rng;
+
+pub use crate::ticket::{ShareMeta, Ticket, TicketMeta};
+use crate::{
+    export::{export, ExportOptions, Limits},
+    import::{import, HashCache, WalkOptions},
+    manifest::{load_collection, verify_manifest},
+    transfer::execute_get,
+};
+
+/// A share that is provided until it is shut down, see [`send()`].
+#[derive(Debug)]
+pub struct Share {
+    router: Router,
+    ticket: Ticket,
+    /// The temporary store of the share.
+    dir: PathBuf,
+    /// Protects the shared data until shutdown.
+    _tag: TempTag,
+}
+
+impl Share {
+    /// The ticket to pass to [`receive()`].
+    pub fn ticket(&self) -> &Ticket {
+        &self.ticket
+    }
+
+    /// Stop providing the share and remove its temporary store.
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        self.router.shutdown().await?;
+        tokio::fs::remove_dir_all(&self.dir).await?;
+        Ok(())
+    }
+}
+
+/// Share a file or directory until the returned [`Share`] is shut down.
+///
+/// The data is imported into a temporary store in the system temp directory,
+/// using a new random node id. Unlike `sendme send`, this does not show any
+/// progress.
+pub async fn send(path: impl AsRef<Path>) -> anyhow::Result<Share> {
+    let path = path.as_ref().to_path_buf();
+    let suffix = rand::thread_rng().gen::<[u8; 16]>();
+    let dir = std::env::temp_dir().join(format!(".sendme-send-{}", HEXLOWER.encode(&suffix)));
+    let secret_key = SecretKey::generate(rand::rngs::OsRng);
+    let endpoint = Endpoint::builder()
+        .alpns(vec![iroh_blobs::protocol::ALPN.to_vec()])
+        .secret_key(secret_key.clone())
+        .bind()
+        .await?;
+    let store = FsStore::load(&dir).await?;
+    let blobs = Blobs::new(&store, endpoint.clone(), None);
+    let mut mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+    let paths = [path];
+    let (temp_tag, size, collection, _, meta) = import(
+        &paths,
+        blobs.store(),
+        &secret_key,
+        &mut HashCache::default(),
+        num_cpus::get(),
+        &mut mp,
+        &WalkOptions::default(),
+        false,
+        0,
+    )
+    .await?;
+    let router = Router::builder(endpoint)
+        .accept(iroh_blobs::ALPN, blobs)
+        .spawn();
+    // wait for the endpoint to figure out its address before making a ticket
+    let _ = router.endpoint().home_relay().initialized().await?;
+    let addr = router.endpoint().node_addr().initialized().await?;
+    let ticket = Ticket::for_share(
+        addr,
+        &paths,
+        *temp_tag.hash(),
+        &collection,
+        size,
+        meta,
+        false,
+    )?;
+    Ok(Share {
+        router,
+        ticket,
+        dir,
+        _tag: temp_tag,
+    })
+}
+
+/// Download the share of `ticket` and export it into the directory `target`.
+///
+/// The data is downloaded into a temporary store in `target` first, which is
+/// removed once the share is exported. Signed shares are verified and the
+/// default [`Limits`] are checked before exporting. Modification times are
+/// restored if the sender included them. Returns the exported collection.
+pub async fn receive(ticket: &Ticket, target: impl AsRef<Path>) -> anyhow::Result<Collection> {
+    let target = target.as_ref();
+    let addr = ticket.node_addr().clone();
+    let mut builder = Endpoint::builder().alpns(vec![]);
+    if addr.relay_url.is_none() && addr.direct_addresses.is_empty() {
+        builder = builder.add_discovery(DnsDiscovery::n0_dns());
+    }
+    let endpoint = builder.bind().await?;
+    let dir = target.join(format!(".sendme-recv-{}", ticket.hash().to_hex()));
+    let db = FsStore::load(&dir).await?;
+    let res = async {
+        let local = db.remote().local(ticket.hash_and_format()).await?;
+        if !local.is_complete() {
+            let connection = endpoint.connect(addr, iroh_blobs::protocol::ALPN).await?;
+            execute_get(&db, connection, local.missing(), None, |_| {}).await?;
+        }
+        let (collection, meta) = load_collection(&db, ticket).await?;
+        Limits::default().check(&collection)?;
+        if meta.signature.is_some() {
+            let node_id = ticket.meta.signer.unwrap_or(ticket.node_addr().node_id);
+            verify_manifest(&collection, &meta, node_id)?;
+        }
+        let mut mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        export(
+            &db,
+            collection.clone(),
+            &meta,
+            target,
+            &mut mp,
+            ExportOptions::default(),
+        )
+        .await?;
+        anyhow::Ok(collection)
+    }
+    .await;
+    db.shutdown().await?;
+    endpoint.close().await;
+    // keep the data of a failed download, so it is not fetched again
+    if res.is_ok() {
+        tokio::fs::remove_dir_all(&dir).await?;
+    }
+    res
+}
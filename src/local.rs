@@ -0,0 +1,112 @@
+//! Finding shares on the local network without a ticket.
+//!
+//! A sender announces its share with mDNS, as the user data of its node. The
+//! announcement is `sendme:<hash>:<format>:<name>`, where the format is `r`
+//! for a single blob and `s` for a collection. A receiver listens for these
+//! announcements and makes a ticket from the first one with a matching name.
+//!
+//! Anyone on the network can see the announcement, and announce a share with
+//! any name, so this is only meant for networks where the other nodes are
+//! trusted.
+
+use std::{str::FromStr, time::Duration};
+
+use anyhow::Context;
+use iroh::{
+    discovery::{DiscoveryItem, UserData},
+    Endpoint,
+};
+use iroh_blobs::{ticket::BlobTicket, BlobFormat, Hash};
+use n0_future::StreamExt;
+
+use crate::ticket::{Ticket, TicketMeta};
+
+/// Prefix of the user data of an announcement.
+const PREFIX: &str = "sendme:";
+
+/// Name of shares that have none.
+const DEFAULT_NAME: &str = "share";
+
+/// A share that was announced on the local network.
+#[derive(Debug, Clone)]
+pub struct LocalShare {
+    /// The name the share was announced with.
+    pub name: String,
+    /// A ticket for the share, with the addresses from the announcement.
+    pub ticket: Ticket,
+}
+
+/// The user data that announces the share of `ticket`, and the name it is
+/// announced with.
+///
+/// Names that do not fit into the user data are shortened.
+pub fn announcement(ticket: &Ticket) -> anyhow::Result<(UserData, String)> {
+    let format = match ticket.format() {
+        BlobFormat::Raw => 'r',
+        BlobFormat::HashSeq => 's',
+    };
+    let mut data = format!("{PREFIX}{}:{format}:", ticket.hash().to_hex());
+    let mut name = ticket.meta.name.as_deref().unwrap_or(DEFAULT_NAME);
+    let space = UserData::MAX_LENGTH.saturating_sub(data.len());
+    if name.len() > space {
+        let mut end = space;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = &name[..end];
+    }
+    data.push_str(name);
+    let data = UserData::try_from(data).context("invalid announcement")?;
+    Ok((data, name.to_string()))
+}
+
+/// Parse the announcement of a discovered node, if it is one.
+pub fn parse_announcement(item: &DiscoveryItem) -> Option<LocalShare> {
+    let data = item.user_data()?;
+    let mut parts = data.as_ref().strip_prefix(PREFIX)?.splitn(3, ':');
+    let hash = Hash::from_str(parts.next()?).ok()?;
+    let format = match parts.next()? {
+        "r" => BlobFormat::Raw,
+        "s" => BlobFormat::HashSeq,
+        _ => return None,
+    };
+    let name = parts.next()?.to_string();
+    let meta = TicketMeta {
+        name: Some(name.clone()),
+        ..Default::default()
+    };
+    let ticket = Ticket::new(BlobTicket::new(item.to_node_addr(), hash, format), meta);
+    Some(LocalShare { name, ticket })
+}
+
+/// Wait for a share announced on the local network, using the mDNS discovery
+/// of `endpoint`.
+///
+/// Takes the first share if `name` is `None`. Fails if no share was found
+/// within `timeout`.
+pub async fn find(
+    endpoint: &Endpoint,
+    name: Option<&str>,
+    timeout: Duration,
+) -> anyhow::Result<LocalShare> {
+    let mut items = std::pin::pin!(endpoint.discovery_stream());
+    let found = tokio::time::timeout(timeout, async {
+        while let Some(item) = items.next().await {
+            // lagging only means that some announcements were missed
+            let Ok(item) = item else { continue };
+            let Some(share) = parse_announcement(&item) else {
+                continue;
+            };
+            if name.is_none() || name == Some(share.name.as_str()) {
+                return Some(share);
+            }
+        }
+        None
+    })
+    .await;
+    match (found, name) {
+        (Ok(Some(share)), _) => Ok(share),
+        (_, Some(name)) => anyhow::bail!("no share named {name} found on the local network"),
+        (_, None) => anyhow::bail!("no share found on the local network"),
+    }
+}
@@ -0,0 +1,144 @@
+//! Log outputs that are safe to share.
+//!
+//! Everything that is logged goes through [`redact`], so logs don't contain
+//! tickets, keys, hashes or node ids, see [`Redacting`]. Log files are rotated,
+//! see [`RotatingFile`].
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Replace everything that looks like a hash, node id, ticket or key in `text`.
+///
+/// These are long runs of lowercase letters and digits, as produced by hex and
+/// base32 encoding.
+pub fn redact(text: &str) -> String {
+    const MIN_LEN: usize = 32;
+    let mut res = String::with_capacity(text.len());
+    let mut run = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            run.push(c);
+            continue;
+        }
+        if run.len() >= MIN_LEN && run.chars().any(|c| c.is_ascii_digit()) {
+            res.push_str("<redacted>");
+        } else {
+            res.push_str(&run);
+        }
+        run.clear();
+        res.push(c);
+    }
+    res.pop();
+    res
+}
+
+/// A [`MakeWriter`] that redacts everything written to the writers of the
+/// inner one, see [`redact`].
+#[derive(Debug)]
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+/// Writer of [`Redacting`].
+///
+/// Every write is redacted on its own, which works since log lines are
+/// written at once.
+#[derive(Debug)]
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// A log file that is moved aside once it gets too large.
+///
+/// When the file at `path` would exceed `max_size`, it is renamed to
+/// `path.1`, the previous `path.1` to `path.2` and so on, keeping `keep` old
+/// files.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Default size of a log file before it is rotated.
+    pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+    /// Default number of old log files to keep.
+    pub const DEFAULT_KEEP: usize = 3;
+
+    /// Open the log file at `path` for appending.
+    pub fn open(path: impl Into<PathBuf>, max_size: u64, keep: usize) -> io::Result<Self> {
+        let path = path.into();
+        let file = File::options().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, i: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{i}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for i in (1..self.keep).rev() {
+                let from = self.rotated(i);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(i + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = File::options().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
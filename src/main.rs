@@ -1,67 +1,46 @@
 //! Command line arguments.
 
 use std::{
-    collections::BTreeMap,
     fmt::{Display, Formatter},
-    net::{SocketAddrV4, SocketAddrV6},
-    path::{Component, Path, PathBuf},
+    net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    path::{Path, PathBuf},
     str::FromStr,
-    time::{Duration, Instant},
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Context;
-#[cfg(feature = "zstd")]
-use async_compression::tokio::bufread::{ZstdDecoder, ZstdEncoder};
-#[cfg(feature = "zstd")]
-use async_compression::Level;
 use clap::{
     error::{ContextKind, ErrorKind},
     CommandFactory, Parser, Subcommand,
 };
 use console::style;
-use data_encoding::HEXLOWER;
-use futures_buffered::BufferedStreamExt;
-use indicatif::{
-    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+use indicatif::HumanBytes;
+use iroh::{discovery::dns::DnsDiscovery, Endpoint, NodeId, RelayUrl, SecretKey, Watcher};
+use iroh_blobs::{format::collection::Collection, store::mem::MemStore};
+use sendme::{
+    audit::AuditLog,
+    code::ShortCode,
+    config::{cache_dir, parse_rate, parse_size, Config, Profile, ProgressMode},
+    export::{get_export_path, Limits, OnConflict},
+    import::{blake3_throughput, Symlinks},
+    logging::{redact, Redacting, RotatingFile},
+    manifest::Manifest,
+    net::{nat_type, NetOptions, RelayModeOption},
+    output::{confirm, emit, prompt, set_json_output, Format, JsonEvent},
+    progress::TRACE_TARGET as PROGRESS_TARGET,
+    receive::{fetch_entries, receive, PreviewSpec, ReceiveOptions, RECV_BUFFER_MIN},
+    send::{send, FanoutTargets, SendOptions},
+    serve::{pack_share, serve, unpack_share, PackOptions, ServeOptions, UnpackOptions},
+    ticket::AddrInfoOptions,
+    Ticket,
 };
-use iroh::{
-    discovery::{dns::DnsDiscovery, pkarr::PkarrPublisher},
-    Endpoint, NodeAddr, RelayMode, RelayUrl, SecretKey, Watcher,
+use tracing::trace;
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+    Layer as _,
 };
-#[cfg(feature = "zstd")]
-use iroh_blobs::api::blobs::EncodedItem;
-#[cfg(feature = "zstd")]
-use iroh_blobs::protocol::ChunkRanges;
-use iroh_blobs::{
-    api::{
-        blobs::{
-            AddPathOptions, AddProgress, AddProgressItem, ExportMode, ExportOptions,
-            ExportProgressItem, ImportMode,
-        },
-        remote::GetProgressItem,
-        Store, TempTag,
-    },
-    format::collection::Collection,
-    get::{request::get_hash_seq_and_sizes, GetError, Stats},
-    net_protocol::Blobs,
-    provider::{self, Event},
-    store::fs::FsStore,
-    ticket::BlobTicket,
-    BlobFormat, Hash,
-};
-use n0_future::{task::AbortOnDropHandle, StreamExt};
-use rand::Rng;
-use serde::{Deserialize, Serialize};
-
-#[cfg(feature = "zstd")]
-use tokio::fs::{create_dir_all, File};
-#[cfg(feature = "zstd")]
-use tokio::io::{BufReader, BufWriter};
-use tokio::{select, sync::mpsc};
-#[cfg(feature = "zstd")]
-use tokio_util::io::{ReaderStream, StreamReader};
-use tracing::{error, trace};
-use walkdir::WalkDir;
+use url::Url;
 
 /// Send a file or directory between two machines, using blake3 verified streaming.
 ///
@@ -73,53 +52,240 @@ use walkdir::WalkDir;
 #[derive(Parser, Debug)]
 #[command(version, about)]
 pub struct Args {
+    /// Print newline delimited json events to stdout, instead of progress bars
+    /// and messages, to drive sendme from scripts.
+    ///
+    /// Messages for humans are printed to stderr.
+    #[clap(long, global = true)]
+    pub json: bool,
+
+    /// Keep a debug log of this run in the cache directory, for `sendme report`.
+    ///
+    /// Every run gets its own log, only the last few are kept. Set
+    /// `report_log = true` in the config file to keep them for every run.
+    #[clap(long, global = true)]
+    pub report_log: bool,
+
+    /// Also write the log to this file, rotated when it gets large.
+    ///
+    /// Uses the directives of `RUST_LOG` if set, or logs at debug level for
+    /// sendme otherwise. Tickets, keys, hashes and node ids are redacted.
+    #[clap(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Format of the log file, `text` or `json` with a json object per line.
+    ///
+    /// The json lines contain the fields of every event separately, e.g. the
+    /// connection and request ids of the transfers of a provider.
+    #[clap(long, global = true, default_value_t = LogFormat::Text, requires = "log_file")]
+    pub log_format: LogFormat,
+
+    /// Don't redact the log file, so it shows the hashes and node ids of the
+    /// transfers, e.g. to find out after the fact who got what.
+    ///
+    /// Logs written with this contain everything needed to fetch the data
+    /// again, so don't attach them to bug reports.
+    #[clap(long, global = true, requires = "log_file")]
+    pub log_unredacted: bool,
+
+    /// Log everything of these parts of sendme, separated by commas, in
+    /// addition to `RUST_LOG`.
+    ///
+    /// The parts are `net` for connections and hole punching, `store` for the
+    /// blob store, `import`, `export`, `transfer` for the requests between the
+    /// nodes and `progress` for every progress event. Useful to attach focused
+    /// logs to performance issues.
+    #[clap(long, global = true, value_delimiter = ',')]
+    pub trace: Vec<TraceTarget>,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+/// Format of the log file, see [`Args::log_format`].
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-pub enum Format {
+pub enum LogFormat {
     #[default]
-    Hex,
-    Cid,
+    Text,
+    Json,
 }
 
-impl FromStr for Format {
+impl FromStr for LogFormat {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_ascii_lowercase().as_str() {
-            "hex" => Ok(Format::Hex),
-            "cid" => Ok(Format::Cid),
-            _ => Err(anyhow::anyhow!("invalid format")),
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow::anyhow!("expected text or json")),
         }
     }
 }
 
-impl Display for Format {
+impl Display for LogFormat {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Format::Hex => write!(f, "hex"),
-            Format::Cid => write!(f, "cid"),
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// A part of sendme to log with `--trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceTarget {
+    Net,
+    Store,
+    Import,
+    Export,
+    Transfer,
+    Progress,
+}
+
+impl TraceTarget {
+    /// The tracing targets of this part.
+    fn targets(self) -> &'static [&'static str] {
+        match self {
+            TraceTarget::Net => &["iroh::magicsock", "iroh::net_report", "iroh_relay"],
+            TraceTarget::Store => &["iroh_blobs::store"],
+            TraceTarget::Import => &["sendme::import"],
+            TraceTarget::Export => &["sendme::export"],
+            TraceTarget::Transfer => &[
+                "sendme::transfer",
+                "iroh_blobs::get",
+                "iroh_blobs::provider",
+            ],
+            TraceTarget::Progress => &[PROGRESS_TARGET],
+        }
+    }
+}
+
+impl FromStr for TraceTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "net" => Ok(TraceTarget::Net),
+            "store" => Ok(TraceTarget::Store),
+            "import" => Ok(TraceTarget::Import),
+            "export" => Ok(TraceTarget::Export),
+            "transfer" => Ok(TraceTarget::Transfer),
+            "progress" => Ok(TraceTarget::Progress),
+            _ => Err(anyhow::anyhow!(
+                "expected net, store, import, export, transfer or progress"
+            )),
         }
     }
 }
 
-fn print_hash(hash: &Hash, format: Format) -> String {
-    match format {
-        Format::Hex => hash.to_hex().to_string(),
-        Format::Cid => hash.to_string(),
+/// Add the directives for `--trace` to `filter`.
+fn with_trace(mut filter: EnvFilter, trace: &[TraceTarget]) -> EnvFilter {
+    for target in trace.iter().flat_map(|part| part.targets()) {
+        filter = filter.add_directive(
+            format!("{target}=trace")
+                .parse()
+                .expect("valid tracing target"),
+        );
     }
+    filter
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Send a file or directory.
+    #[clap(visible_alias = "provide")]
     Send(SendArgs),
 
     /// Receive a file or directory.
-    #[clap(visible_alias = "recv")]
+    #[clap(visible_aliases = ["recv", "get"])]
     Receive(ReceiveArgs),
+
+    /// Verify files on disk against a manifest or the share of a ticket.
+    Verify(VerifyArgs),
+
+    /// Check that an audit log written with `--audit-log` was not modified.
+    ///
+    /// Prints the number of records, the node that signed them and the hash
+    /// of the last one. Compare the node id with the one of the node that
+    /// wrote the log, and keep the hash elsewhere to also detect records
+    /// removed from the end later.
+    Audit(AuditArgs),
+
+    /// Send a file or directory to a list of hosts, by running `sendme receive`
+    /// on each of them over ssh.
+    Fanout(FanoutArgs),
+
+    /// Ask a running sender to fetch and keep the data of another ticket.
+    Pin(PinArgs),
+
+    /// Provide several shares from one long running node.
+    ///
+    /// The shares are kept in a persistent store, and are provided again after
+    /// a restart. Shares are managed with commands on stdin, one per line:
+    /// `add PATH` shares a file or directory and prints its receive command,
+    /// `remove HASH` stops providing a share and `list` shows all shares,
+    /// including the data pinned by the nodes of `--allow-pin`.
+    ///
+    /// Receivers are asked to download at most the rate of the `[[schedule]]`
+    /// of the config file for the current time. `rate RATE` overrides the
+    /// schedule, `rate off` removes the limit and `rate auto` goes back to the
+    /// schedule. `rate global RATE` limits all receivers together and `rate
+    /// share HASH RATE` the receivers of one share together, splitting the
+    /// budget evenly between them; `off` removes a budget. Changes apply to
+    /// running downloads. Receivers that don't honor the limit are
+    /// disconnected once they get more than 16 MiB ahead.
+    Serve(ServeArgs),
+
+    /// Write a share of `sendme serve` into a single archive file.
+    ///
+    /// The archive contains the data with its hashes, e.g. to carry a share
+    /// across an air gap. `sendme unpack` verifies it.
+    Pack(PackArgs),
+
+    /// Verify an archive written by `sendme pack` and export its files.
+    Unpack(UnpackArgs),
+
+    /// Bundle diagnostics to attach to a bug report.
+    ///
+    /// This collects the log of the last run with `--report-log`, version and
+    /// configuration info and a connectivity check into a tarball. Secrets,
+    /// hashes and node ids are redacted. Nothing is sent anywhere.
+    Report(ReportArgs),
+
+    /// Set up a persistent key, download directory, relay and progress style.
+    ///
+    /// Asks for each setting and writes the config file. Command line options
+    /// still take precedence over the config file.
+    Init,
+
+    /// Manage names for the node ids of peers.
+    ///
+    /// The names can be used instead of node ids, e.g. `--allow laptop`.
+    Peers(PeersArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct PeersArgs {
+    #[clap(subcommand)]
+    pub command: PeersCommands,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PeersCommands {
+    /// Add a peer, or change the node id of an existing one.
+    Add {
+        /// The name to use for the peer.
+        name: String,
+        /// The node id of the peer.
+        node_id: NodeId,
+    },
+    /// Remove a peer.
+    Remove {
+        /// The name of the peer.
+        name: String,
+    },
+    /// List all peers.
+    List,
 }
 
 #[derive(Parser, Debug)]
@@ -138,6 +304,30 @@ pub struct CommonArgs {
     #[clap(long, default_value = None)]
     pub magic_ipv6_addr: Option<SocketAddrV6>,
 
+    /// The address that magicsocket will listen on, with an optional port,
+    /// e.g. to use a single interface of a host with several.
+    ///
+    /// Takes the place of `--magic-ipv4-addr` or `--magic-ipv6-addr`, depending
+    /// on the address family.
+    #[clap(
+        long,
+        value_name = "IP[:PORT]",
+        value_parser = parse_bind_addr,
+        conflicts_with_all = ["magic_ipv4_addr", "magic_ipv6_addr"]
+    )]
+    pub bind_addr: Option<SocketAddr>,
+
+    /// Only use IPv4.
+    ///
+    /// The IPv6 socket is bound to the loopback address, so no traffic leaves
+    /// the host over IPv6, and tickets only contain IPv4 addresses.
+    #[clap(short = '4', long, conflicts_with = "ipv6_only")]
+    pub ipv4_only: bool,
+
+    /// Only use IPv6, see `--ipv4-only`.
+    #[clap(short = '6', long)]
+    pub ipv6_only: bool,
+
     #[clap(long, default_value_t = Format::Hex)]
     pub format: Format,
 
@@ -145,22 +335,86 @@ pub struct CommonArgs {
     pub verbose: u8,
 
     /// Suppress progress bars.
-    #[clap(long, default_value_t = false)]
+    ///
+    /// Also set by `progress = "none"` in the config file.
+    #[clap(long)]
     pub no_progress: bool,
 
-    /// The relay URL to use as a home relay,
+    /// The relay URL to use as a home relay, or several separated by commas.
     ///
     /// Can be set to "disabled" to disable relay servers and "default"
-    /// to configure default servers.
-    #[clap(long, default_value_t = RelayModeOption::Default)]
-    pub relay: RelayModeOption,
+    /// to configure default servers. Defaults to the relay of the config
+    /// file, see `sendme init`. With several relays, the one with the lowest
+    /// latency is used.
+    #[clap(long, visible_alias = "relay-url")]
+    pub relay: Option<RelayModeOption>,
+
+    /// Use the relays listed in this file, one URL per line, instead of
+    /// `--relay`.
+    ///
+    /// Empty lines and lines starting with `#` are ignored. Useful to share the
+    /// self-hosted relays of a network in one place.
+    #[clap(
+        long,
+        visible_alias = "derp-map",
+        value_name = "FILE",
+        value_parser = parse_relay_map,
+        conflicts_with = "relay"
+    )]
+    pub relay_map: Option<RelayModeOption>,
+
+    /// Never relay any data, only use direct connections.
+    ///
+    /// Relays are disabled altogether, and receivers fail if hole punching
+    /// does not get a direct connection quickly. For networks where data must
+    /// not pass through third party servers.
+    #[clap(long, conflicts_with_all = ["relay", "relay_map"])]
+    pub direct_only: bool,
+
+    /// HTTP proxy for the connections to the relays, e.g.
+    /// `http://proxy.example:3128`.
+    ///
+    /// Defaults to the proxy in `HTTPS_PROXY` or `HTTP_PROXY`. Only the relay
+    /// connections go through the proxy, so behind a proxy that blocks
+    /// everything else, all data is relayed. SOCKS proxies are not supported.
+    #[clap(
+        long,
+        value_name = "URL",
+        value_parser = parse_proxy,
+        conflicts_with = "direct_only"
+    )]
+    pub proxy: Option<Url>,
+
+    /// Find nodes by their node id with the public n0 DNS discovery, also for
+    /// tickets with addresses.
+    ///
+    /// Senders publish their addresses, so receivers with a ticket from
+    /// before an address change can still find them, and receivers look them
+    /// up. Without this, DNS discovery is only used for tickets without
+    /// addresses, see `--ticket-type id`. Set `dns_discovery = true` in the
+    /// config file to always use it.
+    #[clap(long)]
+    pub dns_discovery: bool,
+
+    /// Append the connections of `send` and `receive` to this tamper evident
+    /// log, with the node ids, times, protocols and password hashes.
+    ///
+    /// Check the log with `sendme audit`.
+    #[clap(long, value_name = "FILE")]
+    pub audit_log: Option<PathBuf>,
 
+    /// Print the secret key to stderr if it was generated.
+    ///
+    /// It is never logged, regardless of the verbosity.
     #[clap(long)]
     pub show_secret: bool,
 
     /// Use zstd to compress outgoing and decompress incoming data
+    ///
+    /// Compressed shares are marked as such, so receivers decompress them
+    /// without this flag.
     #[cfg(feature = "zstd")]
-    #[clap(short = 'z', long)]
+    #[clap(short = 'z', long, visible_alias = "compress")]
     pub zstd: bool,
 
     /// Compression level for zstd
@@ -173,56 +427,197 @@ pub struct CommonArgs {
     pub zstd: bool,
 }
 
-/// Available command line options for configuring relays.
-#[derive(Clone, Debug)]
-pub enum RelayModeOption {
-    /// Disables relays altogether.
-    Disabled,
-    /// Uses the default relay servers.
-    Default,
-    /// Uses a single, custom relay server by URL.
-    Custom(RelayUrl),
+/// The config file, loaded on first use.
+///
+/// A config file that can not be read is ignored with a warning.
+fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        Config::load().unwrap_or_else(|cause| {
+            eprintln!("{} {cause:#}", style("warning:").yellow());
+            Config::default()
+        })
+    })
 }
 
-impl FromStr for RelayModeOption {
-    type Err = anyhow::Error;
+/// The relay mode of the config file, or the default relays.
+fn default_relay() -> RelayModeOption {
+    config()
+        .relay
+        .as_deref()
+        .and_then(|relay| RelayModeOption::from_str(relay).ok())
+        .unwrap_or(RelayModeOption::Default)
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "disabled" => Ok(Self::Disabled),
-            "default" => Ok(Self::Default),
-            _ => Ok(Self::Custom(RelayUrl::from_str(s)?)),
-        }
+/// Parse an IP address with an optional port, which defaults to a random one.
+fn parse_bind_addr(addr: &str) -> anyhow::Result<SocketAddr> {
+    match addr.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 0)),
+        Err(_) => addr
+            .parse()
+            .with_context(|| format!("invalid address {addr}, expected IP or IP:PORT")),
     }
 }
 
-impl Display for RelayModeOption {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Disabled => f.write_str("disabled"),
-            Self::Default => f.write_str("default"),
-            Self::Custom(url) => url.fmt(f),
+/// Parse the URL of an HTTP proxy, see [`CommonArgs::proxy`].
+fn parse_proxy(url: &str) -> anyhow::Result<Url> {
+    let url = Url::parse(url).with_context(|| format!("invalid proxy url {url}"))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme if scheme.starts_with("socks") => {
+            anyhow::bail!("SOCKS proxies are not supported, use an HTTP proxy")
         }
+        scheme => anyhow::bail!("unsupported proxy scheme {scheme}, expected http or https"),
     }
 }
 
-impl From<RelayModeOption> for RelayMode {
-    fn from(value: RelayModeOption) -> Self {
-        match value {
-            RelayModeOption::Disabled => RelayMode::Disabled,
-            RelayModeOption::Default => RelayMode::Default,
-            RelayModeOption::Custom(url) => RelayMode::Custom(url.into()),
+/// Read a file with a relay URL per line, see [`CommonArgs::relay_map`].
+fn parse_relay_map(path: &str) -> anyhow::Result<RelayModeOption> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+    let urls = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| RelayUrl::from_str(line).with_context(|| format!("invalid relay url {line}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(!urls.is_empty(), "{path} contains no relays");
+    Ok(RelayModeOption::Custom(urls))
+}
+
+impl CommonArgs {
+    /// The network options, with the defaults of the config file.
+    fn net(&self) -> NetOptions {
+        NetOptions {
+            magic_ipv4_addr: self.magic_ipv4_addr,
+            magic_ipv6_addr: self.magic_ipv6_addr,
+            bind_addr: self.bind_addr,
+            ipv4_only: self.ipv4_only,
+            ipv6_only: self.ipv6_only,
+            relay: self
+                .relay_map
+                .clone()
+                .or_else(|| self.relay.clone())
+                .unwrap_or_else(default_relay),
+            direct_only: self.direct_only,
+            proxy: self.proxy.clone(),
+            dns_discovery: self.dns_discovery || config().dns_discovery,
         }
     }
+
+    /// Whether progress bars are suppressed, by `--no-progress` or the config
+    /// file.
+    fn no_progress(&self) -> bool {
+        self.no_progress || default_no_progress()
+    }
+}
+
+/// Parse a duration in seconds, with an optional `s`, `m`, `h` or `d` suffix.
+fn parse_duration(duration: &str) -> anyhow::Result<Duration> {
+    let (number, unit) = match duration.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&duration[..i], c.to_ascii_lowercase()),
+        _ => (duration, 's'),
+    };
+    let factor: u64 = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => anyhow::bail!("unknown unit {unit}, expected s, m, h or d"),
+    };
+    let number: u64 = number.parse().context("invalid duration")?;
+    let secs = number
+        .checked_mul(factor)
+        .context("duration is too large")?;
+    anyhow::ensure!(secs > 0, "the duration must be larger than 0");
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parse a node id or the name of a peer, see [`Config::resolve_peer`].
+fn parse_peer(peer: &str) -> anyhow::Result<NodeId> {
+    config().resolve_peer(peer)
+}
+
+/// Whether the config file disables progress bars.
+fn default_no_progress() -> bool {
+    config().progress == ProgressMode::None
 }
 
 #[derive(Parser, Debug)]
 pub struct SendArgs {
-    /// Path to the file or directory to send.
+    /// Paths of the files or directories to send, or `-` to send stdin.
     ///
-    /// The last component of the path will be used as the name of the data
-    /// being shared.
-    pub path: PathBuf,
+    /// The last component of each path will be used as the name of the data
+    /// being shared. Several paths are shared together, as if they were in one
+    /// directory, so their names must be different.
+    #[clap(required_unless_present_any = ["profile", "tar_in"], value_name = "PATH")]
+    pub paths: Vec<PathBuf>,
+
+    /// Read a tar stream from stdin and send its entries, e.g.
+    /// `tar c dir | sendme send --tar-in`.
+    ///
+    /// The entries keep the names of the tar headers, and are streamed into
+    /// the store without writing the archive or the files to disk. Entries
+    /// other than files, directories and links, e.g. devices or sparse files,
+    /// are an error.
+    #[clap(long, conflicts_with_all = ["paths", "profile", "watch", "dry_run"])]
+    pub tar_in: bool,
+
+    /// Use the options of this profile of the config file.
+    ///
+    /// Profiles are `[profile.<name>]` tables with `paths`, `include`,
+    /// `exclude`, `gitignore`, `allow`, `message` and `expire`. Options given
+    /// on the command line are added to the ones of the profile.
+    #[clap(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Name of the file that receivers get when sending stdin.
+    #[clap(long, default_value = "stdin")]
+    pub name: String,
+
+    /// Only list the entries that would be shared, with their sizes, and exit.
+    ///
+    /// Nothing is hashed or stored, and no connections are made, so this is
+    /// fast also for huge directories.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// How to handle symlinks: ignore, follow or preserve.
+    ///
+    /// Follow shares the files and directories that links point to. Preserve
+    /// shares the links themselves, receivers recreate links that stay inside
+    /// the shared directory.
+    #[clap(long, value_name = "MODE", default_value_t = Symlinks::Ignore)]
+    pub symlinks: Symlinks,
+
+    /// Only share files matching one of these globs, e.g. `*.rs`.
+    ///
+    /// Globs without a slash match the file name in any directory.
+    #[clap(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Don't share files or directories matching one of these globs, e.g.
+    /// `target/` or `*.log`.
+    ///
+    /// Globs without a slash match in any directory. Excluded directories are
+    /// not read at all, and excluding takes precedence over `--include`.
+    #[clap(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Don't share files that are ignored by `.gitignore` or `.ignore` files,
+    /// like git would.
+    ///
+    /// This also works outside of git repositories, and also uses the ignore
+    /// files of the parent directories.
+    #[clap(long)]
+    pub gitignore: bool,
+
+    /// Ask receivers to get files matching these globs before the others,
+    /// e.g. an installer before its assets.
+    ///
+    /// Files are served in the order of the globs that match them first.
+    /// Receivers can still get everything in order with `--in-order`.
+    #[clap(long, value_name = "GLOB")]
+    pub first: Vec<String>,
 
     /// What type of ticket to use.
     ///
@@ -240,6 +635,208 @@ pub struct SendArgs {
     #[clap(long, default_value_t = AddrInfoOptions::RelayAndAddresses)]
     pub ticket_type: AddrInfoOptions,
 
+    /// Keep the store in the user cache directory instead of a temp directory.
+    ///
+    /// Files that are unchanged since they were last shared with this option
+    /// are not hashed again. Only one send with this option can run at a time.
+    #[clap(long)]
+    pub cache: bool,
+
+    /// Keep the store in this directory instead of a temp directory.
+    ///
+    /// Like `--cache`, but with a directory of your choice, e.g. one per
+    /// project. Files that are unchanged since they were last shared from
+    /// this store are not hashed again, and their data is reused.
+    #[clap(long, value_name = "DIR", conflicts_with = "cache")]
+    pub store: Option<PathBuf>,
+
+    /// Upper bound for the size of the store of --cache or --store, e.g. `20G`.
+    ///
+    /// Once the store is larger, the data that was not shared for the longest
+    /// time is deleted. The data of the current share is always kept.
+    #[clap(long, value_name = "SIZE", default_value = "10G", value_parser = parse_size)]
+    pub cache_size: u64,
+
+    /// Directory for the temporary store, instead of the current directory.
+    ///
+    /// Useful if the current directory is read only, or to keep project
+    /// directories clean, e.g. with a directory in the user cache directory.
+    #[clap(long, value_name = "DIR", env = "SENDME_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Number of files to hash in parallel during import.
+    ///
+    /// Defaults to the number of cpus. The store hashes each file on a single
+    /// thread. Files of 64 MiB or more that changed since they were last shared
+    /// from --store or --cache are first hashed with all threads, and are not
+    /// imported again if their data is still in the store.
+    #[clap(long)]
+    pub hash_threads: Option<usize>,
+
+    /// Write a json manifest of the share to this file.
+    ///
+    /// The manifest contains the collection hash as well as the name, size and
+    /// blake3 hash of every entry, and can be checked with `sendme verify`.
+    /// Note that with --zstd, sizes and hashes are those of the compressed data.
+    #[clap(long)]
+    pub manifest_out: Option<PathBuf>,
+
+    /// Check the files against a checksum file written by `b3sum` before
+    /// printing the ticket, and fail if any of them differ or are missing.
+    ///
+    /// Paths in the checksum file may be relative to the shared directory or
+    /// to its parent.
+    #[clap(long, value_name = "FILE", conflicts_with = "zstd")]
+    pub checksums: Option<PathBuf>,
+
+    /// Before printing the ticket, check that the provider can be reached
+    /// through its relay, by fetching a bit of a random file with a new node.
+    ///
+    /// Fails if that does not work, so you know right away that receivers
+    /// would not get through either.
+    #[clap(long, conflicts_with = "direct_only")]
+    pub self_test: bool,
+
+    /// Write only the ticket to stdout, and everything else to stderr.
+    ///
+    /// This is useful to capture the ticket in scripts.
+    #[clap(long)]
+    pub print_ticket_only: bool,
+
+    /// Also show the ticket as a QR code, e.g. to scan it with a phone.
+    #[clap(long)]
+    pub qr: bool,
+
+    /// Also print the ticket as a `sendme://` link, e.g. to paste into a chat.
+    ///
+    /// Where sendme is registered as the handler of the scheme, with
+    /// `sendme receive %u`, clicking the link starts the download. The QR code
+    /// contains the link as well.
+    #[clap(long)]
+    pub url: bool,
+
+    /// Put the name, size and MIME type of the share into the ticket, so
+    /// receivers can show them before connecting.
+    ///
+    /// Single files are then shared without a collection, which saves a round
+    /// trip, and the deadline of --expire is part of the ticket. Such tickets
+    /// are longer, and only work with recent versions of sendme.
+    #[clap(long)]
+    pub ticket_meta: bool,
+
+    /// Keep watching the shared paths, and share them again when they change.
+    ///
+    /// A new ticket is printed for every change. Older tickets keep working
+    /// for the data they were made for, until the provider is stopped.
+    #[clap(long)]
+    pub watch: bool,
+
+    /// Also announce the share on the local network, so it can be received
+    /// with `sendme receive --local` without the ticket.
+    ///
+    /// Everyone on the network can see the name and hash of the share, so
+    /// use this only on networks you trust. The announcement would go to every
+    /// discovery service, so DNS discovery is off and `--ticket-type id`
+    /// can't be used.
+    #[clap(long, conflicts_with_all = ["watch", "dns_discovery"])]
+    pub local: bool,
+
+    /// Also print a short code like `7-crimson-otter`, that can be read out
+    /// instead of the ticket.
+    ///
+    /// The code is valid for a single receiver, and for a single attempt, so
+    /// a mistyped code has to be replaced by a new one.
+    #[clap(long)]
+    pub code: bool,
+
+    /// Exit once a receiver has completed a download, instead of waiting
+    /// for ctrl-c.
+    ///
+    /// A download counts as completed when the receiver disconnects after
+    /// getting all the data it asked for.
+    #[clap(long)]
+    pub wait: bool,
+
+    /// Serve the data to a single receiver, and exit once it got everything.
+    ///
+    /// Other nodes are rejected while the first receiver is downloading, and
+    /// the ticket stops working once the download is complete, even if it
+    /// leaks later. If the receiver disconnects before it is done, the next
+    /// node can try.
+    #[clap(long)]
+    pub once: bool,
+
+    /// Allow this node to ask for other data to be fetched with `sendme pin`.
+    ///
+    /// Takes a node id or the name of a peer, see `sendme peers`. The node id
+    /// of the asking side is only stable if it sets IROH_SECRET.
+    #[clap(long, value_name = "PEER", value_parser = parse_peer)]
+    pub allow_pin: Vec<NodeId>,
+
+    /// Refuse to pin data that is larger than this, e.g. `500M`.
+    #[clap(long, value_name = "SIZE", default_value = "10G", value_parser = parse_size)]
+    pub max_pin_size: u64,
+
+    /// Only serve this node, can be given multiple times.
+    ///
+    /// Takes a node id or the name of a peer, see `sendme peers`. By default,
+    /// everyone with the ticket can get the data. The node id of the receiver
+    /// is only stable if it sets IROH_SECRET.
+    #[clap(long, value_name = "PEER", value_parser = parse_peer)]
+    pub allow: Vec<NodeId>,
+
+    /// Ask receivers to back off when the network gets congested by other
+    /// applications, see `sendme receive --nice`.
+    ///
+    /// Only receivers that support sendme extensions honor this.
+    #[clap(long)]
+    pub nice: bool,
+
+    /// Ask receivers to download at most this many bytes per second, e.g.
+    /// `500K` or `2M`.
+    ///
+    /// The limit is for each receiver. Receivers that support sendme
+    /// extensions read slower, others are disconnected once they get more
+    /// than 16 MiB ahead of the limit.
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    pub limit_rate: Option<u64>,
+
+    /// With --wait or --once, fail if no download completed within this many
+    /// seconds.
+    #[clap(long)]
+    pub timeout: Option<u64>,
+
+    /// Stop providing the data after this time, e.g. `30m`, `2h` or `7d`.
+    ///
+    /// The time is counted from when the ticket is printed. With
+    /// --ticket-meta, the ticket contains the deadline, so receivers don't try
+    /// after it. Plain numbers are seconds.
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub expire: Option<Duration>,
+
+    /// Serve Prometheus metrics of the provider on this address, e.g.
+    /// `127.0.0.1:9090`.
+    ///
+    /// The metrics are at `/metrics`, with the bytes served, the open
+    /// connections and the completed transfers, in total and per receiver.
+    #[clap(long, value_name = "ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Run this command whenever a receiver completed a download.
+    ///
+    /// The command is run with `sh -c`, or `cmd /C` on Windows. It gets the
+    /// ticket in `SENDME_TICKET`, the node id of the receiver in `SENDME_PEER`,
+    /// the bytes sent in `SENDME_BYTES` and, if a single path is shared, the
+    /// path in `SENDME_PATH`. Its output goes to stderr.
+    #[clap(long, value_name = "COMMAND")]
+    pub on_complete: Option<String>,
+
+    /// Show a desktop notification whenever a receiver completed a download,
+    /// and when sending fails.
+    #[cfg(feature = "notifications")]
+    #[clap(long)]
+    pub notify: bool,
+
     #[clap(flatten)]
     pub common: CommonArgs,
 
@@ -247,1013 +844,1055 @@ pub struct SendArgs {
     #[cfg(feature = "clipboard")]
     #[clap(short = 'c', long)]
     pub clipboard: bool,
+
+    /// Put the receive command on the system clipboard, in addition to
+    /// printing it.
+    ///
+    /// Like `--clipboard`, this copies `sendme receive <ticket>`, but it does
+    /// not go through the terminal, so it also works in terminals without
+    /// clipboard support. On Linux, the command stays on the clipboard while
+    /// sendme is running.
+    #[cfg(feature = "clipboard")]
+    #[clap(long)]
+    pub copy: bool,
+
+    /// A short message to show to every receiver, e.g. what this share is.
+    #[clap(short = 'm', long)]
+    pub message: Option<String>,
+
+    /// Send every line typed on stdin as a message to the connected receivers.
+    ///
+    /// Receivers with --chat can answer. Only receivers that support sendme
+    /// extensions get the messages.
+    #[clap(long, conflicts_with = "tar_in")]
+    pub chat: bool,
+
+    /// Only serve receivers that pass the same password with `--password`.
+    ///
+    /// The password is checked on the extension protocol, so plain iroh-blobs
+    /// clients are rejected as well.
+    #[clap(long, visible_alias = "token")]
+    pub password: Option<String>,
 }
 
 #[derive(Parser, Debug)]
 pub struct ReceiveArgs {
     /// The ticket to use to connect to the sender.
-    pub ticket: BlobTicket,
+    #[clap(required_unless_present_any = ["resume", "code", "local"])]
+    pub ticket: Option<Ticket>,
 
-    #[clap(flatten)]
-    pub common: CommonArgs,
-}
+    /// Directory to export the share into, like `--output`.
+    #[clap(value_name = "DIR", requires = "ticket", conflicts_with = "output")]
+    pub dest: Option<PathBuf>,
 
-/// Options to configure what is included in a [`NodeAddr`]
-#[derive(
-    Copy,
-    Clone,
-    PartialEq,
-    Eq,
-    Default,
-    Debug,
-    derive_more::Display,
-    derive_more::FromStr,
-    Serialize,
-    Deserialize,
-)]
-pub enum AddrInfoOptions {
-    /// Only the Node ID is added.
-    ///
-    /// This usually means that iroh-dns discovery is used to find address information.
-    #[default]
-    Id,
-    /// Includes the Node ID and both the relay URL, and the direct addresses.
-    RelayAndAddresses,
-    /// Includes the Node ID and the relay URL.
-    Relay,
-    /// Includes the Node ID and the direct addresses.
-    Addresses,
-}
+    /// Don't ask before downloading.
+    ///
+    /// In a terminal, receive shows the name and size of the share and asks
+    /// before it downloads anything. Without a terminal it never asks.
+    #[clap(short, long)]
+    pub yes: bool,
 
-fn apply_options(addr: &mut NodeAddr, opts: AddrInfoOptions) {
-    match opts {
-        AddrInfoOptions::Id => {
-            addr.direct_addresses.clear();
-            addr.relay_url = None;
-        }
-        AddrInfoOptions::RelayAndAddresses => {
-            // nothing to do
-        }
-        AddrInfoOptions::Relay => {
-            addr.direct_addresses.clear();
-        }
-        AddrInfoOptions::Addresses => {
-            addr.relay_url = None;
-        }
-    }
-}
+    /// Get the ticket with a short code from `sendme send --code` instead.
+    #[clap(long, conflicts_with_all = ["ticket", "resume"])]
+    pub code: Option<ShortCode>,
 
-/// Get the secret key or generate a new one.
-///
-/// Print the secret key to stderr if it was generated, so the user can save it.
-fn get_or_create_secret(print: bool) -> anyhow::Result<SecretKey> {
-    match std::env::var("IROH_SECRET") {
-        Ok(secret) => SecretKey::from_str(&secret).context("invalid secret"),
-        Err(_) => {
-            let key = SecretKey::generate(rand::rngs::OsRng);
-            if print {
-                let key = hex::encode(key.to_bytes());
-                eprintln!("using secret key {key}");
-            }
-            Ok(key)
-        }
-    }
-}
+    /// Find the share on the local network instead, announced by `sendme send
+    /// --local`.
+    ///
+    /// Takes the name of the share, or the first one that is found if no name
+    /// is given. Only use this on networks you trust, since anyone on them
+    /// can announce a share.
+    #[clap(
+        long,
+        value_name = "NAME",
+        num_args = 0..=1,
+        default_missing_value = "",
+        conflicts_with_all = ["ticket", "code", "resume"]
+    )]
+    pub local: Option<String>,
+
+    /// Resume an interrupted download from a checkpoint file.
+    ///
+    /// The partial data is expected next to the checkpoint, so a download can
+    /// be continued on another machine by copying both. If the sender was
+    /// restarted, pass its new ticket as well, which must be for the same data.
+    #[clap(long)]
+    pub resume: Option<PathBuf>,
 
-fn validate_path_component(component: &str) -> anyhow::Result<()> {
-    anyhow::ensure!(
-        !component.contains('/'),
-        "path components must not contain the only correct path separator, /"
-    );
-    Ok(())
-}
+    /// Directory to export the share into, instead of the current directory.
+    ///
+    /// The directory is created if it does not exist. The partial data of an
+    /// interrupted download is kept there as well.
+    #[clap(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
 
-/// This function converts an already canonicalized path to a string.
-///
-/// If `must_be_relative` is true, the function will fail if any component of the path is
-/// `Component::RootDir`
-///
-/// This function will also fail if the path is non canonical, i.e. contains
-/// `..` or `.`, or if the path components contain any windows or unix path
-/// separators.
-pub fn canonicalized_path_to_string(
-    path: impl AsRef<Path>,
-    must_be_relative: bool,
-) -> anyhow::Result<String> {
-    let mut path_str = String::new();
-    let parts = path
-        .as_ref()
-        .components()
-        .filter_map(|c| match c {
-            Component::Normal(x) => {
-                let c = match x.to_str() {
-                    Some(c) => c,
-                    None => return Some(Err(anyhow::anyhow!("invalid character in path"))),
-                };
-
-                if !c.contains('/') && !c.contains('\\') {
-                    Some(Ok(c))
-                } else {
-                    Some(Err(anyhow::anyhow!("invalid path component {:?}", c)))
-                }
-            }
-            Component::RootDir => {
-                if must_be_relative {
-                    Some(Err(anyhow::anyhow!("invalid path component {:?}", c)))
-                } else {
-                    path_str.push('/');
-                    None
-                }
-            }
-            _ => Some(Err(anyhow::anyhow!("invalid path component {:?}", c))),
-        })
-        .collect::<anyhow::Result<Vec<_>>>()?;
-    let parts = parts.join("/");
-    path_str.push_str(&parts);
-    Ok(path_str)
-}
+    /// Directory for the temporary store and the checkpoint of a download,
+    /// instead of the output directory.
+    ///
+    /// Useful to keep the partial data of interrupted downloads out of the
+    /// output directory, or to download to a read only location via `--tar`.
+    #[clap(long, value_name = "DIR", env = "SENDME_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
 
-/// Import from a file or directory into the database.
-///
-/// The returned tag always refers to a collection. If the input is a file, this
-/// is a collection with a single blob, named like the file.
-///
-/// If the input is a directory, the collection contains all the files in the
-/// directory.
-async fn import(
-    path: PathBuf,
-    db: &Store,
-    mp: &mut MultiProgress,
-    _do_compress: bool,
-    _compression_level: u8,
-) -> anyhow::Result<(TempTag, u64, Collection)> {
-    let parallelism = num_cpus::get();
-    let path = path.canonicalize()?;
-    anyhow::ensure!(path.exists(), "path {} does not exist", path.display());
-    let root = path.parent().context("context get parent")?;
-    // walkdir also works for files, so we don't need to special case them
-    let files = WalkDir::new(path.clone()).into_iter();
-    // flatten the directory structure into a list of (name, path) pairs.
-    // ignore symlinks.
-    let data_sources: Vec<(String, PathBuf)> = files
-        .map(|entry| {
-            let entry = entry?;
-            if !entry.file_type().is_file() {
-                // Skip symlinks. Directories are handled by WalkDir.
-                return Ok(None);
-            }
-            let path = entry.into_path();
-            let relative = path.strip_prefix(root)?;
-            let name = canonicalized_path_to_string(relative, true)?;
-            anyhow::Ok(Some((name, path)))
-        })
-        .filter_map(Result::transpose)
-        .collect::<anyhow::Result<Vec<_>>>()?;
-    // import all the files, using num_cpus workers, return names and temp tags
-    let op = mp.add(make_import_overall_progress());
-    op.set_message(format!("importing {} files", data_sources.len()));
-    op.set_length(data_sources.len() as u64);
-    let mut names_and_tags = n0_future::stream::iter(data_sources)
-        .map(|(name, path)| {
-            let db = db.clone();
-            let op = op.clone();
-            let mp = mp.clone();
-            async move {
-                op.inc(1);
-                let pb = mp.add(make_import_item_progress());
-                pb.set_message(format!("copying {name}"));
-                let import: AddProgress;
-
-                #[cfg(feature = "zstd")]
-                if _do_compress {
-                    let file_stream = File::open(&path).await?;
-                    pb.set_message(format!("Compressing {name}"));
-                    pb.set_length(file_stream.metadata().await?.len());
-                    let reader = BufReader::new(file_stream);
-                    let encoder =
-                        ZstdEncoder::with_quality(reader, Level::Precise(_compression_level as _));
-
-                    let compressed_stream = ReaderStream::new(encoder);
-                    import = db.add_stream(compressed_stream).await;
-                } else {
-                    import = db.add_path_with_opts(AddPathOptions {
-                        path,
-                        mode: ImportMode::TryReference,
-                        format: BlobFormat::Raw,
-                    });
-                }
+    /// Only fetch the start of a single file and write it to stdout.
+    ///
+    /// The number of bytes defaults to 16 KiB. The data is verified like a full
+    /// download, so it can be used to inspect a share before getting all of it.
+    #[clap(long, value_name = "FILE[:BYTES]", conflicts_with = "resume")]
+    pub preview: Option<PreviewSpec>,
 
-                #[cfg(not(feature = "zstd"))]
-                {
-                    import = db.add_path_with_opts(AddPathOptions {
-                        path,
-                        mode: ImportMode::TryReference,
-                        format: BlobFormat::Raw,
-                    });
-                }
+    /// Only list the names and sizes of the entries, without downloading them.
+    ///
+    /// The sizes are claimed by the sender, they are only verified by a
+    /// download. `--only` and `--skip` apply to the listed entries.
+    #[clap(long, conflicts_with_all = ["resume", "preview"])]
+    pub list: bool,
 
-                let mut stream = import.stream().await;
-                let mut item_size = 0;
-                let temp_tag = loop {
-                    let item = stream
-                        .next()
-                        .await
-                        .context("import stream ended without a tag")?;
-                    trace!("importing {name} {item:?}");
-                    match item {
-                        AddProgressItem::Size(size) => {
-                            item_size = size;
-                            pb.set_length(size);
-                        }
-                        AddProgressItem::CopyProgress(offset) => {
-                            pb.set_position(offset);
-                        }
-                        AddProgressItem::CopyDone => {
-                            pb.set_message(format!("computing outboard {name}"));
-                            pb.set_position(0);
-                        }
-                        AddProgressItem::OutboardProgress(offset) => {
-                            pb.set_position(offset);
-                        }
-                        AddProgressItem::Error(cause) => {
-                            pb.finish_and_clear();
-                            anyhow::bail!("error importing {}: {}", name, cause);
-                        }
-                        AddProgressItem::Done(tt) => {
-                            pb.finish_and_clear();
-                            break tt;
-                        }
-                    }
-                };
-                anyhow::Ok((name, temp_tag, item_size))
-            }
-        })
-        .buffered_unordered(parallelism)
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .collect::<anyhow::Result<Vec<_>>>()?;
-    op.finish_and_clear();
-    names_and_tags.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
-    // total size of all files
-    let size = names_and_tags.iter().map(|(_, _, size)| *size).sum::<u64>();
-    // collect the (name, hash) tuples into a collection
-    // we must also keep the tags around so the data does not get gced.
-    let (collection, tags) = names_and_tags
-        .into_iter()
-        .map(|(name, tag, _)| ((name, *tag.hash()), tag))
-        .unzip::<_, _, Collection, Vec<_>>();
-    let temp_tag = collection.clone().store(db).await?;
-    // now that the collection is stored, we can drop the tags
-    // data is protected by the collection
-    drop(tags);
-    Ok((temp_tag, size, collection))
-}
+    /// Fail unless the share is signed by the node in the ticket.
+    ///
+    /// For tickets printed by `--mirror`, this is the original sender instead.
+    /// Signatures of signed shares are always checked, this flag additionally
+    /// rejects shares without a signature.
+    #[clap(long)]
+    pub require_signed: bool,
 
-fn get_export_path(root: &Path, name: &str) -> anyhow::Result<PathBuf> {
-    let parts = name.split('/');
-    let mut path = root.to_path_buf();
-    for part in parts {
-        validate_path_component(part)?;
-        path.push(part);
-    }
-    Ok(path)
-}
+    /// Fail unless the share is signed by this node, a node id or the name of
+    /// a peer, see `sendme peers`.
+    ///
+    /// This is checked against the ticket before connecting, and against the
+    /// signature once the data arrives.
+    #[clap(long, value_name = "PEER", value_parser = parse_peer)]
+    pub expect_identity: Option<NodeId>,
 
-async fn export_single_file(
-    db: &Store,
-    mp: &MultiProgress,
-    hash: &Hash,
-    target: PathBuf,
-    name: &String,
-) -> anyhow::Result<()> {
-    let mut stream = db
-        .export_with_opts(ExportOptions {
-            hash: *hash,
-            target: target.clone(),
-            mode: ExportMode::TryReference,
-        })
-        .stream()
-        .await;
+    /// After the download, provide the share from this machine until
+    /// interrupted, and print a ticket to pass it on.
+    ///
+    /// Only complete shares can be mirrored, so this can not be combined with
+    /// `--only`, `--skip` or `-i`.
+    #[clap(long, conflicts_with_all = ["preview", "only", "skip", "interactive"])]
+    pub mirror: bool,
 
-    let pb = mp.add(make_export_item_progress());
-    pb.set_message(format!("exporting {name}"));
+    /// Number of requests to keep in flight while downloading a collection.
+    ///
+    /// With the default of 1, all missing data is requested in a single request.
+    /// Higher values request each file on its own stream, which keeps the
+    /// connection busy on high latency links when getting many files.
+    #[clap(long, default_value_t = 1)]
+    pub pipeline_depth: usize,
 
-    while let Some(item) = stream.next().await {
-        match item {
-            ExportProgressItem::Size(size) => {
-                pb.set_length(size);
-            }
-            ExportProgressItem::CopyProgress(offset) => {
-                pb.set_position(offset);
-            }
-            ExportProgressItem::Done => {
-                pb.finish_and_clear();
-            }
-            ExportProgressItem::Error(cause) => {
-                pb.finish_and_clear();
-                anyhow::bail!("error exporting {}: {}", name, cause);
-            }
-        }
-    }
+    /// How long to keep trying to reconnect after losing the connection, in seconds.
+    ///
+    /// This allows the download to continue when the sender was suspended or
+    /// changed networks. Data that was already downloaded is kept.
+    #[clap(long, default_value_t = 300)]
+    pub reconnect_timeout: u64,
 
-    Ok(())
-}
+    /// Press s to skip the file that is currently downloading.
+    ///
+    /// This needs a terminal, and requests every file on its own stream, like
+    /// a --pipeline-depth above 1. Skipped files are not exported.
+    #[clap(short = 'i', long)]
+    pub interactive: bool,
 
-async fn export(
-    db: &Store,
-    collection: Collection,
-    mp: &mut MultiProgress,
-    _decompress: bool,
-    _postfix_target: bool,
-) -> anyhow::Result<()> {
-    let root = std::env::current_dir()?;
-    let op = mp.add(make_export_overall_progress());
-    op.set_length(collection.len() as u64);
-    for (i, (name, hash)) in collection.iter().enumerate() {
-        op.set_position(i as u64);
-        let target = get_export_path(&root, name)?;
-
-        #[cfg(not(feature = "zstd"))]
-        let target = if _postfix_target {
-            let file_name = target
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| format!("{}.zst", n))
-                .ok_or_else(|| {
-                    std::io::Error::new(std::io::ErrorKind::Other, "Invalid file name")
-                })?;
-
-            target.with_file_name(file_name)
-        } else {
-            target
-        };
+    /// Also download from the sender of this ticket, which must be for the
+    /// same data. Can be given multiple times.
+    ///
+    /// The files are split into parts that are requested from all senders at
+    /// once. If a sender disconnects, the others take over its parts.
+    #[clap(long, value_name = "TICKET")]
+    pub from: Vec<Ticket>,
 
-        if target.exists() {
-            eprintln!(
-                "target {} already exists. Export stopped.",
-                target.display()
-            );
-            eprintln!("You can remove the file or directory and try again. The download will not be repeated.");
-            anyhow::bail!("target {} already exists", target.display());
-        }
+    /// Write a json report with download timing, stalls and connection path
+    /// changes to this file, to attach to bug reports.
+    #[clap(long)]
+    pub debug_report: Option<PathBuf>,
 
-        #[cfg(feature = "zstd")]
-        if _decompress {
-            let pb = mp.add(make_export_item_progress());
-            pb.set_message(format!("Decompressing {name}"));
-            let byte_stream = db
-                .export_bao(*hash, ChunkRanges::all())
-                .stream()
-                .inspect(|res| match res {
-                    EncodedItem::Size(size) => {
-                        pb.set_length(*size);
-                    }
-                    EncodedItem::Leaf(leaf) => {
-                        pb.set_position(leaf.offset);
-                    }
-                    EncodedItem::Done => {
-                        pb.finish_and_clear();
-                    }
-                    _ => {}
-                })
-                .filter_map(|res| match res {
-                    EncodedItem::Leaf(leaf) => Some(Ok(leaf.data)),
-                    EncodedItem::Error(err) => Some(Err(tokio::io::Error::other(err.to_string()))),
-                    _ => None,
-                });
-
-            let reader = StreamReader::new(byte_stream);
-            let mut decoder = ZstdDecoder::new(reader);
-            if let Some(parent) = target.parent() {
-                create_dir_all(parent).await?;
-            }
-            let target_file = File::create(&target).await?;
-            let mut output_writer = BufWriter::new(target_file);
-            tokio::io::copy(&mut decoder, &mut output_writer).await?;
-        } else {
-            export_single_file(db, mp, hash, target, name).await?;
-        }
+    /// A short message to show to the sender.
+    #[clap(short = 'm', long)]
+    pub message: Option<String>,
 
-        #[cfg(not(feature = "zstd"))]
-        {
-            export_single_file(db, mp, hash, target, name).await?;
-        }
-    }
-    op.finish_and_clear();
-    Ok(())
-}
+    /// Send every line typed on stdin as a message to the sender.
+    ///
+    /// After the download, sendme waits until stdin is closed, e.g. with
+    /// Control-D, and the sender ended the chat as well.
+    #[clap(long)]
+    pub chat: bool,
+
+    /// The password of a sender that was started with `--password`.
+    #[clap(long, visible_alias = "token")]
+    pub password: Option<String>,
 
-#[derive(Debug)]
-struct PerConnectionProgress {
-    main: ProgressBar,
-    requests: BTreeMap<u64, ProgressBar>,
+    /// Maximum number of received bytes to buffer in memory, for devices with
+    /// little RAM.
+    ///
+    /// Received data is written to disk as it arrives, so this bounds the
+    /// memory used for buffering regardless of the size of the files, also
+    /// with `--pipeline-depth`. Smaller values limit the throughput on high
+    /// latency links. Defaults to the QUIC defaults of a few MiB.
+    #[clap(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(RECV_BUFFER_MIN..))]
+    pub recv_buffer: Option<u32>,
+
+    /// Back off when the round trip time grows, which means that the network is
+    /// congested by other applications, e.g. a call on the same connection.
+    ///
+    /// This slows down reading, which also slows down the sender. The rate
+    /// backs off by how much the round trip time grew, and recovers once it is
+    /// back to normal.
+    #[clap(long)]
+    pub nice: bool,
+
+    /// Download at most this many bytes per second, e.g. `500K` or `2M`.
+    ///
+    /// Like `--nice`, this slows down reading. Data arriving in the receive
+    /// buffer can exceed the rate briefly, see `--recv-buffer`. If the sender
+    /// asks for a lower limit, that one is used.
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    pub limit_rate: Option<u64>,
+
+    /// Only download entries matching one of these globs, e.g. `docs/**`.
+    ///
+    /// Globs without a slash match the file name in any directory.
+    #[clap(long, value_name = "GLOB")]
+    pub only: Vec<String>,
+
+    /// Don't download entries matching one of these globs, e.g. `*.iso`.
+    ///
+    /// Globs without a slash match the file name in any directory. Skipping
+    /// takes precedence over `--only`.
+    #[clap(long, value_name = "GLOB")]
+    pub skip: Vec<String>,
+
+    /// Get the files in collection order, even if the sender asked to serve
+    /// some of them first with `send --first`.
+    #[clap(long)]
+    pub in_order: bool,
+
+    /// Refuse shares with more entries than this.
+    ///
+    /// This is checked before any files are downloaded.
+    #[clap(long, default_value_t = Limits::default().max_entries)]
+    pub max_entries: u64,
+
+    /// Refuse shares with entry names longer than this many bytes, including
+    /// the directories.
+    ///
+    /// This is checked before any files are downloaded.
+    #[clap(long, value_name = "BYTES", default_value_t = Limits::default().max_name_len)]
+    pub max_name_len: usize,
+
+    /// What to do if a file to export already exists: overwrite, skip, rename
+    /// or fail.
+    ///
+    /// With fail, nothing is exported if any file exists. Rename exports next
+    /// to the existing file, as `name (1).ext`.
+    #[clap(long, value_name = "POLICY", default_value_t = OnConflict::Fail)]
+    pub on_conflict: OnConflict,
+
+    /// Choose which of the downloaded files to export before anything is
+    /// written.
+    ///
+    /// The files are listed with their sizes, and are toggled by number. The
+    /// data of the files that are not exported is discarded.
+    #[clap(long)]
+    pub review: bool,
+
+    /// Delete files replaced by `--on-conflict overwrite`, instead of moving
+    /// them to the `.sendme-backup` directory.
+    #[clap(long)]
+    pub no_backup: bool,
+
+    /// Don't restore the modification times of the sender, exported files
+    /// get the time of the export instead.
+    #[clap(long)]
+    pub no_times: bool,
+
+    /// Open the received file or directory with the default application once
+    /// it is exported.
+    #[clap(long)]
+    pub open: bool,
+
+    /// Run this command once the download is complete.
+    ///
+    /// The command is run with `sh -c`, or `cmd /C` on Windows. It gets the
+    /// ticket in `SENDME_TICKET`, the node id of the sender in `SENDME_PEER`,
+    /// the size of the share in `SENDME_BYTES` and the exported file or
+    /// directory in `SENDME_PATH`, which is not set with `--stdout` or
+    /// `--tar`. Its output goes to stderr.
+    #[clap(long, value_name = "COMMAND")]
+    pub on_complete: Option<String>,
+
+    /// Show a desktop notification when the download is complete, or when it
+    /// failed.
+    #[cfg(feature = "notifications")]
+    #[clap(long)]
+    pub notify: bool,
+
+    /// Write the received file to stdout instead of exporting it, e.g. to pipe
+    /// it into another program.
+    ///
+    /// This only works for shares of a single file. The file is downloaded
+    /// into the temporary store first, and written to stdout once it is
+    /// complete. All messages go to stderr.
+    #[clap(long, conflicts_with_all = ["open", "preview"])]
+    pub stdout: bool,
+
+    /// Write the received share to stdout as a tar stream instead of exporting
+    /// it, e.g. `sendme receive <ticket> --tar | tar x -C /dest`.
+    ///
+    /// The whole share is downloaded into the temporary store first, and then
+    /// streamed from there, so it is not written to disk twice. All messages
+    /// go to stderr.
+    #[clap(
+        long,
+        visible_alias = "tar-out",
+        conflicts_with_all = ["open", "preview", "stdout"]
+    )]
+    pub tar: bool,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
 }
 
-async fn show_provide_progress(
-    mp: MultiProgress,
-    mut recv: mpsc::Receiver<provider::Event>,
-) -> anyhow::Result<()> {
-    let mut connections = BTreeMap::new();
-    while let Some(item) = recv.recv().await {
-        trace!("got event {item:?}");
-        match item {
-            Event::ClientConnected {
-                connection_id,
-                node_id,
-                permitted,
-            } => {
-                permitted.send(true).await.ok();
-                let pb = mp.add(ProgressBar::hidden());
-                pb.set_style(
-                    indicatif::ProgressStyle::default_bar()
-                        .template("{msg}") // Only display the message
-                        .unwrap(),
-                );
-                pb.set_message(format!("{node_id} {connection_id}"));
-                connections.insert(
-                    connection_id,
-                    PerConnectionProgress {
-                        main: pb,
-                        requests: BTreeMap::new(),
-                    },
-                );
-            }
-            Event::ConnectionClosed { connection_id } => {
-                let Some(connection) = connections.remove(&connection_id) else {
-                    error!("got close for unknown connection {connection_id}");
-                    continue;
-                };
-                for pb in connection.requests.values() {
-                    pb.finish_and_clear();
-                }
-                connection.main.finish_and_clear();
-            }
-            Event::GetRequestReceived {
-                connection_id,
-                request_id,
-                hash,
-                ..
-            } => {
-                let pb = mp.add(ProgressBar::hidden());
-                pb.set_style(
-                    ProgressStyle::with_template(
-                        "{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes}",
-                    )?
-                        .progress_chars("#>-"),
-                );
-                pb.set_message(format!("{request_id} {hash}"));
-                let Some(connection) = connections.get_mut(&connection_id) else {
-                    error!("got request for unknown connection {connection_id}");
-                    continue;
-                };
-                connection.requests.insert(request_id, pb);
-            }
-            Event::TransferStarted {
-                connection_id,
-                request_id,
-                hash,
-                size,
-                index,
-            } => {
-                let Some(connection) = connections.get_mut(&connection_id) else {
-                    error!("got request for unknown connection {connection_id}");
-                    continue;
-                };
-                let Some(pb) = connection.requests.get_mut(&request_id) else {
-                    error!("got update for unknown request {request_id}");
-                    continue;
-                };
-                pb.set_message(format!("    {} {} {}", request_id, index, hash.fmt_short()));
-                pb.set_length(size);
-            }
-            Event::TransferProgress {
-                connection_id,
-                request_id,
-                end_offset,
-                ..
-            } => {
-                let Some(connection) = connections.get_mut(&connection_id) else {
-                    error!("got request for unknown connection {connection_id}");
-                    continue;
-                };
-                let Some(pb) = connection.requests.get_mut(&request_id) else {
-                    error!("got update for unknown request {request_id}");
-                    continue;
-                };
-                pb.set_position(end_offset);
-            }
-            Event::TransferCompleted {
-                connection_id,
-                request_id,
-                ..
-            } => {
-                if let Some(msg) = connections.get_mut(&connection_id) {
-                    if let Some(pb) = msg.requests.remove(&request_id) {
-                        // todo: show stats and hide after a delay
-                        pb.finish_and_clear();
-                    }
-                }
-            }
-            Event::TransferAborted {
-                connection_id,
-                request_id,
-                ..
-            } => {
-                if let Some(msg) = connections.get_mut(&connection_id) {
-                    if let Some(pb) = msg.requests.remove(&request_id) {
-                        // todo: show stats and hide after a delay
-                        pb.finish_and_clear();
-                    }
-                }
-            }
-            _ => {}
-        }
-    }
-    Ok(())
+#[derive(Parser, Debug)]
+pub struct FanoutArgs {
+    /// File with the hosts to send to, one per line, as accepted by ssh.
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    #[clap(long)]
+    pub hosts: PathBuf,
+
+    /// The ssh command to use.
+    #[clap(long, default_value = "ssh")]
+    pub ssh: String,
+
+    /// Directory on the hosts to receive into, instead of the login directory.
+    #[clap(long)]
+    pub remote_dir: Option<String>,
+
+    #[clap(flatten)]
+    pub send: SendArgs,
 }
 
-fn zstd_enabled(zstd_requested: bool, _is_sending: bool) -> bool {
-    #[cfg(feature = "zstd")]
-    return zstd_requested;
+#[derive(Parser, Debug)]
+pub struct PinArgs {
+    /// Ticket of the sender that should fetch the data.
+    pub provider: Ticket,
 
-    #[cfg(not(feature = "zstd"))]
-    {
-        if zstd_requested {
-            if _is_sending {
-                eprintln!(
-                    "Warning: --zstd ignored (no support in this build). Sending uncompressed."
-                );
-            } else {
-                eprintln!("Warning: This build does not support zstd decompression. Files will be saved with a `.zst` extension. You can manually decompress them using `unzstd <filename>`.");
-            }
-        }
-        return false;
-    }
+    /// Ticket of the data to fetch.
+    pub ticket: Ticket,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
 }
 
-async fn send(args: SendArgs) -> anyhow::Result<()> {
-    let secret_key = get_or_create_secret(args.common.verbose > 0)?;
-    if args.common.show_secret {
-        let secret_key = hex::encode(secret_key.to_bytes());
-        eprintln!("using secret key {secret_key}");
-    }
-    // create a magicsocket endpoint
-    let mut builder = Endpoint::builder()
-        .alpns(vec![iroh_blobs::protocol::ALPN.to_vec()])
-        .secret_key(secret_key)
-        .relay_mode(args.common.relay.into());
-    if args.ticket_type == AddrInfoOptions::Id {
-        builder = builder.add_discovery(PkarrPublisher::n0_dns());
-    }
-    if let Some(addr) = args.common.magic_ipv4_addr {
-        builder = builder.bind_addr_v4(addr);
-    }
-    if let Some(addr) = args.common.magic_ipv6_addr {
-        builder = builder.bind_addr_v6(addr);
-    }
+#[derive(Parser, Debug)]
+pub struct ServeArgs {
+    /// Files or directories to add as shares on startup, each as its own share.
+    pub paths: Vec<PathBuf>,
 
-    // use a flat store - todo: use a partial in mem store instead
-    let suffix = rand::thread_rng().gen::<[u8; 16]>();
-    let cwd = std::env::current_dir()?;
-    let blobs_data_dir = cwd.join(format!(".sendme-send-{}", HEXLOWER.encode(&suffix)));
-    if blobs_data_dir.exists() {
-        println!(
-            "can not share twice from the same directory: {}",
-            cwd.display(),
-        );
-        std::process::exit(1);
-    }
-    let do_compress = zstd_enabled(args.common.zstd, true);
-
-    let mut mp = MultiProgress::new();
-    let mp2 = mp.clone();
-    let path = args.path;
-    let path2 = path.clone();
-    let blobs_data_dir2 = blobs_data_dir.clone();
-    let (progress_tx, progress_rx) = mpsc::channel(32);
-    let progress = AbortOnDropHandle::new(n0_future::task::spawn(show_provide_progress(
-        mp2,
-        progress_rx,
-    )));
-    let setup = async move {
-        let t0 = Instant::now();
-        tokio::fs::create_dir_all(&blobs_data_dir2).await?;
-
-        let endpoint = builder.bind().await?;
-        let draw_target = if args.common.no_progress {
-            ProgressDrawTarget::hidden()
-        } else {
-            ProgressDrawTarget::stderr()
-        };
-        mp.set_draw_target(draw_target);
-        let store = FsStore::load(&blobs_data_dir2).await?;
-        let blobs = Blobs::new(&store, endpoint.clone(), Some(progress_tx));
-
-        #[cfg(feature = "zstd")]
-        let compression_quality = args.common.compression_quality.clamp(1, 22);
-
-        #[cfg(not(feature = "zstd"))]
-        let compression_quality = 0;
-
-        let import_result = import(
-            path2,
-            blobs.store(),
-            &mut mp,
-            do_compress,
-            compression_quality,
-        )
-        .await?;
-        let dt = t0.elapsed();
-
-        let router = iroh::protocol::Router::builder(endpoint)
-            .accept(iroh_blobs::ALPN, blobs.clone())
-            .spawn();
-        // wait for the endpoint to figure out its address before making a ticket
-        let _ = router.endpoint().home_relay().initialized().await?;
-        anyhow::Ok((router, import_result, dt))
-    };
-    let (router, (temp_tag, size, collection), dt) = select! {
-        x = setup => x?,
-        _ = tokio::signal::ctrl_c() => {
-            std::process::exit(130);
-        }
-    };
-    let hash = *temp_tag.hash();
-
-    // make a ticket
-    let mut addr = router.endpoint().node_addr().initialized().await?;
-    apply_options(&mut addr, args.ticket_type);
-    let ticket = BlobTicket::new(addr, hash, BlobFormat::HashSeq);
-    let entry_type = if path.is_file() { "file" } else { "directory" };
-    println!(
-        "imported {} {}, {}, hash {}",
-        entry_type,
-        path.display(),
-        HumanBytes(size),
-        print_hash(&hash, args.common.format),
-    );
-    if args.common.verbose > 1 {
-        for (name, hash) in collection.iter() {
-            println!("    {} {name}", print_hash(hash, args.common.format));
-        }
-        println!(
-            "{}s, {}/s",
-            dt.as_secs_f64(),
-            HumanBytes(((size as f64) / dt.as_secs_f64()).floor() as u64)
-        );
-    }
+    /// Directory of the persistent store and the list of shares.
+    ///
+    /// Defaults to `serve` in the user cache directory.
+    #[clap(long)]
+    pub dir: Option<PathBuf>,
 
-    println!("to get this data, use");
-    println!(
-        "sendme receive{} {}",
-        if do_compress { " -z" } else { "" },
-        ticket
-    );
+    /// What type of ticket to use, see `sendme send --ticket-type`.
+    #[clap(long, default_value_t = AddrInfoOptions::RelayAndAddresses)]
+    pub ticket_type: AddrInfoOptions,
 
-    #[cfg(feature = "clipboard")]
-    {
-        use console::{Key, Term};
+    /// Put the name and size of shares into their tickets, see `sendme send
+    /// --ticket-meta`.
+    #[clap(long)]
+    pub ticket_meta: bool,
 
-        // Add command to the clipboard
-        if args.clipboard {
-            add_to_clipboard(&ticket, do_compress);
-        }
+    /// Serve Prometheus metrics on this address, see `sendme send
+    /// --metrics-addr`.
+    #[clap(long, value_name = "ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
 
-        let _keyboard = tokio::task::spawn(async move {
-            let term = Term::stdout();
-            println!("press c to copy command to clipboard, or use the --clipboard argument");
-            loop {
-                if let Ok(Key::Char('c')) = term.read_key() {
-                    add_to_clipboard(&ticket, do_compress);
-                }
-            }
-        });
-    }
+    /// Allow this node to ask for other data to be fetched with `sendme pin`.
+    ///
+    /// Pinned data is kept across restarts, until it is removed with `remove
+    /// HASH` like a share. See `sendme send --allow-pin`.
+    #[clap(long, value_name = "PEER", value_parser = parse_peer)]
+    pub allow_pin: Vec<NodeId>,
+
+    /// Refuse to pin data that is larger than this, e.g. `500M`.
+    #[clap(long, value_name = "SIZE", default_value = "10G", value_parser = parse_size)]
+    pub max_pin_size: u64,
+
+    /// Run this command whenever data is added to or removed from the store,
+    /// e.g. to keep a catalog of the shares.
+    ///
+    /// The command is run like `sendme send --on-complete`, one event at a
+    /// time. It gets the event in `SENDME_EVENT`: `added` for a new share,
+    /// `pinned` for data pinned with `sendme pin` and `removed` for a removed
+    /// share or pin. `SENDME_HASH` and `SENDME_TICKET` are the hash and
+    /// ticket of the share, `SENDME_BLOBS` are the hashes of the blobs of an
+    /// added share, or the deleted blobs of a removed one, separated by
+    /// spaces, and for added shares `SENDME_PATH` is the shared path.
+    #[clap(long, value_name = "COMMAND")]
+    pub on_event: Option<String>,
+
+    /// Only answer probes and range checks, and refuse downloads.
+    ///
+    /// Receivers can check the sizes of blobs, list the entries of shares,
+    /// e.g. with `sendme verify --ticket`, and verify ranges of up to 16 KiB
+    /// per request. The connections of receivers that ask for more are closed
+    /// as soon as the request arrives, so only the data that is already in
+    /// flight gets out. Files of up to 16 KiB can still be got as a whole.
+    #[clap(long)]
+    pub verify_only: bool,
 
-    tokio::signal::ctrl_c().await?;
+    #[clap(flatten)]
+    pub common: CommonArgs,
+}
 
-    drop(temp_tag);
+#[derive(Parser, Debug)]
+pub struct PackArgs {
+    /// The hex encoded hash of the share, as shown by `list` in `sendme serve`.
+    pub hash: String,
 
-    println!("shutting down");
-    tokio::time::timeout(Duration::from_secs(2), router.shutdown()).await??;
-    tokio::fs::remove_dir_all(blobs_data_dir).await?;
-    // drop everything that owns blobs to close the progress sender
-    drop(router);
-    // await progress completion so the progress bar is cleared
-    progress.await.ok();
+    /// The archive to write.
+    #[clap(long, short)]
+    pub output: PathBuf,
 
-    Ok(())
+    /// The directory of `sendme serve`, see `sendme serve --dir`.
+    #[clap(long)]
+    pub dir: Option<PathBuf>,
 }
 
-#[cfg(feature = "clipboard")]
-fn add_to_clipboard(ticket: &BlobTicket, add_decompress_tag: bool) {
-    use std::io::{stdout, Write};
+#[derive(Parser, Debug)]
+pub struct UnpackArgs {
+    /// The archive to unpack.
+    pub path: PathBuf,
 
-    use base64::prelude::{Engine, BASE64_STANDARD};
+    /// Directory to export the files to, instead of the current directory.
+    #[clap(long, short, conflicts_with = "serve")]
+    pub output: Option<PathBuf>,
 
-    // Use OSC 52 to copy content to clipboard.
-    print!(
-        "\x1B]52;c;{}\x07",
-        BASE64_STANDARD.encode(format!(
-            "sendme receive{} {ticket}",
-            if add_decompress_tag { " -z" } else { "" }
-        ))
-    );
+    /// Add the share to the shares of `sendme serve` instead of exporting it.
+    ///
+    /// It is provided with the same hash the next time `sendme serve` starts,
+    /// which must not be running while unpacking.
+    #[clap(long)]
+    pub serve: bool,
+
+    /// The directory of `sendme serve`, see `sendme serve --dir`.
+    #[clap(long, requires = "serve")]
+    pub dir: Option<PathBuf>,
+}
 
-    stdout()
-        .flush()
-        .unwrap_or_else(|e| eprintln!("Failed to flush stdout: {e}"));
+#[derive(Parser, Debug)]
+pub struct AuditArgs {
+    /// The audit log to check.
+    pub path: PathBuf,
 }
 
-const TICK_MS: u64 = 250;
+#[derive(Parser, Debug)]
+pub struct VerifyArgs {
+    /// The manifest to verify against, as written by `sendme send --manifest-out`.
+    #[clap(long, required_unless_present = "ticket")]
+    pub manifest: Option<PathBuf>,
 
-fn make_import_overall_progress() -> ProgressBar {
-    let pb = ProgressBar::hidden();
-    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len}",
-        )
-        .unwrap()
-        .progress_chars("#>-"),
-    );
-    pb
+    /// Verify against the share of this ticket instead of a manifest.
+    ///
+    /// The names and hashes of the entries are fetched from the sender, so it
+    /// must still be providing the share.
+    #[clap(long, conflicts_with = "manifest")]
+    pub ticket: Option<Ticket>,
+
+    /// The password of a sender that was started with `--password`, for
+    /// `--ticket`.
+    #[clap(long, visible_alias = "token", requires = "ticket")]
+    pub password: Option<String>,
+
+    /// The directory the manifest entries are relative to.
+    ///
+    /// Defaults to the current directory.
+    pub path: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub common: CommonArgs,
 }
 
-fn make_import_item_progress() -> ProgressBar {
-    let pb = ProgressBar::hidden();
-    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
-    pb.set_style(
-        ProgressStyle::with_template("{msg}{spinner:.green} XXXX [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes}")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-    pb
+#[derive(Parser, Debug)]
+pub struct ReportArgs {
+    /// Include the structure of this ticket, with hashes and node ids redacted.
+    #[clap(long)]
+    pub ticket: Option<Ticket>,
+
+    /// Where to write the report, defaults to `sendme-report-<time>.tar.gz`
+    /// in the current directory.
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
 }
 
-fn make_connect_progress() -> ProgressBar {
-    let pb = ProgressBar::hidden();
-    pb.set_style(
-        ProgressStyle::with_template("{prefix}{spinner:.green} Connecting ... [{elapsed_precise}]")
-            .unwrap(),
-    );
-    pb.set_prefix(format!("{} ", style("[1/4]").bold().dim()));
-    pb.enable_steady_tick(Duration::from_millis(TICK_MS));
-    pb
+/// Get the secret key from `IROH_SECRET` or the config file, or generate a
+/// new one.
+///
+/// Print the secret key to stderr if it was generated and `print` is set, so
+/// the user can save it.
+fn get_or_create_secret(print: bool) -> anyhow::Result<SecretKey> {
+    match std::env::var("IROH_SECRET") {
+        Ok(secret) => SecretKey::from_str(&secret).context("invalid secret"),
+        Err(_) => {
+            if let Some(key) = config().secret_key()? {
+                return Ok(key);
+            }
+            let key = SecretKey::generate(rand::rngs::OsRng);
+            if print {
+                let key = hex::encode(key.to_bytes());
+                eprintln!("using secret key {key}");
+            }
+            Ok(key)
+        }
+    }
 }
 
-fn make_get_sizes_progress() -> ProgressBar {
-    let pb = ProgressBar::hidden();
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{prefix}{spinner:.green} Getting sizes... [{elapsed_precise}]",
-        )
-        .unwrap(),
-    );
-    pb.set_prefix(format!("{} ", style("[2/4]").bold().dim()));
-    pb.enable_steady_tick(Duration::from_millis(TICK_MS));
-    pb
+/// Directory of the logs of recent runs in the cache directory, for
+/// `sendme report`, see `--report-log`.
+const RUN_LOG_DIR: &str = "logs";
+
+/// Number of logs of recent runs that are kept.
+const RUN_LOG_KEEP: usize = 5;
+
+/// Log directives for the logs of runs, independent of `RUST_LOG`.
+const LAST_RUN_LOG_FILTER: &str = "warn,sendme=debug,iroh=info,iroh_blobs=info";
+
+/// Create the log of this run, removing the oldest logs.
+///
+/// Every run gets its own log, so concurrent runs don't replace each other's.
+fn open_run_log() -> anyhow::Result<std::fs::File> {
+    let dir = cache_dir()?.join(RUN_LOG_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let logs = run_logs(&dir)?;
+    for old in &logs[..logs.len().saturating_sub(RUN_LOG_KEEP - 1)] {
+        std::fs::remove_file(old).ok();
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = dir.join(format!("run-{now}-{}.log", std::process::id()));
+    Ok(std::fs::File::create(path)?)
 }
 
-fn make_download_progress() -> ProgressBar {
-    let pb = ProgressBar::hidden();
-    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
-    pb.set_style(
-        ProgressStyle::with_template("{prefix}{spinner:.green}{msg} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {binary_bytes_per_sec}")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-    pb.set_prefix(format!("{} ", style("[3/4]").bold().dim()));
-    pb.set_message("Downloading ...".to_string());
-    pb
+/// The logs of recent runs in `dir`, the most recently written one last.
+fn run_logs(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut logs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("run-") && name.ends_with(".log") {
+            logs.push((entry.metadata()?.modified()?, entry.path()));
+        }
+    }
+    logs.sort();
+    Ok(logs.into_iter().map(|(_, path)| path).collect())
 }
 
-fn make_export_overall_progress() -> ProgressBar {
-    let pb = ProgressBar::hidden();
-    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
-    pb.set_style(
-        ProgressStyle::with_template("{prefix}{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} {per_sec}")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-    pb.set_prefix(format!("{}", style("[4/4]").bold().dim()));
-    pb
+impl SendArgs {
+    /// Add the options of `profile`, see [`SendArgs::profile`].
+    fn apply_profile(&mut self, profile: &Profile) -> anyhow::Result<()> {
+        if self.paths.is_empty() {
+            self.paths = profile.paths.clone();
+        }
+        self.include.extend(profile.include.iter().cloned());
+        self.exclude.extend(profile.exclude.iter().cloned());
+        self.gitignore |= profile.gitignore;
+        for peer in &profile.allow {
+            self.allow.push(config().resolve_peer(peer)?);
+        }
+        if self.message.is_none() {
+            self.message = profile.message.clone();
+        }
+        if let (None, Some(expire)) = (self.expire, &profile.expire) {
+            self.expire =
+                Some(parse_duration(expire).with_context(|| format!("invalid expire {expire}"))?);
+        }
+        Ok(())
+    }
+
+    /// The options of [`send`], after adding the options of the profile.
+    fn into_options(mut self) -> anyhow::Result<SendOptions> {
+        if let Some(name) = self.profile.clone() {
+            let profile = config()
+                .profiles
+                .get(&name)
+                .with_context(|| format!("unknown profile {name}"))?;
+            self.apply_profile(profile)?;
+            anyhow::ensure!(!self.paths.is_empty(), "profile {name} has no paths");
+        }
+        Ok(SendOptions {
+            secret_key: get_or_create_secret(self.common.show_secret)?,
+            net: self.common.net(),
+            no_progress: self.common.no_progress(),
+            audit_log: self.common.audit_log,
+            format: self.common.format,
+            verbose: self.common.verbose,
+            zstd: self.common.zstd,
+            #[cfg(feature = "zstd")]
+            compression_quality: self.common.compression_quality,
+            paths: self.paths,
+            tar_in: self.tar_in,
+            name: self.name,
+            dry_run: self.dry_run,
+            symlinks: self.symlinks,
+            include: self.include,
+            exclude: self.exclude,
+            gitignore: self.gitignore,
+            first: self.first,
+            ticket_type: self.ticket_type,
+            cache: self.cache,
+            store: self.store,
+            cache_size: self.cache_size,
+            data_dir: self.data_dir,
+            hash_threads: self.hash_threads,
+            manifest_out: self.manifest_out,
+            checksums: self.checksums,
+            self_test: self.self_test,
+            print_ticket_only: self.print_ticket_only,
+            qr: self.qr,
+            url: self.url,
+            ticket_meta: self.ticket_meta,
+            watch: self.watch,
+            local: self.local,
+            code: self.code,
+            wait: self.wait,
+            once: self.once,
+            allow_pin: self.allow_pin,
+            max_pin_size: self.max_pin_size,
+            allow: self.allow,
+            nice: self.nice,
+            limit_rate: self.limit_rate,
+            timeout: self.timeout,
+            expire: self.expire,
+            metrics_addr: self.metrics_addr,
+            on_complete: self.on_complete,
+            #[cfg(feature = "notifications")]
+            notify: self.notify,
+            #[cfg(feature = "clipboard")]
+            clipboard: self.clipboard,
+            #[cfg(feature = "clipboard")]
+            copy: self.copy,
+            message: self.message,
+            chat: self.chat,
+            password: self.password,
+        })
+    }
 }
 
-fn make_export_item_progress() -> ProgressBar {
-    let pb = ProgressBar::hidden();
-    pb.enable_steady_tick(std::time::Duration::from_millis(100));
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes}",
-        )
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-    pb
+impl ReceiveArgs {
+    /// The options of [`receive`], exporting to `--output`, the destination
+    /// or the download directory of the config file.
+    fn into_options(self) -> anyhow::Result<ReceiveOptions> {
+        Ok(ReceiveOptions {
+            secret_key: get_or_create_secret(self.common.show_secret)?,
+            net: self.common.net(),
+            no_progress: self.common.no_progress(),
+            audit_log: self.common.audit_log,
+            format: self.common.format,
+            verbose: self.common.verbose,
+            zstd: self.common.zstd,
+            output: self
+                .output
+                .or(self.dest)
+                .or_else(|| config().download_dir.clone()),
+            limits: Limits {
+                max_entries: self.max_entries,
+                max_name_len: self.max_name_len,
+            },
+            ticket: self.ticket,
+            yes: self.yes,
+            code: self.code,
+            local: self.local,
+            resume: self.resume,
+            data_dir: self.data_dir,
+            preview: self.preview,
+            list: self.list,
+            require_signed: self.require_signed,
+            expect_identity: self.expect_identity,
+            mirror: self.mirror,
+            pipeline_depth: self.pipeline_depth,
+            reconnect_timeout: self.reconnect_timeout,
+            interactive: self.interactive,
+            from: self.from,
+            debug_report: self.debug_report,
+            message: self.message,
+            chat: self.chat,
+            password: self.password,
+            recv_buffer: self.recv_buffer,
+            nice: self.nice,
+            limit_rate: self.limit_rate,
+            only: self.only,
+            skip: self.skip,
+            in_order: self.in_order,
+            on_conflict: self.on_conflict,
+            review: self.review,
+            no_backup: self.no_backup,
+            no_times: self.no_times,
+            open: self.open,
+            on_complete: self.on_complete,
+            #[cfg(feature = "notifications")]
+            notify: self.notify,
+            stdout: self.stdout,
+            tar: self.tar,
+        })
+    }
 }
 
-pub async fn show_download_progress(
-    mp: MultiProgress,
-    mut recv: mpsc::Receiver<u64>,
-    local_size: u64,
-    total_size: u64,
-) -> anyhow::Result<()> {
-    let op = mp.add(make_download_progress());
-    op.set_length(total_size);
-    while let Some(offset) = recv.recv().await {
-        op.set_position(local_size + offset);
+impl ServeArgs {
+    /// The options of [`serve`], with the schedule of the config file.
+    fn into_options(self) -> anyhow::Result<ServeOptions> {
+        // a config that fails to load is ignored elsewhere, but without the
+        // schedule receivers would not be limited at all
+        let schedule = Config::load()?.schedule;
+        Ok(ServeOptions {
+            secret_key: get_or_create_secret(self.common.show_secret)?,
+            net: self.common.net(),
+            no_progress: self.common.no_progress(),
+            schedule,
+            paths: self.paths,
+            dir: self.dir,
+            ticket_type: self.ticket_type,
+            ticket_meta: self.ticket_meta,
+            metrics_addr: self.metrics_addr,
+            allow_pin: self.allow_pin,
+            max_pin_size: self.max_pin_size,
+            on_event: self.on_event,
+            verify_only: self.verify_only,
+        })
     }
-    op.finish_and_clear();
+}
+
+/// Send to the hosts of `args`, see [`FanoutTargets`].
+async fn fanout(args: FanoutArgs) -> anyhow::Result<()> {
+    let targets = FanoutTargets::load(&args.hosts, args.ssh, args.remote_dir)?;
+    send(args.send.into_options()?, Some(targets)).await
+}
+
+/// Ask a sender to fetch and keep data, see [`sendme::ext::pin`].
+async fn pin(args: PinArgs) -> anyhow::Result<()> {
+    let secret_key = get_or_create_secret(args.common.show_secret)?;
+    let response =
+        sendme::ext::pin(&args.common.net(), secret_key, &args.provider, &args.ticket).await?;
+    println!("{response}");
     Ok(())
 }
 
-fn show_get_error(e: GetError) -> GetError {
-    match &e {
-        GetError::NotFound { .. } => {
-            eprintln!("{}", style("send side no longer has a file").yellow())
+/// The structure of a ticket, without anything that identifies the share.
+fn ticket_structure(ticket: &Ticket) -> serde_json::Value {
+    let redacted = |set: bool| if set { "<redacted>" } else { "none" };
+    serde_json::json!({
+        "format": format!("{:?}", ticket.format()),
+        "hash": "<redacted>",
+        "node_id": "<redacted>",
+        "relay_url": ticket.node_addr().relay_url.as_ref().map(|url| url.to_string()),
+        "direct_addresses": ticket.node_addr().direct_addresses.len(),
+        "name": redacted(ticket.meta.name.is_some()),
+        "size": ticket.meta.size,
+        "mime": ticket.meta.mime,
+        "signer": redacted(ticket.meta.signer.is_some()),
+        "expires": ticket.meta.expires,
+        "signed": ticket.meta.share.signature.is_some(),
+    })
+}
+
+/// Check connectivity to the relays and classify the NAT.
+async fn connectivity_report() -> anyhow::Result<String> {
+    let endpoint = Endpoint::builder().alpns(vec![]).bind().await?;
+    let report = tokio::time::timeout(Duration::from_secs(10), endpoint.net_report().initialized())
+        .await
+        .context("timeout waiting for the network report")??;
+    let home_relay = endpoint
+        .home_relay()
+        .get()?
+        .into_iter()
+        .map(|url| url.to_string())
+        .collect::<Vec<_>>();
+    endpoint.close().await;
+    let (nat, connectivity) = nat_type(&report);
+    Ok(format!(
+        "nat: {nat}, {connectivity}\nudp v4: {}\nudp v6: {}\nmapping varies by destination: {:?}\nhome relay: {}\n",
+        report.udp_v4,
+        report.udp_v6,
+        report.mapping_varies_by_dest_ipv4,
+        home_relay.join(", ")
+    ))
+}
+
+/// Write a tarball with diagnostics for a bug report.
+async fn report(args: ReportArgs) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut files = Vec::new();
+    let version = format!(
+        "sendme {}\nos: {} {}\nfeatures: zstd={} clipboard={}\nblake3: {}/s per thread\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        cfg!(feature = "zstd"),
+        cfg!(feature = "clipboard"),
+        HumanBytes(blake3_throughput()),
+    );
+    files.push(("version.txt", version));
+    let cache_dir = cache_dir()?;
+    let config = format!(
+        "IROH_SECRET: {}\nRUST_LOG: {}\ncache dir: {} ({})\n",
+        if std::env::var_os("IROH_SECRET").is_some() {
+            "<redacted>"
+        } else {
+            "not set"
+        },
+        std::env::var("RUST_LOG").unwrap_or_else(|_| "not set".to_string()),
+        cache_dir.display(),
+        if cache_dir.exists() {
+            "exists"
+        } else {
+            "missing"
+        },
+    );
+    files.push(("config.txt", config));
+    let last_run = run_logs(&cache_dir.join(RUN_LOG_DIR))
+        .ok()
+        .and_then(|logs| logs.last().cloned());
+    match last_run.map(std::fs::read_to_string) {
+        Some(Ok(log)) => files.push(("last-run.log", redact(&log))),
+        Some(Err(cause)) => eprintln!("failed to read the log of the last run: {cause}"),
+        None => {
+            eprintln!("no log of the last run, run the failing command again with --report-log")
         }
-        GetError::RemoteReset { .. } => eprintln!("{}", style("remote reset").yellow()),
-        GetError::NoncompliantNode { .. } => {
-            eprintln!("{}", style("non-compliant remote").yellow())
+    }
+    if let Some(ticket) = &args.ticket {
+        let structure = serde_json::to_string_pretty(&ticket_structure(ticket))?;
+        files.push(("ticket.json", structure));
+    }
+    eprintln!("checking connectivity...");
+    let network = match connectivity_report().await {
+        Ok(network) => network,
+        Err(cause) => format!("connectivity check failed: {cause}\n"),
+    };
+    files.push(("network.txt", redact(&network)));
+
+    let output = match args.output {
+        Some(output) => output,
+        None => std::env::current_dir()?.join(format!("sendme-report-{now}.tar.gz")),
+    };
+    let file = std::fs::File::create(&output)
+        .with_context(|| format!("failed to create {}", output.display()))?;
+    let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    for (name, data) in &files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(now);
+        tar.append_data(&mut header, name, data.as_bytes())?;
+    }
+    tar.into_inner()?.finish()?;
+    println!("wrote {}", output.display());
+    println!("please check the contents before attaching it to an issue");
+    Ok(())
+}
+
+/// Compute the blake3 hash of a file on disk.
+fn hash_file(path: &Path) -> anyhow::Result<(u64, blake3::Hash)> {
+    let mut hasher = blake3::Hasher::new();
+    let file = std::fs::File::open(path)?;
+    hasher.update_reader(file)?;
+    Ok((hasher.count(), hasher.finalize()))
+}
+
+async fn verify(args: VerifyArgs) -> anyhow::Result<()> {
+    let manifest = match (&args.manifest, &args.ticket) {
+        (_, Some(ticket)) => fetch_manifest(&args, ticket).await?,
+        (Some(path), None) => {
+            let manifest = std::fs::read(path)
+                .with_context(|| format!("failed to read manifest {}", path.display()))?;
+            serde_json::from_slice(&manifest).context("invalid manifest")?
         }
-        GetError::Io { source, .. } => eprintln!(
-            "{}",
-            style(format!("generic network error: {source}")).yellow()
-        ),
-        GetError::BadRequest { .. } => eprintln!("{}", style("bad request").yellow()),
-        GetError::LocalFailure { source, .. } => {
-            eprintln!("{} {source:?}", style("local failure").yellow())
+        (None, None) => anyhow::bail!("either a manifest or a ticket is needed"),
+    };
+    anyhow::ensure!(
+        manifest.version == 1,
+        "unsupported manifest version {}",
+        manifest.version
+    );
+    let root = match args.path {
+        Some(path) => path,
+        None => std::env::current_dir()?,
+    };
+    let mut failed = 0;
+    for entry in &manifest.entries {
+        let path = get_export_path(&root, &entry.name)?;
+        let res = tokio::task::spawn_blocking(move || hash_file(&path)).await?;
+        let error = match res {
+            Ok((size, hash)) if size == entry.size && hash.to_hex().as_str() == entry.blake3 => {
+                None
+            }
+            Ok(_) => Some("mismatch"),
+            Err(cause) => {
+                trace!("error hashing {}: {cause}", entry.name);
+                Some("missing")
+            }
+        };
+        match error {
+            None => println!("{} {}", style("ok").green(), entry.name),
+            Some(error) => {
+                failed += 1;
+                println!("{} {}", style(error).red(), entry.name);
+            }
         }
     }
-    e
+    anyhow::ensure!(
+        failed == 0,
+        "{failed} of {} entries failed verification",
+        manifest.entries.len()
+    );
+    println!("all {} entries verified", manifest.entries.len());
+    Ok(())
 }
 
-async fn receive(args: ReceiveArgs) -> anyhow::Result<()> {
-    let ticket = args.ticket;
-    let addr = ticket.node_addr().clone();
-    let secret_key = get_or_create_secret(args.common.verbose > 0)?;
+/// The manifest of the share of `ticket`, with the names, hashes and sizes of
+/// the entries fetched from the sender, see [`fetch_entries`].
+async fn fetch_manifest(args: &VerifyArgs, ticket: &Ticket) -> anyhow::Result<Manifest> {
+    let secret_key = get_or_create_secret(args.common.show_secret)?;
+    let net = args.common.net();
     let mut builder = Endpoint::builder()
         .alpns(vec![])
         .secret_key(secret_key)
-        .relay_mode(args.common.relay.into());
-
-    if ticket.node_addr().relay_url.is_none() && ticket.node_addr().direct_addresses.is_empty() {
+        .relay_mode(net.relay_mode());
+    let addr = ticket.node_addr();
+    if addr.relay_url.is_none() && addr.direct_addresses.is_empty() || net.dns_discovery {
         builder = builder.add_discovery(DnsDiscovery::n0_dns());
     }
-    if let Some(addr) = args.common.magic_ipv4_addr {
-        builder = builder.bind_addr_v4(addr);
+    let endpoint = net.bind(builder).bind().await?;
+    let db = MemStore::new();
+    let password = args.password.clone();
+    let res = async {
+        let (entries, meta) =
+            fetch_entries(&db, &endpoint, &net, ticket, password, &Limits::default()).await?;
+        anyhow::ensure!(
+            !meta.compressed,
+            "the share is compressed, so its hashes don't match the files on disk"
+        );
+        let sizes = entries.iter().map(|(_, _, size)| *size).collect::<Vec<_>>();
+        let collection = entries
+            .into_iter()
+            .map(|(name, hash, _)| (name, hash))
+            .collect::<Collection>();
+        anyhow::Ok(Manifest::new(ticket.hash(), &collection, &sizes))
     }
-    if let Some(addr) = args.common.magic_ipv6_addr {
-        builder = builder.bind_addr_v6(addr);
+    .await;
+    endpoint.close().await;
+    res
+}
+
+/// Walk the user through the settings of the config file and write it.
+///
+/// The current settings are kept by pressing enter, so this can be run again
+/// to change a single setting.
+async fn init() -> anyhow::Result<()> {
+    let path = Config::path()?;
+    let mut config = Config::load().unwrap_or_else(|cause| {
+        eprintln!("{} {cause:#}, starting over", style("warning:").yellow());
+        Config::default()
+    });
+    eprintln!(
+        "This writes the config file {}. Press enter to keep the value in brackets.\n",
+        path.display()
+    );
+
+    let question = if config.secret_key.is_some() {
+        "Keep your persistent secret key"
+    } else {
+        "Use a persistent secret key, so your node id stays the same"
+    };
+    if confirm(question, true)? {
+        let key = match config.secret_key()? {
+            Some(key) => key,
+            None => {
+                let key = SecretKey::generate(rand::rngs::OsRng);
+                config.secret_key = Some(hex::encode(key.to_bytes()));
+                key
+            }
+        };
+        eprintln!("your node id is {}", key.public());
+    } else {
+        config.secret_key = None;
     }
-    let endpoint = builder.bind().await?;
-    let dir_name = format!(".sendme-recv-{}", ticket.hash().to_hex());
-    let iroh_data_dir = std::env::current_dir()?.join(dir_name);
-    let db = iroh_blobs::store::fs::FsStore::load(&iroh_data_dir).await?;
-    let db2 = db.clone();
-
-    let do_decompress = zstd_enabled(args.common.zstd, false);
-
-    trace!("load done!");
-    let fut = async move {
-        trace!("running");
-        let mut mp: MultiProgress = MultiProgress::new();
-        let draw_target = if args.common.no_progress {
-            ProgressDrawTarget::hidden()
-        } else {
-            ProgressDrawTarget::stderr()
+
+    let current = match &config.download_dir {
+        Some(dir) => dir.display().to_string(),
+        None => ".".to_string(),
+    };
+    if let Some(answer) = prompt(
+        "Download directory, `.` for the current directory",
+        &current,
+    )? {
+        config.download_dir = match answer.as_str() {
+            "." => None,
+            _ => match (answer.strip_prefix("~/"), dirs::home_dir()) {
+                (Some(rest), Some(home)) => Some(home.join(rest)),
+                _ => Some(PathBuf::from(answer)),
+            },
         };
-        mp.set_draw_target(draw_target);
-        let hash_and_format = ticket.hash_and_format();
-        trace!("computing local");
-        let local = db.remote().local(hash_and_format).await?;
-        trace!("local done");
-        let (stats, total_files, payload_size) = if !local.is_complete() {
-            trace!("{} not complete", hash_and_format.hash);
-            let cp = mp.add(make_connect_progress());
-            let connection = endpoint.connect(addr, iroh_blobs::protocol::ALPN).await?;
-            cp.finish_and_clear();
-            let sp = mp.add(make_get_sizes_progress());
-            let (_hash_seq, sizes) =
-                get_hash_seq_and_sizes(&connection, &hash_and_format.hash, 1024 * 1024 * 32, None)
-                    .await
-                    .map_err(show_get_error)?;
-            sp.finish_and_clear();
-            let total_size = sizes.iter().copied().sum::<u64>();
-            let payload_size = sizes.iter().skip(2).copied().sum::<u64>();
-            let total_files = (sizes.len().saturating_sub(1)) as u64;
-            eprintln!(
-                "getting collection {} {} files, {}",
-                print_hash(&ticket.hash(), args.common.format),
-                total_files,
-                HumanBytes(payload_size)
-            );
-            // print the details of the collection only in verbose mode
-            if args.common.verbose > 0 {
-                eprintln!(
-                    "getting {} blobs in total, {}",
-                    total_files + 1,
-                    HumanBytes(total_size)
-                );
-            }
-            let (tx, rx) = mpsc::channel(32);
-            let local_size = local.local_bytes();
-            let get = db.remote().execute_get(connection, local.missing());
-            let task = tokio::spawn(show_download_progress(
-                mp.clone(),
-                rx,
-                local_size,
-                total_size,
-            ));
-            // let mut stream = get.stream();
-            let mut stats = Stats::default();
-            let mut stream = get.stream();
-            while let Some(item) = stream.next().await {
-                trace!("got item {item:?}");
-                match item {
-                    GetProgressItem::Progress(offset) => {
-                        tx.send(offset).await.ok();
-                    }
-                    GetProgressItem::Done(value) => {
-                        stats = value;
-                        break;
-                    }
-                    GetProgressItem::Error(cause) => {
-                        anyhow::bail!(show_get_error(cause));
-                    }
-                }
-            }
-            drop(tx);
-            task.await.ok();
-            (stats, total_files, payload_size)
-        } else {
-            println!("{} already complete", hash_and_format.hash);
-            let total_files = local.children().unwrap() - 1;
-            let payload_bytes = 0; // todo local.sizes().skip(2).map(Option::unwrap).sum::<u64>();
-            (Stats::default(), total_files, payload_bytes)
+    }
+
+    loop {
+        let current = config.relay.as_deref().unwrap_or("default");
+        let Some(answer) = prompt("Relay, `default`, `disabled` or a relay url", current)? else {
+            break;
         };
-        let collection = Collection::load(hash_and_format.hash, db.as_ref()).await?;
-        if args.common.verbose > 1 {
-            for (name, hash) in collection.iter() {
-                println!("    {} {name}", print_hash(hash, args.common.format));
-            }
-        }
-        if let Some((name, _)) = collection.iter().next() {
-            if let Some(first) = name.split('/').next() {
-                println!(
-                    "exporting to {first}{}",
-                    if do_decompress != args.common.zstd && collection.len() == 1 {
-                        ".zst"
-                    } else {
-                        ""
-                    }
-                );
+        match RelayModeOption::from_str(&answer) {
+            Ok(RelayModeOption::Default) => config.relay = None,
+            Ok(relay) => config.relay = Some(relay.to_string()),
+            Err(cause) => {
+                eprintln!("invalid relay: {cause}");
+                continue;
             }
         }
+        break;
+    }
 
-        export(
-            &db,
-            collection,
-            &mut mp,
-            do_decompress,
-            do_decompress != args.common.zstd,
-        )
-        .await?;
-        anyhow::Ok((total_files, payload_size, stats))
+    let bars = confirm("Show progress bars", config.progress == ProgressMode::Bars)?;
+    config.progress = if bars {
+        ProgressMode::Bars
+    } else {
+        ProgressMode::None
     };
-    let (total_files, payload_size, stats) = select! {
-        x = fut => match x {
-            Ok(x) => x,
-            Err(e) => {
-                // make sure we shutdown the db before exiting
-                db2.shutdown().await?;
-                eprintln!("error: {e}");
-                std::process::exit(1);
+
+    let path = config.save()?;
+    println!("wrote {}", path.display());
+    Ok(())
+}
+
+/// Change or list the peers of the config file.
+fn peers(args: PeersArgs) -> anyhow::Result<()> {
+    // a broken config file must not be replaced
+    let mut config = Config::load()?;
+    match args.command {
+        PeersCommands::Add { name, node_id } => {
+            anyhow::ensure!(
+                !name.is_empty() && NodeId::from_str(&name).is_err(),
+                "invalid peer name {name}"
+            );
+            config.peers.insert(name, node_id);
+            config.save()?;
+        }
+        PeersCommands::Remove { name } => {
+            anyhow::ensure!(config.peers.remove(&name).is_some(), "unknown peer {name}");
+            config.save()?;
+        }
+        PeersCommands::List => {
+            for (name, node_id) in &config.peers {
+                println!("{name}\t{node_id}");
             }
-        },
-        _ = tokio::signal::ctrl_c() => {
-            db2.shutdown().await?;
-            std::process::exit(130);
         }
-    };
-    tokio::fs::remove_dir_all(iroh_data_dir).await?;
-    if args.common.verbose > 0 {
-        println!(
-            "downloaded {} files, {}. took {} ({}/s)",
-            total_files,
-            HumanBytes(payload_size),
-            HumanDuration(stats.elapsed),
-            HumanBytes((stats.total_bytes_read() as f64 / stats.elapsed.as_secs_f64()) as u64),
-        );
     }
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
     let args = match Args::try_parse() {
         Ok(args) => args,
         Err(cause) => {
@@ -1269,12 +1908,110 @@ async fn main() -> anyhow::Result<()> {
             }
         }
     };
+    // log to stderr, so stdout can be used for output meant for scripts. the
+    // log of the last run is kept for `sendme report`, so it must not replace it.
+    // all logs are redacted, so they can be shared.
+    let last_run_log = match args.command {
+        Commands::Report(_) => None,
+        _ if args.report_log || config().report_log => open_run_log().ok(),
+        _ => None,
+    };
+    let log_file = match &args.log_file {
+        Some(path) => Some(
+            RotatingFile::open(
+                path,
+                RotatingFile::DEFAULT_MAX_SIZE,
+                RotatingFile::DEFAULT_KEEP,
+            )
+            .with_context(|| format!("failed to open log file {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let log_file_filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(_) => EnvFilter::from_default_env(),
+        Err(_) => EnvFilter::new(LAST_RUN_LOG_FILTER),
+    };
+    // --trace applies to all logs, so `sendme report` includes it as well
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(Redacting(std::io::stderr))
+                .with_filter(with_trace(EnvFilter::from_default_env(), &args.trace)),
+        )
+        .with(last_run_log.map(|file| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Redacting(std::sync::Mutex::new(file)))
+                .with_filter(with_trace(EnvFilter::new(LAST_RUN_LOG_FILTER), &args.trace))
+        }))
+        .with(log_file.map(|file| {
+            let file = std::sync::Mutex::new(file);
+            let writer = if args.log_unredacted {
+                BoxMakeWriter::new(file)
+            } else {
+                BoxMakeWriter::new(Redacting(file))
+            };
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer);
+            let layer = match args.log_format {
+                LogFormat::Text => layer.boxed(),
+                LogFormat::Json => layer.json().boxed(),
+            };
+            layer.with_filter(with_trace(log_file_filter, &args.trace))
+        }))
+        .init();
+    set_json_output(args.json);
+    #[cfg(feature = "notifications")]
+    let notify = match &args.command {
+        Commands::Send(args) => args.notify,
+        Commands::Receive(args) => args.notify,
+        _ => false,
+    };
     let res = match args.command {
-        Commands::Send(args) => send(args).await,
-        Commands::Receive(args) => receive(args).await,
+        Commands::Send(args) => async { send(args.into_options()?, None).await }.await,
+        Commands::Receive(args) => async { receive(args.into_options()?).await }.await,
+        Commands::Verify(args) => verify(args).await,
+        Commands::Audit(args) => AuditLog::verify(&args.path).map(|(count, local, last)| {
+            match local {
+                Some(local) => println!("{count} records signed by {local}"),
+                None => println!("{count} records"),
+            }
+            println!("the last one has the hash {last}");
+        }),
+        Commands::Fanout(args) => fanout(args).await,
+        Commands::Pin(args) => pin(args).await,
+        Commands::Serve(args) => async { serve(args.into_options()?).await }.await,
+        Commands::Pack(args) => {
+            pack_share(PackOptions {
+                hash: args.hash,
+                output: args.output,
+                dir: args.dir,
+            })
+            .await
+        }
+        Commands::Unpack(args) => {
+            unpack_share(UnpackOptions {
+                path: args.path,
+                output: args.output,
+                serve: args.serve,
+                dir: args.dir,
+            })
+            .await
+        }
+        Commands::Report(args) => report(args).await,
+        Commands::Init => init().await,
+        Commands::Peers(args) => peers(args),
     };
     if let Err(e) = &res {
+        emit(JsonEvent::Error {
+            message: e.to_string(),
+        });
         eprintln!("{e}");
+        #[cfg(feature = "notifications")]
+        if notify {
+            sendme::hook::notify_desktop("sendme failed", &e.to_string()).await;
+        }
     }
     match res {
         Ok(()) => std::process::exit(0),
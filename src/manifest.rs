@@ -0,0 +1,311 @@
+//! Share manifests, their signatures and the metadata entry of collections.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use data_encoding::BASE64;
+use iroh::{endpoint::Connection, NodeId, SecretKey};
+use iroh_blobs::{
+    api::{Store, TempTag},
+    format::collection::Collection,
+    protocol::{ChunkRanges, ChunkRangesExt, GetRequest},
+    BlobFormat, Hash, HashAndFormat,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ticket::{ShareMeta, Ticket},
+    transfer::execute_get,
+};
+
+/// Name of the collection entry that carries the [`ShareMeta`].
+///
+/// The entry is appended as the last entry of the collection and is never exported.
+///
+/// Receivers that don't know about the entry, i.e. older versions of sendme and
+/// other iroh-blobs clients, see it as a regular file and export it. This is
+/// a deliberate compatibility break, documented in the readme, since the
+/// collection format has no place for metadata. Importing skips such files
+/// and rejects other top level files with this name, so the entry is never
+/// mistaken for the metadata.
+pub const META_NAME: &str = ".sendme-meta.json";
+
+/// Entries up to this size are inlined into the metadata, see [`inline_entries`].
+const MAX_INLINE_ENTRY_SIZE: u64 = 16 * 1024;
+
+/// Upper bound for the data of all inlined entries of a collection.
+const MAX_INLINE_SIZE: u64 = 1024 * 1024;
+
+/// Upper bound for the metadata entry that receivers get ahead of the other
+/// entries, see [`get_share_meta`].
+pub const MAX_META_SIZE: u64 = 32 * 1024 * 1024;
+
+/// The bytes the provider signs for a collection.
+///
+/// This is a fixed prefix followed by the length prefixed name and the hash of
+/// each entry, in collection order, and then the length prefixed escaped name
+/// and original bytes of each of the [`ShareMeta::raw_names`], so receivers
+/// can trust the names they restore.
+fn manifest_bytes(collection: &Collection, raw_names: &BTreeMap<String, String>) -> Vec<u8> {
+    let mut res = entry_bytes(MANIFEST_PREFIX, collection);
+    for (name, raw) in raw_names {
+        res.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        res.extend_from_slice(name.as_bytes());
+        res.extend_from_slice(&(raw.len() as u64).to_le_bytes());
+        res.extend_from_slice(raw.as_bytes());
+    }
+    res
+}
+
+/// Prefix of the signed bytes of a collection.
+const MANIFEST_PREFIX: &[u8] = b"sendme-manifest-v2";
+
+/// Prefix of the signed bytes of older versions, which don't cover the raw names.
+const MANIFEST_PREFIX_V1: &[u8] = b"sendme-manifest-v1";
+
+/// `prefix` followed by the length prefixed name and the hash of each entry.
+///
+/// With [`MANIFEST_PREFIX_V1`], these are the bytes older versions sign.
+fn entry_bytes(prefix: &[u8], collection: &Collection) -> Vec<u8> {
+    let mut res = prefix.to_vec();
+    for (name, hash) in collection.iter() {
+        res.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        res.extend_from_slice(name.as_bytes());
+        res.extend_from_slice(hash.as_bytes());
+    }
+    res
+}
+
+/// Sign the manifest of a collection with the node key.
+///
+/// `raw_names` are the original names of the entries, see
+/// [`ShareMeta::raw_names`].
+pub fn sign_manifest(
+    collection: &Collection,
+    raw_names: &BTreeMap<String, String>,
+    secret_key: &SecretKey,
+) -> String {
+    let signature = secret_key.sign(&manifest_bytes(collection, raw_names));
+    hex::encode(signature.to_bytes())
+}
+
+/// Verify that the manifest of a collection was signed by `node_id`.
+pub fn verify_manifest(
+    collection: &Collection,
+    meta: &ShareMeta,
+    node_id: NodeId,
+) -> anyhow::Result<()> {
+    let signature = meta.signature.as_ref().context("share is not signed")?;
+    let signature: [u8; 64] = hex::decode(signature)
+        .context("invalid signature encoding")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("invalid signature length"))?;
+    let signature = ed25519_dalek::Signature::from_bytes(&signature);
+    let res = node_id.verify(&manifest_bytes(collection, &meta.raw_names), &signature);
+    // older versions only sign the entries, which is enough without raw names
+    let res = match res {
+        Err(_) if meta.raw_names.is_empty() => {
+            node_id.verify(&entry_bytes(MANIFEST_PREFIX_V1, collection), &signature)
+        }
+        res => res,
+    };
+    res.context("invalid signature")?;
+    Ok(())
+}
+
+/// Store a collection, with the metadata as an additional last entry.
+pub(crate) async fn store_collection(
+    db: &Store,
+    collection: &Collection,
+    meta: &ShareMeta,
+) -> anyhow::Result<TempTag> {
+    let meta_tag = db.add_bytes(serde_json::to_vec(meta)?).temp_tag().await?;
+    let mut collection = collection.clone();
+    collection.push(META_NAME.to_string(), *meta_tag.hash());
+    let temp_tag = collection.store(db).await?;
+    // the metadata is now protected by the collection
+    drop(meta_tag);
+    Ok(temp_tag)
+}
+
+/// The data of the small entries of a collection, for [`ShareMeta::inline`].
+///
+/// `sizes` are the sizes of the entries, in collection order. Entries of up to
+/// 16 KiB are inlined in collection order, until their data reaches 1 MiB.
+/// For collections of many tiny files, this saves the receiver a request per
+/// file, at the cost of sending their data twice to receivers that don't
+/// know about inlining.
+pub async fn inline_entries(
+    db: &Store,
+    collection: &Collection,
+    sizes: &[u64],
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut inline = BTreeMap::new();
+    let mut total = 0;
+    for ((_, hash), &size) in collection.iter().zip(sizes) {
+        let key = hash.to_hex().to_string();
+        if size > MAX_INLINE_ENTRY_SIZE
+            || total + size > MAX_INLINE_SIZE
+            || inline.contains_key(&key)
+        {
+            continue;
+        }
+        let data = db.get_bytes(*hash).await?;
+        total += size;
+        inline.insert(key, BASE64.encode(&data));
+    }
+    Ok(inline)
+}
+
+/// Add the inlined entries of a received collection to the store, see
+/// [`ShareMeta::inline`].
+///
+/// The data is checked against the hashes of the entries, so a download only
+/// requests the entries that are still missing afterwards. Returns the number
+/// of bytes that were added.
+pub async fn add_inline(
+    db: &Store,
+    collection: &Collection,
+    meta: &ShareMeta,
+) -> anyhow::Result<u64> {
+    let hashes = collection
+        .iter()
+        .map(|(_, hash)| *hash)
+        .collect::<BTreeSet<_>>();
+    let mut added = 0;
+    for (hash, data) in &meta.inline {
+        let hash = Hash::from_str(hash).context("invalid hash of inlined entry")?;
+        anyhow::ensure!(
+            hashes.contains(&hash),
+            "inlined entry {hash} is not part of the share"
+        );
+        let data = BASE64
+            .decode(data.as_bytes())
+            .context("invalid data of inlined entry")?;
+        anyhow::ensure!(
+            Hash::new(&data) == hash,
+            "inlined data does not match the entry {hash}"
+        );
+        added += data.len() as u64;
+        // nothing collects garbage while receiving, so the tag can be dropped
+        db.add_bytes(data).temp_tag().await?;
+    }
+    Ok(added)
+}
+
+/// Split the metadata entry off a received collection.
+///
+/// Collections from older senders don't have a metadata entry, in which case
+/// the default metadata is returned.
+async fn split_meta(db: &Store, collection: Collection) -> anyhow::Result<(Collection, ShareMeta)> {
+    let Some((_, meta_hash)) = collection.iter().find(|(name, _)| name == META_NAME) else {
+        return Ok((collection, ShareMeta::default()));
+    };
+    let bytes = db.get_bytes(*meta_hash).await?;
+    let meta = serde_json::from_slice(&bytes).context("invalid share metadata")?;
+    let collection = collection
+        .iter()
+        .filter(|(name, _)| name != META_NAME)
+        .map(|(name, hash)| (name.clone(), *hash))
+        .collect();
+    Ok((collection, meta))
+}
+
+/// Get the metadata of a collection from the provider, ahead of the entries.
+///
+/// This is used to honor [`ShareMeta::first`] while downloading. Collections
+/// from older senders don't have a metadata entry, in which case the default
+/// metadata is returned. The entry usually came with the collection, see
+/// [`get_collection`](crate::transfer::get_collection), otherwise at most
+/// [`MAX_META_SIZE`] of it is requested.
+pub async fn get_share_meta(
+    db: &Store,
+    connection: Connection,
+    collection: &Collection,
+) -> anyhow::Result<ShareMeta> {
+    let Some((_, meta_hash)) = collection.iter().find(|(name, _)| name == META_NAME) else {
+        return Ok(ShareMeta::default());
+    };
+    let local = db.remote().local(HashAndFormat::raw(*meta_hash)).await?;
+    if !local.is_complete() {
+        let request = GetRequest::builder()
+            .root(ChunkRanges::bytes(..MAX_META_SIZE))
+            .build(*meta_hash);
+        execute_get(db, connection, request, None, |_| {}).await?;
+        let local = db.remote().local(HashAndFormat::raw(*meta_hash)).await?;
+        anyhow::ensure!(local.is_complete(), "share metadata is too large");
+    }
+    let bytes = db.get_bytes(*meta_hash).await?;
+    serde_json::from_slice(&bytes).context("invalid share metadata")
+}
+
+/// Load the collection of a completely downloaded share, with its metadata.
+///
+/// Raw shares are returned as a collection with a single entry, named like in
+/// the ticket.
+pub async fn load_collection(
+    db: &Store,
+    ticket: &Ticket,
+) -> anyhow::Result<(Collection, ShareMeta)> {
+    let hash = ticket.hash();
+    match ticket.format() {
+        BlobFormat::Raw => {
+            // plain blob tickets don't have a name, fall back to the hash
+            let name = match &ticket.meta.name {
+                Some(name) => name.clone(),
+                None => hash.to_hex().to_string(),
+            };
+            let collection = std::iter::once((name, hash)).collect::<Collection>();
+            Ok((collection, ticket.meta.share.clone()))
+        }
+        BlobFormat::HashSeq => {
+            let collection = Collection::load(hash, db).await?;
+            split_meta(db, collection).await
+        }
+    }
+}
+
+/// A manifest of a share, in a stable format for release pipelines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of the manifest format, currently always 1.
+    pub version: u32,
+    /// Hex encoded hash contained in the ticket, of the collection or of the
+    /// file for single file shares.
+    pub collection: String,
+    /// The entries of the collection, in collection order.
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// A single entry of a [`Manifest`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Name of the entry, with `/` as the path separator.
+    pub name: String,
+    /// Size of the entry in bytes.
+    pub size: u64,
+    /// Hex encoded blake3 hash of the entry.
+    pub blake3: String,
+}
+
+impl Manifest {
+    pub fn new(hash: Hash, collection: &Collection, sizes: &[u64]) -> Self {
+        let entries = collection
+            .iter()
+            .zip(sizes)
+            .map(|((name, hash), size)| ManifestEntry {
+                name: name.clone(),
+                size: *size,
+                blake3: hash.to_hex().to_string(),
+            })
+            .collect();
+        Self {
+            version: 1,
+            collection: hash.to_hex().to_string(),
+            entries,
+        }
+    }
+}
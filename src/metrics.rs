@@ -0,0 +1,260 @@
+//! Prometheus metrics of a provider, see [`ProviderMetrics`].
+//!
+//! The metrics are computed from the events of the provider, and served in
+//! the Prometheus text format by [`serve`]. Node ids are used as labels of the
+//! per peer metrics, so only expose them where node ids may be seen.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use iroh::NodeId;
+use iroh_blobs::provider::Event;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::trace;
+
+/// Upper bound for the size of a request to the metrics endpoint.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Time for a scraper to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Statistics of a single peer.
+#[derive(Debug, Default, Clone, Copy)]
+struct PeerStats {
+    bytes: u64,
+    connections: u64,
+    completed: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    bytes: u64,
+    connections: u64,
+    requests: u64,
+    completed: u64,
+    aborted: u64,
+    /// The node of every open connection.
+    open: BTreeMap<u64, NodeId>,
+    /// The offset of the blob that is being sent, per connection and request.
+    offsets: BTreeMap<(u64, u64), u64>,
+    peers: BTreeMap<NodeId, PeerStats>,
+}
+
+/// Counters of the data a provider served.
+///
+/// Connections are only counted once they are passed to
+/// [`ProviderMetrics::connected`], so rejected connections are not included.
+#[derive(Debug, Default)]
+pub struct ProviderMetrics {
+    state: Mutex<State>,
+}
+
+impl ProviderMetrics {
+    /// Count a connection that was accepted.
+    pub fn connected(&self, connection_id: u64, node_id: NodeId) {
+        let mut state = self.state.lock().unwrap();
+        state.connections += 1;
+        state.open.insert(connection_id, node_id);
+        state.peers.entry(node_id).or_default().connections += 1;
+    }
+
+    /// Update the counters with an event of the provider.
+    ///
+    /// Events of connections that were not passed to [`Self::connected`]
+    /// are ignored.
+    pub fn record(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        match event {
+            Event::ConnectionClosed { connection_id } => {
+                state.open.remove(connection_id);
+                state.offsets.retain(|(id, _), _| id != connection_id);
+            }
+            Event::GetRequestReceived { connection_id, .. }
+            | Event::GetManyRequestReceived { connection_id, .. } => {
+                if state.open.contains_key(connection_id) {
+                    state.requests += 1;
+                }
+            }
+            Event::TransferStarted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                if state.open.contains_key(connection_id) {
+                    state.offsets.insert((*connection_id, *request_id), 0);
+                }
+            }
+            Event::TransferProgress {
+                connection_id,
+                request_id,
+                end_offset,
+                ..
+            } => {
+                let Some(node_id) = state.open.get(connection_id) else {
+                    return;
+                };
+                let Some(offset) = state.offsets.get_mut(&(*connection_id, *request_id)) else {
+                    return;
+                };
+                let sent = end_offset.saturating_sub(*offset);
+                *offset = (*offset).max(*end_offset);
+                state.bytes += sent;
+                state.peers.entry(*node_id).or_default().bytes += sent;
+            }
+            Event::TransferCompleted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                let Some(node_id) = state.open.get(connection_id) else {
+                    return;
+                };
+                state.completed += 1;
+                state.peers.entry(*node_id).or_default().completed += 1;
+                state.offsets.remove(&(*connection_id, *request_id));
+            }
+            Event::TransferAborted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                if state.open.contains_key(connection_id) {
+                    state.aborted += 1;
+                    state.offsets.remove(&(*connection_id, *request_id));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            writeln!(out, "# HELP {name} {help}").ok();
+            writeln!(out, "# TYPE {name} {kind}").ok();
+            writeln!(out, "{name} {value}").ok();
+        };
+        metric(
+            "sendme_bytes_served_total",
+            "counter",
+            "Bytes of data sent to receivers.",
+            state.bytes,
+        );
+        metric(
+            "sendme_connections_active",
+            "gauge",
+            "Connections of receivers that are open.",
+            state.open.len() as u64,
+        );
+        metric(
+            "sendme_connections_total",
+            "counter",
+            "Connections of receivers that were accepted.",
+            state.connections,
+        );
+        metric(
+            "sendme_requests_total",
+            "counter",
+            "Requests of receivers.",
+            state.requests,
+        );
+        metric(
+            "sendme_transfers_completed_total",
+            "counter",
+            "Transfers that were completed.",
+            state.completed,
+        );
+        metric(
+            "sendme_transfers_aborted_total",
+            "counter",
+            "Transfers that were aborted.",
+            state.aborted,
+        );
+        let peers: [(&str, &str, fn(&PeerStats) -> u64); 3] = [
+            (
+                "sendme_peer_bytes_served_total",
+                "Bytes of data sent to a receiver.",
+                |peer| peer.bytes,
+            ),
+            (
+                "sendme_peer_connections_total",
+                "Connections of a receiver that were accepted.",
+                |peer| peer.connections,
+            ),
+            (
+                "sendme_peer_transfers_completed_total",
+                "Transfers to a receiver that were completed.",
+                |peer| peer.completed,
+            ),
+        ];
+        for (name, help, value) in peers {
+            writeln!(out, "# HELP {name} {help}").ok();
+            writeln!(out, "# TYPE {name} counter").ok();
+            for (node_id, peer) in &state.peers {
+                writeln!(out, "{name}{{node_id=\"{node_id}\"}} {}", value(peer)).ok();
+            }
+        }
+        out
+    }
+}
+
+/// Answer a single request of a scraper on `stream`.
+async fn respond(mut stream: TcpStream, metrics: &ProviderMetrics) -> anyhow::Result<()> {
+    // only the request line matters, the headers are read to not reset the
+    // connection of the scraper
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            anyhow::ensure!(request.len() < MAX_REQUEST_SIZE, "request too large");
+            let n = stream.read(&mut buf).await?;
+            anyhow::ensure!(n > 0, "request incomplete");
+            request.extend_from_slice(&buf[..n]);
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_ascii_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics" | "/")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Serve `metrics` on `listener` under `/metrics`, until the future is dropped.
+pub async fn serve(listener: TcpListener, metrics: Arc<ProviderMetrics>) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(cause) => {
+                trace!("failed to accept metrics connection: {cause}");
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(cause) = respond(stream, &metrics).await {
+                trace!("failed to answer metrics request of {addr}: {cause}");
+            }
+        });
+    }
+}
@@ -0,0 +1,251 @@
+//! Connecting to other nodes, see [`NetOptions`].
+
+use std::{
+    fmt::{Display, Formatter},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use iroh::{
+    endpoint::{Connection, ConnectionType},
+    Endpoint, NodeAddr, NodeId, RelayMap, RelayMode, RelayUrl, Watcher,
+};
+use url::Url;
+
+/// How a node binds its endpoint and connects to others.
+#[derive(Debug, Clone, Default)]
+pub struct NetOptions {
+    /// The IPv4 address the endpoint binds to, or a random port.
+    pub magic_ipv4_addr: Option<SocketAddrV4>,
+    /// The IPv6 address the endpoint binds to, or a random port.
+    pub magic_ipv6_addr: Option<SocketAddrV6>,
+    /// The address the endpoint binds to, overriding the one of its family.
+    pub bind_addr: Option<SocketAddr>,
+    /// Only use IPv4, binding IPv6 to localhost.
+    pub ipv4_only: bool,
+    /// Only use IPv6, binding IPv4 to localhost.
+    pub ipv6_only: bool,
+    /// The relays to use.
+    pub relay: RelayModeOption,
+    /// Never use relays, and fail unless connections are direct.
+    pub direct_only: bool,
+    /// The HTTP proxy to connect to relays through, or the one of the
+    /// environment.
+    pub proxy: Option<Url>,
+    /// Use DNS discovery also for tickets with addresses.
+    pub dns_discovery: bool,
+}
+
+impl NetOptions {
+    /// Bind the endpoint of `builder` to the addresses of the options, and
+    /// use the proxy, see [`Self::proxy`].
+    pub fn bind(&self, mut builder: iroh::endpoint::Builder) -> iroh::endpoint::Builder {
+        builder = self.proxy(builder);
+        let (mut v4, mut v6) = (self.magic_ipv4_addr, self.magic_ipv6_addr);
+        match self.bind_addr {
+            Some(SocketAddr::V4(addr)) => v4 = Some(addr),
+            Some(SocketAddr::V6(addr)) => v6 = Some(addr),
+            None => {}
+        }
+        if self.ipv4_only {
+            v6 = Some(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0));
+        }
+        if self.ipv6_only {
+            v4 = Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+        }
+        if let Some(addr) = v4 {
+            builder = builder.bind_addr_v4(addr);
+        }
+        if let Some(addr) = v6 {
+            builder = builder.bind_addr_v6(addr);
+        }
+        builder
+    }
+
+    /// Connect to the relays of `builder` through the proxy of the options,
+    /// or the one of the environment.
+    pub fn proxy(&self, builder: iroh::endpoint::Builder) -> iroh::endpoint::Builder {
+        match &self.proxy {
+            Some(url) => builder.proxy_url(url.clone()),
+            None => builder.proxy_from_env(),
+        }
+    }
+
+    /// Remove the addresses of the address family that is not used from `addr`.
+    pub fn retain_family(&self, addr: &mut NodeAddr) {
+        if self.ipv4_only {
+            addr.direct_addresses.retain(|addr| addr.is_ipv4());
+        }
+        if self.ipv6_only {
+            addr.direct_addresses.retain(|addr| addr.is_ipv6());
+        }
+    }
+
+    /// The relays of the options, or none with [`Self::direct_only`].
+    pub fn relay_mode(&self) -> RelayMode {
+        if self.direct_only {
+            return RelayMode::Disabled;
+        }
+        self.relay.clone().into()
+    }
+
+    /// `addr` without its relay with [`Self::direct_only`], so the endpoint never
+    /// connects through it.
+    pub fn node_addr(&self, mut addr: NodeAddr) -> NodeAddr {
+        if self.direct_only {
+            addr.relay_url = None;
+        }
+        addr
+    }
+
+    /// Connect to `addr` with `alpn`.
+    ///
+    /// With [`Self::direct_only`], the relay of `addr` is left out, and this fails
+    /// if hole punching does not get a direct connection within
+    /// [`DIRECT_CONNECT_TIMEOUT`], see [`NetOptions::ensure_direct`].
+    pub async fn connect(
+        &self,
+        endpoint: &Endpoint,
+        addr: NodeAddr,
+        alpn: &[u8],
+    ) -> anyhow::Result<Connection> {
+        let addr = self.node_addr(addr);
+        let node_id = addr.node_id;
+        if !self.direct_only {
+            return Ok(endpoint.connect(addr, alpn).await?);
+        }
+        let connection = tokio::time::timeout(DIRECT_CONNECT_TIMEOUT, endpoint.connect(addr, alpn))
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "no direct connection to {} within {}s, and --direct-only does not allow relays",
+                    node_id.fmt_short(),
+                    DIRECT_CONNECT_TIMEOUT.as_secs()
+                )
+            })??;
+        self.ensure_direct(endpoint, node_id)?;
+        Ok(connection)
+    }
+
+    /// With [`Self::direct_only`], fail unless the connection to `node_id` is direct.
+    ///
+    /// This is checked before requesting any data, since discovery can still
+    /// add relays for the node.
+    pub fn ensure_direct(&self, endpoint: &Endpoint, node_id: NodeId) -> anyhow::Result<()> {
+        if !self.direct_only {
+            return Ok(());
+        }
+        let conn_type = endpoint
+            .conn_type(node_id)
+            .and_then(|conn_type| conn_type.get().ok());
+        anyhow::ensure!(
+            matches!(conn_type, Some(ConnectionType::Direct(_))),
+            "the connection to {} is not direct, and --direct-only does not allow relays",
+            node_id.fmt_short()
+        );
+        Ok(())
+    }
+}
+
+/// Available options for configuring relays.
+#[derive(Clone, Debug, Default)]
+pub enum RelayModeOption {
+    /// Disables relays altogether.
+    Disabled,
+    /// Uses the default relay servers.
+    #[default]
+    Default,
+    /// Uses custom relay servers by URL.
+    Custom(Vec<RelayUrl>),
+}
+
+impl FromStr for RelayModeOption {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disabled" => Ok(Self::Disabled),
+            "default" => Ok(Self::Default),
+            _ => Ok(Self::Custom(
+                s.split(',')
+                    .map(|url| RelayUrl::from_str(url.trim()))
+                    .collect::<Result<_, _>>()?,
+            )),
+        }
+    }
+}
+
+impl Display for RelayModeOption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => f.write_str("disabled"),
+            Self::Default => f.write_str("default"),
+            Self::Custom(urls) => {
+                let urls = urls.iter().map(|url| url.to_string()).collect::<Vec<_>>();
+                f.write_str(&urls.join(","))
+            }
+        }
+    }
+}
+
+impl From<RelayModeOption> for RelayMode {
+    fn from(value: RelayModeOption) -> Self {
+        match value {
+            RelayModeOption::Disabled => RelayMode::Disabled,
+            RelayModeOption::Default => RelayMode::Default,
+            RelayModeOption::Custom(urls) => RelayMode::Custom(RelayMap::from_iter(urls)),
+        }
+    }
+}
+
+/// How long a receiver with `--direct-only` waits for hole punching to get a
+/// direct connection.
+const DIRECT_CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Classify the NAT from a net report, with the predicted connectivity.
+pub fn nat_type(report: &iroh::net_report::Report) -> (&'static str, &'static str) {
+    if !report.udp_v4 && !report.udp_v6 {
+        return (
+            "udp blocked",
+            "direct connection unlikely, data will be relayed",
+        );
+    }
+    match report.mapping_varies_by_dest_ipv4 {
+        Some(true) => (
+            "symmetric nat",
+            "direct connection unlikely unless the peer has an open nat",
+        ),
+        Some(false) => ("cone nat", "direct connection likely"),
+        None if report.udp_v6 => ("ipv6 only", "direct connection likely with ipv6 peers"),
+        None => ("unknown nat", "direct connection possible"),
+    }
+}
+
+/// Print the NAT type once the first net report is available.
+pub(crate) async fn print_nat_type(endpoint: Endpoint) {
+    let Ok(report) = endpoint.net_report().initialized().await else {
+        return;
+    };
+    let (nat, connectivity) = nat_type(&report);
+    eprintln!("network: {nat}, {connectivity}");
+}
+
+/// Let the endpoint rebind its sockets when the machine wakes up from sleep.
+///
+/// Suspend is detected as a jump of the wall clock between two ticks, since
+/// the monotonic clock does not advance while suspended on all platforms.
+pub(crate) async fn rebind_on_wakeup(endpoint: Endpoint) {
+    const TICK: Duration = Duration::from_secs(1);
+    const MAX_DRIFT: Duration = Duration::from_secs(5);
+    let mut last = SystemTime::now();
+    loop {
+        tokio::time::sleep(TICK).await;
+        let now = SystemTime::now();
+        if now.duration_since(last).unwrap_or_default() > TICK + MAX_DRIFT {
+            eprintln!("woke up from sleep, reconnecting to the network");
+            endpoint.network_change().await;
+        }
+        last = now;
+    }
+}
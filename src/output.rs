@@ -0,0 +1,214 @@
+//! Messages, prompts and the `--json` events of the command line tool.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use indicatif::HumanBytes;
+use iroh_blobs::Hash;
+use serde::Serialize;
+use tokio::select;
+use tracing::error;
+
+/// Whether `--json` was given, see [`emit`].
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Print json events to stdout instead of messages for humans, see [`emit`].
+pub fn set_json_output(json: bool) {
+    JSON_OUTPUT.store(json, Ordering::Relaxed);
+}
+
+/// Whether `--json` was given, see [`set_json_output`].
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Set when stdout carries data, see
+/// [`ReceiveOptions::stdout`](crate::receive::ReceiveOptions::stdout).
+pub(crate) static STDOUT_DATA: AtomicBool = AtomicBool::new(false);
+
+/// Whether stdout is reserved for events or data.
+pub(crate) fn stdout_reserved() -> bool {
+    json_output() || STDOUT_DATA.load(Ordering::Relaxed)
+}
+
+/// Print a message for humans, to stderr with `--json` or `receive --stdout`,
+/// where stdout is reserved for events or data.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::output::stdout_reserved() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use say;
+
+/// An event of the `--json` output.
+///
+/// Hashes and node ids are hex encoded, sizes and offsets are in bytes.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JsonEvent {
+    /// A ticket was created, or updated after an address change.
+    TicketIssued {
+        ticket: String,
+        hash: String,
+        size: u64,
+    },
+    /// A connection to a peer was established.
+    ///
+    /// Providers number the connections, for the transfer events.
+    Connected {
+        node_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        connection_id: Option<u64>,
+    },
+    /// The provider rejected a receiver.
+    Rejected { node_id: String, reason: String },
+    /// The provider started to send a blob.
+    TransferStarted {
+        connection_id: u64,
+        request_id: u64,
+        hash: String,
+        size: u64,
+    },
+    /// The provider sent a blob up to `offset`.
+    TransferProgress {
+        connection_id: u64,
+        request_id: u64,
+        offset: u64,
+    },
+    /// The provider sent all requested data.
+    TransferCompleted { connection_id: u64, request_id: u64 },
+    /// The receiver stopped a request before getting all data.
+    TransferAborted { connection_id: u64, request_id: u64 },
+    /// A receiver disconnected, after getting all data it asked for if
+    /// `completed` is set.
+    Disconnected { connection_id: u64, completed: bool },
+    /// The receiver got `offset` of `total` bytes.
+    DownloadProgress { offset: u64, total: u64 },
+    /// The receiver got and exported the whole share.
+    Completed {
+        files: u64,
+        bytes: u64,
+        elapsed_secs: f64,
+    },
+    /// The command failed.
+    Error { message: String },
+}
+
+/// Print `event` as a line of json to stdout, if `--json` was given.
+pub fn emit(event: JsonEvent) {
+    if !json_output() {
+        return;
+    }
+    match serde_json::to_string(&event) {
+        Ok(line) => println!("{line}"),
+        Err(cause) => error!("failed to serialize event: {cause}"),
+    }
+}
+
+/// How hashes are printed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Hex,
+    Cid,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hex" => Ok(Format::Hex),
+            "cid" => Ok(Format::Cid),
+            _ => Err(anyhow::anyhow!("invalid format")),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Hex => write!(f, "hex"),
+            Format::Cid => write!(f, "cid"),
+        }
+    }
+}
+
+/// Print `hash` in `format`.
+pub fn print_hash(hash: &Hash, format: Format) -> String {
+    match format {
+        Format::Hex => hash.to_hex().to_string(),
+        Format::Cid => hash.to_string(),
+    }
+}
+
+/// Wait until the process is asked to stop, with ctrl-c or, on unix, SIGTERM.
+pub async fn interrupted() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Print the sizes and names of `files` and the targets of `links`, with a
+/// summary line.
+pub(crate) fn print_entries(files: &[(String, u64)], links: &BTreeMap<String, String>) {
+    let mut total = 0;
+    for (name, size) in files {
+        total += size;
+        say!("{size:>12} {name}");
+    }
+    for (name, target) in links {
+        say!("{:>12} {name} -> {target}", "link");
+    }
+    if links.is_empty() {
+        say!("{} files, {}", files.len(), HumanBytes(total));
+    } else {
+        say!(
+            "{} files, {} symlinks, {}",
+            files.len(),
+            links.len(),
+            HumanBytes(total)
+        );
+    }
+}
+
+/// Ask `question` on stderr and read the answer from stdin.
+///
+/// Returns `None` if the answer is empty, to keep the value shown in brackets.
+pub fn prompt(question: &str, current: &str) -> anyhow::Result<Option<String>> {
+    eprint!("{question} [{current}]: ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok((!answer.is_empty()).then(|| answer.to_string()))
+}
+
+/// Ask a yes or no `question`, see [`prompt`].
+pub fn confirm(question: &str, current: bool) -> anyhow::Result<bool> {
+    loop {
+        let answer = prompt(question, if current { "Y/n" } else { "y/N" })?;
+        match answer.as_deref().map(str::to_lowercase).as_deref() {
+            None => return Ok(current),
+            Some("y" | "yes") => return Ok(true),
+            Some("n" | "no") => return Ok(false),
+            Some(_) => eprintln!("please answer yes or no"),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Single file archives of shares, to move them where there is no network.
+//!
+//! An archive is a tar file. Its first entry is a [`PackHeader`] named
+//! [`HEADER_NAME`], followed by every blob of the share as `blobs/<hash>`,
+//! including the hash seq and the collection. The blobs are hashed again when
+//! unpacking, so a damaged or modified archive is detected.
+
+use std::{
+    io::SeekFrom,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use iroh_blobs::{
+    api::{blobs::EncodedItem, Store, TempTag},
+    hashseq::HashSeq,
+    protocol::ChunkRanges,
+    BlobFormat, Hash,
+};
+use n0_future::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+use crate::ticket::Ticket;
+
+/// Name of the entry with the [`PackHeader`].
+pub const HEADER_NAME: &str = "sendme-pack.json";
+
+/// Size of tar headers, and the unit of tar entries.
+const BLOCK_SIZE: u64 = 512;
+
+/// Upper bound for the size of the [`PackHeader`] entry.
+const MAX_HEADER_SIZE: u64 = 1024 * 1024;
+
+/// The first entry of an archive, as json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackHeader {
+    /// Ticket of the share, for its hash, format and metadata.
+    ///
+    /// The address is the one of the node that packed the share, it is not
+    /// needed to unpack it.
+    pub ticket: String,
+}
+
+/// All blobs of the share of `ticket` in `db`, starting with the root.
+pub async fn share_hashes(db: &Store, ticket: &Ticket) -> anyhow::Result<Vec<Hash>> {
+    let mut hashes = vec![ticket.hash()];
+    if ticket.format() == BlobFormat::HashSeq {
+        let bytes = db.get_bytes(ticket.hash()).await?;
+        hashes.extend(HashSeq::try_from(bytes)?.iter());
+    }
+    Ok(hashes)
+}
+
+/// Write the blob `hash` of `db` as a tar entry at `path` to `out`.
+///
+/// `tar` is only used to encode the header, which gets the size of the blob.
+/// The data is streamed from the store, so blobs don't need to fit in memory.
+pub async fn write_tar_blob(
+    db: &Store,
+    hash: Hash,
+    path: &str,
+    header: &mut tar::Header,
+    tar: &mut tar::Builder<Vec<u8>>,
+    out: &mut (impl AsyncWrite + Unpin),
+) -> anyhow::Result<()> {
+    let mut written = 0;
+    let mut stream = db.export_bao(hash, ChunkRanges::all()).stream();
+    while let Some(item) = stream.next().await {
+        match item {
+            EncodedItem::Size(size) => {
+                header.set_size(size);
+                // the reader is empty, so only the header is written
+                tar.append_data(header, path, std::io::empty())?;
+                out.write_all(tar.get_ref()).await?;
+                tar.get_mut().clear();
+            }
+            EncodedItem::Leaf(leaf) => {
+                out.write_all(&leaf.data).await?;
+                written += leaf.data.len() as u64;
+            }
+            EncodedItem::Error(cause) => anyhow::bail!("failed to read {path}: {cause}"),
+            _ => {}
+        }
+    }
+    // pad the data to whole blocks
+    let padding = (BLOCK_SIZE - written % BLOCK_SIZE) % BLOCK_SIZE;
+    out.write_all(&[0u8; BLOCK_SIZE as usize][..padding as usize])
+        .await?;
+    Ok(())
+}
+
+/// Write the share of `ticket` from `db` as an archive to `out`.
+///
+/// Returns the number of blobs that were written.
+pub async fn pack(
+    db: &Store,
+    ticket: &Ticket,
+    out: &mut (impl AsyncWrite + Unpin),
+) -> anyhow::Result<usize> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut tar = tar::Builder::new(Vec::new());
+    let header = serde_json::to_vec(&PackHeader {
+        ticket: ticket.to_string(),
+    })?;
+    let mut entry = tar::Header::new_gnu();
+    entry.set_size(header.len() as u64);
+    entry.set_mode(0o644);
+    entry.set_mtime(now);
+    tar.append_data(&mut entry, HEADER_NAME, header.as_slice())?;
+    out.write_all(tar.get_ref()).await?;
+    tar.get_mut().clear();
+    let hashes = share_hashes(db, ticket).await?;
+    for hash in &hashes {
+        let mut entry = tar::Header::new_gnu();
+        entry.set_mode(0o644);
+        entry.set_mtime(now);
+        let path = format!("blobs/{}", hash.to_hex());
+        write_tar_blob(db, *hash, &path, &mut entry, &mut tar, out).await?;
+    }
+    // the end of archive marker
+    out.write_all(&tar.into_inner()?).await?;
+    out.flush().await?;
+    Ok(hashes.len())
+}
+
+/// Import the share in the archive at `path` into `db`.
+///
+/// Fails if a blob does not match its hash, or if the archive does not contain
+/// the complete share. Returns the ticket from the archive, and the tags that
+/// protect the imported blobs.
+pub async fn unpack(db: &Store, path: &Path) -> anyhow::Result<(Ticket, Vec<TempTag>)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut ticket = None;
+    let mut tags = Vec::new();
+    let mut offset = 0;
+    loop {
+        let mut block = [0u8; BLOCK_SIZE as usize];
+        file.read_exact(&mut block)
+            .await
+            .context("the archive is truncated")?;
+        // the end of archive marker
+        if block.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        let entry = tar::Header::from_byte_slice(&block);
+        let size = entry.entry_size()?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        offset += BLOCK_SIZE;
+        if name == HEADER_NAME {
+            anyhow::ensure!(size <= MAX_HEADER_SIZE, "the archive header is too large");
+            let mut data = vec![0u8; size as usize];
+            file.read_exact(&mut data).await?;
+            let header: PackHeader =
+                serde_json::from_slice(&data).context("invalid archive header")?;
+            ticket = Some(Ticket::from_str(&header.ticket).context("invalid ticket in archive")?);
+        } else if let Some(hex) = name.strip_prefix("blobs/") {
+            let expected = Hash::from_str(hex).with_context(|| format!("invalid entry {name}"))?;
+            let mut data = tokio::fs::File::open(path).await?;
+            data.seek(SeekFrom::Start(offset)).await?;
+            let tag = db
+                .add_stream(ReaderStream::new(data.take(size)))
+                .await
+                .temp_tag()
+                .await?;
+            anyhow::ensure!(
+                *tag.hash() == expected,
+                "blob {hex} of the archive is damaged"
+            );
+            tags.push(tag);
+        }
+        // skip the data of the entry, including the padding
+        offset += size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        file.seek(SeekFrom::Start(offset)).await?;
+    }
+    let ticket = ticket.context("the archive has no header, it was not made by sendme pack")?;
+    let local = db.remote().local(ticket.hash_and_format()).await?;
+    anyhow::ensure!(
+        local.is_complete(),
+        "the archive does not contain all of the share"
+    );
+    Ok((ticket, tags))
+}
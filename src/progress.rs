@@ -0,0 +1,112 @@
+//! Progress bars for the stages of sending and receiving.
+
+use std::time::Duration;
+
+use console::style;
+use indicatif::{ProgressBar, ProgressStyle};
+
+const TICK_MS: u64 = 250;
+
+/// Tracing target of the events behind the progress bars, which are too many
+/// to log by default.
+pub const TRACE_TARGET: &str = "sendme::progress";
+
+pub fn make_import_overall_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len}",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+    pb
+}
+
+pub fn make_import_item_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
+    pb.set_style(
+        ProgressStyle::with_template("{msg}{spinner:.green} XXXX [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb
+}
+
+pub fn make_connect_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.set_style(
+        ProgressStyle::with_template("{prefix}{spinner:.green} Connecting ... [{elapsed_precise}]")
+            .unwrap(),
+    );
+    pb.set_prefix(format!("{} ", style("[1/4]").bold().dim()));
+    pb.enable_steady_tick(Duration::from_millis(TICK_MS));
+    pb
+}
+
+pub fn make_get_sizes_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{prefix}{spinner:.green} Getting sizes... [{elapsed_precise}]",
+        )
+        .unwrap(),
+    );
+    pb.set_prefix(format!("{} ", style("[2/4]").bold().dim()));
+    pb.enable_steady_tick(Duration::from_millis(TICK_MS));
+    pb
+}
+
+pub fn make_download_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
+    pb.set_style(
+        ProgressStyle::with_template("{prefix}{spinner:.green}{msg} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {binary_bytes_per_sec}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb.set_prefix(format!("{} ", style("[3/4]").bold().dim()));
+    pb.set_message("Downloading ...".to_string());
+    pb
+}
+
+pub fn make_fanout_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} hosts",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+    pb.set_message("sending to hosts ");
+    pb
+}
+
+pub fn make_export_overall_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.enable_steady_tick(std::time::Duration::from_millis(TICK_MS));
+    pb.set_style(
+        ProgressStyle::with_template("{prefix}{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} {per_sec}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb.set_prefix(format!("{}", style("[4/4]").bold().dim()));
+    pb
+}
+
+pub fn make_export_item_progress() -> ProgressBar {
+    let pb = ProgressBar::hidden();
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes}",
+        )
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    pb
+}
@@ -0,0 +1,647 @@
+//! Progress, accounting and metrics of the transfers of a provider.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use iroh::NodeId;
+use iroh_blobs::{
+    api::Store,
+    format::collection::Collection,
+    hashseq::HashSeq,
+    protocol::{ChunkRanges, ChunkRangesExt, ChunkRangesSeq},
+    provider::{self, Event},
+    Hash, HashAndFormat,
+};
+use n0_future::task::AbortOnDropHandle;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, trace, warn};
+
+use crate::{
+    audit,
+    ext::{Authorized, RateLimit},
+    manifest::META_NAME,
+    metrics::{serve as serve_metrics, ProviderMetrics},
+    output::{emit, JsonEvent},
+    progress::TRACE_TARGET as PROGRESS_TARGET,
+    ShareMeta,
+};
+
+/// The blobs of a share, to tell receivers that got all of it from probes and
+/// partial downloads, which are not counted as served.
+#[derive(Debug)]
+pub(crate) struct ShareBlobs {
+    /// The hash seq of the share.
+    root: Hash,
+    /// The children of the hash seq.
+    children: Vec<Hash>,
+    /// Children that receivers don't request, since they are inlined into the
+    /// share metadata.
+    inlined: BTreeSet<Hash>,
+    /// The entry of single file shares, which tickets with metadata share as a
+    /// raw blob.
+    single: Option<Hash>,
+}
+
+impl ShareBlobs {
+    pub(crate) async fn load(
+        store: &Store,
+        root: Hash,
+        collection: &Collection,
+        meta: &ShareMeta,
+    ) -> anyhow::Result<Self> {
+        let children = HashSeq::try_from(store.get_bytes(root).await?)?
+            .iter()
+            .collect();
+        let inlined = meta
+            .inline
+            .keys()
+            .filter_map(|hash| Hash::from_str(hash).ok())
+            .collect();
+        let mut entries = collection.iter().filter(|(name, _)| name != META_NAME);
+        let single = match (entries.next(), entries.next()) {
+            (Some((_, hash)), None) => Some(*hash),
+            _ => None,
+        };
+        Ok(Self {
+            root,
+            children,
+            inlined,
+            single,
+        })
+    }
+
+    /// Add the ranges of a request for `hash` to the ranges a receiver got.
+    fn record(&self, got: &mut BTreeMap<Hash, ChunkRanges>, hash: Hash, ranges: &ChunkRangesSeq) {
+        if hash == self.root {
+            let blobs = std::iter::once(self.root).chain(self.children.iter().copied());
+            for (blob, ranges) in blobs.zip(ranges.iter_infinite()) {
+                *got.entry(blob).or_default() |= ranges;
+            }
+        } else if let Some(ranges) = ranges.iter_infinite().next() {
+            *got.entry(hash).or_default() |= ranges;
+        }
+    }
+
+    /// Whether the ranges a receiver got cover all of the share.
+    fn is_complete(&self, got: &BTreeMap<Hash, ChunkRanges>) -> bool {
+        let has = |hash: &Hash| {
+            got.get(hash)
+                .is_some_and(|ranges| *ranges == ChunkRanges::all())
+        };
+        if self.single.as_ref().is_some_and(has) {
+            return true;
+        }
+        has(&self.root)
+            && self
+                .children
+                .iter()
+                .filter(|hash| !self.inlined.contains(hash))
+                .all(has)
+    }
+}
+
+#[derive(Debug)]
+struct PerConnectionProgress {
+    node_id: NodeId,
+    main: ProgressBar,
+    requests: BTreeMap<u64, ProgressBar>,
+    /// Whether any transfer on this connection completed.
+    completed: bool,
+    /// Bytes of data sent on this connection.
+    sent: u64,
+    /// Whether this is the connection of the self test, which is not counted
+    /// as served.
+    self_test: bool,
+}
+
+/// The requests a provider got, for the summary on shutdown.
+#[derive(Debug, Default)]
+pub(crate) struct RequestStats {
+    /// Requests for all of the data of a hash, and for parts of it.
+    by_hash: BTreeMap<Hash, (u64, u64)>,
+    /// Requests for several hashes at once.
+    get_many: u64,
+}
+
+impl RequestStats {
+    fn record(&mut self, hash: Hash, ranges: &ChunkRangesSeq) {
+        let (full, partial) = self.by_hash.entry(hash).or_default();
+        if *ranges == ChunkRangesSeq::all() {
+            *full += 1;
+        } else {
+            *partial += 1;
+        }
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        let by_hash = self.by_hash.values().map(|(full, partial)| full + partial);
+        by_hash.sum::<u64>() + self.get_many
+    }
+
+    /// A line with the number of requests of each type.
+    ///
+    /// Requests for parts of the share `root` are metadata requests, since
+    /// receivers get the entry names and sizes that way.
+    pub(crate) fn summary(&self, root: &Hash) -> String {
+        let full = self.by_hash.values().map(|(full, _)| full).sum::<u64>();
+        let metadata = self.by_hash.get(root).map_or(0, |(_, partial)| *partial);
+        let ranges = self
+            .by_hash
+            .iter()
+            .filter(|(hash, _)| *hash != root)
+            .map(|(_, (_, partial))| partial)
+            .sum::<u64>();
+        let mut summary = format!(
+            "served {} requests: {full} full, {ranges} range, {metadata} metadata",
+            self.total()
+        );
+        if self.get_many > 0 {
+            summary.push_str(&format!(", {} get many", self.get_many));
+        }
+        summary
+    }
+}
+
+/// Show provider progress, counting receivers that got all of one of `shares`
+/// in `served`.
+///
+/// Receivers count once a connection closes after they requested all of a
+/// share, possibly over several connections, and a transfer completed. Only
+/// receivers that are permitted by `auth` are served. The transfers of
+/// receivers other than the self test are counted in `metrics`. The node and
+/// the bytes sent of the connections of served receivers are passed to
+/// `completed`, if given. Receivers other than the self test are held to
+/// `limit`. Returns the requests of all receivers.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn show_provide_progress(
+    mp: MultiProgress,
+    mut recv: mpsc::Receiver<provider::Event>,
+    served: watch::Sender<u64>,
+    shares: watch::Receiver<Vec<ShareBlobs>>,
+    auth: Arc<Authorized>,
+    metrics: Arc<ProviderMetrics>,
+    completed: Option<mpsc::UnboundedSender<(NodeId, u64)>>,
+    limit: RateLimit,
+) -> anyhow::Result<RequestStats> {
+    let mut connections = BTreeMap::new();
+    let mut stats = RequestStats::default();
+    // the ranges each receiver requested so far, until it was served
+    let mut got = BTreeMap::<NodeId, BTreeMap<Hash, ChunkRanges>>::new();
+    while let Some(item) = recv.recv().await {
+        trace!(target: PROGRESS_TARGET, "got event {item:?}");
+        log_provide_event(&item);
+        metrics.record(&item);
+        match item {
+            Event::ClientConnected {
+                connection_id,
+                node_id,
+                permitted,
+            } => {
+                // this is checked before the connection is handled. receivers
+                // give the password on the extension protocol before they
+                // connect for the data.
+                let res = auth.permit(&node_id);
+                permitted.send(res.is_ok()).await.ok();
+                if let Err(reason) = res {
+                    warn!(connection_id, %node_id, %reason, "rejected connection");
+                    audit::record(
+                        node_id,
+                        iroh_blobs::ALPN,
+                        &format!("rejected: {reason}"),
+                        None,
+                    );
+                    emit(JsonEvent::Rejected {
+                        node_id: node_id.to_string(),
+                        reason: reason.to_string(),
+                    });
+                    mp.println(format!("rejected {}: {reason}", node_id.fmt_short()))?;
+                    continue;
+                }
+                audit::record(node_id, iroh_blobs::ALPN, "connected", None);
+                if !auth.is_self_test(&node_id) {
+                    metrics.connected(connection_id, node_id);
+                }
+                emit(JsonEvent::Connected {
+                    node_id: node_id.to_string(),
+                    connection_id: Some(connection_id),
+                });
+                let pb = mp.add(ProgressBar::hidden());
+                pb.set_style(
+                    indicatif::ProgressStyle::default_bar()
+                        .template("{msg}") // Only display the message
+                        .unwrap(),
+                );
+                pb.set_message(format!("{node_id} {connection_id}"));
+                connections.insert(
+                    connection_id,
+                    PerConnectionProgress {
+                        node_id,
+                        main: pb,
+                        requests: BTreeMap::new(),
+                        completed: false,
+                        sent: 0,
+                        self_test: auth.is_self_test(&node_id),
+                    },
+                );
+            }
+            Event::ConnectionClosed { connection_id } => {
+                let Some(connection) = connections.remove(&connection_id) else {
+                    error!("got close for unknown connection {connection_id}");
+                    continue;
+                };
+                for pb in connection.requests.values() {
+                    pb.finish_and_clear();
+                }
+                connection.main.finish_and_clear();
+                let node_id = connection.node_id;
+                if !connections.values().any(|other| other.node_id == node_id) {
+                    limit.forget(&node_id);
+                }
+                emit(JsonEvent::Disconnected {
+                    connection_id,
+                    completed: connection.completed,
+                });
+                let got_all = got
+                    .get(&connection.node_id)
+                    .is_some_and(|got| shares.borrow().iter().any(|share| share.is_complete(got)));
+                if connection.completed && got_all && !connection.self_test {
+                    got.remove(&connection.node_id);
+                    served.send_modify(|served| *served += 1);
+                    if let Some(completed) = &completed {
+                        completed.send((connection.node_id, connection.sent)).ok();
+                    }
+                } else {
+                    auth.release(&connection.node_id);
+                }
+            }
+            Event::GetRequestReceived {
+                connection_id,
+                request_id,
+                hash,
+                ranges,
+            } => {
+                let pb = mp.add(ProgressBar::hidden());
+                pb.set_style(
+                    ProgressStyle::with_template(
+                        "{msg}{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes}",
+                    )?
+                        .progress_chars("#>-"),
+                );
+                pb.set_message(format!("{request_id} {hash}"));
+                let Some(connection) = connections.get_mut(&connection_id) else {
+                    error!("got request for unknown connection {connection_id}");
+                    continue;
+                };
+                if !connection.self_test {
+                    stats.record(hash, &ranges);
+                }
+                let got = got.entry(connection.node_id).or_default();
+                for share in shares.borrow().iter() {
+                    share.record(got, hash, &ranges);
+                }
+                connection.requests.insert(request_id, pb);
+            }
+            Event::GetManyRequestReceived { connection_id, .. } => {
+                if connections
+                    .get(&connection_id)
+                    .is_some_and(|connection| !connection.self_test)
+                {
+                    stats.get_many += 1;
+                }
+            }
+            Event::TransferStarted {
+                connection_id,
+                request_id,
+                hash,
+                size,
+                index,
+            } => {
+                let Some(connection) = connections.get_mut(&connection_id) else {
+                    error!("got request for unknown connection {connection_id}");
+                    continue;
+                };
+                let Some(pb) = connection.requests.get_mut(&request_id) else {
+                    error!("got update for unknown request {request_id}");
+                    continue;
+                };
+                pb.set_message(format!("    {} {} {}", request_id, index, hash.fmt_short()));
+                // the offsets of the progress events are for each blob
+                pb.set_position(0);
+                pb.set_length(size);
+                emit(JsonEvent::TransferStarted {
+                    connection_id,
+                    request_id,
+                    hash: hash.to_hex().to_string(),
+                    size,
+                });
+            }
+            Event::TransferProgress {
+                connection_id,
+                request_id,
+                end_offset,
+                ..
+            } => {
+                let Some(connection) = connections.get_mut(&connection_id) else {
+                    error!("got request for unknown connection {connection_id}");
+                    continue;
+                };
+                let Some(pb) = connection.requests.get_mut(&request_id) else {
+                    error!("got update for unknown request {request_id}");
+                    continue;
+                };
+                let bytes = end_offset.saturating_sub(pb.position());
+                connection.sent += bytes;
+                pb.set_position(end_offset);
+                let node_id = connection.node_id;
+                if !connection.self_test && !limit.sent(node_id, bytes) {
+                    mp.println(format!(
+                        "closed {}: exceeds the rate limit",
+                        node_id.fmt_short()
+                    ))?;
+                }
+                emit(JsonEvent::TransferProgress {
+                    connection_id,
+                    request_id,
+                    offset: end_offset,
+                });
+            }
+            Event::TransferCompleted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                emit(JsonEvent::TransferCompleted {
+                    connection_id,
+                    request_id,
+                });
+                if let Some(msg) = connections.get_mut(&connection_id) {
+                    msg.completed = true;
+                    if let Some(pb) = msg.requests.remove(&request_id) {
+                        // todo: show stats and hide after a delay
+                        pb.finish_and_clear();
+                    }
+                }
+            }
+            Event::TransferAborted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                emit(JsonEvent::TransferAborted {
+                    connection_id,
+                    request_id,
+                });
+                if let Some(msg) = connections.get_mut(&connection_id) {
+                    if let Some(pb) = msg.requests.remove(&request_id) {
+                        // todo: show stats and hide after a delay
+                        pb.finish_and_clear();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(stats)
+}
+
+/// Log the connections and requests of a provider, with the ids as fields
+/// for `--log-format json`.
+fn log_provide_event(event: &provider::Event) {
+    match event {
+        Event::ClientConnected {
+            connection_id,
+            node_id,
+            ..
+        } => debug!(connection_id, %node_id, "client connected"),
+        Event::ConnectionClosed { connection_id } => debug!(connection_id, "connection closed"),
+        Event::GetRequestReceived {
+            connection_id,
+            request_id,
+            hash,
+            ..
+        } => debug!(connection_id, request_id, %hash, "get request"),
+        Event::GetManyRequestReceived { connection_id, .. } => {
+            debug!(connection_id, "get many request")
+        }
+        Event::TransferCompleted {
+            connection_id,
+            request_id,
+            ..
+        } => debug!(connection_id, request_id, "transfer completed"),
+        Event::TransferAborted {
+            connection_id,
+            request_id,
+            ..
+        } => warn!(connection_id, request_id, "transfer aborted"),
+        _ => {}
+    }
+}
+
+/// Most bytes of file data that a request can ask for with `sendme serve
+/// --verify-only`, the size up to which files are inlined into the share
+/// metadata anyway.
+const PROBE_SIZE: u64 = 16 * 1024;
+
+/// Upper bound for the blobs that `sendme serve --verify-only` loads to check
+/// if they are a collection, see [`probe_size`].
+const MAX_PROBE_SEQ_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Bytes of `ranges` of a blob of unknown size, or `None` if they are
+/// unbounded.
+///
+/// The last chunk is open ended, but since that is how sizes are verified it
+/// counts as a single chunk.
+fn probe_bytes(ranges: &ChunkRanges) -> Option<u64> {
+    let boundaries = ranges.boundaries();
+    let (bounded, open) = match boundaries.split_last() {
+        Some((start, rest)) if boundaries.len() % 2 == 1 => (rest, Some(*start)),
+        _ => (boundaries, None),
+    };
+    let mut chunks = bounded
+        .chunks(2)
+        .map(|range| range[1].0.saturating_sub(range[0].0))
+        .fold(0u64, u64::saturating_add);
+    if let Some(start) = open {
+        if ChunkRanges::from(start..) != ChunkRanges::last_chunk() {
+            return None;
+        }
+        chunks = chunks.saturating_add(1);
+    }
+    Some(chunks.saturating_mul(1024))
+}
+
+/// Bytes of file data that a get request for `hash` with `ranges` asks for,
+/// or `None` if that is unbounded.
+///
+/// For collections, the list of hashes, the names and the metadata entry are
+/// not counted, so verifiers can list the entries and check the signature.
+async fn probe_size(
+    store: &Store,
+    hash: Hash,
+    ranges: &ChunkRangesSeq,
+) -> anyhow::Result<Option<u64>> {
+    let mut ranges = ranges.iter_infinite();
+    let root = ranges.next().cloned().unwrap_or_default();
+    let local = store.remote().local(HashAndFormat::raw(hash)).await?;
+    let size = local.local_bytes();
+    let collection = if local.is_complete() && size % 32 == 0 && size <= MAX_PROBE_SEQ_SIZE {
+        Collection::load(hash, store).await.ok()
+    } else {
+        None
+    };
+    let Some(collection) = collection else {
+        return Ok(probe_bytes(&root));
+    };
+    let meta = collection
+        .iter()
+        .find(|(name, _)| name == META_NAME)
+        .map(|(_, hash)| *hash);
+    let children = HashSeq::try_from(store.get_bytes(hash).await?)?;
+    let mut total = 0u64;
+    // the first child are the names of the entries
+    for (child, ranges) in children.iter().zip(ranges).skip(1) {
+        if Some(child) == meta {
+            continue;
+        }
+        let Some(bytes) = probe_bytes(ranges) else {
+            return Ok(None);
+        };
+        total = total.saturating_add(bytes);
+    }
+    Ok(Some(total))
+}
+
+/// Answer the connection events of `sendme serve`, which serves everyone,
+/// hold receivers to `limit` and count the transfers in `metrics`, if given.
+///
+/// With `verify_only`, the store of `sendme serve --verify-only`, the
+/// connections of receivers whose requests are larger than [`PROBE_SIZE`] are
+/// closed.
+pub(crate) async fn record_provide_events(
+    mut recv: mpsc::Receiver<provider::Event>,
+    metrics: Option<Arc<ProviderMetrics>>,
+    limit: RateLimit,
+    verify_only: Option<Store>,
+) {
+    // the receiver of each connection, and the offset of each of its requests
+    let mut connections = BTreeMap::<u64, (NodeId, BTreeMap<u64, u64>)>::new();
+    while let Some(item) = recv.recv().await {
+        trace!(target: PROGRESS_TARGET, "got event {item:?}");
+        log_provide_event(&item);
+        if let Some(metrics) = &metrics {
+            if let Event::ClientConnected {
+                connection_id,
+                node_id,
+                ..
+            } = &item
+            {
+                metrics.connected(*connection_id, *node_id);
+            } else {
+                metrics.record(&item);
+            }
+        }
+        match item {
+            Event::ClientConnected {
+                connection_id,
+                node_id,
+                permitted,
+            } => {
+                permitted.send(true).await.ok();
+                connections.insert(connection_id, (node_id, BTreeMap::new()));
+            }
+            Event::ConnectionClosed { connection_id } => {
+                let Some((node_id, _)) = connections.remove(&connection_id) else {
+                    continue;
+                };
+                if !connections.values().any(|(other, _)| *other == node_id) {
+                    limit.forget(&node_id);
+                }
+            }
+            Event::GetRequestReceived {
+                connection_id,
+                hash,
+                ranges,
+                ..
+            } => {
+                let Some((node_id, _)) = connections.get(&connection_id) else {
+                    continue;
+                };
+                limit.requested(*node_id, hash);
+                let Some(store) = &verify_only else {
+                    continue;
+                };
+                match probe_size(store, hash, &ranges).await {
+                    Ok(Some(size)) if size <= PROBE_SIZE => {}
+                    res => {
+                        if let Err(cause) = res {
+                            warn!("failed to check the request for {hash}: {cause:#}");
+                        }
+                        eprintln!(
+                            "refused a download of {hash} by {}, only probes are served",
+                            node_id.fmt_short()
+                        );
+                        limit.close(node_id, b"only probes are served");
+                    }
+                }
+            }
+            Event::TransferStarted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                if let Some((_, offsets)) = connections.get_mut(&connection_id) {
+                    // the offsets of the progress events are for each blob
+                    offsets.insert(request_id, 0);
+                }
+            }
+            Event::TransferProgress {
+                connection_id,
+                request_id,
+                end_offset,
+                ..
+            } => {
+                let Some((node_id, offsets)) = connections.get_mut(&connection_id) else {
+                    continue;
+                };
+                let offset = offsets.entry(request_id).or_default();
+                let bytes = end_offset.saturating_sub(*offset);
+                *offset = end_offset;
+                limit.sent(*node_id, bytes);
+            }
+            Event::TransferCompleted {
+                connection_id,
+                request_id,
+                ..
+            }
+            | Event::TransferAborted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                if let Some((_, offsets)) = connections.get_mut(&connection_id) {
+                    offsets.remove(&request_id);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Serve the metrics of a provider on `addr`, until the handle is dropped.
+pub(crate) async fn start_metrics(
+    addr: SocketAddr,
+    metrics: Arc<ProviderMetrics>,
+) -> anyhow::Result<AbortOnDropHandle<()>> {
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to listen for metrics on {addr}"))?;
+    Ok(AbortOnDropHandle::new(n0_future::task::spawn(
+        serve_metrics(listener, metrics),
+    )))
+}
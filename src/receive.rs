@@ -0,0 +1,1598 @@
+//! Downloading shares and exporting them, see [`receive`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use console::style;
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressDrawTarget};
+use iroh::{
+    discovery::dns::DnsDiscovery,
+    endpoint::{Connection, TransportConfig, VarInt},
+    Endpoint, NodeId, RelayMode, SecretKey, Watcher,
+};
+use iroh_blobs::{
+    api::{blobs::EncodedItem, Store},
+    format::collection::Collection,
+    get::request::{get_hash_seq_and_sizes, get_unverified_size, get_verified_size},
+    hashseq::HashSeq,
+    net_protocol::Blobs,
+    protocol::{ChunkRanges, ChunkRangesExt, GetRequest},
+    store::mem::MemStore,
+    ticket::BlobTicket,
+    BlobFormat, Hash, HashAndFormat,
+};
+use n0_future::{task::AbortOnDropHandle, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    select,
+    sync::{mpsc, oneshot},
+};
+use tracing::{error, trace};
+
+use crate::{
+    audit,
+    code::{redeem, ShortCode},
+    export::{check_symlink_target, export, get_export_path, ExportOptions, Limits, OnConflict},
+    ext::{
+        chat, chat_input, receive_updates, Extensions, ADDR_FEATURE, CHAT_FEATURE,
+        EXT_HELLO_TIMEOUT, RATE_FEATURE,
+    },
+    filter::EntryFilter,
+    hook::{notify_desktop, run_hook},
+    local::find as find_local,
+    manifest::{add_inline, get_share_meta, load_collection, verify_manifest, META_NAME},
+    net::{print_nat_type, NetOptions},
+    output::{
+        confirm, emit, interrupted, json_output, print_entries, print_hash, prompt, say, Format,
+        JsonEvent, STDOUT_DATA,
+    },
+    pack::write_tar_blob,
+    progress::{make_connect_progress, make_download_progress, make_get_sizes_progress},
+    transfer::{
+        execute_get, get_collection, get_pipelined, get_swarm, is_connection_error, load_store,
+        reconnect, show_get_error, zstd_enabled, Pacer, Skipper, SMALL_SHARE_SIZE,
+    },
+    ShareMeta, Ticket,
+};
+
+/// What [`receive`] downloads and how.
+///
+/// The fields match the options of `sendme receive`, which documents them in
+/// detail.
+#[derive(Debug)]
+pub struct ReceiveOptions {
+    /// The ticket of the share, unless it is found with `code` or `local`.
+    pub ticket: Option<Ticket>,
+    /// Directory to export the share into, or the current directory.
+    pub output: Option<PathBuf>,
+    /// Don't ask before downloading.
+    pub yes: bool,
+    /// Get the ticket from the sender of this short code.
+    pub code: Option<ShortCode>,
+    /// Find the share with this name on the local network, or any share if
+    /// it is empty.
+    pub local: Option<String>,
+    /// Resume the download of this checkpoint file.
+    pub resume: Option<PathBuf>,
+    /// Directory for the store of the download, instead of `output`.
+    pub data_dir: Option<PathBuf>,
+    /// Only write the first bytes of an entry to stdout.
+    pub preview: Option<PreviewSpec>,
+    /// Only list the entries of the share.
+    pub list: bool,
+    /// Fail unless the share is signed by its sender.
+    pub require_signed: bool,
+    /// Fail unless the share is from this node.
+    pub expect_identity: Option<NodeId>,
+    /// Provide the share after the download until interrupted.
+    pub mirror: bool,
+    /// Number of requests for entries to keep in flight.
+    pub pipeline_depth: usize,
+    /// How long to try to reconnect to the sender, in seconds.
+    pub reconnect_timeout: u64,
+    /// Allow skipping the current entry with a key press.
+    pub interactive: bool,
+    /// Other senders of the same data.
+    pub from: Vec<Ticket>,
+    /// Write timing information about the download to this file.
+    pub debug_report: Option<PathBuf>,
+    /// A short message to send to the sender.
+    pub message: Option<String>,
+    /// Send the lines of stdin as messages to the sender.
+    pub chat: bool,
+    /// The password the sender asks for.
+    pub password: Option<String>,
+    /// Size of the receive window of the connection, at least
+    /// [`RECV_BUFFER_MIN`].
+    pub recv_buffer: Option<u32>,
+    /// Back off when the network gets congested.
+    pub nice: bool,
+    /// Bytes per second to download at most.
+    pub limit_rate: Option<u64>,
+    /// Globs of the entries to get.
+    pub only: Vec<String>,
+    /// Globs of the entries to leave out.
+    pub skip: Vec<String>,
+    /// Get the entries in collection order, ignoring the order the sender
+    /// asks for.
+    pub in_order: bool,
+    /// Limits on the share.
+    pub limits: Limits,
+    /// What to do with entries that already exist.
+    pub on_conflict: OnConflict,
+    /// Choose the entries to export after the download.
+    pub review: bool,
+    /// Delete overwritten files instead of keeping a backup.
+    pub no_backup: bool,
+    /// Don't restore the modification times of the entries.
+    pub no_times: bool,
+    /// Open the share with the default application once it is exported.
+    pub open: bool,
+    /// Command to run once the download is complete.
+    pub on_complete: Option<String>,
+    /// Show a desktop notification when the download completed or failed.
+    #[cfg(feature = "notifications")]
+    pub notify: bool,
+    /// Write the single file of the share to stdout instead of exporting it.
+    pub stdout: bool,
+    /// Write the share to stdout as a tar stream instead of exporting it.
+    pub tar: bool,
+    /// The key of the receiving node.
+    pub secret_key: SecretKey,
+    /// Record the connections in this audit log, see [`audit::open_log`].
+    pub audit_log: Option<PathBuf>,
+    /// How hashes are printed.
+    pub format: Format,
+    /// How much to print about the share.
+    pub verbose: u8,
+    /// Don't show progress bars.
+    pub no_progress: bool,
+    /// Decompress the entries, even if the sender did not mark them as
+    /// compressed.
+    pub zstd: bool,
+    /// How to bind the endpoint and connect to other nodes.
+    pub net: NetOptions,
+}
+
+/// The smallest allowed [`ReceiveOptions::recv_buffer`], a few QUIC packets.
+pub const RECV_BUFFER_MIN: i64 = 16 * 1024;
+
+/// The entry and number of bytes for [`ReceiveOptions::preview`].
+#[derive(Debug, Clone)]
+pub struct PreviewSpec {
+    pub name: String,
+    pub bytes: u64,
+}
+
+impl PreviewSpec {
+    const DEFAULT_BYTES: u64 = 16 * 1024;
+}
+
+impl FromStr for PreviewSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // names may contain colons, so only split if the suffix is a number
+        if let Some((name, bytes)) = s.rsplit_once(':') {
+            if let Ok(bytes) = bytes.parse() {
+                return Ok(Self {
+                    name: name.to_string(),
+                    bytes,
+                });
+            }
+        }
+        Ok(Self {
+            name: s.to_string(),
+            bytes: Self::DEFAULT_BYTES,
+        })
+    }
+}
+
+/// Check that a share of `size` bytes, as verified with the provider, can be
+/// received into memory if `in_memory` is set.
+///
+/// Whether to receive into memory is decided by the size in the ticket, which
+/// the sender only claims.
+fn ensure_fits_memory(in_memory: bool, size: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !in_memory || size <= SMALL_SHARE_SIZE,
+        "the share is {}, more than its ticket claims",
+        HumanBytes(size)
+    );
+    Ok(())
+}
+
+/// How long [`ReceiveOptions::local`] waits for a matching announcement.
+const LOCAL_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Show the progress of a download of `total_size` bytes, of which
+/// `local_size` were already there, from the offsets received on `recv`.
+pub async fn show_download_progress(
+    mp: MultiProgress,
+    mut recv: mpsc::Receiver<u64>,
+    local_size: u64,
+    total_size: u64,
+    recorder: Option<Arc<DebugRecorder>>,
+) -> anyhow::Result<()> {
+    // limit the rate of json events, the progress bar does this by itself
+    const JSON_INTERVAL: Duration = Duration::from_millis(100);
+    let op = mp.add(make_download_progress());
+    op.set_length(total_size);
+    let mut last_event = None::<Instant>;
+    while let Some(offset) = recv.recv().await {
+        if let Some(recorder) = &recorder {
+            recorder.record_progress(offset);
+        }
+        op.set_position(local_size + offset);
+        if last_event.map_or(true, |t| t.elapsed() >= JSON_INTERVAL) {
+            last_event = Some(Instant::now());
+            emit(JsonEvent::DownloadProgress {
+                offset: local_size + offset,
+                total: total_size,
+            });
+        }
+    }
+    op.finish_and_clear();
+    Ok(())
+}
+
+/// State of an interrupted download, written next to the partial data.
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    /// The ticket of the download.
+    ticket: String,
+    /// Children of the collection that are complete.
+    completed: Vec<Hash>,
+    /// Children of the collection that are partially present, with the number
+    /// of verified bytes.
+    partial: Vec<(Hash, u64)>,
+}
+
+impl Checkpoint {
+    /// Path of the checkpoint file for a download into `dir`.
+    fn path(dir: &Path, hash: &Hash) -> PathBuf {
+        dir.join(format!(".sendme-recv-{}.checkpoint", hash.to_hex()))
+    }
+
+    /// Record the state of the download of `ticket` in `db`.
+    async fn new(db: &Store, ticket: &Ticket) -> anyhow::Result<Self> {
+        let mut completed = Vec::new();
+        let mut partial = Vec::new();
+        let hashes = match ticket.format() {
+            // a raw share is its own only child
+            BlobFormat::Raw => vec![ticket.hash()],
+            // if the hash seq itself is not complete, there is no progress on the children
+            BlobFormat::HashSeq => match db.get_bytes(ticket.hash()).await {
+                Ok(bytes) => HashSeq::try_from(bytes)?.iter().collect(),
+                Err(_) => Vec::new(),
+            },
+        };
+        for hash in hashes {
+            let local = db.remote().local(HashAndFormat::raw(hash)).await?;
+            if local.is_complete() {
+                completed.push(hash);
+            } else if local.local_bytes() > 0 {
+                partial.push((hash, local.local_bytes()));
+            }
+        }
+        Ok(Self {
+            ticket: ticket.to_string(),
+            completed,
+            partial,
+        })
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("failed to read checkpoint {}", path.display()))?;
+        serde_json::from_slice(&data).context("invalid checkpoint")
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Write a checkpoint for an interrupted download and tell the user how to resume.
+///
+/// Returns false if nothing was downloaded yet. Then no checkpoint is written,
+/// and the store can be removed.
+async fn write_checkpoint(db: &Store, ticket: &Ticket, path: &Path) -> bool {
+    let res = async {
+        let checkpoint = Checkpoint::new(db, ticket).await?;
+        if !checkpoint.completed.is_empty() || !checkpoint.partial.is_empty() {
+            checkpoint.save(path)?;
+        }
+        anyhow::Ok(checkpoint)
+    }
+    .await;
+    match res {
+        Ok(checkpoint) if checkpoint.completed.is_empty() && checkpoint.partial.is_empty() => false,
+        Ok(checkpoint) => {
+            eprintln!(
+                "download interrupted with {} files complete and {} partial",
+                checkpoint.completed.len(),
+                checkpoint.partial.len()
+            );
+            eprintln!("resume with sendme receive --resume {}", path.display());
+            true
+        }
+        Err(cause) => {
+            error!("failed to write checkpoint: {cause}");
+            true
+        }
+    }
+}
+
+/// Minimum time between two progress records in a [`DebugReport`].
+const DEBUG_PROGRESS_INTERVAL_MS: u64 = 100;
+
+/// Minimum time without progress that is recorded as a stall in a [`DebugReport`].
+const DEBUG_STALL_THRESHOLD_MS: u64 = 500;
+
+/// Timing information about a download, see [`ReceiveOptions::debug_report`].
+///
+/// All times are in milliseconds since the start of the download.
+#[derive(Debug, Default, Serialize)]
+struct DebugReport {
+    /// Bytes read over time, at most every [`DEBUG_PROGRESS_INTERVAL_MS`].
+    progress: Vec<DebugProgress>,
+    /// Periods without any progress, which usually indicate packet loss.
+    stalls: Vec<DebugStall>,
+    /// Changes of the path to the sender, e.g. from relayed to direct.
+    path_changes: Vec<DebugPathChange>,
+    /// Connection statistics, sampled every second.
+    samples: Vec<DebugSample>,
+    #[serde(skip)]
+    last_progress_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct DebugProgress {
+    ms: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct DebugStall {
+    ms: u64,
+    duration_ms: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct DebugPathChange {
+    ms: u64,
+    path: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DebugSample {
+    ms: u64,
+    rtt_ms: u64,
+    lost_packets: u64,
+    congestion_events: u64,
+}
+
+/// Collects a [`DebugReport`] while downloading.
+#[derive(Debug)]
+pub struct DebugRecorder {
+    start: Instant,
+    report: std::sync::Mutex<DebugReport>,
+    connection: std::sync::Mutex<Option<Connection>>,
+}
+
+impl DebugRecorder {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            report: Default::default(),
+            connection: Default::default(),
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    /// Set the connection to sample statistics from, e.g. after a reconnect.
+    fn set_connection(&self, connection: Connection) {
+        *self.connection.lock().unwrap() = Some(connection);
+    }
+
+    fn record_progress(&self, bytes: u64) {
+        let ms = self.elapsed_ms();
+        let mut report = self.report.lock().unwrap();
+        if let Some(last) = report.last_progress_ms {
+            if ms - last >= DEBUG_STALL_THRESHOLD_MS {
+                report.stalls.push(DebugStall {
+                    ms: last,
+                    duration_ms: ms - last,
+                    bytes,
+                });
+            }
+        }
+        report.last_progress_ms = Some(ms);
+        let due = report
+            .progress
+            .last()
+            .map_or(true, |last| ms - last.ms >= DEBUG_PROGRESS_INTERVAL_MS);
+        if due {
+            report.progress.push(DebugProgress { ms, bytes });
+        }
+    }
+
+    /// Sample the connection path and statistics every second, forever.
+    async fn sample(self: Arc<Self>, endpoint: Endpoint, node_id: NodeId) {
+        let mut last_path = None;
+        loop {
+            let ms = self.elapsed_ms();
+            let path = endpoint
+                .conn_type(node_id)
+                .and_then(|conn_type| conn_type.get().ok())
+                .map(|conn_type| format!("{conn_type:?}"));
+            let connection = self.connection.lock().unwrap().clone();
+            {
+                let mut report = self.report.lock().unwrap();
+                if let Some(path) = &path {
+                    if last_path.as_ref() != Some(path) {
+                        report.path_changes.push(DebugPathChange {
+                            ms,
+                            path: path.clone(),
+                        });
+                    }
+                }
+                if let Some(connection) = connection {
+                    let stats = connection.stats();
+                    report.samples.push(DebugSample {
+                        ms,
+                        rtt_ms: connection.rtt().as_millis() as u64,
+                        lost_packets: stats.path.lost_packets,
+                        congestion_events: stats.path.congestion_events,
+                    });
+                }
+            }
+            if path.is_some() {
+                last_path = path;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let report = self.report.lock().unwrap();
+        std::fs::write(path, serde_json::to_vec_pretty(&*report)?)
+            .with_context(|| format!("failed to write debug report {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Fetch the first bytes of a single entry of a share and write them to stdout.
+async fn preview(
+    db: &Store,
+    endpoint: &Endpoint,
+    net: &NetOptions,
+    ticket: &Ticket,
+    spec: &PreviewSpec,
+) -> anyhow::Result<()> {
+    let connection = net
+        .connect(
+            endpoint,
+            ticket.node_addr().clone(),
+            iroh_blobs::protocol::ALPN,
+        )
+        .await?;
+    let hash = match ticket.format() {
+        BlobFormat::Raw => {
+            if let Some(name) = &ticket.meta.name {
+                anyhow::ensure!(
+                    *name == spec.name,
+                    "no entry named {} in the share",
+                    spec.name
+                );
+            }
+            ticket.hash()
+        }
+        BlobFormat::HashSeq => {
+            let collection = get_collection(db, connection.clone(), ticket.hash(), &[]).await?;
+            let (_, hash) = collection
+                .iter()
+                .find(|(name, _)| *name == spec.name)
+                .with_context(|| format!("no entry named {} in the share", spec.name))?;
+            *hash
+        }
+    };
+    let ranges = ChunkRanges::bytes(..spec.bytes);
+    let request = GetRequest::builder().root(ranges.clone()).build(hash);
+    execute_get(db, connection, request, None, |_| {}).await?;
+    let mut data = Vec::new();
+    let mut stream = db.export_bao(hash, ranges).stream();
+    while let Some(item) = stream.next().await {
+        match item {
+            EncodedItem::Leaf(leaf) => data.extend_from_slice(&leaf.data),
+            EncodedItem::Error(cause) => anyhow::bail!("failed to read preview: {cause}"),
+            _ => {}
+        }
+    }
+    // the request is rounded up to whole chunks
+    data.truncate(spec.bytes as usize);
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(&data).await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+/// List the entries of the share of `ticket`, see [`ReceiveOptions::list`].
+///
+/// Only the names and the metadata of the share are downloaded into `db`.
+async fn list(
+    db: &Store,
+    endpoint: &Endpoint,
+    net: &NetOptions,
+    ticket: &Ticket,
+    password: Option<String>,
+    filter: &EntryFilter,
+    limits: &Limits,
+) -> anyhow::Result<()> {
+    let (entries, meta) = fetch_entries(db, endpoint, net, ticket, password, limits).await?;
+    let files = entries
+        .into_iter()
+        .filter(|(name, _, _)| filter.matches(name))
+        .map(|(name, _, size)| (name, size))
+        .collect::<Vec<_>>();
+    let links = meta
+        .symlinks
+        .into_iter()
+        .filter(|(name, _)| filter.matches(name))
+        .collect();
+    print_entries(&files, &links);
+    Ok(())
+}
+
+/// The names, hashes and sizes of the entries of the share of `ticket`, and
+/// the metadata of the share, fetched from the sender.
+///
+/// Only the names and the metadata of the share are downloaded into `db`. With
+/// a `password`, the sender is greeted on the extension protocol first, so
+/// senders started with `--password` let the connection in. The connections
+/// honor [`NetOptions::direct_only`].
+pub async fn fetch_entries(
+    db: &Store,
+    endpoint: &Endpoint,
+    net: &NetOptions,
+    ticket: &Ticket,
+    password: Option<String>,
+    limits: &Limits,
+) -> anyhow::Result<(Vec<(String, Hash, u64)>, ShareMeta)> {
+    // the sender keeps the receiver authorized while this is open
+    let _extensions = match password {
+        Some(password) => {
+            let addr = net.node_addr(ticket.node_addr().clone());
+            let (connection, hello, _, _) =
+                Extensions::connect(endpoint, addr, Some(password)).await?;
+            if let Some(reason) = hello.rejected {
+                anyhow::bail!("the sender rejected this receiver: {reason}");
+            }
+            Some(connection)
+        }
+        None => None,
+    };
+    let connection = net
+        .connect(
+            endpoint,
+            ticket.node_addr().clone(),
+            iroh_blobs::protocol::ALPN,
+        )
+        .await?;
+    match ticket.format() {
+        BlobFormat::Raw => {
+            let (size, _) = get_unverified_size(&connection, &ticket.hash())
+                .await
+                .map_err(show_get_error)?;
+            let name = match &ticket.meta.name {
+                Some(name) => name.clone(),
+                None => ticket.hash().to_hex().to_string(),
+            };
+            let entries = vec![(name, ticket.hash(), size)];
+            Ok((entries, ticket.meta.share.clone()))
+        }
+        BlobFormat::HashSeq => {
+            let (hash_seq, sizes) =
+                get_hash_seq_and_sizes(&connection, &ticket.hash(), 1024 * 1024 * 32, None)
+                    .await
+                    .map_err(show_get_error)?;
+            // the sizes are of the hash seq, the collection metadata and the entries
+            limits.check_entries(sizes.len().saturating_sub(2) as u64)?;
+            let collection = get_collection(db, connection.clone(), ticket.hash(), &sizes).await?;
+            limits.check(&collection)?;
+            let meta = get_share_meta(db, connection, &collection).await?;
+            let sizes = hash_seq
+                .iter()
+                .zip(sizes.iter().skip(1).copied())
+                .collect::<BTreeMap<_, _>>();
+            let entries = collection
+                .iter()
+                .filter(|(name, _)| name != META_NAME)
+                .map(|(name, hash)| {
+                    let size = sizes.get(hash).copied().unwrap_or_default();
+                    (name.clone(), *hash, size)
+                })
+                .collect();
+            Ok((entries, meta))
+        }
+    }
+}
+
+/// Write the blob `hash` from the store to stdout.
+async fn write_to_stdout(db: &Store, hash: Hash) -> anyhow::Result<()> {
+    let mut stdout = tokio::io::stdout();
+    // leaves are in order, since the whole blob is exported
+    let mut stream = db.export_bao(hash, ChunkRanges::all()).stream();
+    while let Some(item) = stream.next().await {
+        match item {
+            EncodedItem::Leaf(leaf) => stdout.write_all(&leaf.data).await?,
+            EncodedItem::Error(cause) => anyhow::bail!("failed to read {hash}: {cause}"),
+            _ => {}
+        }
+    }
+    stdout.flush().await?;
+    Ok(())
+}
+
+/// Write the entries of `collection` from the store to stdout, as a tar stream.
+///
+/// The tar builder only writes the headers, the data is streamed from the
+/// store in between. Symlinks of the share are added as links, unless they
+/// point outside of the share.
+async fn write_tar_to_stdout(
+    db: &Store,
+    collection: &Collection,
+    meta: &ShareMeta,
+) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut stdout = tokio::io::stdout();
+    let mut tar = tar::Builder::new(Vec::new());
+    for (name, hash) in collection.iter() {
+        // reject the same names as when exporting
+        get_export_path(Path::new(""), name)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_mtime(meta.mtimes.get(name).copied().unwrap_or(now));
+        write_tar_blob(db, *hash, name, &mut header, &mut tar, &mut stdout).await?;
+    }
+    for (name, link_target) in &meta.symlinks {
+        if let Err(cause) = check_symlink_target(name, link_target, &meta.symlinks) {
+            eprintln!("skipping symlink {name}: {cause}");
+            continue;
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        header.set_mtime(now);
+        tar.append_link(&mut header, name, link_target)?;
+    }
+    // the links and the end of archive marker
+    stdout.write_all(&tar.into_inner()?).await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+/// Find a share announced on the local network, named `name` unless it is
+/// empty, and make a ticket for it.
+async fn receive_local(name: &str, net: &NetOptions) -> anyhow::Result<Ticket> {
+    let name = (!name.is_empty()).then_some(name);
+    match name {
+        Some(name) => eprintln!("looking for {name} on the local network"),
+        None => eprintln!("looking for a share on the local network"),
+    }
+    // only for discovery, the endpoint of the download needs the ticket
+    let builder = Endpoint::builder()
+        .alpns(vec![])
+        .relay_mode(RelayMode::Disabled)
+        .discovery_local_network();
+    let endpoint = net.bind(builder).bind().await?;
+    let res = find_local(&endpoint, name, LOCAL_DISCOVERY_TIMEOUT).await;
+    endpoint.close().await;
+    let share = res?;
+    eprintln!(
+        "found {} from {}",
+        share.name,
+        share.ticket.node_addr().node_id.fmt_short()
+    );
+    Ok(share.ticket)
+}
+
+/// Download a share and export it, see [`ReceiveOptions`].
+pub async fn receive(opts: ReceiveOptions) -> anyhow::Result<()> {
+    if opts.stdout || opts.tar {
+        anyhow::ensure!(
+            !json_output(),
+            "--stdout and --tar can not be combined with --json"
+        );
+        STDOUT_DATA.store(true, Ordering::Relaxed);
+    }
+    let target = match &opts.output {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+            dir.clone()
+        }
+        None => std::env::current_dir()?,
+    };
+    let (ticket, data_root) = match &opts.resume {
+        Some(checkpoint_path) => {
+            let checkpoint = Checkpoint::load(checkpoint_path)?;
+            let previous =
+                Ticket::from_str(&checkpoint.ticket).context("invalid ticket in checkpoint")?;
+            // the partial data is stored by hash, so it can be used with any
+            // ticket for the same data
+            let ticket = match &opts.ticket {
+                Some(ticket) => {
+                    anyhow::ensure!(
+                        ticket.hash_and_format() == previous.hash_and_format(),
+                        "the ticket is for other data than the checkpoint"
+                    );
+                    ticket.clone()
+                }
+                None => previous,
+            };
+            let data_root = match checkpoint_path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => std::env::current_dir()?,
+            };
+            eprintln!(
+                "resuming download with {} files complete and {} partial",
+                checkpoint.completed.len(),
+                checkpoint.partial.len()
+            );
+            (ticket, data_root)
+        }
+        None => {
+            let ticket = match &opts.code {
+                Some(code) => {
+                    eprintln!("looking for the sender of {code}");
+                    redeem(code).await?
+                }
+                None => match &opts.local {
+                    Some(name) => receive_local(name, &opts.net).await?,
+                    None => opts.ticket.clone().context("missing ticket")?,
+                },
+            };
+            let data_root = match &opts.data_dir {
+                Some(dir) => {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("failed to create {}", dir.display()))?;
+                    dir.clone()
+                }
+                None => target.clone(),
+            };
+            (ticket, data_root)
+        }
+    };
+    let addr = opts.net.node_addr(ticket.node_addr().clone());
+    if let Some(expected) = opts.expect_identity {
+        let node_id = ticket.meta.signer.unwrap_or(addr.node_id);
+        anyhow::ensure!(
+            node_id == expected,
+            "the ticket is from {node_id}, not from the expected {expected}"
+        );
+    }
+    for from in &opts.from {
+        anyhow::ensure!(
+            from.hash_and_format() == ticket.hash_and_format(),
+            "the ticket of --from {} is for different data",
+            from.node_addr().node_id.fmt_short()
+        );
+    }
+    if let Some(expires) = ticket.meta.expires {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        anyhow::ensure!(
+            now < expires,
+            "the ticket expired {} ago",
+            HumanDuration(Duration::from_secs(now - expires))
+        );
+    }
+    // show what is being received before connecting. this is claimed by the
+    // sender and only checked once the data arrives.
+    if let Some(name) = &ticket.meta.name {
+        match ticket.meta.size {
+            Some(size) => eprintln!("receiving {name}, {}", HumanBytes(size)),
+            None => eprintln!("receiving {name}"),
+        }
+        if opts.verbose > 0 {
+            if let Some(mime) = &ticket.meta.mime {
+                eprintln!("content type {mime}");
+            }
+        }
+    }
+    // a resumed download was confirmed when it started
+    let ask = !opts.yes
+        && opts.resume.is_none()
+        && !opts.list
+        && opts.preview.is_none()
+        && !json_output()
+        && std::io::stdin().is_terminal()
+        && std::io::stderr().is_terminal();
+    if ask {
+        let what = ticket.meta.name.as_deref().unwrap_or("the share");
+        if !confirm(&format!("Download {what}"), true)? {
+            eprintln!("not downloading");
+            return Ok(());
+        }
+    }
+    let secret_key = opts.secret_key.clone();
+    audit::open_log(opts.audit_log.as_deref(), &secret_key)?;
+    let mut builder = Endpoint::builder()
+        .alpns(vec![])
+        .secret_key(secret_key)
+        .relay_mode(opts.net.relay_mode());
+    if let Some(size) = opts.recv_buffer {
+        // the connection window bounds the data buffered over all streams
+        let size = VarInt::from_u32(size);
+        let mut config = TransportConfig::default();
+        config.receive_window(size).stream_receive_window(size);
+        builder = builder.transport_config(config);
+    }
+
+    // the addresses in the ticket are tried first. discovery finds the sender
+    // by node id if there are none, or with --dns-discovery if they changed
+    // since the ticket was made.
+    let no_addresses = std::iter::once(&addr)
+        .chain(opts.from.iter().map(|ticket| ticket.node_addr()))
+        .any(|addr| addr.relay_url.is_none() && addr.direct_addresses.is_empty());
+    if no_addresses || opts.net.dns_discovery {
+        builder = builder.add_discovery(DnsDiscovery::n0_dns());
+    }
+    if opts.local.is_some() {
+        builder = builder.discovery_local_network();
+    }
+    builder = opts.net.bind(builder);
+    let endpoint = builder.bind().await?;
+    if opts.list {
+        // only the names and metadata are fetched, they don't need a store on disk
+        let db = MemStore::new();
+        let limits = opts.limits;
+        let filter = EntryFilter::new(&opts.only, &opts.skip)?;
+        let password = opts.password.clone();
+        let res = list(
+            &db, &endpoint, &opts.net, &ticket, password, &filter, &limits,
+        )
+        .await;
+        endpoint.close().await;
+        return res;
+    }
+    let endpoint2 = endpoint.clone();
+    let _nat = AbortOnDropHandle::new(n0_future::task::spawn(print_nat_type(endpoint.clone())));
+    let dir_name = format!(".sendme-recv-{}", ticket.hash().to_hex());
+    let iroh_data_dir = data_root.join(dir_name);
+    let checkpoint_path = Checkpoint::path(&data_root, &ticket.hash());
+    let existed = iroh_data_dir.exists();
+    // small shares are received into memory, see SMALL_SHARE_SIZE, unless
+    // there is partial data on disk. the size is only claimed by the sender,
+    // so it is checked again with the provider, see ensure_fits_memory.
+    let in_memory = !existed
+        && opts.resume.is_none()
+        && ticket
+            .meta
+            .size
+            .is_some_and(|size| size <= SMALL_SHARE_SIZE);
+    let (db, _mem_store) = load_store(&iroh_data_dir, in_memory).await?;
+    if let Some(spec) = &opts.preview {
+        let res = preview(&db, &endpoint, &opts.net, &ticket, spec).await;
+        db.shutdown().await?;
+        // keep the data of an interrupted download
+        if !existed && !in_memory {
+            tokio::fs::remove_dir_all(&iroh_data_dir).await?;
+        }
+        return res;
+    }
+    let db2 = db.clone();
+    let ticket2 = ticket.clone();
+    let recorder = opts
+        .debug_report
+        .as_ref()
+        .map(|_| Arc::new(DebugRecorder::new()));
+    let recorder2 = recorder.clone();
+
+    trace!("load done!");
+    let (chat_done_tx, chat_done) = oneshot::channel();
+    let fut = async move {
+        trace!("running");
+        let mut mp: MultiProgress = MultiProgress::new();
+        let draw_target = if opts.no_progress || json_output() {
+            ProgressDrawTarget::hidden()
+        } else {
+            ProgressDrawTarget::stderr()
+        };
+        mp.set_draw_target(draw_target);
+        let hash_and_format = ticket.hash_and_format();
+        trace!("computing local");
+        let local = db.remote().local(hash_and_format).await?;
+        trace!("local done");
+        let skipper = Arc::new(Skipper::default());
+        let filter = EntryFilter::new(&opts.only, &opts.skip)?;
+        let limits = opts.limits;
+        // the extension connection stays open after the download, for --chat
+        let mut extensions = None;
+        let (bytes_read, elapsed, total_files, payload_size) = if !local.is_complete() {
+            trace!("{} not complete", hash_and_format.hash);
+            let cp = mp.add(make_connect_progress());
+            let verbose = opts.verbose;
+            let pacer = Arc::new(Pacer::new(opts.nice));
+            if let Some(rate) = opts.limit_rate {
+                pacer.limit(rate);
+            }
+            let (rejected_tx, rejected) = oneshot::channel();
+            extensions = Some(AbortOnDropHandle::new(n0_future::task::spawn({
+                let endpoint = endpoint.clone();
+                let addr = addr.clone();
+                let message = opts.message.clone();
+                let lines = opts.chat.then(chat_input);
+                let password = opts.password.clone();
+                let mp = mp.clone();
+                let pacer = pacer.clone();
+                let direct_only = opts.net.direct_only;
+                async move {
+                    match Extensions::connect(&endpoint, addr, password).await {
+                        Ok((connection, hello, send, recv)) => {
+                            // before the download starts, which waits for the hello
+                            if hello.nice {
+                                pacer.enable();
+                            }
+                            pacer.request(hello.limit_rate);
+                            rejected_tx.send(hello.rejected.clone()).ok();
+                            if hello.rejected.is_some() {
+                                return;
+                            }
+                            if verbose > 0 {
+                                eprintln!("sender extensions: {}", hello.features.join(", "));
+                            }
+                            let updates =
+                                hello.supports(ADDR_FEATURE) || hello.supports(RATE_FEATURE);
+                            let _updates = updates.then(|| {
+                                AbortOnDropHandle::new(n0_future::task::spawn(receive_updates(
+                                    endpoint.clone(),
+                                    connection.clone(),
+                                    pacer.clone(),
+                                    direct_only,
+                                )))
+                            });
+                            if hello.supports(CHAT_FEATURE) {
+                                if lines.is_some() {
+                                    mp.println(
+                                        "type a message and press enter to send it to the sender",
+                                    )
+                                    .ok();
+                                }
+                                chat(send, recv, message, lines, mp, "sender").await.ok();
+                                chat_done_tx.send(()).ok();
+                            }
+                            connection.closed().await;
+                        }
+                        Err(cause) => trace!("sender does not support extensions: {cause}"),
+                    }
+                }
+            })));
+            // senders that require a password only serve data after the hello.
+            // senders without the extension protocol fail, so this does not wait.
+            if let Ok(Ok(Some(reason))) = tokio::time::timeout(EXT_HELLO_TIMEOUT, rejected).await {
+                anyhow::bail!("the sender rejected this receiver: {reason}");
+            }
+            let mut connection = opts
+                .net
+                .connect(&endpoint, addr.clone(), iroh_blobs::protocol::ALPN)
+                .await?;
+            audit::record(addr.node_id, iroh_blobs::ALPN, "connected", None);
+            cp.finish_and_clear();
+            emit(JsonEvent::Connected {
+                node_id: addr.node_id.to_string(),
+                connection_id: None,
+            });
+            let _sampler = recorder2.as_ref().map(|recorder| {
+                recorder.set_connection(connection.clone());
+                AbortOnDropHandle::new(n0_future::task::spawn(
+                    recorder.clone().sample(endpoint.clone(), addr.node_id),
+                ))
+            });
+            // other senders of the same data, see --from
+            let mut sources = Vec::new();
+            for from in &opts.from {
+                let node_id = from.node_addr().node_id;
+                match opts
+                    .net
+                    .connect(
+                        &endpoint,
+                        from.node_addr().clone(),
+                        iroh_blobs::protocol::ALPN,
+                    )
+                    .await
+                {
+                    Ok(connection) => {
+                        audit::record(node_id, iroh_blobs::ALPN, "connected", None);
+                        sources.push(connection);
+                    }
+                    Err(cause) => eprintln!(
+                        "{}",
+                        style(format!(
+                            "unable to connect to {}, skipping it: {cause}",
+                            node_id.fmt_short()
+                        ))
+                        .yellow()
+                    ),
+                }
+            }
+            let sp = mp.add(make_get_sizes_progress());
+            // the blobs to get with their sizes, for --from
+            let mut blobs = Vec::new();
+            // whether some entries are requested before the others, see `send --first`
+            let mut prioritized = false;
+            let (children, total_size, payload_size, total_files) = match hash_and_format.format {
+                BlobFormat::Raw => {
+                    let name = ticket.meta.name.as_deref().unwrap_or_default();
+                    anyhow::ensure!(
+                        filter.matches(name),
+                        "{name} is excluded by --only or --skip"
+                    );
+                    // the size decides whether the file is received into memory
+                    let (size, _) = if in_memory {
+                        get_verified_size(&connection, &hash_and_format.hash).await
+                    } else {
+                        get_unverified_size(&connection, &hash_and_format.hash).await
+                    }
+                    .map_err(show_get_error)?;
+                    ensure_fits_memory(in_memory, size)?;
+                    sp.finish_and_clear();
+                    eprintln!(
+                        "getting file {} {}, {}",
+                        print_hash(&ticket.hash(), opts.format),
+                        ticket.meta.name.as_deref().unwrap_or_default(),
+                        HumanBytes(size)
+                    );
+                    blobs.push((hash_and_format.hash, size));
+                    (None, size, size, 1)
+                }
+                BlobFormat::HashSeq => {
+                    let (hash_seq, sizes) = get_hash_seq_and_sizes(
+                        &connection,
+                        &hash_and_format.hash,
+                        1024 * 1024 * 32,
+                        None,
+                    )
+                    .await
+                    .map_err(show_get_error)?;
+                    ensure_fits_memory(in_memory, sizes.iter().sum())?;
+                    // check the entry count before getting the names, which can
+                    // be large for pathological collections. the sizes are of
+                    // the hash seq, the collection metadata and the entries.
+                    let entries = sizes.len().saturating_sub(2) as u64;
+                    limits.check_entries(entries)?;
+                    let collection =
+                        get_collection(&db, connection.clone(), hash_and_format.hash, &sizes)
+                            .await?;
+                    limits.check(&collection)?;
+                    // the metadata entry is never written
+                    let files = collection
+                        .iter()
+                        .filter(|(name, _)| name != META_NAME)
+                        .count();
+                    anyhow::ensure!(
+                        !opts.stdout || files <= 1,
+                        "--stdout needs a share with a single file, this one has {files}"
+                    );
+                    // the children to get, with their sizes
+                    let mut children = hash_seq
+                        .iter()
+                        .zip(sizes.iter().skip(1).copied())
+                        .collect::<Vec<_>>();
+                    let meta = get_share_meta(&db, connection.clone(), &collection).await?;
+                    // the names and the metadata are needed for the export
+                    if let Some(&(names, _)) = children.first() {
+                        skipper.keep(names);
+                    }
+                    if let Some((_, hash)) = collection.iter().find(|(name, _)| name == META_NAME) {
+                        skipper.keep(*hash);
+                    }
+                    // small entries that come with the metadata are not requested
+                    let inlined = add_inline(&db, &collection, &meta).await?;
+                    if inlined > 0 {
+                        trace!("{inlined} bytes of small entries were inlined");
+                    }
+                    // the entries the sender wants served first go first, after
+                    // the collection metadata
+                    if !opts.in_order {
+                        let first = meta
+                            .first
+                            .iter()
+                            .filter_map(|first| collection.iter().find(|(name, _)| name == first))
+                            .map(|(_, hash)| *hash)
+                            .collect::<Vec<_>>();
+                        if !first.is_empty() && children.len() > 1 {
+                            // stable, so the other entries stay in collection order
+                            children[1..].sort_by_key(|(hash, _)| {
+                                first.iter().position(|h| h == hash).unwrap_or(first.len())
+                            });
+                            prioritized = true;
+                        }
+                    }
+                    let mut total_size = sizes.iter().copied().sum::<u64>();
+                    let mut payload_size = sizes.iter().skip(2).copied().sum::<u64>();
+                    let mut total_files = (sizes.len().saturating_sub(1)) as u64;
+                    if !filter.is_empty() {
+                        let wanted = collection
+                            .iter()
+                            .filter(|(name, _)| name == META_NAME || filter.matches(name))
+                            .map(|(_, hash)| *hash)
+                            .collect::<BTreeSet<_>>();
+                        // keep the collection metadata, which is the first child
+                        let (excluded, kept) = children
+                            .iter()
+                            .skip(1)
+                            .partition::<Vec<_>, _>(|(hash, _)| !wanted.contains(hash));
+                        let excluded_size = excluded.iter().map(|(_, size)| *size).sum::<u64>();
+                        total_size = total_size.saturating_sub(excluded_size);
+                        payload_size = payload_size.saturating_sub(excluded_size);
+                        total_files = total_files.saturating_sub(excluded.len() as u64);
+                        children = children.iter().take(1).chain(kept).copied().collect();
+                    }
+                    sp.finish_and_clear();
+                    eprintln!(
+                        "getting collection {} {} files, {}",
+                        print_hash(&ticket.hash(), opts.format),
+                        total_files,
+                        HumanBytes(payload_size)
+                    );
+                    // print the details of the collection only in verbose mode
+                    if opts.verbose > 0 {
+                        eprintln!(
+                            "getting {} blobs in total, {}",
+                            total_files + 1,
+                            HumanBytes(total_size)
+                        );
+                    }
+                    blobs = children.clone();
+                    let children = children
+                        .into_iter()
+                        .map(|(hash, _)| hash)
+                        .collect::<Vec<_>>();
+                    (Some(children), total_size, payload_size, total_files)
+                }
+            };
+            let (tx, rx) = mpsc::channel(32);
+            // the store only keeps chunks that were verified against the hash when
+            // they arrived, so partial data of an interrupted download can be used
+            // as is, and only the missing ranges are requested.
+            let local_size = local.local_bytes();
+            if local_size > 0 {
+                eprintln!(
+                    "resuming with {} of {} already downloaded",
+                    HumanBytes(local_size),
+                    HumanBytes(total_size)
+                );
+            }
+            let task = tokio::spawn(show_download_progress(
+                mp.clone(),
+                rx,
+                local_size,
+                total_size,
+                recorder2.clone(),
+            ));
+            // skipping needs a request per file and a terminal to read keys from
+            let interactive = opts.interactive
+                && cfg!(unix)
+                && children.is_some()
+                && sources.is_empty()
+                && !opts.chat
+                && std::io::stdin().is_terminal()
+                && std::io::stderr().is_terminal();
+            #[cfg(unix)]
+            let _keys = if interactive {
+                mp.println("press s to skip the file that is currently downloading")?;
+                Some(skipper.clone().listen()?)
+            } else {
+                None
+            };
+            let t0 = Instant::now();
+            let reconnect_timeout = Duration::from_secs(opts.reconnect_timeout);
+            let bytes_read = loop {
+                // after a reconnect, only request what is still missing
+                let local = db.remote().local(hash_and_format).await?;
+                let base = local.local_bytes().saturating_sub(local_size);
+                let res = match &children {
+                    _ if !sources.is_empty() => {
+                        let connections = std::iter::once(connection.clone())
+                            .chain(sources.iter().cloned())
+                            .collect();
+                        get_swarm(&db, connections, &blobs, tx.clone(), base, pacer.clone()).await
+                    }
+                    Some(children)
+                        if opts.pipeline_depth > 1
+                            || interactive
+                            || !filter.is_empty()
+                            || prioritized =>
+                    {
+                        get_pipelined(
+                            &db,
+                            connection.clone(),
+                            hash_and_format.hash,
+                            children,
+                            opts.pipeline_depth,
+                            tx.clone(),
+                            base,
+                            interactive.then(|| skipper.clone()),
+                            pacer.clone(),
+                        )
+                        .await
+                    }
+                    _ => execute_get(
+                        &db,
+                        connection.clone(),
+                        local.missing(),
+                        Some(&pacer),
+                        |offset| {
+                            tx.try_send(base + offset).ok();
+                        },
+                    )
+                    .await
+                    .map(|stats| stats.total_bytes_read()),
+                };
+                match res {
+                    Ok(bytes_read) => break bytes_read,
+                    Err(cause) if is_connection_error(&cause) => {
+                        eprintln!(
+                            "{}",
+                            style(format!("connection lost ({cause}), reconnecting...")).yellow()
+                        );
+                        connection = reconnect(&endpoint, &addr, reconnect_timeout).await?;
+                        opts.net.ensure_direct(&endpoint, addr.node_id)?;
+                        if let Some(recorder) = &recorder2 {
+                            recorder.set_connection(connection.clone());
+                        }
+                    }
+                    Err(cause) => return Err(cause),
+                }
+            };
+            let elapsed = t0.elapsed();
+            drop(tx);
+            task.await.ok();
+            (bytes_read, elapsed, total_files, payload_size)
+        } else {
+            say!("{} already complete", hash_and_format.hash);
+            let total_files = match hash_and_format.format {
+                BlobFormat::Raw => 1,
+                BlobFormat::HashSeq => local.children().unwrap() - 1,
+            };
+            let payload_bytes = 0; // todo local.sizes().skip(2).map(Option::unwrap).sum::<u64>();
+            (0, Duration::ZERO, total_files, payload_bytes)
+        };
+        let (collection, meta) = load_collection(&db, &ticket).await?;
+        let compressed = opts.zstd || meta.compressed;
+        let do_decompress = zstd_enabled(compressed, false);
+        anyhow::ensure!(
+            !compressed || !(opts.stdout || opts.tar),
+            "--stdout and --tar do not support compressed shares"
+        );
+        // also covers data that was already complete, and single files
+        limits.check(&collection)?;
+        if meta.signature.is_some() || opts.require_signed || opts.expect_identity.is_some() {
+            // the node id was compared with --expect-identity before connecting
+            let node_id = ticket.meta.signer.unwrap_or(ticket.node_addr().node_id);
+            verify_manifest(&collection, &meta, node_id)?;
+            if opts.verbose > 0 {
+                eprintln!("share signed by {node_id}");
+            }
+        }
+        let skipped = skipper.take_skipped();
+        let collection = if skipped.is_empty() && filter.is_empty() {
+            collection
+        } else {
+            let mut kept = Collection::default();
+            for (name, hash) in collection.iter() {
+                if !filter.matches(name) {
+                    continue;
+                }
+                if skipped.contains(hash) {
+                    eprintln!("{} {name}", style("skipped").yellow());
+                } else {
+                    kept.push(name.clone(), *hash);
+                }
+            }
+            kept
+        };
+        let collection = if opts.review {
+            review(&db, collection).await?
+        } else {
+            collection
+        };
+        if opts.verbose > 1 {
+            for (name, hash) in collection.iter() {
+                say!("    {} {name}", print_hash(hash, opts.format));
+            }
+        }
+        if opts.stdout {
+            let (_, hash) = match collection.iter().collect::<Vec<_>>().as_slice() {
+                [entry] => *entry,
+                entries => anyhow::bail!(
+                    "--stdout needs a share with a single file, this one has {}",
+                    entries.len()
+                ),
+            };
+            write_to_stdout(&db, *hash).await?;
+            return anyhow::Ok((
+                total_files,
+                payload_size,
+                bytes_read,
+                elapsed,
+                None,
+                extensions,
+            ));
+        }
+        if opts.tar {
+            write_tar_to_stdout(&db, &collection, &meta).await?;
+            return anyhow::Ok((
+                total_files,
+                payload_size,
+                bytes_read,
+                elapsed,
+                None,
+                extensions,
+            ));
+        }
+        // the single file or the root directory of the share
+        let mut exported = None;
+        if let Some((name, _)) = collection.iter().next() {
+            if let Some(first) = name.split('/').next() {
+                let postfix = if do_decompress != compressed && collection.len() == 1 {
+                    ".zst"
+                } else {
+                    ""
+                };
+                say!("exporting to {first}{postfix}");
+                exported = Some(target.join(format!("{first}{postfix}")));
+            }
+        }
+
+        export(
+            &db,
+            collection,
+            &meta,
+            &target,
+            &mut mp,
+            ExportOptions {
+                on_conflict: opts.on_conflict,
+                backup: !opts.no_backup,
+                restore_times: !opts.no_times,
+                decompress: do_decompress,
+                postfix_target: do_decompress != compressed,
+            },
+        )
+        .await?;
+        anyhow::Ok((
+            total_files,
+            payload_size,
+            bytes_read,
+            elapsed,
+            exported,
+            extensions,
+        ))
+    };
+    let (total_files, payload_size, bytes_read, elapsed, exported, _extensions) = select! {
+        x = fut => match x {
+            Ok(x) => x,
+            Err(e) => {
+                emit(JsonEvent::Error {
+                    message: e.to_string(),
+                });
+                eprintln!("error: {e}");
+                #[cfg(feature = "notifications")]
+                if opts.notify {
+                    notify_desktop("Download failed", &e.to_string()).await;
+                }
+                if let (Some(recorder), Some(path)) = (&recorder, &opts.debug_report) {
+                    recorder.save(path).ok();
+                }
+                // data in memory is lost on exit, so there is nothing to resume
+                let kept = !in_memory && write_checkpoint(&db2, &ticket2, &checkpoint_path).await;
+                // make sure we shutdown the db before exiting
+                db2.shutdown().await?;
+                if !kept && !in_memory {
+                    tokio::fs::remove_dir_all(&iroh_data_dir).await.ok();
+                }
+                std::process::exit(1);
+            }
+        },
+        _ = interrupted() => {
+            let kept = !in_memory && write_checkpoint(&db2, &ticket2, &checkpoint_path).await;
+            db2.shutdown().await?;
+            if !kept && !in_memory {
+                tokio::fs::remove_dir_all(&iroh_data_dir).await.ok();
+            }
+            std::process::exit(130);
+        }
+    };
+    if let (Some(recorder), Some(path)) = (&recorder, &opts.debug_report) {
+        recorder.save(path)?;
+    }
+    if checkpoint_path.exists() {
+        tokio::fs::remove_file(&checkpoint_path).await?;
+    }
+    emit(JsonEvent::Completed {
+        files: total_files,
+        bytes: payload_size,
+        elapsed_secs: elapsed.as_secs_f64(),
+    });
+    if opts.verbose > 0 {
+        say!(
+            "downloaded {} files, {}. took {} ({}/s)",
+            total_files,
+            HumanBytes(payload_size),
+            HumanDuration(elapsed),
+            HumanBytes((bytes_read as f64 / elapsed.as_secs_f64()) as u64),
+        );
+    }
+    if let Some(path) = exported.as_ref().filter(|_| opts.open) {
+        // the download is done, so failing to open it is not an error
+        if let Err(cause) = open_path(path) {
+            eprintln!("failed to open {}: {cause}", path.display());
+        }
+    }
+    #[cfg(feature = "notifications")]
+    if opts.notify {
+        let what = ticket2.meta.name.as_deref().unwrap_or("the share");
+        let body = format!("got {what}, {}", HumanBytes(payload_size));
+        notify_desktop("Download complete", &body).await;
+    }
+    if let Some(command) = &opts.on_complete {
+        let peer = ticket2.node_addr().node_id;
+        let res = run_hook(command, &ticket2, peer, payload_size, exported.as_deref()).await;
+        if let Err(cause) = res {
+            eprintln!("{} {cause:#}", style("on-complete:").yellow());
+        }
+    }
+    // the sender does not support chat if the signal was dropped
+    if opts.chat {
+        select! {
+            _ = chat_done => {}
+            _ = interrupted() => {}
+        }
+    }
+    if opts.mirror {
+        mirror(endpoint2, &db2, &ticket2, opts.zstd).await?;
+    }
+    if !in_memory {
+        tokio::fs::remove_dir_all(iroh_data_dir).await?;
+    }
+    Ok(())
+}
+
+/// Open `path` with the default application of the platform, without waiting
+/// for it.
+fn open_path(path: &Path) -> std::io::Result<()> {
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = std::process::Command::new("cmd");
+        // the first quoted argument of start is the window title
+        command.args(["/C", "start", ""]);
+        command
+    };
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let mut command = std::process::Command::new("xdg-open");
+    command
+        .arg(path)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+/// Provide a received share from the receive store until interrupted.
+async fn mirror(endpoint: Endpoint, db: &Store, ticket: &Ticket, zstd: bool) -> anyhow::Result<()> {
+    // a partial share would fail for everyone who gets the ticket
+    let local = db.remote().local(ticket.hash_and_format()).await?;
+    anyhow::ensure!(
+        local.is_complete(),
+        "not all of the share was downloaded, so it can not be mirrored"
+    );
+    let blobs = Blobs::new(db, endpoint.clone(), None);
+    let router = iroh::protocol::Router::builder(endpoint)
+        .accept(iroh_blobs::ALPN, blobs)
+        .spawn();
+    // wait for the endpoint to figure out its address before making a ticket
+    let _ = router.endpoint().home_relay().initialized().await?;
+    let addr = router.endpoint().node_addr().initialized().await?;
+    // the signature is still the one of the original sender
+    let mut meta = ticket.meta.clone();
+    meta.signer.get_or_insert(ticket.node_addr().node_id);
+    let ticket = Ticket::new(BlobTicket::new(addr, ticket.hash(), ticket.format()), meta);
+    emit(JsonEvent::TicketIssued {
+        ticket: ticket.to_string(),
+        hash: ticket.hash().to_hex().to_string(),
+        size: ticket.meta.size.unwrap_or_default(),
+    });
+    say!("mirroring, to get this data, use");
+    say!("sendme receive{} {ticket}", if zstd { " -z" } else { "" });
+    interrupted().await?;
+    tokio::time::timeout(Duration::from_secs(2), router.shutdown()).await??;
+    db.shutdown().await?;
+    Ok(())
+}
+
+/// Let the user choose the entries of a downloaded collection to export, see
+/// [`ReceiveOptions::review`].
+async fn review(db: &Store, collection: Collection) -> anyhow::Result<Collection> {
+    let mut entries = Vec::new();
+    for (name, hash) in collection.iter() {
+        let size = db
+            .remote()
+            .local(HashAndFormat::raw(*hash))
+            .await?
+            .local_bytes();
+        entries.push((name.clone(), *hash, size, true));
+    }
+    loop {
+        for (i, (name, _, size, selected)) in entries.iter().enumerate() {
+            let mark = if *selected { "x" } else { " " };
+            eprintln!("[{mark}] {:>3} {name} ({})", i + 1, HumanBytes(*size));
+        }
+        let Some(answer) = prompt(
+            "numbers or ranges to toggle, all or none, enter to export",
+            "export",
+        )?
+        else {
+            break;
+        };
+        if let Err(cause) = toggle_entries(&mut entries, &answer) {
+            eprintln!("{} {cause}", style("error:").red());
+        }
+    }
+    let mut kept = Collection::default();
+    for (name, hash, _, selected) in entries {
+        if selected {
+            kept.push(name, hash);
+        } else {
+            eprintln!("{} {name}", style("discarded").yellow());
+        }
+    }
+    anyhow::ensure!(!kept.is_empty(), "no files selected");
+    Ok(kept)
+}
+
+/// Apply an answer of [`review`] to the selection of `entries`.
+fn toggle_entries(entries: &mut [(String, Hash, u64, bool)], answer: &str) -> anyhow::Result<()> {
+    let count = entries.len();
+    let mut toggled = Vec::new();
+    for part in answer.split([',', ' ']).filter(|part| !part.is_empty()) {
+        match part {
+            "all" | "none" => {
+                for entry in entries.iter_mut() {
+                    entry.3 = part == "all";
+                }
+            }
+            _ => {
+                let (start, end) = part.split_once('-').unwrap_or((part, part));
+                let range = start.parse::<usize>()?..=end.parse::<usize>()?;
+                anyhow::ensure!(
+                    *range.start() >= 1 && *range.end() <= count,
+                    "{part} is not between 1 and {count}"
+                );
+                toggled.extend(range);
+            }
+        }
+    }
+    for i in toggled {
+        entries[i - 1].3 = !entries[i - 1].3;
+    }
+    Ok(())
+}
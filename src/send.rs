@@ -0,0 +1,1358 @@
+//! Providing files and directories until interrupted, see [`send`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use console::style;
+use data_encoding::HEXLOWER;
+use indicatif::{
+    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+use iroh::{
+    discovery::pkarr::PkarrPublisher, Endpoint, NodeAddr, NodeId, RelayMode, SecretKey, Watcher,
+};
+use iroh_blobs::{
+    api::Store,
+    format::collection::Collection,
+    hashseq::HashSeq,
+    net_protocol::Blobs,
+    protocol::{ChunkRanges, ChunkRangesExt, GetRequest},
+    store::mem::MemStore,
+    ticket::BlobTicket,
+    Hash,
+};
+use n0_future::{task::AbortOnDropHandle, StreamExt};
+use rand::{seq::IteratorRandom, Rng};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+};
+use tracing::error;
+
+use crate::{
+    audit,
+    code::{offer, ShortCode},
+    config::cache_dir,
+    export::portability_problem,
+    ext::{chat_input, Authorized, Budgets, Extensions, LimitedBlobs, RateLimit, EXT_ALPN},
+    filter::EntryFilter,
+    hook::{notify_desktop, run_hook},
+    import::{
+        blake3_throughput, import, import_reader, import_tar, list_paths, CacheUsage, HashCache,
+        Symlinks, WalkOptions,
+    },
+    local::announcement,
+    manifest::Manifest,
+    metrics::ProviderMetrics,
+    net::{print_nat_type, rebind_on_wakeup, NetOptions},
+    output::{emit, interrupted, json_output, print_entries, print_hash, Format, JsonEvent},
+    progress::make_fanout_progress,
+    provide::{show_provide_progress, start_metrics, ShareBlobs},
+    ticket::{apply_options, format_receive_command, AddrInfoOptions},
+    transfer::{execute_get, load_store, zstd_enabled, SMALL_SHARE_SIZE},
+    Ticket,
+};
+
+/// What [`send`] shares and how it is provided.
+///
+/// The fields match the options of `sendme send`, which documents them in
+/// detail.
+#[derive(Debug)]
+pub struct SendOptions {
+    /// The files or directories to send, or `-` to send stdin.
+    pub paths: Vec<PathBuf>,
+    /// Send the entries of a tar stream on stdin.
+    pub tar_in: bool,
+    /// Name of the file that receivers get when sending stdin.
+    pub name: String,
+    /// Only list the entries that would be shared.
+    pub dry_run: bool,
+    /// How to handle symlinks.
+    pub symlinks: Symlinks,
+    /// Globs of the files to share.
+    pub include: Vec<String>,
+    /// Globs of the files and directories to leave out.
+    pub exclude: Vec<String>,
+    /// Leave out files that are ignored by `.gitignore` or `.ignore` files.
+    pub gitignore: bool,
+    /// Globs of the files receivers should get first.
+    pub first: Vec<String>,
+    /// What the ticket contains besides the node id.
+    pub ticket_type: AddrInfoOptions,
+    /// Keep the store in the user cache directory.
+    pub cache: bool,
+    /// Keep the store in this directory.
+    pub store: Option<PathBuf>,
+    /// Upper bound for the size of a kept store, in bytes.
+    pub cache_size: u64,
+    /// Directory for the temporary store, instead of the current directory.
+    pub data_dir: Option<PathBuf>,
+    /// Number of files to hash in parallel, or the number of cpus.
+    pub hash_threads: Option<usize>,
+    /// Write a json manifest of the share to this file.
+    pub manifest_out: Option<PathBuf>,
+    /// Check the files against a checksum file written by `b3sum`.
+    pub checksums: Option<PathBuf>,
+    /// Check that the provider can be reached through its relay before
+    /// printing the ticket.
+    pub self_test: bool,
+    /// Write only the ticket to stdout.
+    pub print_ticket_only: bool,
+    /// Also show the ticket as a QR code.
+    pub qr: bool,
+    /// Also print the ticket as a `sendme://` link.
+    pub url: bool,
+    /// Put the metadata of the share into the ticket.
+    pub ticket_meta: bool,
+    /// Share the paths again when they change.
+    pub watch: bool,
+    /// Also announce the share on the local network.
+    pub local: bool,
+    /// Also print a short code for the ticket.
+    pub code: bool,
+    /// Exit once a receiver completed a download.
+    pub wait: bool,
+    /// Serve a single receiver, and exit once it got everything.
+    pub once: bool,
+    /// Nodes that may ask for other data to be pinned.
+    pub allow_pin: Vec<NodeId>,
+    /// Upper bound for the size of pinned data, in bytes.
+    pub max_pin_size: u64,
+    /// Only serve these nodes, or everyone if empty.
+    pub allow: Vec<NodeId>,
+    /// Ask receivers to back off on congestion.
+    pub nice: bool,
+    /// Bytes per second each receiver may download at most.
+    pub limit_rate: Option<u64>,
+    /// With `wait` or `once`, fail if no download completed within this many
+    /// seconds.
+    pub timeout: Option<u64>,
+    /// Stop providing the data after this time.
+    pub expire: Option<Duration>,
+    /// Serve Prometheus metrics on this address.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Command to run whenever a receiver completed a download.
+    pub on_complete: Option<String>,
+    /// Show a desktop notification whenever a receiver completed a download.
+    #[cfg(feature = "notifications")]
+    pub notify: bool,
+    /// Put the receive command into the clipboard of the terminal.
+    #[cfg(feature = "clipboard")]
+    pub clipboard: bool,
+    /// Put the receive command on the system clipboard.
+    #[cfg(feature = "clipboard")]
+    pub copy: bool,
+    /// A short message to show to every receiver.
+    pub message: Option<String>,
+    /// Send the lines of stdin as messages to the receivers.
+    pub chat: bool,
+    /// Only serve receivers that give this password.
+    pub password: Option<String>,
+    /// The key of the providing node.
+    pub secret_key: SecretKey,
+    /// Record the connections in this audit log, see [`audit::open_log`].
+    pub audit_log: Option<PathBuf>,
+    /// How hashes are printed.
+    pub format: Format,
+    /// How much to print about the share.
+    pub verbose: u8,
+    /// Don't show progress bars.
+    pub no_progress: bool,
+    /// Compress the files with zstd.
+    pub zstd: bool,
+    /// The zstd compression level.
+    #[cfg(feature = "zstd")]
+    pub compression_quality: u8,
+    /// How to bind the endpoint and connect to other nodes.
+    pub net: NetOptions,
+}
+
+/// Parse a checksum file written by `b3sum`, in either the default or the
+/// `--tag` format.
+fn parse_checksums(text: &str) -> anyhow::Result<Vec<(String, Hash)>> {
+    let mut res = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let (hex, path) = if let Some(rest) = line.strip_prefix("BLAKE3 (") {
+            let (path, hex) = rest
+                .rsplit_once(") = ")
+                .with_context(|| format!("invalid checksum line: {line}"))?;
+            (hex, path)
+        } else {
+            let (hex, path) = line
+                .split_once("  ")
+                .with_context(|| format!("invalid checksum line: {line}"))?;
+            // b3sum escapes paths with special characters and marks the line with a backslash
+            anyhow::ensure!(
+                !hex.starts_with('\\'),
+                "escaped paths are not supported: {line}"
+            );
+            (hex, path)
+        };
+        let hash = Hash::from_str(hex).with_context(|| format!("invalid hash in line: {line}"))?;
+        let path = path.trim_start_matches("./").replace('\\', "/");
+        res.push((path, hash));
+    }
+    Ok(res)
+}
+
+/// Check the entries of a collection against expected hashes by path.
+///
+/// Returns a description of every problem found.
+fn check_checksums(collection: &Collection, expected: &[(String, Hash)]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut checked = BTreeSet::new();
+    for (path, expected_hash) in expected {
+        let entry = collection.iter().find(|(name, _)| {
+            name == path
+                || name.ends_with(&format!("/{path}"))
+                || path.ends_with(&format!("/{name}"))
+        });
+        match entry {
+            Some((name, hash)) if hash == expected_hash => {
+                checked.insert(name.clone());
+            }
+            Some((name, _)) => {
+                checked.insert(name.clone());
+                problems.push(format!(
+                    "{name}: content does not match the checksum of {path}"
+                ));
+            }
+            None => problems.push(format!("{path}: not part of the share")),
+        }
+    }
+    for (name, _) in collection.iter() {
+        if !checked.contains(name) {
+            problems.push(format!("{name}: not in the checksum file"));
+        }
+    }
+    problems
+}
+
+/// Blobs of the last share of each path, persisted next to the cached store.
+///
+/// This is used to tell how much of a share is new to receivers that already
+/// have the previous version.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ShareHistory {
+    shares: BTreeMap<String, Vec<(Hash, u64)>>,
+}
+
+impl ShareHistory {
+    const FILE_NAME: &'static str = "shares.json";
+
+    fn load(dir: &Path) -> anyhow::Result<Self> {
+        match std::fs::read(dir.join(Self::FILE_NAME)) {
+            Ok(data) => Ok(serde_json::from_slice(&data).unwrap_or_else(|cause| {
+                error!("ignoring corrupt share history: {cause}");
+                Self::default()
+            })),
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(cause) => Err(cause.into()),
+        }
+    }
+
+    fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::write(dir.join(Self::FILE_NAME), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Record the blobs of a share of `path`, returning those of the previous share.
+    fn replace(&mut self, path: &Path, blobs: Vec<(Hash, u64)>) -> Option<Vec<(Hash, u64)>> {
+        self.shares
+            .insert(path.to_string_lossy().into_owned(), blobs)
+    }
+}
+
+/// Maximum time for the self test of a provider, see [`SendOptions::self_test`].
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connect to the provider at `addr` through its relay from a new node, and
+/// fetch the first bytes of a random entry of `collection`.
+///
+/// The data is verified like for any download, so this checks both that the
+/// provider is reachable and that it serves the share.
+async fn self_test(
+    net: &NetOptions,
+    addr: &NodeAddr,
+    collection: &Collection,
+    auth: &Authorized,
+) -> anyhow::Result<()> {
+    let relay_url = addr.relay_url.clone().context("no relay to test with")?;
+    let (_, hash) = collection
+        .iter()
+        .choose(&mut rand::thread_rng())
+        .context("nothing to test with")?;
+    let builder = Endpoint::builder()
+        .alpns(vec![])
+        .relay_mode(net.relay_mode());
+    let endpoint = net.proxy(builder).bind().await?;
+    *auth.self_test.lock().unwrap() = Some(endpoint.node_id());
+    let store = MemStore::new();
+    let res = async {
+        let relay_addr = NodeAddr::new(addr.node_id).with_relay_url(relay_url);
+        let connection = endpoint
+            .connect(relay_addr, iroh_blobs::protocol::ALPN)
+            .await?;
+        let request = GetRequest::builder()
+            .root(ChunkRanges::bytes(..1024))
+            .build(*hash);
+        execute_get(&store, connection, request, None, |_| {}).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    endpoint.close().await;
+    res
+}
+
+/// Print an updated ticket when the addresses of the endpoint change, e.g.
+/// after switching networks, and update the command shown by
+/// [`show_provider_status`].
+///
+/// Receivers that already have the old ticket can still connect, since the
+/// provider also publishes its address for discovery by node id.
+async fn update_ticket_on_addr_change(
+    endpoint: Endpoint,
+    ticket: Arc<std::sync::Mutex<Ticket>>,
+    opts: AddrInfoOptions,
+    compressed: bool,
+    mp: MultiProgress,
+    command: Arc<watch::Sender<String>>,
+) {
+    let mut updates = endpoint.node_addr().stream_updates_only();
+    while let Some(addr) = updates.next().await {
+        let Some(mut addr) = addr else {
+            continue;
+        };
+        apply_options(&mut addr, opts);
+        let ticket = {
+            let mut ticket = ticket.lock().unwrap();
+            if &addr == ticket.node_addr() {
+                continue;
+            }
+            let blob = BlobTicket::new(addr, ticket.hash(), ticket.format());
+            *ticket = Ticket::new(blob, ticket.meta.clone());
+            ticket.clone()
+        };
+        emit(JsonEvent::TicketIssued {
+            ticket: ticket.to_string(),
+            hash: ticket.hash().to_hex().to_string(),
+            size: ticket.meta.size.unwrap_or_default(),
+        });
+        let receive_command = format_receive_command(&ticket, compressed);
+        mp.println(format!(
+            "{}\nto get this data, use\n{receive_command}",
+            style("the address of this node changed").yellow()
+        ))
+        .ok();
+        command.send_replace(receive_command);
+    }
+}
+
+/// How long to wait for more changes before sharing the paths again.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// What is needed to import the shared paths again, see [`watch_paths`].
+struct Reimport {
+    paths: Vec<PathBuf>,
+    store: Store,
+    secret_key: SecretKey,
+    hash_cache: HashCache,
+    /// Where the hash cache is kept, for shares from `--store` or `--cache`.
+    cache_dir: Option<PathBuf>,
+    parallelism: usize,
+    walk: WalkOptions,
+    compress: bool,
+    compression_quality: u8,
+    ticket_meta: bool,
+    /// The shares receivers are counted as served for, see [`ShareBlobs`].
+    shares: watch::Sender<Vec<ShareBlobs>>,
+}
+
+/// Import the shared paths again whenever they change, and publish a new ticket.
+///
+/// Changes below `ignore`, the store of the provider, are not reported. The
+/// current ticket is shared with [`update_ticket_on_addr_change`], so an
+/// address change after a content change still announces the latest data.
+async fn watch_paths(
+    mut reimport: Reimport,
+    ignore: PathBuf,
+    ticket: Arc<std::sync::Mutex<Ticket>>,
+    mut mp: MultiProgress,
+    command: Arc<watch::Sender<String>>,
+) -> anyhow::Result<()> {
+    use notify::Watcher;
+
+    // a single slot is enough, the paths are imported as a whole anyway
+    let (tx, mut rx) = mpsc::channel(1);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if !event.paths.iter().all(|path| path.starts_with(&ignore)) {
+                tx.try_send(()).ok();
+            }
+        }
+    })?;
+    for path in &reimport.paths {
+        watcher.watch(path, notify::RecursiveMode::Recursive)?;
+    }
+    // protects the data of the later shares, the first one is kept by the
+    // caller. receivers may still be getting the earlier ones.
+    let mut tags = Vec::new();
+    while rx.recv().await.is_some() {
+        // wait for the changes to settle, e.g. when many files are written
+        while let Ok(Some(())) = tokio::time::timeout(WATCH_DEBOUNCE, rx.recv()).await {}
+        let res = import(
+            &reimport.paths,
+            &reimport.store,
+            &reimport.secret_key,
+            &mut reimport.hash_cache,
+            reimport.parallelism,
+            &mut mp,
+            &reimport.walk,
+            reimport.compress,
+            reimport.compression_quality,
+        )
+        .await;
+        let (temp_tag, size, collection, _, meta) = match res {
+            Ok(res) => res,
+            Err(cause) => {
+                mp.println(format!(
+                    "{} failed to share the changes: {cause:#}",
+                    style("warning:").yellow()
+                ))
+                .ok();
+                continue;
+            }
+        };
+        if let Some(dir) = &reimport.cache_dir {
+            reimport.hash_cache.save(dir)?;
+        }
+        let (addr, expires) = {
+            let ticket = ticket.lock().unwrap();
+            (ticket.node_addr().clone(), ticket.meta.expires)
+        };
+        let mut new = Ticket::for_share(
+            addr,
+            &reimport.paths,
+            *temp_tag.hash(),
+            &collection,
+            size,
+            meta.clone(),
+            reimport.ticket_meta,
+        )?;
+        // changes don't extend the life of the share
+        new.meta.expires = expires;
+        {
+            let mut ticket = ticket.lock().unwrap();
+            // e.g. a file that was saved without changes
+            if new.hash() == ticket.hash() {
+                continue;
+            }
+            *ticket = new.clone();
+        }
+        // receivers of the earlier shares still count as served
+        let share = ShareBlobs::load(&reimport.store, *temp_tag.hash(), &collection, &meta).await?;
+        reimport.shares.send_modify(|shares| shares.push(share));
+        tags.push(temp_tag);
+        emit(JsonEvent::TicketIssued {
+            ticket: new.to_string(),
+            hash: new.hash().to_hex().to_string(),
+            size,
+        });
+        let receive_command = format_receive_command(&new, reimport.compress);
+        mp.println(format!(
+            "{}\nto get this data, use\n{receive_command}",
+            style("the shared files changed").yellow()
+        ))
+        .ok();
+        command.send_replace(receive_command);
+    }
+    Ok(())
+}
+
+/// Keep a status line with the time waited and the number of receivers served.
+///
+/// With `expires`, the time left until the share expires is shown as well. The
+/// receive command is printed again whenever a receiver is done, since progress
+/// output tends to scroll it out of view.
+async fn show_provider_status(
+    mp: MultiProgress,
+    receive_command: watch::Receiver<String>,
+    served: watch::Receiver<u64>,
+    expires: Option<SystemTime>,
+) {
+    let pb = mp.add(ProgressBar::hidden());
+    pb.set_style(ProgressStyle::with_template("{spinner:.green} {msg}").unwrap());
+    let t0 = Instant::now();
+    let mut last_served = 0;
+    loop {
+        let served = *served.borrow();
+        if served != last_served {
+            last_served = served;
+            let receive_command = receive_command.borrow().clone();
+            mp.println(format!("to get this data, use\n{receive_command}"))
+                .ok();
+        }
+        let mut msg = format!(
+            "waiting for {}, {served} receivers served",
+            HumanDuration(t0.elapsed())
+        );
+        if let Some(expires) = expires {
+            let left = expires
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            msg.push_str(&format!(", expires in {}", HumanDuration(left)));
+        }
+        pb.set_message(msg);
+        pb.tick();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Hosts to send a share to, by running `sendme receive` on them over ssh.
+#[derive(Debug)]
+pub struct FanoutTargets {
+    hosts: Vec<String>,
+    ssh: String,
+    remote_dir: Option<String>,
+}
+
+impl FanoutTargets {
+    /// Read the hosts from the file `hosts`, with a host per line, to connect
+    /// to with the `ssh` command and receive in `remote_dir`.
+    pub fn load(hosts: &Path, ssh: String, remote_dir: Option<String>) -> anyhow::Result<Self> {
+        let hosts_file = hosts;
+        let hosts = std::fs::read_to_string(hosts_file)
+            .with_context(|| format!("failed to read hosts file {}", hosts_file.display()))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        anyhow::ensure!(!hosts.is_empty(), "no hosts in {}", hosts_file.display());
+        Ok(Self {
+            hosts,
+            ssh,
+            remote_dir,
+        })
+    }
+
+    /// Run `receive_command` on all hosts, failing if it fails on any of them.
+    async fn run(&self, receive_command: &str, mp: &MultiProgress) -> anyhow::Result<()> {
+        let command = match &self.remote_dir {
+            Some(dir) => format!(
+                "cd '{}' && {receive_command} --no-progress",
+                dir.replace('\'', "'\\''")
+            ),
+            None => format!("{receive_command} --no-progress"),
+        };
+        let pb = mp.add(make_fanout_progress());
+        pb.set_length(self.hosts.len() as u64);
+        let results = futures_buffered::join_all(self.hosts.iter().map(|host| {
+            let pb = pb.clone();
+            let command = command.clone();
+            async move {
+                let output = tokio::process::Command::new(&self.ssh)
+                    .arg(host)
+                    .arg(command)
+                    .stdin(std::process::Stdio::null())
+                    .output()
+                    .await;
+                pb.inc(1);
+                let res = match output {
+                    Ok(output) if output.status.success() => Ok(()),
+                    Ok(output) => {
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        let last = stderr.lines().last().unwrap_or_default();
+                        Err(format!("{} {last}", output.status))
+                    }
+                    Err(cause) => Err(cause.to_string()),
+                };
+                (host, res)
+            }
+        }))
+        .await;
+        pb.finish_and_clear();
+        let mut failed = 0;
+        for (host, res) in results {
+            match res {
+                Ok(()) => println!("{} {host}", style("done").green()),
+                Err(cause) => {
+                    failed += 1;
+                    println!("{} {host}: {cause}", style("failed").red());
+                }
+            }
+        }
+        anyhow::ensure!(
+            failed == 0,
+            "failed on {failed} of {} hosts",
+            self.hosts.len()
+        );
+        println!("sent to all {} hosts", self.hosts.len());
+        Ok(())
+    }
+}
+
+/// Render `text` as a QR code for the terminal, using two rows per character.
+fn render_qr(text: &str) -> anyhow::Result<String> {
+    use qrcode::{render::unicode::Dense1x2, EcLevel, QrCode};
+
+    // tickets are long, so use the lowest error correction to keep the code small
+    let code = QrCode::with_error_correction_level(text, EcLevel::L)
+        .context("ticket is too long for a QR code")?;
+    // inverted, since most terminals have a dark background
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+/// Whether the files of a share of `paths` are at most [`SMALL_SHARE_SIZE`].
+fn is_small_share(paths: &[PathBuf], walk: &WalkOptions) -> anyhow::Result<bool> {
+    let (files, _) = list_paths(paths, walk)?;
+    let mut total = 0;
+    for (_, path) in &files {
+        total += std::fs::metadata(path)
+            .with_context(|| format!("failed to read {}", path.display()))?
+            .len();
+        if total > SMALL_SHARE_SIZE {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// List the entries that a share of `paths` would have, see [`SendOptions::dry_run`].
+fn dry_run(paths: &[PathBuf], walk: &WalkOptions) -> anyhow::Result<()> {
+    let (files, links) = list_paths(paths, walk)?;
+    let files = files
+        .into_iter()
+        .map(|(name, path)| {
+            let size = std::fs::metadata(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?
+                .len();
+            anyhow::Ok((name, size))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    print_entries(&files, &links);
+    Ok(())
+}
+
+/// Provide a file or directory until interrupted.
+///
+/// With `fanout`, this instead stops once all targets have received the data.
+pub async fn send(opts: SendOptions, fanout: Option<FanoutTargets>) -> anyhow::Result<()> {
+    let walk = WalkOptions {
+        symlinks: opts.symlinks,
+        gitignore: opts.gitignore,
+        filter: EntryFilter::new(&opts.include, &opts.exclude)?,
+        first: opts.first.clone(),
+    };
+    let stdin = opts.paths.iter().any(|path| path == Path::new("-"));
+    anyhow::ensure!(
+        !stdin || opts.paths.len() == 1,
+        "stdin can not be sent together with other paths"
+    );
+    anyhow::ensure!(
+        !stdin || !opts.chat,
+        "--chat reads messages from stdin, so stdin can not be sent"
+    );
+    anyhow::ensure!(
+        !stdin || !opts.watch,
+        "stdin can not be watched for changes"
+    );
+    anyhow::ensure!(
+        opts.timeout.is_none() || opts.wait || opts.once,
+        "--timeout needs --wait or --once"
+    );
+    anyhow::ensure!(
+        fanout.is_none() || !opts.once,
+        "--once can not be used with fanout"
+    );
+    if opts.dry_run {
+        anyhow::ensure!(!stdin, "stdin can not be listed without reading it");
+        return dry_run(&opts.paths, &walk);
+    }
+    // with --print-ticket-only, stdout is reserved for the ticket, and with
+    // --json for events
+    let print_ticket_only = opts.print_ticket_only;
+    let stdout_reserved = print_ticket_only || json_output();
+    let qr = opts.qr;
+    let url = opts.url;
+    macro_rules! info {
+        ($($arg:tt)*) => {
+            if stdout_reserved {
+                eprintln!($($arg)*);
+            } else {
+                println!($($arg)*);
+            }
+        };
+    }
+    let secret_key = opts.secret_key.clone();
+    audit::open_log(opts.audit_log.as_deref(), &secret_key)?;
+    // create a magicsocket endpoint
+    let relay_mode = opts.net.relay_mode();
+    let relay_disabled = matches!(relay_mode, RelayMode::Disabled);
+    let mut builder = Endpoint::builder()
+        .alpns(vec![iroh_blobs::protocol::ALPN.to_vec(), EXT_ALPN.to_vec()])
+        .secret_key(secret_key.clone())
+        .relay_mode(relay_mode);
+    if opts.local {
+        // the announcement is the user data of every discovery service, so
+        // publishing to DNS would announce the share to a public server
+        anyhow::ensure!(
+            opts.ticket_type != AddrInfoOptions::Id,
+            "--local can't be used with --ticket-type id, which needs DNS discovery"
+        );
+        if opts.net.dns_discovery {
+            eprintln!(
+                "{} not using DNS discovery of the config file with --local",
+                style("warning:").yellow()
+            );
+        }
+        builder = builder.discovery_local_network();
+    } else if opts.ticket_type == AddrInfoOptions::Id || opts.net.dns_discovery {
+        // tickets without addresses need it, for the others it is opt in, since
+        // it announces the addresses of this node to a public server
+        builder = builder.add_discovery(PkarrPublisher::n0_dns());
+    }
+    builder = opts.net.bind(builder);
+
+    let suffix = rand::thread_rng().gen::<[u8; 16]>();
+    let data_root = match &opts.data_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir()?,
+    };
+    let cache_dir = match &opts.store {
+        Some(dir) => Some(dir.clone()),
+        None if opts.cache => Some(cache_dir()?),
+        None => None,
+    };
+    let cache_size = opts.cache_size;
+    let blobs_data_dir = match &cache_dir {
+        Some(dir) => dir.join("store"),
+        None => data_root.join(format!(".sendme-send-{}", HEXLOWER.encode(&suffix))),
+    };
+    // small shares are kept in memory, see SMALL_SHARE_SIZE. watched paths
+    // can grow, so they always get a store on disk.
+    let in_memory = !stdin
+        && !opts.tar_in
+        && !opts.watch
+        && cache_dir.is_none()
+        && is_small_share(&opts.paths, &walk)?;
+    // the store on disk that is removed on exit
+    let temp_store = cache_dir.is_none() && !in_memory;
+    if temp_store && blobs_data_dir.exists() {
+        info!(
+            "can not share twice from the same directory: {}",
+            data_root.display(),
+        );
+        std::process::exit(1);
+    }
+    let do_compress = zstd_enabled(opts.zstd, true);
+
+    let mut mp = MultiProgress::new();
+    let mp2 = mp.clone();
+    let mp3 = mp.clone();
+    let paths = opts.paths;
+    let paths2 = paths.clone();
+    let name2 = opts.name.clone();
+    let blobs_data_dir2 = blobs_data_dir.clone();
+    let cache_dir2 = cache_dir.clone();
+    let secret_key2 = secret_key.clone();
+    let walk2 = walk.clone();
+    let (progress_tx, progress_rx) = mpsc::channel(32);
+    let limit = RateLimit::new(
+        watch::channel(opts.limit_rate).1,
+        watch::channel(Budgets::default()).1,
+    );
+    let (served_tx, served) = watch::channel(0);
+    let (shares_tx, shares_rx) = watch::channel(Vec::new());
+    let auth = Arc::new(Authorized::new(
+        opts.allow.iter().copied(),
+        opts.password.clone(),
+        opts.once,
+    ));
+    let chat_lines = opts.chat.then(|| Arc::new(chat_input()));
+    let metrics = Arc::new(ProviderMetrics::default());
+    let _metrics = match opts.metrics_addr {
+        Some(addr) => Some(start_metrics(addr, metrics.clone()).await?),
+        None => None,
+    };
+    #[cfg(feature = "notifications")]
+    let notify = opts.notify;
+    #[cfg(not(feature = "notifications"))]
+    let notify = false;
+    let (completed_tx, completed_rx) = if opts.on_complete.is_some() || notify {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+    let progress = AbortOnDropHandle::new(n0_future::task::spawn(show_provide_progress(
+        mp2,
+        progress_rx,
+        served_tx,
+        shares_rx,
+        auth.clone(),
+        metrics,
+        completed_tx,
+        limit.clone(),
+    )));
+    let parallelism = opts.hash_threads.unwrap_or_else(num_cpus::get).max(1);
+
+    #[cfg(feature = "zstd")]
+    let compression_quality = opts.compression_quality.clamp(1, 22);
+
+    #[cfg(not(feature = "zstd"))]
+    let compression_quality = 0;
+
+    let setup = async move {
+        let endpoint = builder.bind().await?;
+        let draw_target = if opts.no_progress || json_output() {
+            ProgressDrawTarget::hidden()
+        } else {
+            ProgressDrawTarget::stderr()
+        };
+        mp.set_draw_target(draw_target);
+        let (store, mem_store) = load_store(&blobs_data_dir2, in_memory).await?;
+        let blobs = Blobs::new(&store, endpoint.clone(), Some(progress_tx));
+
+        let mut hash_cache = match &cache_dir2 {
+            Some(dir) => HashCache::load(dir)?,
+            None => HashCache::default(),
+        };
+        let t0 = Instant::now();
+        let import_result = if opts.tar_in {
+            import_tar(tokio::io::stdin(), blobs.store(), &secret_key, &mut mp).await?
+        } else if stdin {
+            import_reader(
+                &name2,
+                tokio::io::stdin(),
+                blobs.store(),
+                &secret_key,
+                &mut mp,
+            )
+            .await?
+        } else {
+            import(
+                &paths2,
+                blobs.store(),
+                &secret_key,
+                &mut hash_cache,
+                parallelism,
+                &mut mp,
+                &walk,
+                do_compress,
+                compression_quality,
+            )
+            .await?
+        };
+        if let Some(dir) = &cache_dir2 {
+            hash_cache.save(dir)?;
+        }
+        let dt = t0.elapsed();
+
+        let router = iroh::protocol::Router::builder(endpoint.clone())
+            .accept(
+                iroh_blobs::ALPN,
+                LimitedBlobs {
+                    blobs: blobs.clone(),
+                    limit: limit.clone(),
+                },
+            )
+            .accept(
+                EXT_ALPN,
+                Extensions {
+                    message: opts.message.clone(),
+                    mp: mp.clone(),
+                    store: blobs.store().clone(),
+                    endpoint: endpoint.clone(),
+                    allow_pin: opts.allow_pin.clone(),
+                    max_pin_size: opts.max_pin_size,
+                    pins: None,
+                    nice: opts.nice,
+                    limit: limit.clone(),
+                    auth: auth.clone(),
+                    chat: chat_lines.clone(),
+                },
+            )
+            .spawn();
+        // wait for the endpoint to figure out its address before making a
+        // ticket. without relays, the direct addresses are all there is.
+        if !relay_disabled {
+            let _ = router.endpoint().home_relay().initialized().await?;
+        }
+        let store = blobs.store().clone();
+        anyhow::Ok((router, import_result, dt, store, hash_cache, mem_store))
+    };
+    let setup = select! {
+        res = setup => res.map(Some),
+        _ = interrupted() => Ok(None),
+    };
+    let (router, (temp_tag, size, collection, sizes, meta), dt, store, hash_cache, _mem_store) =
+        match setup {
+            Ok(Some(setup)) => setup,
+            res => {
+                // nothing was shared yet, so the incomplete store is of no use
+                if temp_store {
+                    tokio::fs::remove_dir_all(&blobs_data_dir).await.ok();
+                }
+                res?;
+                std::process::exit(130);
+            }
+        };
+    let share = ShareBlobs::load(&store, *temp_tag.hash(), &collection, &meta).await?;
+    shares_tx.send_modify(|shares| shares.push(share));
+    // make a ticket
+    let mut addr = router.endpoint().node_addr().initialized().await?;
+    let own_addr = addr.clone();
+    apply_options(&mut addr, opts.ticket_type);
+    opts.net.retain_family(&mut addr);
+    let make_ticket = |with_meta: bool| {
+        // the raw blob of the single entry, not the collection
+        let entry = collection.iter().next().filter(|_| stdin && with_meta);
+        if let Some((_, hash)) = entry {
+            let ticket = Ticket::for_file(addr.clone(), &opts.name, *hash, size, meta.clone());
+            return Ok(ticket);
+        }
+        Ticket::for_share(
+            addr.clone(),
+            &paths,
+            *temp_tag.hash(),
+            &collection,
+            size,
+            meta.clone(),
+            with_meta,
+        )
+    };
+    let mut ticket = make_ticket(opts.ticket_meta)?;
+    if let Some(expire) = opts.expire {
+        let expires = SystemTime::now()
+            .checked_add(expire)
+            .context("--expire is too far in the future")?;
+        auth.expires.set(expires).ok();
+        if opts.ticket_meta {
+            ticket.meta.expires = Some(expires.duration_since(UNIX_EPOCH)?.as_secs());
+        }
+    }
+    let local_name = if opts.local {
+        // only sendme understands announcements, so they always have the name
+        let (data, name) = announcement(&make_ticket(true)?)?;
+        router.endpoint().set_user_data_for_discovery(Some(data));
+        Some(name)
+    } else {
+        None
+    };
+    let hash = ticket.hash();
+    let (entry_type, source) = match paths.as_slice() {
+        _ if stdin => ("stdin as", opts.name.clone()),
+        _ if opts.tar_in => ("tar stream", "from stdin".to_string()),
+        [path] if path.is_file() => ("file", path.display().to_string()),
+        [path] => ("directory", path.display().to_string()),
+        paths => (
+            "paths",
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+    };
+    info!(
+        "imported {} {}, {}, hash {}, {}/s",
+        entry_type,
+        source,
+        HumanBytes(size),
+        print_hash(&hash, opts.format),
+        HumanBytes(((size as f64) / dt.as_secs_f64()).floor() as u64)
+    );
+    if opts.verbose > 1 {
+        for (name, hash) in collection.iter() {
+            info!("    {} {name}", print_hash(hash, opts.format));
+        }
+        info!(
+            "{}s, blake3 {}/s per thread, {} threads",
+            dt.as_secs_f64(),
+            HumanBytes(blake3_throughput()),
+            parallelism,
+        );
+    }
+    // the history is kept per path, so only for shares of a single one
+    if let (Some(dir), [path]) = (cache_dir.as_ref().filter(|_| !stdin), paths.as_slice()) {
+        let mut history = ShareHistory::load(dir)?;
+        let blobs = collection
+            .iter()
+            .map(|(_, hash)| *hash)
+            .zip(sizes.iter().copied())
+            .collect::<Vec<_>>();
+        let previous = history.replace(&path.canonicalize()?, blobs.clone());
+        history.save(dir)?;
+        if let Some(previous) = previous {
+            let previous = previous
+                .into_iter()
+                .map(|(hash, _)| hash)
+                .collect::<BTreeSet<_>>();
+            let (unchanged, new) = blobs
+                .iter()
+                .partition::<Vec<_>, _>(|(hash, _)| previous.contains(hash));
+            let new_size = new.iter().map(|(_, size)| *size).sum::<u64>();
+            eprintln!(
+                "{} of {} files unchanged since the last share of this path, {} of new data",
+                unchanged.len(),
+                blobs.len(),
+                HumanBytes(new_size)
+            );
+        }
+    }
+    // stores that are kept across shares only grow, so remove the data that
+    // was not shared for the longest time
+    if let Some(dir) = &cache_dir {
+        let root = *temp_tag.hash();
+        let keep = std::iter::once(root)
+            .chain(HashSeq::try_from(store.get_bytes(root).await?)?.iter())
+            .collect::<BTreeSet<_>>();
+        let mut usage = CacheUsage::load(dir)?;
+        usage.touch(keep.iter().copied())?;
+        let evicted = usage.evict(&store, cache_size, &keep).await?;
+        usage.save(dir)?;
+        if evicted > 0 {
+            eprintln!(
+                "removed {} of data that was not shared recently from the store",
+                HumanBytes(evicted)
+            );
+        }
+    }
+    let problems = collection
+        .iter()
+        .filter_map(|(name, _)| portability_problem(name).map(|problem| (name, problem)))
+        .collect::<Vec<_>>();
+    for (name, problem) in &problems {
+        eprintln!("{} {name}: {problem}", style("warning:").yellow());
+    }
+    if !problems.is_empty() {
+        eprintln!(
+            "{}",
+            style(format!(
+                "{} entries might fail to export on some platforms, consider renaming them",
+                problems.len()
+            ))
+            .yellow()
+        );
+    }
+    if let Some(checksums) = &opts.checksums {
+        let text = std::fs::read_to_string(checksums)
+            .with_context(|| format!("failed to read checksum file {}", checksums.display()))?;
+        let problems = check_checksums(&collection, &parse_checksums(&text)?);
+        if !problems.is_empty() {
+            for problem in &problems {
+                eprintln!("{} {problem}", style("error:").red());
+            }
+            tokio::time::timeout(Duration::from_secs(2), router.shutdown()).await??;
+            if temp_store {
+                tokio::fs::remove_dir_all(&blobs_data_dir).await?;
+            }
+            anyhow::bail!(
+                "{} problems checking against {}, not sharing",
+                problems.len(),
+                checksums.display()
+            );
+        }
+        if opts.verbose > 0 {
+            eprintln!("all files match {}", checksums.display());
+        }
+    }
+    if opts.self_test {
+        let res = tokio::time::timeout(
+            SELF_TEST_TIMEOUT,
+            self_test(&opts.net, &own_addr, &collection, &auth),
+        )
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+        if let Err(cause) = res {
+            tokio::time::timeout(Duration::from_secs(2), router.shutdown()).await??;
+            if temp_store {
+                tokio::fs::remove_dir_all(&blobs_data_dir).await?;
+            }
+            anyhow::bail!(
+                "self test failed, receivers will probably not reach this provider: {cause}"
+            );
+        }
+        eprintln!("connectivity verified through the relay");
+    }
+    if let Some(manifest_out) = &opts.manifest_out {
+        let manifest = Manifest::new(hash, &collection, &sizes);
+        let json = serde_json::to_string_pretty(&manifest)?;
+        tokio::fs::write(manifest_out, json)
+            .await
+            .with_context(|| format!("failed to write manifest {}", manifest_out.display()))?;
+    }
+
+    let receive_command = format_receive_command(&ticket, do_compress);
+    let ticket2 = ticket.clone();
+    emit(JsonEvent::TicketIssued {
+        ticket: ticket.to_string(),
+        hash: hash.to_hex().to_string(),
+        size,
+    });
+    if stdout_reserved {
+        eprintln!("to get this data, use");
+        eprintln!("{receive_command}");
+        // with --json, the ticket is in the event
+        if print_ticket_only && !json_output() {
+            println!("{ticket}");
+        }
+    } else {
+        println!("to get this data, use");
+        println!("{receive_command}");
+    }
+    // after the ticket, so scripts reading the first lines are not affected
+    if url {
+        info!("or open {}", ticket.to_url());
+    }
+    if qr {
+        let text = if url {
+            ticket.to_url()
+        } else {
+            ticket.to_string()
+        };
+        let qr = render_qr(&text)?;
+        if stdout_reserved {
+            eprintln!("{qr}");
+        } else {
+            println!("{qr}");
+        }
+    }
+    if let Some(name) = &local_name {
+        info!(
+            "or, on the local network, use\nsendme receive{} --local '{name}'",
+            if do_compress { " -z" } else { "" }
+        );
+    }
+    let completions = completed_rx.map(|mut completed| {
+        let command = opts.on_complete.clone();
+        let ticket = ticket.clone();
+        let path = match paths.as_slice() {
+            [path] if !stdin && !opts.tar_in => Some(path.clone()),
+            _ => None,
+        };
+        n0_future::task::spawn(async move {
+            while let Some((peer, bytes)) = completed.recv().await {
+                if notify {
+                    let what = ticket.meta.name.as_deref().unwrap_or("the share");
+                    let body = format!("{} got {what}, {}", peer.fmt_short(), HumanBytes(bytes));
+                    notify_desktop("Download complete", &body).await;
+                }
+                let Some(command) = &command else {
+                    continue;
+                };
+                if let Err(cause) = run_hook(command, &ticket, peer, bytes, path.as_deref()).await {
+                    eprintln!("{} {cause:#}", style("on-complete:").yellow());
+                }
+            }
+        })
+    });
+    let _code = opts.code.then(|| {
+        let code = ShortCode::generate();
+        info!(
+            "or, to get this data with a short code, use\nsendme receive{} --code {code}",
+            if do_compress { " -z" } else { "" }
+        );
+        let ticket = ticket.clone();
+        let mp = mp3.clone();
+        AbortOnDropHandle::new(n0_future::task::spawn(async move {
+            let msg = match offer(&code, &ticket).await {
+                Ok(node_id) => format!("{} got the ticket for {code}", node_id.fmt_short()),
+                Err(cause) => format!("{} {cause}", style("code:").yellow()),
+            };
+            mp.println(msg).ok();
+        }))
+    });
+
+    // kept until the end, since on Linux the clipboard is only served while
+    // its owner is alive
+    #[cfg(feature = "clipboard")]
+    let _copied = if opts.copy {
+        copy_ticket(&ticket, do_compress)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "clipboard")]
+    {
+        use console::{Key, Term};
+
+        // Add command to the clipboard
+        if opts.clipboard {
+            add_to_clipboard(&ticket, do_compress);
+        }
+
+        // with --chat, stdin is for the messages
+        let _keyboard = (!opts.chat).then(|| {
+            tokio::task::spawn(async move {
+                let term = Term::stdout();
+                info!("press c to copy command to clipboard, or use the --clipboard argument");
+                loop {
+                    if let Ok(Key::Char('c')) = term.read_key() {
+                        add_to_clipboard(&ticket, do_compress);
+                    }
+                }
+            })
+        });
+    }
+    if opts.chat {
+        info!("type a message and press enter to send it to the receivers");
+    }
+
+    let _wakeup = AbortOnDropHandle::new(n0_future::task::spawn(rebind_on_wakeup(
+        router.endpoint().clone(),
+    )));
+    let (command_tx, command_rx) = watch::channel(receive_command.clone());
+    let command_tx = Arc::new(command_tx);
+    let current = Arc::new(std::sync::Mutex::new(ticket2));
+    let _addr = AbortOnDropHandle::new(n0_future::task::spawn(update_ticket_on_addr_change(
+        router.endpoint().clone(),
+        current.clone(),
+        opts.ticket_type,
+        do_compress,
+        mp3.clone(),
+        command_tx.clone(),
+    )));
+    let _watch = opts.watch.then(|| {
+        let reimport = Reimport {
+            paths: paths.clone(),
+            store,
+            secret_key: secret_key2,
+            hash_cache,
+            cache_dir: cache_dir.clone(),
+            parallelism,
+            walk: walk2,
+            compress: do_compress,
+            compression_quality,
+            ticket_meta: opts.ticket_meta,
+            shares: shares_tx,
+        };
+        let ignore = blobs_data_dir.clone();
+        let mp = mp3.clone();
+        AbortOnDropHandle::new(n0_future::task::spawn(async move {
+            if let Err(cause) = watch_paths(reimport, ignore, current, mp.clone(), command_tx).await
+            {
+                mp.println(format!("{} {cause:#}", style("watch:").yellow()))
+                    .ok();
+            }
+        }))
+    });
+    let _status = AbortOnDropHandle::new(n0_future::task::spawn(show_provider_status(
+        mp3.clone(),
+        command_rx,
+        served.clone(),
+        auth.expires.get().copied(),
+    )));
+    // this is only printed after the ticket, so it does not push it out of view
+    let _nat = AbortOnDropHandle::new(n0_future::task::spawn(print_nat_type(
+        router.endpoint().clone(),
+    )));
+
+    let expired = async {
+        match opts.expire {
+            Some(expire) => tokio::time::sleep(expire).await,
+            None => std::future::pending().await,
+        }
+        eprintln!("the share expired, stopping");
+    };
+    let res = match &fanout {
+        Some(targets) => select! {
+            res = targets.run(&receive_command, &mp3) => res,
+            _ = expired => Ok(()),
+            _ = interrupted() => Ok(()),
+        },
+        None if opts.wait || opts.once => {
+            let mut served = served.clone();
+            let timeout = opts.timeout.map_or(Duration::MAX, Duration::from_secs);
+            select! {
+                res = tokio::time::timeout(timeout, served.wait_for(|served| *served > 0)) => match res {
+                    Ok(res) => res.map(|_| ()).map_err(anyhow::Error::from),
+                    Err(_) => Err(anyhow::anyhow!("no download completed within the timeout")),
+                },
+                _ = expired => Err(anyhow::anyhow!("the share expired before a download completed")),
+                _ = interrupted() => Err(anyhow::anyhow!("interrupted before a download completed")),
+            }
+        }
+        None => select! {
+            _ = expired => Ok(()),
+            res = interrupted() => res.map_err(anyhow::Error::from),
+        },
+    };
+
+    drop(temp_tag);
+
+    info!("shutting down");
+    tokio::time::timeout(Duration::from_secs(2), router.shutdown()).await??;
+    if temp_store {
+        tokio::fs::remove_dir_all(blobs_data_dir).await?;
+    }
+    // drop everything that owns blobs to close the progress sender
+    drop(router);
+    // await progress completion so the progress bar is cleared
+    if let Ok(Ok(stats)) = progress.await {
+        if stats.total() > 0 {
+            info!("{}", stats.summary(&hash));
+        }
+    }
+    // the hooks and notifications of the last receivers may still be running
+    if let Some(completions) = completions {
+        completions.await.ok();
+    }
+
+    res
+}
+
+/// Put the command to receive `ticket` on the system clipboard, see
+/// [`SendOptions::copy`].
+///
+/// Returns the clipboard, which has to be kept for the command to stay there.
+#[cfg(feature = "clipboard")]
+fn copy_ticket(ticket: &Ticket, compressed: bool) -> Option<arboard::Clipboard> {
+    let res = arboard::Clipboard::new().and_then(|mut clipboard| {
+        clipboard.set_text(format_receive_command(ticket, compressed))?;
+        Ok(clipboard)
+    });
+    match res {
+        Ok(clipboard) => {
+            eprintln!("copied the receive command to the clipboard");
+            Some(clipboard)
+        }
+        Err(cause) => {
+            eprintln!("failed to copy the ticket to the clipboard: {cause}");
+            None
+        }
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn add_to_clipboard(ticket: &Ticket, add_decompress_tag: bool) {
+    use std::io::{stdout, Write};
+
+    use base64::prelude::{Engine, BASE64_STANDARD};
+
+    // Use OSC 52 to copy content to clipboard.
+    print!(
+        "\x1B]52;c;{}\x07",
+        BASE64_STANDARD.encode(format!(
+            "sendme receive{} {ticket}",
+            if add_decompress_tag { " -z" } else { "" }
+        ))
+    );
+
+    stdout()
+        .flush()
+        .unwrap_or_else(|e| eprintln!("Failed to flush stdout: {e}"));
+}
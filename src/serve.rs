@@ -0,0 +1,724 @@
+//! Providing the shares of a persistent store, see [`serve`], and moving them
+//! between stores with archives.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::Local;
+use console::style;
+use data_encoding::HEXLOWER;
+use indicatif::{HumanBytes, MultiProgress, ProgressDrawTarget};
+use iroh::{
+    discovery::pkarr::PkarrPublisher, Endpoint, NodeAddr, NodeId, RelayMode, SecretKey, Watcher,
+};
+use iroh_blobs::{
+    api::Store, net_protocol::Blobs, store::fs::FsStore, ticket::BlobTicket, Hash, HashAndFormat,
+};
+use n0_future::task::AbortOnDropHandle;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncBufReadExt,
+    select,
+    sync::{mpsc, watch},
+};
+
+use crate::{
+    config::{cache_dir, parse_rate, scheduled_rate, validate_schedule, RateWindow},
+    export::{export, ExportOptions, Limits},
+    ext::{pin_tag, Authorized, Budgets, Extensions, LimitedBlobs, RateLimit, EXT_ALPN},
+    hook::hook_command,
+    import::{import, HashCache, WalkOptions},
+    manifest::{load_collection, verify_manifest},
+    metrics::ProviderMetrics,
+    net::NetOptions,
+    output::{interrupted, json_output},
+    pack::{pack, share_hashes, unpack},
+    provide::{record_provide_events, start_metrics},
+    ticket::{apply_options, format_receive_command, AddrInfoOptions},
+    transfer::{load_store, zstd_enabled, SMALL_SHARE_SIZE},
+    Ticket, TicketMeta,
+};
+
+/// What [`serve`] provides and how.
+///
+/// The fields match the options of `sendme serve`, which documents them in
+/// detail.
+#[derive(Debug)]
+pub struct ServeOptions {
+    /// Files or directories to add as shares on startup.
+    pub paths: Vec<PathBuf>,
+    /// Directory of the store and the list of shares, or `serve` in the user
+    /// cache directory.
+    pub dir: Option<PathBuf>,
+    /// What the tickets of the shares contain.
+    pub ticket_type: AddrInfoOptions,
+    /// Put the name and size of shares into their tickets.
+    pub ticket_meta: bool,
+    /// Serve Prometheus metrics on this address.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Nodes that may ask for other data to be pinned.
+    pub allow_pin: Vec<NodeId>,
+    /// Refuse to pin data that is larger than this.
+    pub max_pin_size: u64,
+    /// Command to run whenever data is added to or removed from the store.
+    pub on_event: Option<String>,
+    /// Only answer probes and range checks, and refuse downloads.
+    pub verify_only: bool,
+    /// When to limit the rate of the receivers, unless it is set on stdin.
+    pub schedule: Vec<RateWindow>,
+    /// The key of the providing node.
+    pub secret_key: SecretKey,
+    /// Don't show progress bars.
+    pub no_progress: bool,
+    /// How to bind the endpoint and connect to other nodes.
+    pub net: NetOptions,
+}
+
+/// Which share [`pack_share`] writes into which archive.
+#[derive(Debug)]
+pub struct PackOptions {
+    /// The hex encoded hash of the share.
+    pub hash: String,
+    /// The archive to write.
+    pub output: PathBuf,
+    /// The directory of [`serve`], see [`ServeOptions::dir`].
+    pub dir: Option<PathBuf>,
+}
+
+/// Which archive [`unpack_share`] reads and where it puts the share.
+#[derive(Debug)]
+pub struct UnpackOptions {
+    /// The archive to unpack.
+    pub path: PathBuf,
+    /// Directory to export the files to, instead of the current directory.
+    pub output: Option<PathBuf>,
+    /// Add the share to the shares of [`serve`] instead of exporting it.
+    pub serve: bool,
+    /// The directory of [`serve`], see [`ServeOptions::dir`].
+    pub dir: Option<PathBuf>,
+}
+
+/// Write a share of `sendme serve` into an archive, see `sendme pack`.
+pub async fn pack_share(opts: PackOptions) -> anyhow::Result<()> {
+    let dir = serve_dir(opts.dir)?;
+    let shares = ServedShares::load(&dir)?;
+    let ticket = shares
+        .tickets
+        .get(&opts.hash)
+        .with_context(|| format!("no share with hash {} in {}", opts.hash, dir.display()))?;
+    let ticket = Ticket::from_str(ticket)?;
+    let store = FsStore::load(dir.join("store")).await?;
+    let res = async {
+        let file = tokio::fs::File::create(&opts.output)
+            .await
+            .with_context(|| format!("failed to create {}", opts.output.display()))?;
+        let mut out = tokio::io::BufWriter::new(file);
+        let blobs = pack(&store, &ticket, &mut out).await?;
+        out.into_inner().sync_all().await?;
+        eprintln!("packed {blobs} blobs into {}", opts.output.display());
+        anyhow::Ok(())
+    }
+    .await;
+    store.shutdown().await?;
+    if res.is_err() {
+        tokio::fs::remove_file(&opts.output).await.ok();
+    }
+    res
+}
+
+/// Verify an archive and export it or add it to the shares of `sendme
+/// serve`, see `sendme unpack`.
+pub async fn unpack_share(opts: UnpackOptions) -> anyhow::Result<()> {
+    if opts.serve {
+        let dir = serve_dir(opts.dir)?;
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut shares = ServedShares::load(&dir)?;
+        let store = FsStore::load(dir.join("store")).await?;
+        let res = async {
+            let (ticket, _tags) = unpack(&store, &opts.path).await?;
+            store
+                .tags()
+                .set(Server::tag(&ticket), ticket.hash_and_format())
+                .await?;
+            shares
+                .tickets
+                .insert(ticket.hash().to_hex().to_string(), ticket.to_string());
+            shares.save(&dir)?;
+            anyhow::Ok(ticket)
+        }
+        .await;
+        store.shutdown().await?;
+        let ticket = res?;
+        eprintln!(
+            "added share {}, it is provided by the next sendme serve",
+            ticket.hash().to_hex()
+        );
+        return Ok(());
+    }
+    let target = match opts.output {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+            dir
+        }
+        None => std::env::current_dir()?,
+    };
+    let size = std::fs::metadata(&opts.path)
+        .with_context(|| format!("failed to read {}", opts.path.display()))?
+        .len();
+    let suffix = rand::thread_rng().gen::<[u8; 16]>();
+    let store_dir = target.join(format!(".sendme-unpack-{}", HEXLOWER.encode(&suffix)));
+    let in_memory = size <= SMALL_SHARE_SIZE;
+    let (db, _mem_store) = load_store(&store_dir, in_memory).await?;
+    let res = async {
+        let (ticket, _tags) = unpack(&db, &opts.path).await?;
+        let (collection, meta) = load_collection(&db, &ticket).await?;
+        Limits::default().check(&collection)?;
+        if meta.signature.is_some() {
+            let node_id = ticket.meta.signer.unwrap_or(ticket.node_addr().node_id);
+            verify_manifest(&collection, &meta, node_id)?;
+        }
+        let files = collection.len();
+        let mut mp = MultiProgress::new();
+        let decompress = zstd_enabled(meta.compressed, false);
+        export(
+            &db,
+            collection,
+            &meta,
+            &target,
+            &mut mp,
+            ExportOptions {
+                decompress,
+                postfix_target: decompress != meta.compressed,
+                ..Default::default()
+            },
+        )
+        .await?;
+        eprintln!("unpacked {files} files to {}", target.display());
+        anyhow::Ok(())
+    }
+    .await;
+    db.shutdown().await?;
+    if !in_memory {
+        tokio::fs::remove_dir_all(&store_dir).await?;
+    }
+    res
+}
+
+/// The shares of `sendme serve`, persisted next to its store.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ServedShares {
+    /// Tickets of the shares, by the hex encoded hash.
+    tickets: BTreeMap<String, String>,
+    /// Hex encoded collections of the shares whose tickets are for the single
+    /// raw entry, by the hex encoded hash of the ticket.
+    ///
+    /// The collection holds the names and the metadata of the share.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    roots: BTreeMap<String, String>,
+    /// Tickets of the data pinned with `sendme pin`, by the hex encoded hash.
+    ///
+    /// The tickets are of the node the data was fetched from.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pins: BTreeMap<String, String>,
+}
+
+impl ServedShares {
+    const FILE_NAME: &'static str = "serve.json";
+
+    fn load(dir: &Path) -> anyhow::Result<Self> {
+        match std::fs::read(dir.join(Self::FILE_NAME)) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(cause) => Err(cause.into()),
+        }
+    }
+
+    fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::write(dir.join(Self::FILE_NAME), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    fn tickets(&self) -> anyhow::Result<Vec<Ticket>> {
+        self.tickets
+            .values()
+            .map(|ticket| Ok(Ticket::from_str(ticket)?))
+            .collect()
+    }
+
+    fn pins(&self) -> anyhow::Result<Vec<Ticket>> {
+        self.pins
+            .values()
+            .map(|ticket| Ok(Ticket::from_str(ticket)?))
+            .collect()
+    }
+
+    /// The data that keeps the share of `ticket` in the store, the collection
+    /// if there is one.
+    fn root(&self, ticket: &Ticket) -> anyhow::Result<HashAndFormat> {
+        match self.roots.get(ticket.hash().to_hex().as_str()) {
+            Some(root) => Ok(HashAndFormat::hash_seq(Hash::from_str(root)?)),
+            None => Ok(ticket.hash_and_format()),
+        }
+    }
+
+    /// All blobs of the share of `ticket` in `store`, see [`share_hashes`].
+    async fn hashes(&self, store: &Store, ticket: &Ticket) -> anyhow::Result<Vec<Hash>> {
+        let mut hashes = share_hashes(store, ticket).await?;
+        let root = self.root(ticket)?;
+        if root != ticket.hash_and_format() {
+            let blob = BlobTicket::new(ticket.node_addr().clone(), root.hash, root.format);
+            let root = Ticket::new(blob, TicketMeta::default());
+            hashes.extend(share_hashes(store, &root).await?);
+        }
+        Ok(hashes)
+    }
+}
+
+/// The state of [`serve`].
+struct Server {
+    dir: PathBuf,
+    store: Store,
+    secret_key: SecretKey,
+    /// The address for the tickets of new shares.
+    addr: NodeAddr,
+    shares: ServedShares,
+    hash_cache: HashCache,
+    mp: MultiProgress,
+    /// Whether tickets carry the name and size, see [`ServeOptions::ticket_meta`].
+    ticket_meta: bool,
+    /// The hook to run when shares are added or removed, see
+    /// [`ServeOptions::on_event`].
+    on_event: Option<String>,
+}
+
+impl Server {
+    /// The name of the tag that keeps the data of a share in the store, see
+    /// [`ServedShares::root`].
+    fn tag(ticket: &Ticket) -> String {
+        format!("share-{}", ticket.hash())
+    }
+
+    /// Import `path` as a new share and return its ticket.
+    async fn add(&mut self, path: PathBuf) -> anyhow::Result<Ticket> {
+        let paths = [path];
+        let (temp_tag, size, collection, _, meta) = import(
+            &paths,
+            &self.store,
+            &self.secret_key,
+            &mut self.hash_cache,
+            num_cpus::get(),
+            &mut self.mp,
+            &WalkOptions::default(),
+            false,
+            0,
+        )
+        .await?;
+        self.hash_cache.save(&self.dir)?;
+        let ticket = Ticket::for_share(
+            self.addr.clone(),
+            &paths,
+            *temp_tag.hash(),
+            &collection,
+            size,
+            meta,
+            self.ticket_meta,
+        )?;
+        if ticket.hash() != *temp_tag.hash() {
+            self.shares.roots.insert(
+                ticket.hash().to_hex().to_string(),
+                temp_tag.hash().to_hex().to_string(),
+            );
+        }
+        self.store
+            .tags()
+            .set(Self::tag(&ticket), self.shares.root(&ticket)?)
+            .await?;
+        self.shares
+            .tickets
+            .insert(ticket.hash().to_hex().to_string(), ticket.to_string());
+        self.shares.save(&self.dir)?;
+        let blobs = self.shares.hashes(&self.store, &ticket).await?;
+        self.event("added", &ticket, &blobs, Some(&paths[0])).await;
+        Ok(ticket)
+    }
+
+    /// Keep the data of `ticket`, which was pinned with `sendme pin`.
+    ///
+    /// The data is already in the store and protected by its tag, this
+    /// remembers it across restarts.
+    async fn pin(&mut self, ticket: &Ticket) -> anyhow::Result<()> {
+        self.shares
+            .pins
+            .insert(ticket.hash().to_hex().to_string(), ticket.to_string());
+        self.shares.save(&self.dir)?;
+        let blobs = self.shares.hashes(&self.store, ticket).await?;
+        self.event("pinned", ticket, &blobs, None).await;
+        Ok(())
+    }
+
+    /// Stop providing the share or pinned data with the hex encoded `hash`.
+    ///
+    /// Its data is deleted, except for blobs that other shares or pins contain
+    /// as well.
+    async fn remove(&mut self, hash: &str) -> anyhow::Result<()> {
+        let (ticket, tag) = match self.shares.tickets.remove(hash) {
+            Some(ticket) => {
+                let ticket = Ticket::from_str(&ticket)?;
+                let tag = Self::tag(&ticket);
+                (ticket, tag)
+            }
+            None => {
+                let ticket = self
+                    .shares
+                    .pins
+                    .remove(hash)
+                    .with_context(|| format!("no share with hash {hash}"))?;
+                let ticket = Ticket::from_str(&ticket)?;
+                let tag = pin_tag(&ticket.hash());
+                (ticket, tag)
+            }
+        };
+        let mut keep = BTreeSet::new();
+        for other in self
+            .shares
+            .tickets()?
+            .into_iter()
+            .chain(self.shares.pins()?)
+        {
+            keep.extend(self.shares.hashes(&self.store, &other).await?);
+        }
+        let unused = self
+            .shares
+            .hashes(&self.store, &ticket)
+            .await?
+            .into_iter()
+            .filter(|hash| !keep.contains(hash))
+            .collect::<Vec<_>>();
+        self.shares.roots.remove(hash);
+        self.store.tags().delete(tag).await?;
+        self.store.blobs().delete(unused.clone()).await?;
+        self.shares.save(&self.dir)?;
+        self.event("removed", &ticket, &unused, None).await;
+        Ok(())
+    }
+
+    /// Run the [`ServeOptions::on_event`] hook for `event` of the share of `ticket`.
+    ///
+    /// `blobs` are the blobs the event is about, i.e. the blobs of an added
+    /// share or the deleted blobs of a removed share. A failing hook is only
+    /// reported, the change to the store is done at that point.
+    async fn event(&self, event: &str, ticket: &Ticket, blobs: &[Hash], path: Option<&Path>) {
+        let Some(command) = &self.on_event else {
+            return;
+        };
+        let blobs = blobs
+            .iter()
+            .map(|hash| hash.to_hex().to_string())
+            .collect::<Vec<_>>();
+        let mut hook = hook_command(command);
+        hook.env("SENDME_EVENT", event)
+            .env("SENDME_HASH", ticket.hash().to_hex().as_str())
+            .env("SENDME_TICKET", ticket.to_string())
+            .env("SENDME_BLOBS", blobs.join(" "));
+        if let Some(path) = path {
+            hook.env("SENDME_PATH", path);
+        }
+        let res = match hook.status().await {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(anyhow::anyhow!("the hook failed with {status}")),
+            Err(cause) => Err(anyhow::Error::new(cause).context("failed to run the hook")),
+        };
+        if let Err(cause) = res {
+            eprintln!("{} {cause:#}", style("on-event:").yellow());
+        }
+    }
+}
+
+/// The directory of [`serve`], `dir` or one in the user cache directory.
+fn serve_dir(dir: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    match dir {
+        Some(dir) => Ok(dir),
+        None => Ok(cache_dir()?.join("serve")),
+    }
+}
+
+/// How often `sendme serve` checks the schedule of the config.
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Ask the receivers of `sendme serve` to download at most `rate` bytes per
+/// second, and tell the user if that is a change.
+fn set_serve_rate(rate_tx: &watch::Sender<Option<u64>>, rate: Option<u64>) {
+    let changed = rate_tx.send_if_modified(|current| {
+        let changed = *current != rate;
+        *current = rate;
+        changed
+    });
+    if changed {
+        match rate {
+            Some(rate) => eprintln!("limiting receivers to {}/s", HumanBytes(rate)),
+            None => eprintln!("not limiting receivers"),
+        }
+    }
+}
+
+/// Change a budget of `sendme serve`, with the arguments of a `rate global
+/// RATE` or `rate share HASH RATE` command, and tell the user.
+///
+/// A rate of `off` removes the budget.
+fn set_budget(
+    budgets: &watch::Sender<Budgets>,
+    shares: &ServedShares,
+    args: &str,
+) -> anyhow::Result<()> {
+    let parse = |rate: &str| match rate {
+        "off" => Ok(None),
+        rate => parse_rate(rate).map(Some),
+    };
+    match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["global", rate] => {
+            let rate = parse(rate)?;
+            budgets.send_modify(|budgets| budgets.global = rate);
+            match rate {
+                Some(rate) => {
+                    eprintln!("limiting all receivers together to {}/s", HumanBytes(rate))
+                }
+                None => eprintln!("not limiting all receivers"),
+            }
+        }
+        ["share", hash, rate] => {
+            let hash = Hash::from_str(hash)?;
+            anyhow::ensure!(
+                shares.tickets.contains_key(&hash.to_string()),
+                "no share with hash {hash}"
+            );
+            let rate = parse(rate)?;
+            budgets.send_modify(|budgets| match rate {
+                Some(rate) => {
+                    budgets.shares.insert(hash, rate);
+                }
+                None => {
+                    budgets.shares.remove(&hash);
+                }
+            });
+            match rate {
+                Some(rate) => {
+                    eprintln!(
+                        "limiting the receivers of {hash} together to {}/s",
+                        HumanBytes(rate)
+                    )
+                }
+                None => eprintln!("not limiting the receivers of {hash}"),
+            }
+        }
+        _ => anyhow::bail!("expected rate global RATE or rate share HASH RATE"),
+    }
+    Ok(())
+}
+
+/// Provide the shares of a persistent store until interrupted, see
+/// [`ServeOptions`].
+pub async fn serve(opts: ServeOptions) -> anyhow::Result<()> {
+    let schedule = opts.schedule;
+    validate_schedule(&schedule)?;
+    let dir = serve_dir(opts.dir)?;
+    tokio::fs::create_dir_all(&dir).await?;
+    let secret_key = opts.secret_key.clone();
+    let mut builder = Endpoint::builder()
+        .alpns(vec![iroh_blobs::protocol::ALPN.to_vec(), EXT_ALPN.to_vec()])
+        .secret_key(secret_key.clone())
+        .relay_mode(opts.net.relay_mode());
+    if opts.ticket_type == AddrInfoOptions::Id || opts.net.dns_discovery {
+        builder = builder.add_discovery(PkarrPublisher::n0_dns());
+    }
+    builder = opts.net.bind(builder);
+    let endpoint = builder.bind().await?;
+    let store = FsStore::load(dir.join("store")).await?;
+    let (rate_tx, rate_rx) = watch::channel(None);
+    let (budgets_tx, budgets_rx) = watch::channel(Budgets::default());
+    let limit = RateLimit::new(rate_rx, budgets_rx);
+    // events are needed for the rate limit and the metrics, serve has no progress
+    let (metrics, _metrics_server) = match opts.metrics_addr {
+        Some(addr) => {
+            let metrics = Arc::new(ProviderMetrics::default());
+            let server = start_metrics(addr, metrics.clone()).await?;
+            (Some(metrics), Some(server))
+        }
+        None => (None, None),
+    };
+    let (events, events_rx) = mpsc::channel(32);
+    let verify_only = opts.verify_only.then(|| Store::clone(&store));
+    let _recorder = AbortOnDropHandle::new(n0_future::task::spawn(record_provide_events(
+        events_rx,
+        metrics,
+        limit.clone(),
+        verify_only,
+    )));
+    let blobs = Blobs::new(&store, endpoint.clone(), Some(events));
+    let (pins_tx, mut pins_rx) = mpsc::channel(8);
+    let mp = MultiProgress::new();
+    if opts.no_progress || json_output() {
+        mp.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    let router = iroh::protocol::Router::builder(endpoint.clone())
+        .accept(
+            iroh_blobs::ALPN,
+            LimitedBlobs {
+                blobs: blobs.clone(),
+                limit: limit.clone(),
+            },
+        )
+        .accept(
+            EXT_ALPN,
+            Extensions {
+                message: None,
+                mp: mp.clone(),
+                store: blobs.store().clone(),
+                endpoint: endpoint.clone(),
+                allow_pin: opts.allow_pin,
+                max_pin_size: opts.max_pin_size,
+                pins: Some(pins_tx),
+                nice: false,
+                limit: limit.clone(),
+                auth: Arc::new(Authorized::new([], None, false)),
+                chat: None,
+            },
+        )
+        .spawn();
+    // wait for the endpoint to figure out its address before making tickets
+    if !matches!(opts.net.relay_mode(), RelayMode::Disabled) {
+        let _ = endpoint.home_relay().initialized().await?;
+    }
+    let mut addr = endpoint.node_addr().initialized().await?;
+    apply_options(&mut addr, opts.ticket_type);
+    opts.net.retain_family(&mut addr);
+    let mut server = Server {
+        store: blobs.store().clone(),
+        secret_key,
+        addr,
+        shares: ServedShares::load(&dir)?,
+        hash_cache: HashCache::load(&dir)?,
+        dir,
+        mp,
+        ticket_meta: opts.ticket_meta,
+        on_event: opts.on_event,
+    };
+    // the tickets of earlier runs get the current address
+    for ticket in server.shares.tickets()? {
+        let blob = BlobTicket::new(server.addr.clone(), ticket.hash(), ticket.format());
+        let ticket = Ticket::new(blob, ticket.meta.clone());
+        server
+            .shares
+            .tickets
+            .insert(ticket.hash().to_hex().to_string(), ticket.to_string());
+    }
+    server.shares.save(&server.dir)?;
+    eprintln!(
+        "serving {} shares as {}",
+        server.shares.tickets.len(),
+        server.addr.node_id
+    );
+    for ticket in server.shares.tickets()? {
+        println!("{}", format_receive_command(&ticket, false));
+    }
+    for path in opts.paths {
+        let ticket = server.add(path).await?;
+        println!("{}", format_receive_command(&ticket, false));
+    }
+    // a rate set with the rate command, instead of the schedule of the config
+    let mut override_rate = None;
+    let mut schedule_tick = tokio::time::interval(SCHEDULE_INTERVAL);
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    // keep serving when stdin is closed, e.g. when started in the background
+    let mut stdin_open = true;
+    loop {
+        let line = select! {
+            line = lines.next_line(), if stdin_open => line?,
+            _ = schedule_tick.tick() => {
+                let rate = match override_rate {
+                    Some(rate) => Ok(rate),
+                    None => scheduled_rate(&schedule, Local::now().naive_local()),
+                };
+                match rate {
+                    Ok(rate) => set_serve_rate(&rate_tx, rate),
+                    Err(cause) => eprintln!("{} invalid schedule: {cause:#}", style("error:").red()),
+                }
+                continue;
+            }
+            Some(ticket) = pins_rx.recv() => {
+                if let Err(cause) = server.pin(&ticket).await {
+                    eprintln!("{} {cause:#}", style("error:").red());
+                }
+                continue;
+            }
+            _ = interrupted() => break,
+        };
+        let Some(line) = line else {
+            stdin_open = false;
+            continue;
+        };
+        let line = line.trim();
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let res = match (command, arg.trim()) {
+            ("", _) => Ok(()),
+            ("add", path) if !path.is_empty() => server
+                .add(PathBuf::from(path))
+                .await
+                .map(|ticket| println!("{}", format_receive_command(&ticket, false))),
+            ("remove", hash) if !hash.is_empty() => server.remove(hash).await.map(|()| {
+                if let Ok(hash) = Hash::from_str(hash) {
+                    budgets_tx.send_if_modified(|budgets| budgets.shares.remove(&hash).is_some());
+                }
+                eprintln!("removed {hash}")
+            }),
+            ("rate", "auto") => {
+                override_rate = None;
+                scheduled_rate(&schedule, Local::now().naive_local())
+                    .map(|rate| set_serve_rate(&rate_tx, rate))
+            }
+            ("rate", budget) if budget.starts_with("global ") || budget.starts_with("share ") => {
+                set_budget(&budgets_tx, &server.shares, budget)
+            }
+            ("rate", "off") => {
+                override_rate = Some(None);
+                set_serve_rate(&rate_tx, None);
+                Ok(())
+            }
+            ("rate", rate) if !rate.is_empty() => parse_rate(rate).map(|rate| {
+                override_rate = Some(Some(rate));
+                set_serve_rate(&rate_tx, Some(rate));
+            }),
+            ("list", "") => server.shares.tickets().and_then(|tickets| {
+                for ticket in tickets {
+                    println!(
+                        "{}\t{}\t{ticket}",
+                        ticket.hash(),
+                        ticket.meta.name.as_deref().unwrap_or_default()
+                    );
+                }
+                for ticket in server.shares.pins()? {
+                    println!("{}\tpinned\t{ticket}", ticket.hash());
+                }
+                Ok(())
+            }),
+            _ => Err(anyhow::anyhow!(
+                "unknown command {line:?}, expected add PATH, remove HASH, rate RATE, rate global RATE, rate share HASH RATE or list"
+            )),
+        };
+        if let Err(cause) = res {
+            eprintln!("{} {cause:#}", style("error:").red());
+        }
+    }
+    eprintln!("shutting down");
+    tokio::time::timeout(Duration::from_secs(2), router.shutdown()).await??;
+    store.shutdown().await?;
+    Ok(())
+}
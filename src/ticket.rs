@@ -0,0 +1,325 @@
+//! Tickets, which carry everything needed to get a share.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::Context;
+use data_encoding::BASE32_NOPAD;
+use iroh::{NodeAddr, NodeId};
+use iroh_blobs::{
+    format::collection::Collection, ticket::BlobTicket, BlobFormat, Hash, HashAndFormat,
+};
+use serde::{Deserialize, Serialize};
+
+/// Sendme specific metadata for a share.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareMeta {
+    /// Hex encoded signature of the providing node over the collection, see
+    /// [`sign_manifest`](crate::manifest::sign_manifest).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Hex encoded original bytes of entry names that are not valid unicode.
+    ///
+    /// The entry names are escaped, see
+    /// [`canonicalized_path_to_string`](crate::import::canonicalized_path_to_string).
+    /// Receivers that support byte paths restore the original names from this.
+    /// The names are covered by the signature.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub raw_names: BTreeMap<String, String>,
+    /// Modification times of the entries, in seconds since the unix epoch.
+    ///
+    /// Receivers restore these on export, entries without a time get the time
+    /// of the export.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mtimes: BTreeMap<String, u64>,
+    /// Symlinks of the share, from entry name to the `/` separated link
+    /// target.
+    ///
+    /// Symlinks have no data, so they are not entries of the collection.
+    /// Targets are relative to the directory of the link. Receivers only
+    /// recreate links that stay inside the shared directory.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub symlinks: BTreeMap<String, String>,
+    /// Whether the entries are zstd compressed.
+    ///
+    /// Receivers decompress the entries on export, without needing `--zstd`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compressed: bool,
+    /// Entries that receivers should get before the others, in this order.
+    ///
+    /// E.g. an installer that is useful before its assets arrive. Receivers
+    /// that don't know this get the entries in collection order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub first: Vec<String>,
+    /// Data of small entries, from the hex encoded hash to the base64 encoded
+    /// data, see [`inline_entries`](crate::manifest::inline_entries).
+    ///
+    /// Receivers add the data to their store before downloading, so small
+    /// entries don't need a request each. Receivers that don't know this get
+    /// the entries as usual. This is never part of a ticket.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inline: BTreeMap<String, String>,
+}
+
+/// Metadata carried in a [`Ticket`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TicketMeta {
+    /// Name of the shared file or directory.
+    ///
+    /// For raw shares this is the name the file is exported as, otherwise it is
+    /// only used for display.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Total size of the shared data in bytes, as claimed by the sender.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// MIME type of the shared file, for single file shares.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    /// The node that signed the share, if it is not the node in the ticket.
+    ///
+    /// This is set for tickets of mirrored shares.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<NodeId>,
+    /// When the provider stops serving the share, in seconds since the unix
+    /// epoch, see `sendme send --expire`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    /// Share metadata, for raw shares that can not carry a metadata entry.
+    #[serde(flatten)]
+    pub share: ShareMeta,
+}
+
+/// A ticket for a share.
+///
+/// Tickets without metadata are formatted as plain blob tickets, so they can be
+/// used by any iroh-blobs client and older versions of sendme. This is the
+/// default, see [`Ticket::for_share`]. Tickets with metadata are formatted as
+/// `sendme` followed by the base32 encoding of a version byte, the length
+/// prefixed binary blob ticket and the json encoded [`TicketMeta`].
+///
+/// Both forms are also accepted as `sendme://<ticket>` links, see
+/// [`Ticket::to_url`].
+#[derive(Debug, Clone)]
+pub struct Ticket {
+    pub blob: BlobTicket,
+    pub meta: TicketMeta,
+}
+
+impl Ticket {
+    const PREFIX: &'static str = "sendme";
+    const VERSION: u8 = 1;
+
+    /// Scheme of tickets in the form of links.
+    pub const URL_SCHEME: &'static str = "sendme://";
+
+    pub fn new(blob: BlobTicket, meta: TicketMeta) -> Self {
+        Self { blob, meta }
+    }
+
+    pub fn node_addr(&self) -> &NodeAddr {
+        self.blob.node_addr()
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.blob.hash()
+    }
+
+    pub fn format(&self) -> BlobFormat {
+        self.blob.format()
+    }
+
+    pub fn hash_and_format(&self) -> HashAndFormat {
+        self.blob.hash_and_format()
+    }
+
+    /// The ticket as a `sendme://` link, for chat apps and url handlers.
+    pub fn to_url(&self) -> String {
+        format!("{}{self}", Self::URL_SCHEME)
+    }
+
+    /// Make the ticket for a share of a single file called `name`.
+    ///
+    /// Single files are shared as raw blobs, which saves the receiver the
+    /// round trip for the collection. The name goes into the ticket instead.
+    pub fn for_file(addr: NodeAddr, name: &str, hash: Hash, size: u64, share: ShareMeta) -> Self {
+        // the inlined data would only make the ticket longer
+        let share = ShareMeta {
+            inline: BTreeMap::new(),
+            ..share
+        };
+        let meta = TicketMeta {
+            name: Some(name.to_string()),
+            size: Some(size),
+            mime: mime_guess::from_path(name)
+                .first()
+                .map(|mime| mime.to_string()),
+            signer: None,
+            expires: None,
+            share,
+        };
+        Self::new(BlobTicket::new(addr, hash, BlobFormat::Raw), meta)
+    }
+
+    /// Make the ticket for a share of `paths`, as returned by [`crate::import::import`].
+    ///
+    /// `root` is the hash of the stored collection and `size` the total size of
+    /// the entries. Unless `with_meta` is set, this is a plain ticket for the
+    /// collection, which the share metadata is part of. Otherwise the ticket
+    /// carries the name and size, and single files are shared as raw blobs,
+    /// see [`Ticket::for_file`]. The name of a share of several paths lists
+    /// all of them.
+    pub fn for_share(
+        addr: NodeAddr,
+        paths: &[PathBuf],
+        root: Hash,
+        collection: &Collection,
+        size: u64,
+        share: ShareMeta,
+        with_meta: bool,
+    ) -> anyhow::Result<Self> {
+        if !with_meta {
+            let blob = BlobTicket::new(addr, root, BlobFormat::HashSeq);
+            return Ok(Self::new(blob, TicketMeta::default()));
+        }
+        let ticket = match (collection.iter().next(), paths) {
+            (Some((name, hash)), [path]) if path.is_file() && collection.len() == 1 => {
+                Self::for_file(addr, name, *hash, size, share)
+            }
+            _ => {
+                let mut names = Vec::new();
+                for path in paths {
+                    if let Some(name) = path.canonicalize()?.file_name() {
+                        names.push(name.to_string_lossy().into_owned());
+                    }
+                }
+                let name = (!names.is_empty()).then(|| names.join(", "));
+                let meta = TicketMeta {
+                    name,
+                    size: Some(size),
+                    share: ShareMeta {
+                        inline: BTreeMap::new(),
+                        ..share
+                    },
+                    ..Default::default()
+                };
+                Self::new(BlobTicket::new(addr, root, BlobFormat::HashSeq), meta)
+            }
+        };
+        Ok(ticket)
+    }
+}
+
+impl Display for Ticket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.meta == TicketMeta::default() {
+            return write!(f, "{}", self.blob);
+        }
+        let blob = self.blob.to_string();
+        let blob = blob.strip_prefix("blob").ok_or(std::fmt::Error)?;
+        let blob = BASE32_NOPAD
+            .decode(blob.to_ascii_uppercase().as_bytes())
+            .map_err(|_| std::fmt::Error)?;
+        let meta = serde_json::to_vec(&self.meta).map_err(|_| std::fmt::Error)?;
+        let mut data = vec![Self::VERSION];
+        data.extend_from_slice(&(blob.len() as u16).to_le_bytes());
+        data.extend_from_slice(&blob);
+        data.extend_from_slice(&meta);
+        let data = BASE32_NOPAD.encode(&data).to_ascii_lowercase();
+        write!(f, "{}{data}", Self::PREFIX)
+    }
+}
+
+impl FromStr for Ticket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // links may get a trailing slash from browsers and url handlers
+        let s = match s.get(..Self::URL_SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(Self::URL_SCHEME) => {
+                s[Self::URL_SCHEME.len()..].trim_end_matches('/')
+            }
+            _ => s,
+        };
+        let Some(data) = s.strip_prefix(Self::PREFIX) else {
+            let blob = BlobTicket::from_str(s)?;
+            return Ok(Self::new(blob, TicketMeta::default()));
+        };
+        let data = BASE32_NOPAD
+            .decode(data.to_ascii_uppercase().as_bytes())
+            .context("invalid ticket encoding")?;
+        let (&version, data) = data.split_first().context("empty ticket")?;
+        anyhow::ensure!(
+            version == Self::VERSION,
+            "unsupported ticket version {version}, try updating sendme"
+        );
+        anyhow::ensure!(data.len() >= 2, "ticket too short");
+        let (len, data) = data.split_at(2);
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        anyhow::ensure!(data.len() >= len, "ticket too short");
+        let (blob, meta) = data.split_at(len);
+        let blob = format!("blob{}", BASE32_NOPAD.encode(blob).to_ascii_lowercase());
+        let blob = BlobTicket::from_str(&blob)?;
+        let meta = serde_json::from_slice(meta).context("invalid ticket metadata")?;
+        Ok(Self::new(blob, meta))
+    }
+}
+
+/// Options to configure what is included in a [`NodeAddr`]
+#[derive(
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Default,
+    Debug,
+    derive_more::Display,
+    derive_more::FromStr,
+    Serialize,
+    Deserialize,
+)]
+pub enum AddrInfoOptions {
+    /// Only the Node ID is added.
+    ///
+    /// This usually means that iroh-dns discovery is used to find address information.
+    #[default]
+    Id,
+    /// Includes the Node ID and both the relay URL, and the direct addresses.
+    RelayAndAddresses,
+    /// Includes the Node ID and the relay URL.
+    Relay,
+    /// Includes the Node ID and the direct addresses.
+    Addresses,
+}
+
+/// Remove what `opts` leaves out from `addr`.
+pub fn apply_options(addr: &mut NodeAddr, opts: AddrInfoOptions) {
+    match opts {
+        AddrInfoOptions::Id => {
+            addr.direct_addresses.clear();
+            addr.relay_url = None;
+        }
+        AddrInfoOptions::RelayAndAddresses => {
+            // nothing to do
+        }
+        AddrInfoOptions::Relay => {
+            addr.direct_addresses.clear();
+        }
+        AddrInfoOptions::Addresses => {
+            addr.relay_url = None;
+        }
+    }
+}
+
+/// The command to get the data of `ticket`.
+pub(crate) fn format_receive_command(ticket: &Ticket, compressed: bool) -> String {
+    format!(
+        "sendme receive{} {ticket}",
+        if compressed { " -z" } else { "" }
+    )
+}
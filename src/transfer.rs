@@ -0,0 +1,726 @@
+//! Downloading shares from a provider.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use console::style;
+use futures_buffered::BufferedStreamExt;
+use iroh::{endpoint::Connection, Endpoint, NodeAddr};
+use iroh_blobs::{
+    api::{remote::GetProgressItem, Store},
+    format::collection::Collection,
+    get::{GetError, Stats},
+    protocol::{ChunkRanges, ChunkRangesExt, GetRequest},
+    store::{fs::FsStore, mem::MemStore},
+    Hash, HashAndFormat,
+};
+use n0_future::StreamExt;
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+};
+use tracing::trace;
+
+use crate::{manifest::MAX_META_SIZE, progress::TRACE_TARGET};
+
+/// Print a description of a get error, returning the error.
+pub fn show_get_error(e: GetError) -> GetError {
+    match &e {
+        GetError::NotFound { .. } => {
+            eprintln!("{}", style("send side no longer has a file").yellow())
+        }
+        GetError::RemoteReset { .. } => eprintln!("{}", style("remote reset").yellow()),
+        GetError::NoncompliantNode { .. } => {
+            eprintln!("{}", style("non-compliant remote").yellow())
+        }
+        GetError::Io { source, .. } => eprintln!(
+            "{}",
+            style(format!("generic network error: {source}")).yellow()
+        ),
+        GetError::BadRequest { .. } => eprintln!("{}", style("bad request").yellow()),
+        GetError::LocalFailure { source, .. } => {
+            eprintln!("{} {source:?}", style("local failure").yellow())
+        }
+    }
+    e
+}
+
+/// Round trip times above this multiple of the minimum count as congestion.
+const NICE_RTT_FACTOR: u32 = 2;
+
+/// How often the rate of `--nice` is adjusted to the measured congestion.
+const NICE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lowest rate `--nice` backs off to, in bytes per second.
+const NICE_MIN_RATE: f64 = 16.0 * 1024.0;
+
+/// Backs off reading when the round trip time of a connection inflates, which
+/// means that queues along the path fill up, e.g. due to other traffic, and
+/// limits the rate of reading with a token bucket.
+///
+/// Reading slower lets the flow control window run full, so the sender backs
+/// off as well. One pacer is shared by all requests of a download, so the rate
+/// limit is for all of them together.
+#[derive(Debug)]
+pub struct Pacer {
+    enabled: AtomicBool,
+    /// Minimum round trip time seen so far, in microseconds.
+    min_rtt: AtomicU64,
+    /// Bytes per second to read at most, or 0 for no limit.
+    rate: AtomicU64,
+    /// Bytes per second the provider asks to read at most, or 0 for no limit.
+    requested_rate: AtomicU64,
+    /// The tokens of the bucket in bytes, negative while reading ahead of the
+    /// rate, and when they were last updated.
+    bucket: Mutex<(f64, Instant)>,
+    /// The rate of `--nice` in bytes per second, or 0 while there is no
+    /// congestion, and the bytes read since it was last adjusted, and when.
+    nice: Mutex<(f64, u64, Instant)>,
+}
+
+impl Pacer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            min_rtt: AtomicU64::new(u64::MAX),
+            rate: AtomicU64::new(0),
+            requested_rate: AtomicU64::new(0),
+            bucket: Mutex::new((0.0, Instant::now())),
+            nice: Mutex::new((0.0, 0, Instant::now())),
+        }
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Read at most `rate` bytes per second.
+    pub fn limit(&self, rate: u64) {
+        self.rate.store(rate, Ordering::Relaxed);
+    }
+
+    /// Set the limit the provider asks for, which can change during a download.
+    ///
+    /// The lower of this and [`Pacer::limit`] applies.
+    pub fn request(&self, rate: Option<u64>) {
+        self.requested_rate
+            .store(rate.unwrap_or_default(), Ordering::Relaxed);
+    }
+
+    /// How long to wait after reading `bytes` from `connection`, to stay below
+    /// the rate limit and the rate of `--nice`.
+    ///
+    /// The bucket holds at most one second of data, so a transfer can burst
+    /// by that much after a pause.
+    fn throttle(&self, connection: &Connection, bytes: u64) -> Option<Duration> {
+        let nice = self.nice_rate(connection, bytes);
+        let rate = [
+            self.rate.load(Ordering::Relaxed),
+            self.requested_rate.load(Ordering::Relaxed),
+        ]
+        .into_iter()
+        .map(|rate| rate as f64)
+        .chain([nice])
+        .filter(|rate| *rate > 0.0)
+        .reduce(f64::min)?;
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, updated) = &mut *bucket;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * rate).min(rate);
+        *updated = now;
+        *tokens -= bytes as f64;
+        (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / rate))
+    }
+
+    /// The rate of `--nice` after reading `bytes` from `connection`, or 0 for
+    /// no limit.
+    ///
+    /// While the round trip time is inflated, the rate backs off to the
+    /// measured throughput, scaled down by how much the round trip time is
+    /// inflated. Otherwise it grows by a quarter per [`NICE_INTERVAL`], until
+    /// it no longer limits reading.
+    fn nice_rate(&self, connection: &Connection, bytes: u64) -> f64 {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return 0.0;
+        }
+        let mut nice = self.nice.lock().unwrap();
+        let (rate, read, since) = &mut *nice;
+        *read += bytes;
+        let elapsed = since.elapsed();
+        if elapsed < NICE_INTERVAL {
+            return *rate;
+        }
+        let throughput = *read as f64 / elapsed.as_secs_f64();
+        let rtt = connection.rtt();
+        let rtt_us = rtt.as_micros() as u64;
+        let min_rtt = self
+            .min_rtt
+            .fetch_min(rtt_us, Ordering::Relaxed)
+            .min(rtt_us);
+        let min_rtt = Duration::from_micros(min_rtt);
+        if rtt > min_rtt * NICE_RTT_FACTOR {
+            let inflation = min_rtt.as_secs_f64() / rtt.as_secs_f64();
+            *rate = (throughput * inflation).max(NICE_MIN_RATE);
+        } else if *rate > 0.0 {
+            *rate *= 1.25;
+            // the path is the bottleneck again
+            if *rate > throughput * 2.0 {
+                *rate = 0.0;
+            }
+        }
+        *read = 0;
+        *since = Instant::now();
+        *rate
+    }
+}
+
+/// Execute a get request, reporting the number of bytes read so far to `progress`.
+///
+/// With a `pacer`, reading is delayed while the connection is congested, or
+/// to stay below its rate limit.
+pub async fn execute_get(
+    db: &Store,
+    connection: Connection,
+    request: GetRequest,
+    pacer: Option<&Pacer>,
+    mut progress: impl FnMut(u64),
+) -> anyhow::Result<Stats> {
+    let mut stream = db
+        .remote()
+        .execute_get(connection.clone(), request)
+        .stream();
+    let mut read = 0;
+    while let Some(item) = stream.next().await {
+        trace!(target: TRACE_TARGET, "got item {item:?}");
+        match item {
+            GetProgressItem::Progress(offset) => {
+                progress(offset);
+                let Some(pacer) = pacer else {
+                    continue;
+                };
+                let delay = pacer.throttle(&connection, offset.saturating_sub(read));
+                read = offset;
+                if let Some(delay) = delay {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            GetProgressItem::Done(stats) => return Ok(stats),
+            GetProgressItem::Error(cause) => anyhow::bail!(show_get_error(cause)),
+        }
+    }
+    Ok(Stats::default())
+}
+
+/// Get the hash seq and the collection metadata of a collection, but none of
+/// the files.
+///
+/// `sizes` are the sizes of the hash seq and its children, as returned by
+/// `get_hash_seq_and_sizes`. Senders put the
+/// [`META_NAME`](crate::manifest::META_NAME) entry last, so the last child is
+/// got in the same request if it claims to be at most [`MAX_META_SIZE`]. No
+/// more than that is requested, in case the claim is wrong.
+pub async fn get_collection(
+    db: &Store,
+    connection: Connection,
+    hash: Hash,
+    sizes: &[u64],
+) -> anyhow::Result<Collection> {
+    let mut request = GetRequest::builder()
+        .root(ChunkRanges::all())
+        .child(0, ChunkRanges::all());
+    // the first size is of the hash seq, and the first child are the names
+    let last = sizes.len().saturating_sub(2) as u64;
+    if last > 0 && sizes.last().is_some_and(|size| *size <= MAX_META_SIZE) {
+        request = request.child(last, ChunkRanges::bytes(..MAX_META_SIZE));
+    }
+    execute_get(db, connection, request.build(hash), None, |_| {}).await?;
+    Collection::load(hash, db).await
+}
+
+/// Lets the user skip the file that is currently downloading.
+#[derive(Debug)]
+pub struct Skipper {
+    /// Number of times the user asked to skip so far.
+    presses: watch::Sender<u64>,
+    /// Number of presses that were taken by a download, or that were made
+    /// before the downloads that are still waiting started.
+    taken: AtomicU64,
+    /// Children that were skipped, so they are not requested again after a reconnect.
+    skipped: std::sync::Mutex<BTreeSet<Hash>>,
+    /// Children that are never skipped, like the collection metadata.
+    keep: std::sync::Mutex<BTreeSet<Hash>>,
+}
+
+impl Default for Skipper {
+    fn default() -> Self {
+        Self {
+            presses: watch::channel(0).0,
+            taken: AtomicU64::new(0),
+            skipped: Default::default(),
+            keep: Default::default(),
+        }
+    }
+}
+
+impl Skipper {
+    /// Skip the current file whenever `s` is pressed, until the returned
+    /// [`KeyListener`] is dropped.
+    ///
+    /// The terminal reads single keys without echoing them while listening.
+    /// Control-C still interrupts.
+    #[cfg(unix)]
+    pub fn listen(self: Arc<Self>) -> anyhow::Result<KeyListener> {
+        use std::{io::BufRead, os::fd::AsFd};
+
+        use nix::{
+            errno::Errno,
+            poll::{poll, PollFd, PollFlags, PollTimeout},
+            sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg},
+        };
+
+        let stdin = std::io::stdin();
+        let original = tcgetattr(stdin.as_fd())?;
+        let mut termios = original.clone();
+        termios.local_flags &= !(LocalFlags::ICANON | LocalFlags::ECHO);
+        tcsetattr(stdin.as_fd(), SetArg::TCSANOW, &termios)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    // wake up regularly, to notice when to stop
+                    let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+                    match poll(&mut fds, PollTimeout::from(100u16)) {
+                        Ok(0) | Err(Errno::EINTR) => continue,
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                    let mut stdin = stdin.lock();
+                    let Ok(buf) = stdin.fill_buf() else { break };
+                    if buf.is_empty() {
+                        break;
+                    }
+                    let presses = buf.iter().filter(|&&b| b == b's').count() as u64;
+                    let len = buf.len();
+                    stdin.consume(len);
+                    if presses > 0 {
+                        self.presses.send_modify(|n| *n += presses);
+                    }
+                }
+            }
+        });
+        Ok(KeyListener {
+            stop,
+            thread: Some(thread),
+            original,
+        })
+    }
+
+    /// Never skip `hash`.
+    pub fn keep(&self, hash: Hash) {
+        self.keep.lock().unwrap().insert(hash);
+    }
+
+    fn is_skipped(&self, hash: &Hash) -> bool {
+        self.skipped.lock().unwrap().contains(hash)
+    }
+
+    /// Wait until the user asks to skip `hash`, and mark it as skipped.
+    ///
+    /// Each press skips one of the downloads that are waiting. Presses from
+    /// before the download started are ignored.
+    async fn skipped(&self, hash: Hash) {
+        if self.keep.lock().unwrap().contains(&hash) {
+            return std::future::pending().await;
+        }
+        let mut presses = self.presses.subscribe();
+        let start = *presses.borrow_and_update();
+        while presses.changed().await.is_ok() {
+            let pressed = *presses.borrow_and_update();
+            let mut taken = self.taken.load(Ordering::Relaxed);
+            loop {
+                let next = taken.max(start) + 1;
+                if next > pressed {
+                    break;
+                }
+                match self
+                    .taken
+                    .compare_exchange(taken, next, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        self.skipped.lock().unwrap().insert(hash);
+                        return;
+                    }
+                    Err(current) => taken = current,
+                }
+            }
+        }
+        std::future::pending().await
+    }
+
+    /// Take the children that were skipped so far.
+    pub fn take_skipped(&self) -> BTreeSet<Hash> {
+        std::mem::take(&mut *self.skipped.lock().unwrap())
+    }
+}
+
+/// Reads keys for a [`Skipper`], see [`Skipper::listen`].
+///
+/// Dropping it stops reading keys and restores the terminal.
+#[cfg(unix)]
+pub struct KeyListener {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    original: nix::sys::termios::Termios,
+}
+
+#[cfg(unix)]
+impl Drop for KeyListener {
+    fn drop(&mut self) {
+        use std::os::fd::AsFd;
+
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        let stdin = std::io::stdin();
+        nix::sys::termios::tcsetattr(
+            stdin.as_fd(),
+            nix::sys::termios::SetArg::TCSANOW,
+            &self.original,
+        )
+        .ok();
+    }
+}
+
+/// Download a collection with up to `depth` requests in flight.
+///
+/// The hash sequence is requested first, then every child in `children` that
+/// is not yet complete is requested on its own stream. `progress` receives the total number
+/// of bytes read so far, starting at `progress_base`. Returns the number of
+/// bytes read.
+///
+/// If a `skipper` is given, one of the children that are currently downloading
+/// is abandoned whenever it is notified. The `pacer` is passed to [`execute_get`].
+#[allow(clippy::too_many_arguments)]
+pub async fn get_pipelined(
+    db: &Store,
+    connection: Connection,
+    root: Hash,
+    children: &[Hash],
+    depth: usize,
+    progress: mpsc::Sender<u64>,
+    progress_base: u64,
+    skipper: Option<Arc<Skipper>>,
+    pacer: Arc<Pacer>,
+) -> anyhow::Result<u64> {
+    let root_stats = execute_get(
+        db,
+        connection.clone(),
+        GetRequest::blob(root),
+        Some(&pacer),
+        |_| {},
+    )
+    .await?;
+    let mut requests = Vec::new();
+    for &hash in children {
+        if skipper.as_ref().is_some_and(|s| s.is_skipped(&hash)) {
+            continue;
+        }
+        let local = db.remote().local(HashAndFormat::raw(hash)).await?;
+        if !local.is_complete() {
+            requests.push((hash, local.missing()));
+        }
+    }
+    let total_read = Arc::new(AtomicU64::new(progress_base));
+    let results = n0_future::stream::iter(requests)
+        .map(|(hash, request)| {
+            let db = db.clone();
+            let connection = connection.clone();
+            let progress = progress.clone();
+            let total_read = total_read.clone();
+            let skipper = skipper.clone();
+            let pacer = pacer.clone();
+            async move {
+                let mut last = 0;
+                let get = execute_get(&db, connection, request, Some(&pacer), |offset| {
+                    let delta = offset.saturating_sub(last);
+                    last = offset;
+                    let total = total_read.fetch_add(delta, Ordering::Relaxed) + delta;
+                    progress.try_send(total).ok();
+                });
+                let Some(skipper) = skipper else {
+                    return anyhow::Ok(get.await?.total_bytes_read());
+                };
+                select! {
+                    stats = get => anyhow::Ok(stats?.total_bytes_read()),
+                    _ = skipper.skipped(hash) => Ok(0),
+                }
+            }
+        })
+        .buffered_unordered(depth)
+        .collect::<Vec<_>>()
+        .await;
+    let mut bytes_read = root_stats.total_bytes_read();
+    for res in results {
+        bytes_read += res?;
+    }
+    Ok(bytes_read)
+}
+
+/// Size of the parts that blobs are split into by [`get_swarm`].
+const SWARM_PART_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The missing ranges of the raw blob `hash`.
+async fn missing_ranges(db: &Store, hash: Hash) -> anyhow::Result<ChunkRanges> {
+    let local = db.remote().local(HashAndFormat::raw(hash)).await?;
+    let missing = local.missing();
+    Ok(missing
+        .ranges
+        .iter_infinite()
+        .next()
+        .cloned()
+        .unwrap_or_default())
+}
+
+/// Number of bytes of a blob of `size` bytes that are in `ranges`.
+fn range_bytes(ranges: &ChunkRanges, size: u64) -> u64 {
+    ranges
+        .boundaries()
+        .chunks(2)
+        .map(|range| {
+            let start = range[0].to_bytes().min(size);
+            let end = range.get(1).map_or(size, |end| end.to_bytes().min(size));
+            end - start
+        })
+        .sum()
+}
+
+/// A part of a blob for [`get_swarm`].
+struct SwarmPart {
+    hash: Hash,
+    size: u64,
+    ranges: ChunkRanges,
+    /// Bytes of the part that were already passed to the progress.
+    counted: u64,
+}
+
+/// Download `blobs`, given with their sizes, from several providers at once.
+///
+/// The blobs that are not yet complete are split into parts of
+/// [`SWARM_PART_SIZE`], and every provider requests the next part whenever it
+/// finished one, so faster providers serve more. Only the missing ranges of a
+/// part are requested. The part of a provider that fails is requested from the
+/// others, and the progress is corrected to what the failed provider stored.
+/// Fails once all providers failed. `progress` receives the total number of
+/// bytes read so far, starting at `progress_base`. Returns the number of bytes
+/// read.
+pub async fn get_swarm(
+    db: &Store,
+    connections: Vec<Connection>,
+    blobs: &[(Hash, u64)],
+    progress: mpsc::Sender<u64>,
+    progress_base: u64,
+    pacer: Arc<Pacer>,
+) -> anyhow::Result<u64> {
+    let mut parts = VecDeque::new();
+    for &(hash, size) in blobs {
+        let missing = missing_ranges(db, hash).await?;
+        if missing.is_empty() {
+            continue;
+        }
+        let mut start = 0;
+        loop {
+            let end = start + SWARM_PART_SIZE;
+            // the last part is open, so it includes the proof of the size
+            let ranges = if end >= size {
+                ChunkRanges::bytes(start..)
+            } else {
+                ChunkRanges::bytes(start..end)
+            };
+            // data that is already there is part of `progress_base`
+            let counted = range_bytes(&ranges, size) - range_bytes(&(&ranges & &missing), size);
+            parts.push_back(SwarmPart {
+                hash,
+                size,
+                ranges,
+                counted,
+            });
+            if end >= size {
+                break;
+            }
+            start = end;
+        }
+    }
+    let parts = std::sync::Mutex::new(parts);
+    let total_read = AtomicU64::new(progress_base);
+    let mut connections = connections;
+    let mut bytes_read = 0;
+    loop {
+        // the parts of a failed provider go back to the queue, possibly after
+        // the others finished, so go around until the queue stays empty
+        let results = futures_buffered::join_all(connections.into_iter().map(|connection| {
+            let parts = &parts;
+            let total_read = &total_read;
+            let progress = progress.clone();
+            let pacer = pacer.clone();
+            async move {
+                let mut bytes_read = 0;
+                loop {
+                    let Some(mut part) = parts.lock().unwrap().pop_front() else {
+                        return (connection, Ok(bytes_read));
+                    };
+                    // a failed provider may have stored some of the part
+                    let missing = match missing_ranges(db, part.hash).await {
+                        Ok(missing) => &part.ranges & &missing,
+                        Err(cause) => {
+                            parts.lock().unwrap().push_back(part);
+                            return (connection, Err(cause));
+                        }
+                    };
+                    let stored =
+                        range_bytes(&part.ranges, part.size) - range_bytes(&missing, part.size);
+                    if stored > part.counted {
+                        total_read.fetch_add(stored - part.counted, Ordering::Relaxed);
+                    } else {
+                        total_read.fetch_sub(part.counted - stored, Ordering::Relaxed);
+                    }
+                    part.counted = stored;
+                    if missing.is_empty() {
+                        continue;
+                    }
+                    let request = GetRequest::builder().root(missing).build(part.hash);
+                    let mut last = 0;
+                    let get =
+                        execute_get(db, connection.clone(), request, Some(&pacer), |offset| {
+                            let delta = offset.saturating_sub(last);
+                            last = offset;
+                            let total = total_read.fetch_add(delta, Ordering::Relaxed) + delta;
+                            progress.try_send(total).ok();
+                        });
+                    match get.await {
+                        Ok(stats) => bytes_read += stats.total_bytes_read(),
+                        Err(cause) => {
+                            eprintln!(
+                                "{}",
+                                style(format!("a provider failed ({cause}), using the others"))
+                                    .yellow()
+                            );
+                            // corrected by the provider that gets the part next
+                            part.counted += last;
+                            parts.lock().unwrap().push_back(part);
+                            return (connection, Err(cause));
+                        }
+                    }
+                }
+            }
+        }))
+        .await;
+        connections = Vec::new();
+        let mut last_error = None;
+        for (connection, res) in results {
+            match res {
+                Ok(read) => {
+                    bytes_read += read;
+                    connections.push(connection);
+                }
+                Err(cause) => last_error = Some(cause),
+            }
+        }
+        if parts.lock().unwrap().is_empty() {
+            return Ok(bytes_read);
+        }
+        if connections.is_empty() {
+            return Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no providers")));
+        }
+    }
+}
+
+/// Whether a download error is likely caused by a lost connection.
+pub fn is_connection_error(cause: &anyhow::Error) -> bool {
+    matches!(
+        cause.downcast_ref::<GetError>(),
+        Some(GetError::Io { .. } | GetError::RemoteReset { .. })
+    )
+}
+
+/// Reconnect to the sender after the connection was lost.
+///
+/// Retries with exponential backoff until `timeout` has elapsed, so a sender
+/// that is suspended for a while only pauses the download.
+pub async fn reconnect(
+    endpoint: &Endpoint,
+    addr: &NodeAddr,
+    timeout: Duration,
+) -> anyhow::Result<Connection> {
+    let start = Instant::now();
+    let mut delay = Duration::from_secs(1);
+    loop {
+        tokio::time::sleep(delay).await;
+        match endpoint
+            .connect(addr.clone(), iroh_blobs::protocol::ALPN)
+            .await
+        {
+            Ok(connection) => {
+                eprintln!("reconnected");
+                return Ok(connection);
+            }
+            Err(cause) if start.elapsed() < timeout => {
+                trace!("reconnecting failed: {cause}");
+                delay = (delay * 2).min(Duration::from_secs(30));
+            }
+            Err(cause) => {
+                return Err(anyhow::Error::from(cause).context("unable to reconnect to sender"))
+            }
+        }
+    }
+}
+
+/// Whether to compress or decompress with zstd, warning if it was requested
+/// but this build does not support it.
+pub(crate) fn zstd_enabled(zstd_requested: bool, _is_sending: bool) -> bool {
+    #[cfg(feature = "zstd")]
+    return zstd_requested;
+
+    #[cfg(not(feature = "zstd"))]
+    {
+        if zstd_requested {
+            if _is_sending {
+                eprintln!(
+                    "Warning: --zstd ignored (no support in this build). Sending uncompressed."
+                );
+            } else {
+                eprintln!("Warning: This build does not support zstd decompression. Files will be saved with a `.zst` extension. You can manually decompress them using `unzstd <filename>`.");
+            }
+        }
+        return false;
+    }
+}
+
+/// Shares up to this size are kept in memory on both sides instead of in a
+/// store on disk, since for small shares the file system dominates the latency.
+pub(crate) const SMALL_SHARE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Load the store in `dir`, or a store in memory if `in_memory` is set.
+///
+/// The store in memory lives as long as the returned [`MemStore`].
+pub(crate) async fn load_store(
+    dir: &Path,
+    in_memory: bool,
+) -> anyhow::Result<(Store, Option<MemStore>)> {
+    if in_memory {
+        let store = MemStore::new();
+        Ok(((*store).clone(), Some(store)))
+    } else {
+        tokio::fs::create_dir_all(dir).await?;
+        let store = FsStore::load(dir).await?;
+        Ok(((*store).clone(), None))
+    }
+}
@@ -1,11 +1,9 @@
 use std::{
-    io::{self, Read},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
     str::FromStr,
 };
 
-use iroh_blobs::ticket::BlobTicket;
-
 // binary path
 fn sendme_bin() -> &'static str {
     env!("CARGO_BIN_EXE_sendme")
@@ -35,6 +33,37 @@ fn read_ascii_lines(mut n: usize, reader: &mut impl Read) -> io::Result<Vec<u8>>
     Ok(res)
 }
 
+/// Start `sendme send` in `dir` with `args`, returning the running sender and
+/// the ticket it printed.
+fn spawn_send(dir: &Path, args: &[&str]) -> (duct::ReaderHandle, String) {
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        std::iter::once("send").chain(args.iter().copied()),
+    )
+    .dir(dir)
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap().to_string();
+    (send_cmd, ticket)
+}
+
+/// Run `sendme receive` in `dir` with `args` until it is done.
+fn run_receive(dir: &Path, args: &[&str]) -> std::process::Output {
+    duct::cmd(
+        sendme_bin(),
+        std::iter::once("receive").chain(args.iter().copied()),
+    )
+    .dir(dir)
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .run()
+    .unwrap()
+}
+
 // fn wait2() -> Arc<Barrier> {
 //     Arc::new(Barrier::new(2))
 // }
@@ -65,8 +94,9 @@ fn send_recv_file() {
     let output = read_ascii_lines(3, &mut send_cmd).unwrap();
     let output = String::from_utf8(output).unwrap();
     let ticket = output.split_ascii_whitespace().last().unwrap();
-    let ticket = BlobTicket::from_str(ticket).unwrap();
-    let receive_output = duct::cmd(sendme_bin(), ["receive", &ticket.to_string()])
+    // plain tickets work with any iroh-blobs client
+    assert!(ticket.starts_with("blob"));
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
         .dir(tgt_dir.path())
         .env_remove("RUST_LOG") // disable tracing
         .stderr_to_stdout()
@@ -78,6 +108,28 @@ fn send_recv_file() {
     assert_eq!(tgt_data, data);
 }
 
+#[test]
+fn send_recv_ticket_meta() {
+    let name = "somefile.txt";
+    let data = vec![b'a'; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[src_file.as_os_str().to_str().unwrap(), "--ticket-meta"],
+    );
+    // single files are shared as raw blobs, with the name in the ticket
+    let parsed = sendme::Ticket::from_str(&ticket).unwrap();
+    assert!(ticket.starts_with("sendme"));
+    assert_eq!(parsed.meta.name.as_deref(), Some(name));
+    assert_eq!(parsed.meta.mime.as_deref(), Some("text/plain"));
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+}
+
 #[test]
 fn send_recv_dir() {
     fn create_file(base: &Path, i: usize, j: usize, k: usize) -> (PathBuf, Vec<u8>) {
@@ -117,8 +169,7 @@ fn send_recv_dir() {
     let output = read_ascii_lines(3, &mut send_cmd).unwrap();
     let output = String::from_utf8(output).unwrap();
     let ticket = output.split_ascii_whitespace().last().unwrap();
-    let ticket = BlobTicket::from_str(ticket).unwrap();
-    let receive_output = duct::cmd(sendme_bin(), ["receive", &ticket.to_string()])
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
         .dir(tgt_dir.path())
         .env_remove("RUST_LOG") // disable tracing
         .stderr_to_stdout()
@@ -136,3 +187,2758 @@ fn send_recv_dir() {
         }
     }
 }
+
+#[test]
+fn recv_resume_with_new_ticket() {
+    let name = "somefile.bin";
+    let data = vec![16u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &[src_file.as_os_str().to_str().unwrap()]);
+    // a checkpoint of an earlier share of the same data, by a sender that is gone
+    let mut previous = sendme::Ticket::from_str(&ticket).unwrap();
+    previous.blob = iroh_blobs::ticket::BlobTicket::new(
+        iroh::SecretKey::generate(rand::rngs::OsRng).public().into(),
+        previous.hash(),
+        previous.format(),
+    );
+    let checkpoint = tgt_dir.path().join("download.checkpoint");
+    std::fs::write(
+        &checkpoint,
+        format!(r#"{{"ticket":"{previous}","completed":[],"partial":[]}}"#),
+    )
+    .unwrap();
+    let receive_output = run_receive(
+        tgt_dir.path(),
+        &[
+            &ticket,
+            "--resume",
+            checkpoint.as_os_str().to_str().unwrap(),
+        ],
+    );
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+}
+
+#[test]
+fn send_recv_multiple_paths() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"a").unwrap();
+    std::fs::create_dir_all(src_dir.path().join("photos")).unwrap();
+    std::fs::write(src_dir.path().join("photos/b.jpg"), b"b").unwrap();
+    let mut send_cmd = duct::cmd(sendme_bin(), ["send", "a.txt", "photos"])
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .reader()
+        .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join("a.txt")).unwrap(), b"a");
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("photos/b.jpg")).unwrap(),
+        b"b"
+    );
+}
+
+#[test]
+fn send_recv_first() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let shared = src_dir.path().join("app");
+    std::fs::create_dir_all(shared.join("assets")).unwrap();
+    std::fs::write(shared.join("assets/a.bin"), vec![1u8; 100_000]).unwrap();
+    std::fs::write(shared.join("setup.exe"), b"setup").unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &["--first", "setup.exe", "app"]);
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("app/setup.exe")).unwrap(),
+        b"setup"
+    );
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("app/assets/a.bin")).unwrap(),
+        vec![1u8; 100_000]
+    );
+}
+
+#[test]
+fn send_recv_only() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let shared = src_dir.path().join("app");
+    std::fs::create_dir_all(shared.join("docs/api")).unwrap();
+    std::fs::write(shared.join("docs/api/index.md"), b"index").unwrap();
+    std::fs::write(shared.join("manual.pdf"), b"manual").unwrap();
+    std::fs::write(shared.join("big.bin"), vec![1u8; 100_000]).unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &["app"]);
+    let receive_output = run_receive(
+        tgt_dir.path(),
+        &[&ticket, "--only", "app/docs/**", "--only", "*.pdf"],
+    );
+    assert!(receive_output.status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("app/docs/api/index.md")).unwrap(),
+        b"index"
+    );
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("app/manual.pdf")).unwrap(),
+        b"manual"
+    );
+    assert!(!tgt_dir.path().join("app/big.bin").exists());
+}
+
+#[test]
+fn recv_mirror_only_fails() {
+    let output = duct::cmd(sendme_bin(), ["receive", "--mirror", "--only", "*.txt"])
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("cannot be used with"), "{stdout}");
+}
+
+#[test]
+fn recv_list_entries() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let shared = src_dir.path().join("data");
+    std::fs::create_dir_all(shared.join("sub")).unwrap();
+    std::fs::write(shared.join("b.txt"), vec![0u8; 10]).unwrap();
+    std::fs::write(shared.join("sub/a.txt"), vec![0u8; 20]).unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &["data"]);
+    let output = duct::cmd(sendme_bin(), ["receive", &ticket, "--list"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().map(str::trim).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        ["10 data/b.txt", "20 data/sub/a.txt", "2 files, 30 B"]
+    );
+    // nothing was downloaded
+    assert_eq!(std::fs::read_dir(tgt_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn recv_list_with_password() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let shared = src_dir.path().join("data");
+    std::fs::create_dir_all(&shared).unwrap();
+    std::fs::write(shared.join("b.txt"), vec![0u8; 10]).unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &["data", "--password", "secret"]);
+    let output = duct::cmd(
+        sendme_bin(),
+        ["receive", &ticket, "--list", "--password", "secret"],
+    )
+    .dir(tgt_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdout_capture()
+    .run()
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("10 data/b.txt"), "{stdout}");
+    // verifying against the ticket needs the password as well
+    let output = duct::cmd(
+        sendme_bin(),
+        ["verify", "--ticket", &ticket, "--password", "secret"],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .stdout_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+}
+
+#[test]
+fn recv_review_discards_files() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(src_dir.path().join("dir")).unwrap();
+    std::fs::write(src_dir.path().join("dir/a.txt"), b"a").unwrap();
+    std::fs::write(src_dir.path().join("dir/b.iso"), b"b").unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &["dir"]);
+    // the entries are listed by name, so the second one is b.iso
+    let receive_output = duct::cmd(sendme_bin(), ["receive", "--review", &ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdin_bytes("2\n\n")
+        .stderr_to_stdout()
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let stdout = String::from_utf8_lossy(&receive_output.stdout);
+    assert!(stdout.contains("discarded dir/b.iso"), "{stdout}");
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("dir/a.txt")).unwrap(),
+        b"a"
+    );
+    assert!(!tgt_dir.path().join("dir/b.iso").exists());
+}
+
+#[test]
+fn send_same_name_twice_fails() {
+    let src_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(src_dir.path().join("x")).unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(src_dir.path().join("x/a.txt"), b"b").unwrap();
+    let output = duct::cmd(sendme_bin(), ["send", "--dry-run", "a.txt", "x/a.txt"])
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("have the same name"), "{stdout}");
+}
+
+#[test]
+fn send_relay_map_needs_relays() {
+    let src_dir = tempfile::tempdir().unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(src_dir.path().join("relays.txt"), "# no relays yet\n\n").unwrap();
+    let output = duct::cmd(
+        sendme_bin(),
+        ["send", "--dry-run", "a.txt", "--relay-map", "relays.txt"],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .stdout_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("contains no relays"), "{stdout}");
+}
+
+#[test]
+fn send_socks_proxy_rejected() {
+    let src_dir = tempfile::tempdir().unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"a").unwrap();
+    let output = duct::cmd(
+        sendme_bin(),
+        ["send", "a.txt", "--proxy", "socks5://127.0.0.1:1080"],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .stdout_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("SOCKS proxies are not supported"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn send_recv_verify_manifest() {
+    let name = "somefile.bin";
+    let data = vec![1u8; 1000];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    let manifest = src_dir.path().join("manifest.json");
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--manifest-out",
+            manifest.as_os_str().to_str().unwrap(),
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let manifest_json: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&manifest).unwrap()).unwrap();
+    assert_eq!(manifest_json["entries"][0]["name"].as_str().unwrap(), name);
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let verify_output = duct::cmd(
+        sendme_bin(),
+        [
+            "verify",
+            "--manifest",
+            manifest.as_os_str().to_str().unwrap(),
+        ],
+    )
+    .dir(tgt_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(verify_output.status.success());
+    // corrupt the file and check that verification fails
+    std::fs::write(tgt_dir.path().join(name), b"corrupted").unwrap();
+    let verify_output = duct::cmd(
+        sendme_bin(),
+        [
+            "verify",
+            "--manifest",
+            manifest.as_os_str().to_str().unwrap(),
+        ],
+    )
+    .dir(tgt_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!verify_output.status.success());
+}
+
+#[test]
+fn send_recv_verify_ticket() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(src_dir.path().join("data")).unwrap();
+    std::fs::write(src_dir.path().join("data/a.txt"), b"a").unwrap();
+    std::fs::write(src_dir.path().join("data/b.txt"), b"b").unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &["data"]);
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    // the received data is checked where it was copied to
+    let copy_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(copy_dir.path().join("data")).unwrap();
+    std::fs::copy(
+        tgt_dir.path().join("data/a.txt"),
+        copy_dir.path().join("data/a.txt"),
+    )
+    .unwrap();
+    std::fs::write(copy_dir.path().join("data/b.txt"), b"corrupted").unwrap();
+    let verify_output = duct::cmd(
+        sendme_bin(),
+        [
+            "verify",
+            "--ticket",
+            &ticket,
+            copy_dir.path().to_str().unwrap(),
+        ],
+    )
+    .env_remove("RUST_LOG") // disable tracing
+    .stdout_capture()
+    .stderr_null()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!verify_output.status.success());
+    let stdout = String::from_utf8(verify_output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines, ["ok data/a.txt", "mismatch data/b.txt"]);
+}
+
+#[test]
+fn send_recv_print_ticket_only() {
+    let name = "somefile.bin";
+    let data = vec![2u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--print-ticket-only",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_null()
+    .reader()
+    .unwrap();
+    // the ticket is the first and only line on stdout
+    let output = read_ascii_lines(1, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.trim();
+    assert!(!ticket.contains(char::is_whitespace));
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+#[test]
+fn send_wait_exits_after_download() {
+    let name = "somefile.bin";
+    let data = vec![3u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--wait",
+            "--timeout",
+            "60",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    // reading to the end fails if the sender exits with an error
+    let mut rest = Vec::new();
+    send_cmd.read_to_end(&mut rest).unwrap();
+}
+
+#[test]
+fn send_wait_ignores_list() {
+    let name = "somefile.bin";
+    // too large to be inlined into the metadata, which --list gets
+    let data = vec![3u8; 100_000];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (mut send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--wait",
+            "--timeout",
+            "10",
+        ],
+    );
+    let list_output = duct::cmd(sendme_bin(), ["receive", "--list", &ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert!(list_output.status.success());
+    // the sender runs into the timeout, since listing is not a download
+    let mut rest = Vec::new();
+    assert!(send_cmd.read_to_end(&mut rest).is_err());
+    let rest = String::from_utf8_lossy(&rest);
+    assert!(rest.contains("no download completed"), "{rest}");
+}
+
+#[test]
+fn send_once_exits_after_download() {
+    let name = "somefile.bin";
+    let data = vec![4u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--once",
+            "--timeout",
+            "60",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    // reading to the end fails if the sender exits with an error
+    let mut rest = Vec::new();
+    send_cmd.read_to_end(&mut rest).unwrap();
+}
+
+#[test]
+fn send_expire() {
+    let name = "somefile.bin";
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![4u8; 100]).unwrap();
+    let (mut send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--expire",
+            "5s",
+            "--ticket-meta",
+        ],
+    );
+    let receive = || {
+        let tgt_dir = tempfile::tempdir().unwrap();
+        duct::cmd(sendme_bin(), ["receive", &ticket])
+            .dir(tgt_dir.path())
+            .env_remove("RUST_LOG") // disable tracing
+            .stderr_to_stdout()
+            .stdout_capture()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+    assert!(receive().status.success());
+    // the sender exits by itself, reading to the end fails if it is an error
+    let mut rest = Vec::new();
+    send_cmd.read_to_end(&mut rest).unwrap();
+    let rest = String::from_utf8_lossy(&rest);
+    assert!(rest.contains("the share expired"), "{rest}");
+    // the receiver does not even try
+    let receive_output = receive();
+    assert!(!receive_output.status.success());
+    let output = String::from_utf8_lossy(&receive_output.stdout);
+    assert!(output.contains("the ticket expired"), "{output}");
+}
+
+#[test]
+fn send_expire_overflow() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join("somefile.bin");
+    std::fs::write(&src_file, vec![4u8; 100]).unwrap();
+    let output = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--expire",
+            u64::MAX.to_string().as_str(),
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_null()
+    .stderr_capture()
+    .stdout_null()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("too far in the future"), "{stderr}");
+}
+
+#[test]
+fn send_reserved_meta_name() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let send = |content: &[u8]| {
+        std::fs::write(src_dir.path().join(".sendme-meta.json"), content).unwrap();
+        std::fs::write(src_dir.path().join("a.txt"), b"a").unwrap();
+        duct::cmd(
+            sendme_bin(),
+            ["send", ".sendme-meta.json", "a.txt", "--no-progress"],
+        )
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .reader()
+        .unwrap()
+    };
+    // a user file with the name of the metadata entry is rejected
+    let mut send_cmd = send(b"not metadata");
+    let mut output = Vec::new();
+    assert!(send_cmd.read_to_end(&mut output).is_err());
+    let output = String::from_utf8_lossy(&output);
+    assert!(
+        output.contains("is reserved for the share metadata"),
+        "{output}"
+    );
+    // the metadata that an older receiver wrote to disk is skipped
+    let mut send_cmd = send(br#"{"mtimes":{"a.txt":0}}"#);
+    let output = read_ascii_lines(4, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("received by an older version"), "{output}");
+}
+
+#[test]
+fn send_store_reuses_data() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let store_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(src_dir.path().join("data")).unwrap();
+    std::fs::write(src_dir.path().join("data/a.bin"), vec![5u8; 1000]).unwrap();
+    let send = || {
+        duct::cmd(
+            sendme_bin(),
+            [
+                "send",
+                "data",
+                "--store",
+                store_dir.path().to_str().unwrap(),
+            ],
+        )
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .reader()
+        .unwrap()
+    };
+    let mut send_cmd = send();
+    read_ascii_lines(3, &mut send_cmd).unwrap();
+    send_cmd.kill().unwrap();
+    // the second share of the same store knows the data of the first one
+    let mut send_cmd = send();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("1 of 1 files unchanged"), "{output}");
+}
+
+#[test]
+fn send_store_evicts_old_data() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let store_dir = tempfile::tempdir().unwrap();
+    for (dir, byte) in [("old", 5u8), ("new", 6u8)] {
+        std::fs::create_dir_all(src_dir.path().join(dir)).unwrap();
+        std::fs::write(src_dir.path().join(dir).join("a.bin"), vec![byte; 1000]).unwrap();
+    }
+    let send = |path: &str| {
+        duct::cmd(
+            sendme_bin(),
+            [
+                "send",
+                path,
+                "--store",
+                store_dir.path().to_str().unwrap(),
+                "--cache-size",
+                "1K",
+            ],
+        )
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .reader()
+        .unwrap()
+    };
+    let mut send_cmd = send("old");
+    read_ascii_lines(3, &mut send_cmd).unwrap();
+    send_cmd.kill().unwrap();
+    // the store only has room for one of the shares
+    let mut send_cmd = send("new");
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("removed"), "{output}");
+}
+
+#[test]
+fn send_recv_data_dir() {
+    let name = "somefile.bin";
+    // larger than a share that is kept in memory
+    let data = vec![6u8; 5 * 1024 * 1024];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env("SENDME_DATA_DIR", data_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    // the store of the sender is in the data directory
+    let stores = std::fs::read_dir(data_dir.path()).unwrap().count();
+    assert_eq!(stores, 1);
+    assert_eq!(std::fs::read_dir(src_dir.path()).unwrap().count(), 1);
+    let receive_output = run_receive(
+        tgt_dir.path(),
+        &[ticket, "--data-dir", data_dir.path().to_str().unwrap()],
+    );
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+    // only the exported file, no temporary store
+    assert_eq!(std::fs::read_dir(tgt_dir.path()).unwrap().count(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn send_recv_small_share_in_memory() {
+    let name = "somefile.bin";
+    let data = vec![6u8; 1000];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &[src_file.as_os_str().to_str().unwrap()]);
+    // no store next to the shared file
+    assert_eq!(std::fs::read_dir(src_dir.path()).unwrap().count(), 1);
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+    assert_eq!(std::fs::read_dir(tgt_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn send_sigterm_removes_store() {
+    use nix::{
+        sys::signal::{kill, Signal},
+        unistd::Pid,
+    };
+
+    let name = "somefile.bin";
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    // larger than a share that is kept in memory
+    std::fs::write(&src_file, vec![7u8; 5 * 1024 * 1024]).unwrap();
+    let (mut send_cmd, _) = spawn_send(src_dir.path(), &[src_file.as_os_str().to_str().unwrap()]);
+    let pid = Pid::from_raw(send_cmd.pids()[0] as i32);
+    kill(pid, Signal::SIGTERM).unwrap();
+    // reading to the end fails if the sender exits with an error
+    let mut rest = Vec::new();
+    send_cmd.read_to_end(&mut rest).unwrap();
+    // only the shared file is left
+    assert_eq!(std::fs::read_dir(src_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn send_recv_audit_log() {
+    let name = "somefile.bin";
+    let data = vec![8u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let log = src_dir.path().join("audit.log");
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (mut send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--audit-log",
+            log.to_str().unwrap(),
+            "--wait",
+        ],
+    );
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    let mut rest = Vec::new();
+    send_cmd.read_to_end(&mut rest).unwrap();
+    let text = std::fs::read_to_string(&log).unwrap();
+    assert!(text.contains("\"connected\""), "{text}");
+    let audit = duct::cmd(sendme_bin(), ["audit", log.to_str().unwrap()])
+        .stderr_to_stdout()
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert!(audit.status.success());
+    let stdout = String::from_utf8_lossy(&audit.stdout);
+    assert!(stdout.contains("signed by"), "{stdout}");
+    // changing a record breaks the chain at the next one, the receiver said
+    // hello on the extension protocol before connecting for the data
+    assert!(text.lines().count() >= 2, "{text}");
+    std::fs::write(&log, text.replacen("\"event\":\"", "\"event\":\"x", 1)).unwrap();
+    let audit = duct::cmd(sendme_bin(), ["audit", log.to_str().unwrap()])
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!audit.status.success());
+}
+
+#[test]
+fn send_recv_small_recv_buffer() {
+    let name = "somefile.bin";
+    // larger than the receive buffer, so the transfer needs several windows
+    let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "--recv-buffer", "65536"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+/// The receiver must not buffer a large file in memory, with a small
+/// `--recv-buffer` or without.
+#[cfg(target_os = "linux")]
+#[test]
+fn recv_memory_is_bounded() {
+    let name = "large.bin";
+    let data = (0..192 * 1024 * 1024)
+        .map(|i| (i / 4096) as u8)
+        .collect::<Vec<_>>();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &[src_file.as_os_str().to_str().unwrap()]);
+    let receive = duct::cmd(
+        sendme_bin(),
+        ["receive", &ticket, "--recv-buffer", "262144"],
+    )
+    .dir(tgt_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdout_null()
+    .stderr_null()
+    .start()
+    .unwrap();
+    let pid = receive.pids()[0];
+    // the peak resident set size of the receiver, sampled until it exits
+    let mut peak_kib = 0;
+    while receive.try_wait().unwrap().is_none() {
+        let status = std::fs::read_to_string(format!("/proc/{pid}/status")).unwrap_or_default();
+        let hwm = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))
+            .and_then(|kib| kib.trim().trim_end_matches("kB").trim().parse::<u64>().ok());
+        peak_kib = peak_kib.max(hwm.unwrap_or_default());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(receive.wait().unwrap().status.success());
+    assert!(peak_kib > 0, "no memory samples");
+    assert!(
+        peak_kib < 96 * 1024,
+        "the receiver used {peak_kib} KiB for a file of {} KiB",
+        data.len() / 1024
+    );
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert!(tgt_data == data, "received data differs");
+}
+
+#[test]
+fn send_recv_limit_rate() {
+    let name = "somefile.bin";
+    let data = vec![17u8; 300 * 1024];
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--limit-rate",
+            "100K",
+        ],
+    );
+    // the limit of the sender, and a lower one of the receiver
+    for args in [vec![], vec!["--limit-rate", "50k"]] {
+        let tgt_dir = tempfile::tempdir().unwrap();
+        let start = std::time::Instant::now();
+        let receive_output = run_receive(
+            tgt_dir.path(),
+            &[&[ticket.as_str()][..], &args[..]].concat(),
+        );
+        let elapsed = start.elapsed();
+        assert!(receive_output.status.success());
+        assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+        let expected = if args.is_empty() { 2 } else { 5 };
+        assert!(
+            elapsed >= std::time::Duration::from_secs(expected),
+            "{elapsed:?}"
+        );
+    }
+}
+
+// the config directory only follows XDG_CONFIG_HOME on linux
+#[cfg(target_os = "linux")]
+#[test]
+fn init_writes_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let downloads = dir.path().join("downloads");
+    // keep the key, set the download dir, disable relays and progress bars
+    let answers = format!("\n{}\ndisabled\nn\n", downloads.display());
+    // an existing config file that others can read
+    std::fs::create_dir(dir.path().join("sendme")).unwrap();
+    std::fs::write(dir.path().join("sendme/config.toml"), "").unwrap();
+    let init_output = duct::cmd(sendme_bin(), ["init"])
+        .dir(dir.path())
+        .env("XDG_CONFIG_HOME", dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdin_bytes(answers)
+        .stderr_null()
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert!(init_output.status.success());
+    let config = std::fs::read_to_string(dir.path().join("sendme/config.toml")).unwrap();
+    assert!(config.contains("secret_key"));
+    assert!(config.contains(&format!("download_dir = \"{}\"", downloads.display())));
+    assert!(config.contains("relay = \"disabled\""));
+    assert!(config.contains("progress = \"none\""));
+    let mode = std::fs::metadata(dir.path().join("sendme/config.toml"))
+        .unwrap()
+        .permissions();
+    assert_eq!(
+        std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+        0o600
+    );
+}
+
+// the config directory only follows XDG_CONFIG_HOME on linux
+#[cfg(target_os = "linux")]
+#[test]
+fn help_ignores_invalid_config() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sendme")).unwrap();
+    std::fs::write(dir.path().join("sendme/config.toml"), "relay = [").unwrap();
+    let help_output = duct::cmd(sendme_bin(), ["send", "--help"])
+        .env("XDG_CONFIG_HOME", dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_capture()
+        .stdout_null()
+        .run()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&help_output.stderr);
+    assert!(!stderr.contains("invalid config file"), "{stderr}");
+}
+
+// the config directory only follows XDG_CONFIG_HOME on linux
+#[cfg(target_os = "linux")]
+#[test]
+fn send_dry_run_profile() {
+    let config_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    let data_dir = src_dir.path().join("builds");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(data_dir.join("app"), b"app").unwrap();
+    std::fs::write(data_dir.join("app.d"), b"deps").unwrap();
+    std::fs::create_dir_all(config_dir.path().join("sendme")).unwrap();
+    std::fs::write(
+        config_dir.path().join("sendme/config.toml"),
+        format!(
+            "[profile.builds]\npaths = [{:?}]\nexclude = [\"*.d\"]\n",
+            data_dir.display().to_string()
+        ),
+    )
+    .unwrap();
+    let output = duct::cmd(sendme_bin(), ["send", "--profile", "builds", "--dry-run"])
+        .dir(src_dir.path())
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .read()
+        .unwrap();
+    assert!(output.contains("builds/app"), "{output}");
+    assert!(!output.contains("app.d"), "{output}");
+    // the expiry of a profile is parsed like --expire
+    std::fs::write(
+        config_dir.path().join("sendme/config.toml"),
+        format!(
+            "[profile.builds]\npaths = [{:?}]\nexpire = \"0\"\n",
+            data_dir.display().to_string()
+        ),
+    )
+    .unwrap();
+    let output = duct::cmd(sendme_bin(), ["send", "--profile", "builds", "--dry-run"])
+        .dir(src_dir.path())
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("invalid expire 0"), "{stderr}");
+}
+
+#[test]
+fn report_writes_tarball() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("report.tar.gz");
+    let report_output = duct::cmd(
+        sendme_bin(),
+        ["report", "--output", output.as_os_str().to_str().unwrap()],
+    )
+    .dir(dir.path())
+    .env("XDG_CACHE_HOME", cache_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .run()
+    .unwrap();
+    assert!(report_output.status.success());
+    assert!(std::fs::metadata(&output).unwrap().len() > 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn report_log_is_opt_in() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"a").unwrap();
+    let logs = cache_dir.path().join("sendme").join("logs");
+    let run = |args: &[&str]| {
+        duct::cmd(sendme_bin(), args)
+            .dir(dir.path())
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .env_remove("RUST_LOG") // disable tracing
+            .stderr_to_stdout()
+            .stdout_capture()
+            .run()
+            .unwrap()
+    };
+    run(&["send", "--dry-run", "a.txt"]);
+    assert!(!logs.exists());
+    // every run gets its own log
+    run(&["send", "--dry-run", "--report-log", "a.txt"]);
+    run(&["send", "--dry-run", "--report-log", "a.txt"]);
+    assert_eq!(std::fs::read_dir(&logs).unwrap().count(), 2);
+}
+
+#[test]
+fn recv_log_file_is_redacted() {
+    let name = "somefile.bin";
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let log_file = tgt_dir.path().join("sendme.log");
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![1u8; 100]).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(
+        sendme_bin(),
+        [
+            "receive",
+            ticket,
+            "--log-file",
+            log_file.as_os_str().to_str().unwrap(),
+        ],
+    )
+    .dir(tgt_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .run()
+    .unwrap();
+    assert!(receive_output.status.success());
+    let log = std::fs::read_to_string(&log_file).unwrap();
+    assert!(!log.contains(ticket));
+}
+
+#[test]
+fn send_log_file_json() {
+    let name = "somefile.bin";
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let log_file = src_dir.path().join("sendme.log");
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![1u8; 100]).unwrap();
+    let (_send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--log-file",
+            log_file.as_os_str().to_str().unwrap(),
+            "--log-format",
+            "json",
+            "--log-unredacted",
+        ],
+    );
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    // the provider logs the end of the transfer a bit later
+    let mut log = String::new();
+    for _ in 0..50 {
+        log = std::fs::read_to_string(&log_file).unwrap();
+        if log.contains("transfer completed") {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let lines = log
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let connected = lines
+        .iter()
+        .find(|line| line["fields"]["message"] == "client connected")
+        .expect("no connection in the log");
+    let node_id = connected["fields"]["node_id"].as_str().unwrap();
+    assert_eq!(node_id.len(), 64, "{node_id}");
+    assert!(lines
+        .iter()
+        .any(|line| line["fields"]["message"] == "transfer completed"));
+}
+
+#[test]
+fn recv_trace_export() {
+    let name = "somefile.bin";
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![1u8; 100]).unwrap();
+    let (_send_cmd, ticket) = spawn_send(src_dir.path(), &[src_file.as_os_str().to_str().unwrap()]);
+    let receive_output = duct::cmd(sendme_bin(), ["receive", &ticket, "--trace", "export"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_capture()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let log = String::from_utf8_lossy(&receive_output.stderr);
+    assert!(log.contains(&format!("exported {name}")), "{log}");
+    // only the export is traced
+    assert!(!log.contains("sendme::transfer"), "{log}");
+}
+
+// other unix file systems, e.g. on macos, only allow unicode names
+#[cfg(target_os = "linux")]
+#[test]
+fn send_recv_non_unicode_name() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let name = OsStr::from_bytes(b"caf\xe9 100%.txt");
+    let data = vec![5u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("data");
+    std::fs::create_dir_all(&src_data_dir).unwrap();
+    std::fs::write(src_data_dir.join(name), &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_data_dir.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    // the original name is restored
+    let tgt_data = std::fs::read(tgt_dir.path().join("data").join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+#[test]
+fn recv_rejects_too_many_entries() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("data");
+    std::fs::create_dir_all(&src_data_dir).unwrap();
+    for i in 0..3 {
+        std::fs::write(src_data_dir.join(format!("file-{i}")), vec![6u8; 100]).unwrap();
+    }
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_data_dir.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "--max-entries", "2"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!receive_output.status.success());
+    let stdout = String::from_utf8_lossy(&receive_output.stdout);
+    assert!(stdout.contains("more than the limit of 2"), "{stdout}");
+    // nothing was exported
+    assert!(!tgt_dir.path().join("data").exists());
+}
+
+#[test]
+fn send_recv_password() {
+    let name = "somefile.bin";
+    let data = vec![7u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--password",
+            "secret",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "--password", "wrong"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!receive_output.status.success());
+    let stdout = String::from_utf8_lossy(&receive_output.stdout);
+    assert!(stdout.contains("wrong password"), "{stdout}");
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "--password", "secret"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+#[test]
+fn send_password_guessing_keeps_known_nodes() {
+    let name = "somefile.bin";
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![7u8; 100]).unwrap();
+    let (_send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--password",
+            "secret",
+        ],
+    );
+    let receive = |secret: &iroh::SecretKey, password: &str| {
+        let tgt_dir = tempfile::tempdir().unwrap();
+        let output = duct::cmd(sendme_bin(), ["receive", &ticket, "--password", password])
+            .dir(tgt_dir.path())
+            .env("IROH_SECRET", hex::encode(secret.to_bytes()))
+            .env_remove("RUST_LOG") // disable tracing
+            .stderr_to_stdout()
+            .stdout_capture()
+            .unchecked()
+            .run()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        (output.status.success(), stdout)
+    };
+    let known = iroh::SecretKey::generate(rand::rngs::OsRng);
+    let (success, stdout) = receive(&known, "secret");
+    assert!(success, "{stdout}");
+    // guess with more new nodes than wrong passwords are allowed per minute
+    std::thread::scope(|scope| {
+        let guesses = (0..25)
+            .map(|_| {
+                scope.spawn(|| receive(&iroh::SecretKey::generate(rand::rngs::OsRng), "wrong"))
+            })
+            .collect::<Vec<_>>();
+        for guess in guesses {
+            let (success, _) = guess.join().unwrap();
+            assert!(!success);
+        }
+    });
+    let (success, stdout) = receive(&known, "secret");
+    assert!(success, "{stdout}");
+    let (success, stdout) = receive(&iroh::SecretKey::generate(rand::rngs::OsRng), "secret");
+    assert!(!success);
+    assert!(stdout.contains("too many wrong passwords"), "{stdout}");
+}
+
+#[test]
+fn send_allow_rejects_other_nodes() {
+    let name = "somefile.bin";
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![8u8; 100]).unwrap();
+    let other = iroh::SecretKey::generate(rand::rngs::OsRng).public();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--allow",
+            &other.to_string(),
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!receive_output.status.success());
+    let stdout = String::from_utf8_lossy(&receive_output.stdout);
+    assert!(stdout.contains("not in the allow list"), "{stdout}");
+    assert!(!tgt_dir.path().join(name).exists());
+}
+
+// the config directory only follows XDG_CONFIG_HOME on linux
+#[cfg(target_os = "linux")]
+#[test]
+fn recv_expect_identity_peer() {
+    let name = "somefile.bin";
+    let config_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![9u8; 100]).unwrap();
+    let other = iroh::SecretKey::generate(rand::rngs::OsRng).public();
+    duct::cmd(sendme_bin(), ["peers", "add", "laptop", &other.to_string()])
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .run()
+        .unwrap();
+    let list_output = duct::cmd(sendme_bin(), ["peers", "list"])
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .read()
+        .unwrap();
+    assert_eq!(list_output, format!("laptop\t{other}"));
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(
+        sendme_bin(),
+        ["receive", ticket, "--expect-identity", "laptop"],
+    )
+    .dir(tgt_dir.path())
+    .env("XDG_CONFIG_HOME", config_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .stdout_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!receive_output.status.success());
+    let stdout = String::from_utf8_lossy(&receive_output.stdout);
+    assert!(stdout.contains("not from the expected"), "{stdout}");
+    assert!(!tgt_dir.path().join(name).exists());
+}
+
+#[test]
+fn send_recv_json() {
+    let name = "somefile.bin";
+    let data = vec![9u8; 1000];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    // messages go to stderr, so the first line on stdout is the ticket event
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", "--json", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(1, &mut send_cmd).unwrap();
+    let event: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(event["event"], "ticket_issued");
+    assert_eq!(event["size"], data.len() as u64);
+    let ticket = event["ticket"].as_str().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", "--json", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_null()
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let events = String::from_utf8(receive_output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let completed = events.last().unwrap();
+    assert_eq!(completed["event"], "completed");
+    assert_eq!(completed["files"], 1);
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+#[test]
+fn send_watch_reshares_changes() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let shared = src_dir.path().join("shared");
+    std::fs::create_dir_all(&shared).unwrap();
+    std::fs::write(shared.join("a.txt"), b"old").unwrap();
+    let mut send_cmd = duct::cmd(sendme_bin(), ["send", "--json", "--watch", "shared"])
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_null()
+        .reader()
+        .unwrap();
+    let output = read_ascii_lines(1, &mut send_cmd).unwrap();
+    let event: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let first = event["hash"].as_str().unwrap().to_string();
+    std::fs::write(shared.join("a.txt"), b"new").unwrap();
+    // address changes also issue tickets, but for the same hash
+    let ticket = loop {
+        let output = read_ascii_lines(1, &mut send_cmd).unwrap();
+        let event: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        if event["event"] == "ticket_issued" && event["hash"] != first.as_str() {
+            break event["ticket"].as_str().unwrap().to_string();
+        }
+    };
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("shared/a.txt")).unwrap(),
+        b"new"
+    );
+}
+
+#[test]
+fn serve_add_recv() {
+    let serve_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"served").unwrap();
+    let mut serve_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "serve",
+            "--dir",
+            serve_dir.path().to_str().unwrap(),
+            "--no-progress",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_bytes("add a.txt\n")
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(1, &mut serve_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = run_receive(tgt_dir.path(), &[ticket]);
+    assert!(receive_output.status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("a.txt")).unwrap(),
+        b"served"
+    );
+    // the share is remembered for the next run
+    let shares = std::fs::read_to_string(serve_dir.path().join("serve.json")).unwrap();
+    assert!(shares.contains(ticket), "{shares}");
+}
+
+#[test]
+fn serve_rate_command() {
+    let serve_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let data = vec![17u8; 300 * 1024];
+    std::fs::write(src_dir.path().join("a.bin"), &data).unwrap();
+    let mut serve_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "serve",
+            "--dir",
+            serve_dir.path().to_str().unwrap(),
+            "--no-progress",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_bytes("rate 100K\nadd a.bin\n")
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(1, &mut serve_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let start = std::time::Instant::now();
+    let receive_output = run_receive(tgt_dir.path(), &[ticket]);
+    let elapsed = start.elapsed();
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join("a.bin")).unwrap(), data);
+    assert!(elapsed >= std::time::Duration::from_secs(2), "{elapsed:?}");
+}
+
+#[test]
+fn serve_global_budget() {
+    let serve_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let data = vec![17u8; 300 * 1024];
+    std::fs::write(src_dir.path().join("a.bin"), &data).unwrap();
+    let mut serve_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "serve",
+            "--dir",
+            serve_dir.path().to_str().unwrap(),
+            "--no-progress",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_bytes("rate global 100K\nadd a.bin\n")
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(1, &mut serve_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let start = std::time::Instant::now();
+    let receive_output = run_receive(tgt_dir.path(), &[ticket]);
+    let elapsed = start.elapsed();
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join("a.bin")).unwrap(), data);
+    // a single receiver gets the whole budget
+    assert!(elapsed >= std::time::Duration::from_secs(2), "{elapsed:?}");
+}
+
+#[cfg(unix)]
+#[test]
+fn serve_on_event() {
+    let serve_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"indexed").unwrap();
+    let events = src_dir.path().join("events.txt");
+    let hook = format!(
+        "echo \"$SENDME_EVENT $SENDME_HASH $SENDME_BLOBS\" >> {}",
+        events.display()
+    );
+    let serve = |input: String| {
+        duct::cmd(
+            sendme_bin(),
+            [
+                "serve",
+                "--dir",
+                serve_dir.path().to_str().unwrap(),
+                "--no-progress",
+                "--on-event",
+                &hook,
+            ],
+        )
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdin_bytes(input)
+        .stderr_null()
+        .reader()
+        .unwrap()
+    };
+    let mut serve_cmd = serve("add a.txt\n".to_string());
+    let output = read_ascii_lines(1, &mut serve_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let hash = sendme::Ticket::from_str(ticket).unwrap().hash().to_hex();
+    // the hook runs before the receive command is printed
+    let added = std::fs::read_to_string(&events).unwrap();
+    assert!(added.starts_with(&format!("added {hash} ")), "{added}");
+    drop(serve_cmd);
+    let _serve_cmd = serve(format!("remove {hash}\n"));
+    let removed = (0..50).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::fs::read_to_string(&events)
+            .is_ok_and(|events| events.contains(&format!("removed {hash} ")))
+    });
+    assert!(removed);
+}
+
+#[test]
+fn serve_verify_only() {
+    let serve_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(src_dir.path().join("data")).unwrap();
+    // large enough that the download can not finish before it is refused
+    std::fs::write(
+        src_dir.path().join("data/a.bin"),
+        vec![5u8; 64 * 1024 * 1024],
+    )
+    .unwrap();
+    let mut serve_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "serve",
+            "--dir",
+            serve_dir.path().to_str().unwrap(),
+            "--no-progress",
+            "--verify-only",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_bytes("add data\n")
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(1, &mut serve_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    // the entries can be listed and checked
+    let output = duct::cmd(sendme_bin(), ["verify", "--ticket", ticket])
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    // but not downloaded
+    let output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!tgt_dir.path().join("data/a.bin").exists());
+}
+
+#[test]
+fn serve_pin() {
+    let serve_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    std::fs::write(src_dir.path().join("small.bin"), vec![1u8; 100]).unwrap();
+    std::fs::write(src_dir.path().join("large.bin"), vec![2u8; 2 * 1024 * 1024]).unwrap();
+    std::fs::write(src_dir.path().join("served.txt"), b"served").unwrap();
+    let (_small_cmd, small) = spawn_send(src_dir.path(), &["small.bin", "--no-progress"]);
+    let (_large_cmd, large) = spawn_send(src_dir.path(), &["large.bin", "--no-progress"]);
+    let secret = iroh::SecretKey::generate(rand::rngs::OsRng);
+    let mut serve_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "serve",
+            "--dir",
+            serve_dir.path().to_str().unwrap(),
+            "--no-progress",
+            "--allow-pin",
+            &secret.public().to_string(),
+            "--max-pin-size",
+            "1M",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_bytes("add served.txt\n")
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(1, &mut serve_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let provider = output.split_ascii_whitespace().last().unwrap();
+    let pin = |ticket: &str| {
+        duct::cmd(sendme_bin(), ["pin", provider, ticket])
+            .env("IROH_SECRET", hex::encode(secret.to_bytes()))
+            .env_remove("RUST_LOG") // disable tracing
+            .stderr_to_stdout()
+            .stdout_capture()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+    let output = pin(&small);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("pinned"), "{stdout}");
+    let output = pin(&large);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(stdout.contains("larger than the limit"), "{stdout}");
+    // the pin is remembered for the next run
+    let hash = sendme::Ticket::from_str(&small).unwrap().hash().to_hex();
+    let pinned = (0..50).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::fs::read_to_string(serve_dir.path().join("serve.json"))
+            .is_ok_and(|shares| shares.contains(&format!("\"pins\":{{\"{hash}\"")))
+    });
+    assert!(pinned);
+}
+
+// the config directory only follows XDG_CONFIG_HOME on linux
+#[cfg(target_os = "linux")]
+#[test]
+fn serve_rejects_invalid_schedule() {
+    let config_dir = tempfile::tempdir().unwrap();
+    let serve_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(config_dir.path().join("sendme")).unwrap();
+    std::fs::write(
+        config_dir.path().join("sendme/config.toml"),
+        "[[schedule]]\nstart = \"9:00am\"\nend = \"18:00\"\nrate = \"1M\"\n",
+    )
+    .unwrap();
+    let output = duct::cmd(
+        sendme_bin(),
+        ["serve", "--dir", serve_dir.path().to_str().unwrap()],
+    )
+    .env("XDG_CONFIG_HOME", config_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_null()
+    .stderr_capture()
+    .stdout_null()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("invalid schedule window 1"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn serve_pack_unpack() {
+    use nix::{
+        sys::signal::{kill, Signal},
+        unistd::Pid,
+    };
+
+    let serve_dir = tempfile::tempdir().unwrap();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(src_dir.path().join("data/sub")).unwrap();
+    std::fs::write(src_dir.path().join("data/a.txt"), b"packed").unwrap();
+    std::fs::write(src_dir.path().join("data/sub/b.bin"), vec![3u8; 10_000]).unwrap();
+    let mut serve_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "serve",
+            "--dir",
+            serve_dir.path().to_str().unwrap(),
+            "--no-progress",
+            "data",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_null()
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(1, &mut serve_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let hash = sendme::Ticket::from_str(ticket).unwrap().hash().to_hex();
+    // the store can only be used by one process at a time
+    let pid = Pid::from_raw(serve_cmd.pids()[0] as i32);
+    kill(pid, Signal::SIGTERM).unwrap();
+    let mut rest = Vec::new();
+    serve_cmd.read_to_end(&mut rest).unwrap();
+    let archive = src_dir.path().join("data.pack");
+    let pack_output = duct::cmd(
+        sendme_bin(),
+        [
+            "pack",
+            hash.as_str(),
+            "--output",
+            archive.to_str().unwrap(),
+            "--dir",
+            serve_dir.path().to_str().unwrap(),
+        ],
+    )
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .run()
+    .unwrap();
+    assert!(pack_output.status.success());
+    let unpack_output = duct::cmd(
+        sendme_bin(),
+        [
+            "unpack",
+            archive.to_str().unwrap(),
+            "--output",
+            tgt_dir.path().to_str().unwrap(),
+        ],
+    )
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .run()
+    .unwrap();
+    assert!(unpack_output.status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("data/a.txt")).unwrap(),
+        b"packed"
+    );
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("data/sub/b.bin")).unwrap(),
+        vec![3u8; 10_000]
+    );
+    // a damaged archive is rejected
+    let mut bytes = std::fs::read(&archive).unwrap();
+    let pos = bytes
+        .windows(6)
+        .position(|window| window == b"packed")
+        .unwrap();
+    bytes[pos] = b'P';
+    std::fs::write(&archive, bytes).unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let unpack_output = duct::cmd(
+        sendme_bin(),
+        [
+            "unpack",
+            archive.to_str().unwrap(),
+            "--output",
+            tgt_dir.path().to_str().unwrap(),
+        ],
+    )
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!unpack_output.status.success());
+    assert!(!tgt_dir.path().join("data/a.txt").exists());
+}
+
+#[test]
+fn send_recv_output_dir() {
+    let name = "somefile.bin";
+    let data = vec![10u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "-o", "out/nested"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read(tgt_dir.path().join("out/nested").join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+    assert!(!tgt_dir.path().join(name).exists());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn send_recv_compressed() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("logs");
+    std::fs::create_dir_all(&src_data_dir).unwrap();
+    let data = "GET /index.html 200\n".repeat(1000);
+    std::fs::write(src_data_dir.join("access.log"), &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_data_dir.as_os_str().to_str().unwrap(),
+            "--compress",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    // no -z, the share says it is compressed
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read_to_string(tgt_dir.path().join("logs/access.log")).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+#[test]
+fn send_recv_from_two_senders() {
+    let name = "somefile.bin";
+    let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut tickets = Vec::new();
+    let mut senders = Vec::new();
+    for _ in 0..2 {
+        let mut send_cmd = duct::cmd(
+            sendme_bin(),
+            ["send", src_file.as_os_str().to_str().unwrap()],
+        )
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .reader()
+        .unwrap();
+        let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        tickets.push(output.split_ascii_whitespace().last().unwrap().to_string());
+        senders.push(send_cmd);
+    }
+    let receive_output = duct::cmd(
+        sendme_bin(),
+        ["receive", &tickets[0], "--from", &tickets[1]],
+    )
+    .dir(tgt_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .run()
+    .unwrap();
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+#[test]
+fn send_recv_stdin() {
+    let data = vec![14u8; 100];
+    // plain tickets and tickets with the name, which point to the raw entry
+    for extra in [None, Some("--ticket-meta")] {
+        let src_dir = tempfile::tempdir().unwrap();
+        let tgt_dir = tempfile::tempdir().unwrap();
+        let args = ["send", "-", "--name", "piped.bin"]
+            .into_iter()
+            .chain(extra);
+        let mut send_cmd = duct::cmd(sendme_bin(), args)
+            .dir(src_dir.path())
+            .env_remove("RUST_LOG") // disable tracing
+            .stdin_bytes(data.clone())
+            .stderr_null()
+            .reader()
+            .unwrap();
+        let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let ticket = output.split_ascii_whitespace().last().unwrap();
+        let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+            .dir(tgt_dir.path())
+            .env_remove("RUST_LOG") // disable tracing
+            .stderr_to_stdout()
+            .run()
+            .unwrap();
+        assert!(receive_output.status.success());
+        let tgt_data = std::fs::read(tgt_dir.path().join("piped.bin")).unwrap();
+        assert_eq!(tgt_data, data);
+    }
+}
+
+#[test]
+fn send_recv_stdout() {
+    let name = "somefile.bin";
+    let data = vec![15u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "--stdout"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdout_capture()
+        .stderr_null()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    assert_eq!(receive_output.stdout, data);
+    assert!(!tgt_dir.path().join(name).exists());
+}
+
+#[test]
+fn send_recv_tar() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("dir");
+    std::fs::create_dir_all(src_data_dir.join("sub")).unwrap();
+    std::fs::write(src_data_dir.join("a.txt"), vec![1u8; 1000]).unwrap();
+    std::fs::write(src_data_dir.join("sub/b.txt"), vec![2u8; 512]).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_data_dir.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "--tar"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdout_capture()
+        .stderr_null()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    assert!(!tgt_dir.path().join("dir").exists());
+    let mut archive = tar::Archive::new(receive_output.stdout.as_slice());
+    archive.unpack(tgt_dir.path()).unwrap();
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("dir/a.txt")).unwrap(),
+        vec![1u8; 1000]
+    );
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("dir/sub/b.txt")).unwrap(),
+        vec![2u8; 512]
+    );
+}
+
+#[test]
+fn send_get_dest() {
+    let name = "somefile.bin";
+    let data = vec![8u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["provide", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["get", ticket, "downloads", "-y"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("downloads").join(name)).unwrap(),
+        data
+    );
+}
+
+#[test]
+fn send_recv_local() {
+    // unique, so shares of other tests on the network are not picked up
+    let name = format!("local-{}.bin", std::process::id());
+    let data = vec![10u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(&name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, _) = spawn_send(
+        src_dir.path(),
+        &[src_file.as_os_str().to_str().unwrap(), "--local"],
+    );
+    let receive_output = run_receive(tgt_dir.path(), &["--local", &name, "-y"]);
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(&name)).unwrap(), data);
+    // the announcement must not be published with DNS discovery
+    let output = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--local",
+            "--ticket-type",
+            "id",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdin_null()
+    .stderr_capture()
+    .stdout_null()
+    .unchecked()
+    .run()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("--ticket-type id"), "{stderr}");
+}
+
+#[test]
+fn send_metrics() {
+    let name = "somefile.bin";
+    let data = vec![11u8; 1000];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let metrics_addr = format!("127.0.0.1:{port}");
+    let (_send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--metrics-addr",
+            &metrics_addr,
+        ],
+    );
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket]);
+    assert!(receive_output.status.success());
+    // the close of the connection is seen by the provider a bit later
+    let mut metrics = String::new();
+    for _ in 0..50 {
+        let mut stream = std::net::TcpStream::connect(&metrics_addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        metrics.clear();
+        stream.read_to_string(&mut metrics).unwrap();
+        if metrics.contains("sendme_connections_active 0") {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert!(metrics.starts_with("HTTP/1.1 200 OK"), "{metrics}");
+    assert!(
+        !metrics.contains("sendme_bytes_served_total 0\n"),
+        "{metrics}"
+    );
+    assert!(metrics.contains("sendme_connections_active 0"), "{metrics}");
+    assert!(
+        metrics.contains("sendme_peer_bytes_served_total{node_id="),
+        "{metrics}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn send_recv_on_complete() {
+    let name = "somefile.bin";
+    let data = vec![12u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (mut send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "--wait",
+            "--on-complete",
+            "echo \"$SENDME_BYTES $SENDME_PATH\" > hook.txt",
+        ],
+    );
+    let receive_output = run_receive(
+        tgt_dir.path(),
+        &[
+            &ticket,
+            "--on-complete",
+            "echo \"$SENDME_BYTES $SENDME_PATH $SENDME_PEER\" > hook.txt",
+        ],
+    );
+    assert!(receive_output.status.success());
+    let hook = std::fs::read_to_string(tgt_dir.path().join("hook.txt")).unwrap();
+    let node_id = sendme::Ticket::from_str(&ticket)
+        .unwrap()
+        .node_addr()
+        .node_id;
+    assert_eq!(
+        hook.trim(),
+        format!("100 {} {node_id}", tgt_dir.path().join(name).display())
+    );
+    // send exits with --wait once the hook ran
+    let mut rest = Vec::new();
+    send_cmd.read_to_end(&mut rest).unwrap();
+    let hook = std::fs::read_to_string(src_dir.path().join("hook.txt")).unwrap();
+    assert!(hook.starts_with("100 "), "{hook}");
+    assert!(hook.trim().ends_with(name), "{hook}");
+}
+
+#[test]
+fn send_recv_url() {
+    let name = "somefile.bin";
+    let data = vec![13u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap(), "--url"],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    // the link follows the ticket, other messages may come in between
+    let mut output = String::new();
+    let url = loop {
+        let line = read_ascii_lines(1, &mut send_cmd).unwrap();
+        assert!(!line.is_empty(), "no link in {output}");
+        output.push_str(&String::from_utf8(line).unwrap());
+        if let Some(url) = output
+            .split_ascii_whitespace()
+            .find(|word| word.starts_with("sendme://"))
+        {
+            break url.to_string();
+        }
+    };
+    let receive_output = run_receive(tgt_dir.path(), &[&url]);
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+}
+
+#[test]
+fn send_recv_ipv4_only() {
+    let name = "somefile.bin";
+    let data = vec![9u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[
+            src_file.as_os_str().to_str().unwrap(),
+            "-4",
+            "--bind-addr",
+            "0.0.0.0",
+        ],
+    );
+    let parsed = sendme::Ticket::from_str(&ticket).unwrap();
+    assert!(parsed
+        .node_addr()
+        .direct_addresses
+        .iter()
+        .all(|addr| addr.is_ipv4()));
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket, "-4"]);
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+}
+
+#[test]
+fn send_recv_direct_only() {
+    let name = "somefile.bin";
+    let data = vec![6u8; 1000];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    let (_send_cmd, ticket) = spawn_send(
+        src_dir.path(),
+        &[src_file.as_os_str().to_str().unwrap(), "--direct-only"],
+    );
+    // listing connects like a download
+    let list_output = duct::cmd(
+        sendme_bin(),
+        ["receive", &ticket, "--direct-only", "--list"],
+    )
+    .dir(tgt_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_null()
+    .stdout_capture()
+    .run()
+    .unwrap();
+    assert!(list_output.status.success());
+    let listing = String::from_utf8_lossy(&list_output.stdout);
+    assert!(listing.contains(name), "{listing}");
+    let receive_output = run_receive(tgt_dir.path(), &[&ticket, "--direct-only"]);
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+}
+
+#[test]
+fn send_tar_in_recv_tar_out() {
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let long_name = format!("dir/{}.txt", "x".repeat(120));
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, data) in [("dir/a.txt", vec![1u8; 1000]), (&long_name, vec![2u8; 512])] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(1_000_000_000);
+        builder
+            .append_data(&mut header, name, data.as_slice())
+            .unwrap();
+    }
+    let archive = builder.into_inner().unwrap();
+    let mut send_cmd = duct::cmd(sendme_bin(), ["send", "--tar-in"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdin_bytes(archive)
+        .stderr_to_stdout()
+        .reader()
+        .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket, "--tar-out"])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stdout_capture()
+        .stderr_null()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let mut archive = tar::Archive::new(receive_output.stdout.as_slice());
+    archive.unpack(tgt_dir.path()).unwrap();
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("dir/a.txt")).unwrap(),
+        vec![1u8; 1000]
+    );
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join(&long_name)).unwrap(),
+        vec![2u8; 512]
+    );
+}
+
+#[test]
+fn send_tar_in_pax_size_and_fifo() {
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let send = |archive: Vec<u8>| {
+        duct::cmd(sendme_bin(), ["send", "--tar-in", "--no-progress"])
+            .dir(tgt_dir.path())
+            .env_remove("RUST_LOG") // disable tracing
+            .stdin_bytes(archive)
+            .stderr_to_stdout()
+            .reader()
+            .unwrap()
+    };
+    // the size in the header is wrong, only the pax size is right
+    let data = vec![3u8; 600];
+    let mut builder = tar::Builder::new(Vec::new());
+    let pax = b"12 size=600\n";
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_size(pax.len() as u64);
+    builder
+        .append_data(&mut header, "PaxHeaders/a.bin", pax.as_slice())
+        .unwrap();
+    let mut header = tar::Header::new_ustar();
+    header.set_size(0);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, "a.bin", data.as_slice())
+        .unwrap();
+    let mut send_cmd = send(builder.into_inner().unwrap());
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = run_receive(tgt_dir.path(), &[ticket]);
+    assert!(receive_output.status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join("a.bin")).unwrap(), data);
+    // other entry types are an error, instead of being left out
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::Fifo);
+    header.set_size(0);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, "pipe", std::io::empty())
+        .unwrap();
+    let mut send_cmd = send(builder.into_inner().unwrap());
+    let mut output = Vec::new();
+    assert!(send_cmd.read_to_end(&mut output).is_err());
+    let output = String::from_utf8_lossy(&output);
+    assert!(
+        output.contains("pipe is not a file, directory or link"),
+        "{output}"
+    );
+}
+
+#[test]
+fn send_recv_on_conflict() {
+    let name = "somefile.bin";
+    let data = vec![11u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    std::fs::write(tgt_dir.path().join(name), b"existing").unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive = |policy: &str| {
+        duct::cmd(sendme_bin(), ["receive", ticket, "--on-conflict", policy])
+            .dir(tgt_dir.path())
+            .env_remove("RUST_LOG") // disable tracing
+            .stderr_to_stdout()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+    assert!(!receive("fail").status.success());
+    assert!(receive("skip").status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join(name)).unwrap(),
+        b"existing"
+    );
+    assert!(receive("rename").status.success());
+    assert_eq!(
+        std::fs::read(tgt_dir.path().join("somefile (1).bin")).unwrap(),
+        data
+    );
+    assert!(receive("overwrite").status.success());
+    assert_eq!(std::fs::read(tgt_dir.path().join(name)).unwrap(), data);
+    // the previous file is kept as a backup
+    let backups = std::fs::read_dir(tgt_dir.path().join(".sendme-backup"))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(backups.len(), 1);
+    assert_eq!(
+        std::fs::read(backups[0].path().join(name)).unwrap(),
+        b"existing"
+    );
+}
+
+#[test]
+fn send_recv_mtime() {
+    let name = "somefile.bin";
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, vec![12u8; 100]).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&src_file)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        ["send", src_file.as_os_str().to_str().unwrap()],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_to_stdout()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let metadata = std::fs::metadata(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(metadata.modified().unwrap(), mtime);
+}
+
+#[cfg(unix)]
+#[test]
+fn send_recv_preserve_symlinks() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("data");
+    std::fs::create_dir_all(src_data_dir.join("sub")).unwrap();
+    std::fs::write(src_data_dir.join("a.txt"), b"hello").unwrap();
+    std::os::unix::fs::symlink("../a.txt", src_data_dir.join("sub").join("link")).unwrap();
+    std::os::unix::fs::symlink("/etc/passwd", src_data_dir.join("absolute")).unwrap();
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_data_dir.as_os_str().to_str().unwrap(),
+            "--symlinks",
+            "preserve",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let link = tgt_dir.path().join("data").join("sub").join("link");
+    assert_eq!(
+        std::fs::read_link(&link).unwrap(),
+        std::path::Path::new("../a.txt")
+    );
+    assert_eq!(std::fs::read(&link).unwrap(), b"hello");
+    assert!(!tgt_dir.path().join("data").join("absolute").exists());
+}
+
+#[test]
+fn send_self_test() {
+    let name = "somefile.bin";
+    let data = vec![13u8; 100];
+    let src_dir = tempfile::tempdir().unwrap();
+    let tgt_dir = tempfile::tempdir().unwrap();
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data).unwrap();
+    // with --once, the self test must not count as the one receiver
+    let mut send_cmd = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            src_file.as_os_str().to_str().unwrap(),
+            "--self-test",
+            "--once",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stderr_null()
+    .reader()
+    .unwrap();
+    let output = read_ascii_lines(3, &mut send_cmd).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let ticket = output.split_ascii_whitespace().last().unwrap();
+    let receive_output = duct::cmd(sendme_bin(), ["receive", ticket])
+        .dir(tgt_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .stderr_to_stdout()
+        .run()
+        .unwrap();
+    assert!(receive_output.status.success());
+    let tgt_data = std::fs::read(tgt_dir.path().join(name)).unwrap();
+    assert_eq!(tgt_data, data);
+}
+
+#[test]
+fn send_dry_run_include_exclude() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("data");
+    std::fs::create_dir_all(src_data_dir.join("target")).unwrap();
+    std::fs::create_dir_all(src_data_dir.join("src")).unwrap();
+    std::fs::write(src_data_dir.join("target").join("a.rs"), vec![0u8; 10]).unwrap();
+    std::fs::write(src_data_dir.join("src").join("b.rs"), vec![0u8; 20]).unwrap();
+    std::fs::write(src_data_dir.join("src").join("c.log"), vec![0u8; 30]).unwrap();
+    std::fs::write(src_data_dir.join("README"), vec![0u8; 40]).unwrap();
+    let output = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            "data",
+            "--dry-run",
+            "--include",
+            "*.rs",
+            "--exclude",
+            "target/",
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .read()
+    .unwrap();
+    let lines = output.lines().map(str::trim).collect::<Vec<_>>();
+    assert_eq!(lines, ["20 data/src/b.rs", "1 files, 20 B"]);
+}
+
+#[test]
+fn send_dry_run_gitignore() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("data");
+    std::fs::create_dir_all(src_data_dir.join("target")).unwrap();
+    std::fs::write(src_data_dir.join(".gitignore"), b"target/\n").unwrap();
+    std::fs::write(src_data_dir.join("target").join("a.o"), vec![0u8; 10]).unwrap();
+    std::fs::write(src_data_dir.join("b.rs"), vec![0u8; 20]).unwrap();
+    let output = duct::cmd(sendme_bin(), ["send", "data", "--dry-run", "--gitignore"])
+        .dir(src_dir.path())
+        .env_remove("RUST_LOG") // disable tracing
+        .read()
+        .unwrap();
+    let lines = output.lines().map(str::trim).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        ["8 data/.gitignore", "20 data/b.rs", "2 files, 28 B"]
+    );
+}
+
+#[test]
+fn send_dry_run_lists_entries() {
+    let src_dir = tempfile::tempdir().unwrap();
+    let src_data_dir = src_dir.path().join("data");
+    std::fs::create_dir_all(src_data_dir.join("sub")).unwrap();
+    std::fs::write(src_data_dir.join("b.txt"), vec![0u8; 10]).unwrap();
+    std::fs::write(src_data_dir.join("sub").join("a.txt"), vec![0u8; 20]).unwrap();
+    let output = duct::cmd(
+        sendme_bin(),
+        [
+            "send",
+            "--dry-run",
+            src_data_dir.as_os_str().to_str().unwrap(),
+        ],
+    )
+    .dir(src_dir.path())
+    .env_remove("RUST_LOG") // disable tracing
+    .stdout_capture()
+    .run()
+    .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().map(str::trim).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        ["10 data/b.txt", "20 data/sub/a.txt", "2 files, 30 B"]
+    );
+    // nothing was stored
+    let stores = std::fs::read_dir(src_dir.path())
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with(".sendme-")
+        })
+        .count();
+    assert_eq!(stores, 0);
+}
@@ -0,0 +1,42 @@
+use std::str::FromStr;
+
+#[tokio::test]
+async fn send_receive_api() -> anyhow::Result<()> {
+    let name = "somefile.bin";
+    let data = vec![4u8; 100_000];
+    let src_dir = tempfile::tempdir()?;
+    let tgt_dir = tempfile::tempdir()?;
+    let src_file = src_dir.path().join(name);
+    std::fs::write(&src_file, &data)?;
+    let share = sendme::send(&src_file).await?;
+    // tickets round trip through their string form
+    let ticket = sendme::Ticket::from_str(&share.ticket().to_string())?;
+    // and through their link form
+    let url = sendme::Ticket::from_str(&format!("{}/", share.ticket().to_url()))?;
+    assert_eq!(url.to_string(), ticket.to_string());
+    let collection = sendme::receive(&ticket, tgt_dir.path()).await?;
+    assert_eq!(collection.len(), 1);
+    assert_eq!(std::fs::read(tgt_dir.path().join(name))?, data);
+    share.shutdown().await?;
+    Ok(())
+}
+
+#[test]
+fn symlink_chains_stay_inside() {
+    use sendme::export::check_symlink_target;
+    let links = [
+        ("a/b/c/up", "../.."),
+        ("a/b/c/out", "up/../../.."),
+        ("a/b/c/in", "up/../x"),
+        ("loop", "loop"),
+    ]
+    .into_iter()
+    .map(|(name, target)| (name.to_string(), target.to_string()))
+    .collect();
+    assert!(check_symlink_target("a/b/c/up", "../..", &links).is_ok());
+    assert!(check_symlink_target("a/b/c/in", "up/../x", &links).is_ok());
+    // each step stays inside, but up points to a, so this leaves the share
+    assert!(check_symlink_target("a/b/c/out", "up/../../..", &links).is_err());
+    assert!(check_symlink_target("loop", "loop", &links).is_err());
+    assert!(check_symlink_target("link", "/etc/passwd", &links).is_err());
+}